@@ -1,8 +1,8 @@
+use blockchaintree::miner::{Miner, MiningJob};
 use blockchaintree::static_values::BLOCKS_PER_EPOCH;
 use blockchaintree::tools;
 use blockchaintree::{blockchaintree::BlockChainTree, static_values};
-use primitive_types::U256;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::AtomicBool;
 
 fn main() {
     let rt = tokio::runtime::Runtime::new().unwrap();
@@ -10,6 +10,7 @@ fn main() {
     let mut tree = BlockChainTree::new("./BlockChainTree").unwrap();
 
     let main_chain = tree.get_main_chain();
+    let miner = Miner::with_available_threads();
 
     let wallet: [u8; 33] = [
         2, 178, 140, 81, 31, 206, 208, 171, 143, 240, 128, 134, 115, 82, 188, 63, 146, 189, 14, 59,
@@ -27,56 +28,34 @@ fn main() {
             tree.get_amount(&static_values::ROOT_PUBLIC_ADDRESS)
                 .unwrap()
         );
-        let mut nonce = U256::zero();
-        let last_block = main_chain.get_last_block().unwrap().unwrap();
-        let prev_hash = last_block.hash().unwrap();
-        let difficulty = last_block.get_info().difficulty;
+        let template = rt.block_on(tree.get_block_template()).unwrap();
         println!(
             "Current difficulty: {}",
-            tools::count_leading_zeros(&difficulty)
+            tools::count_leading_zeros(&template.difficulty)
         );
-        while nonce < U256::MAX {
-            let mut pow = [0u8; 32];
-            nonce.to_big_endian(&mut pow);
-            if tools::check_pow(&prev_hash, &difficulty, &pow) {
-                let timestamp = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-
-                println!("Found nonce! {}", nonce);
-
-                let transactions: &[[u8; 32]] =
-                    if ((last_block.get_info().height + 1) % BLOCKS_PER_EPOCH).is_zero() {
-                        println!("Cycle ended!");
-                        &[]
-                    } else {
-                        &[[25u8; 32]]
-                    };
-
-                let block = rt
-                    .block_on(tree.emmit_new_main_block(&pow, &wallet, transactions, timestamp))
-                    .unwrap();
-
-                // Node should handle this
-                tree.send_amount(
-                    &static_values::ROOT_PUBLIC_ADDRESS,
-                    &wallet,
-                    *static_values::MAIN_CHAIN_PAYMENT,
-                )
-                .unwrap();
-
-                let fee = tools::recalculate_fee(&last_block.get_info().difficulty);
-                for _ in transactions {
-                    tree.add_amount(&wallet, fee).unwrap();
-                }
-
-                println!("Added new block! {:?}\n", block.hash().unwrap());
-
-                rt.block_on(tree.flush()).unwrap();
-                break;
-            }
-            nonce += U256::one();
+        if (template.height % BLOCKS_PER_EPOCH).is_zero() {
+            println!("Cycle ended!");
+        } else if template.transactions.is_empty() {
+            println!("Waiting for transactions");
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            continue;
         }
+        let job = MiningJob::new(template);
+        let result = miner.mine(&job, &AtomicBool::new(false));
+        let pow = result.pow.unwrap();
+        println!("Found pow! {:.0} H/s", result.hashrate());
+
+        let block = rt
+            .block_on(tree.emmit_new_main_block(
+                &pow,
+                &wallet,
+                &job.transactions,
+                tools::current_timestamp(),
+            ))
+            .unwrap();
+
+        println!("Added new block! {:?}\n", block.hash().unwrap());
+
+        rt.block_on(tree.flush()).unwrap();
     }
 }
@@ -1,6 +1,6 @@
 use blockchaintree::block::Block as _;
-use blockchaintree::tools;
 use blockchaintree::{blockchaintree::BlockChainTree, static_values};
+use blockchaintree::{merkletree::MerkleVersion, tools};
 use primitive_types::U256;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -23,7 +23,7 @@ fn main() {
             tree.get_gas(&wallet).unwrap()
         );
         let mut nonce = U256::zero();
-        let (prev_hash, difficulty, _prev_timestamp, _height) =
+        let (prev_hash, difficulty, _prev_timestamp, height) =
             if let Some(block) = chain.get_last_block().unwrap() {
                 (
                     block.hash().unwrap(),
@@ -51,7 +51,13 @@ fn main() {
         while nonce < U256::MAX {
             let mut pow = [0u8; 32];
             nonce.to_big_endian(&mut pow);
-            if tools::check_pow(&prev_hash, &difficulty, &pow) {
+            if tools::check_pow(
+                &prev_hash,
+                &difficulty,
+                &[],
+                &pow,
+                MerkleVersion::for_height(&height),
+            ) {
                 let timestamp = SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .unwrap()
@@ -63,10 +69,6 @@ fn main() {
                     .block_on(tree.emmit_new_derivative_block(&pow, &wallet, timestamp))
                     .unwrap();
 
-                // Node should handle this
-                tree.add_gas(&wallet, *static_values::MAIN_CHAIN_PAYMENT)
-                    .unwrap();
-
                 println!("Added new block! {:?}\n", block.hash().unwrap());
 
                 rt.block_on(chain.flush()).unwrap();
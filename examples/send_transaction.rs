@@ -1,9 +1,9 @@
+use blockchaintree::merkletree::MerkleVersion;
 use blockchaintree::static_values::BLOCKS_PER_EPOCH;
 use blockchaintree::transaction::Transactionable;
 use blockchaintree::{blockchaintree::BlockChainTree, static_values};
 use blockchaintree::{tools, transaction};
 use primitive_types::U256;
-use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 fn main() {
@@ -32,16 +32,18 @@ fn main() {
         .unwrap()
         .as_secs();
     let transaction = transaction::Transaction::new(
-        wallet.clone(),
+        wallet,
         receiver,
         timestamp,
+        tree.get_nonce(&wallet).unwrap(),
         U256::from_str_radix("228", 10).unwrap(),
         wallet_private,
         None,
     )
     .unwrap();
     let transaction_hash = transaction.hash();
-    tree.send_transaction(&transaction).unwrap();
+    rt.block_on(tree.send_transaction(Box::new(transaction)))
+        .unwrap();
 
     println!("Transaction created: {:?}", &transaction_hash);
     println!("Sender amount: {}", tree.get_amount(&wallet).unwrap());
@@ -53,39 +55,26 @@ fn main() {
     let last_block = main_chain.get_last_block().unwrap().unwrap();
     let prev_hash = last_block.hash().unwrap();
     let difficulty = last_block.get_info().difficulty;
+    let version = MerkleVersion::for_height(&(last_block.get_info().height + 1));
+    let transactions: Vec<[u8; 32]> =
+        if ((last_block.get_info().height + 1) % BLOCKS_PER_EPOCH).is_zero() {
+            Vec::with_capacity(0)
+        } else {
+            vec![transaction_hash]
+        };
     while nonce < U256::MAX {
         let mut pow = [0u8; 32];
         nonce.to_big_endian(&mut pow);
-        if tools::check_pow(&prev_hash, &difficulty, &pow) {
+        if tools::check_pow(&prev_hash, &difficulty, &transactions, &pow, version) {
             let timestamp = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs();
 
-            let transactions: Vec<[u8; 32]> =
-                if ((last_block.get_info().height + 1) % BLOCKS_PER_EPOCH).is_zero() {
-                    Vec::with_capacity(0)
-                } else {
-                    vec![transaction_hash]
-                };
-
             let block = rt
                 .block_on(tree.emmit_new_main_block(&pow, &wallet, &transactions, timestamp))
                 .unwrap();
 
-            // Node should handle this
-            tree.send_amount(
-                &static_values::ROOT_PUBLIC_ADDRESS,
-                &wallet,
-                *static_values::MAIN_CHAIN_PAYMENT,
-            )
-            .unwrap();
-
-            let fee = tools::recalculate_fee(&last_block.get_info().difficulty);
-            for _ in transactions {
-                tree.add_amount(&wallet, fee).unwrap();
-            }
-
             println!("Added new block! {:?}\n", block.hash().unwrap());
 
             rt.block_on(tree.flush()).unwrap();
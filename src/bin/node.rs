@@ -0,0 +1,159 @@
+use std::{
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use blockchaintree::{
+    blockchaintree::{BlockChainTree, SharedBlockChainTree},
+    config::NodeConfig,
+    miner::{self, Miner},
+    network::Node,
+    rpc::{Rpc, RpcServer},
+    stratum::StratumServer,
+    tools,
+};
+use tokio::sync::RwLock;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "blockchaintree.toml".into());
+    let config = match NodeConfig::load(&path) {
+        Ok(config) => config,
+        Err(report) => {
+            eprintln!("{:?}", report);
+            return ExitCode::FAILURE;
+        }
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&config.log_level))
+        .init();
+
+    let tree = match BlockChainTree::new(&config.data_dir) {
+        Ok(tree) => tree,
+        Err(report) => {
+            log::error!("Failed to open the tree: {:?}", report);
+            return ExitCode::FAILURE;
+        }
+    };
+    let tree: SharedBlockChainTree = Arc::new(RwLock::new(tree));
+
+    let node = match Node::bind(tree.clone(), config.network.listen).await {
+        Ok(node) => node,
+        Err(report) => {
+            log::error!("Failed to start the network: {:?}", report);
+            return ExitCode::FAILURE;
+        }
+    };
+    log::info!("Listening for peers on {}", node.local_address());
+    for peer in config.network.peers.iter() {
+        if let Err(report) = node.connect(*peer).await {
+            log::warn!("Failed to connect to {}: {:?}", peer, report);
+        }
+    }
+
+    let rpc_server = match &config.rpc {
+        Some(rpc) => {
+            match RpcServer::bind(Rpc::new(tree.clone(), Some(node.clone())), rpc.listen).await {
+                Ok(server) => {
+                    log::info!("Rpc server is listening on {}", server.local_address());
+                    Some(server)
+                }
+                Err(report) => {
+                    log::error!("Failed to start the rpc server: {:?}", report);
+                    node.shutdown();
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let stratum_server = match &config.stratum {
+        Some(stratum) => {
+            match StratumServer::bind(
+                tree.clone(),
+                Some(node.clone()),
+                stratum.founder,
+                tools::difficulty_from_zeros(stratum.share_difficulty),
+                stratum.listen,
+            )
+            .await
+            {
+                Ok(server) => {
+                    log::info!("Stratum server is listening on {}", server.local_address());
+                    Some(server)
+                }
+                Err(report) => {
+                    log::error!("Failed to start the stratum server: {:?}", report);
+                    if let Some(server) = rpc_server {
+                        server.shutdown();
+                    }
+                    node.shutdown();
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let miner = config.mining.as_ref().map(|mining| {
+        let miner = match mining.threads {
+            Some(threads) => Miner::new(threads),
+            None => Miner::with_available_threads(),
+        };
+        log::info!(
+            "Mining to {} with {} threads",
+            hex::encode(mining.founder),
+            miner.threads()
+        );
+        tokio::spawn(miner::mine_blocks(
+            Arc::new(miner),
+            tree.clone(),
+            Some(node.clone()),
+            mining.founder,
+            stop.clone(),
+        ))
+    });
+
+    wait_for_signal().await;
+    log::info!("Shutting down");
+
+    stop.store(true, Ordering::Relaxed);
+    if let Some(server) = rpc_server {
+        server.shutdown();
+    }
+    if let Some(server) = stratum_server {
+        server.shutdown();
+    }
+    node.shutdown();
+    if let Some(miner) = miner {
+        let _ = miner.await;
+    }
+
+    if let Err(report) = tree.read().await.flush().await {
+        log::error!("Failed to flush the tree: {:?}", report);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
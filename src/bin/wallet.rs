@@ -0,0 +1,236 @@
+use std::{net::SocketAddr, process::ExitCode};
+
+use blockchaintree::{
+    rpc::RpcClient,
+    tools,
+    transaction::{Transaction, Transactionable},
+    types::Address,
+    wallet::Keystore,
+};
+use error_stack::{Report, ResultExt};
+use primitive_types::U256;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+static USAGE: &str = "Usage: bct-wallet [--keystore <path>] [--rpc <address>] <command>
+
+Commands:
+    new <name>                                  generate new key
+    import <name> <private key>                 import hex encoded private key
+    list                                        list stored keys
+    balance <name | address>                    show amount and gas of the address
+    sign <from> <to> <amount> [--nonce <n>]     print hex dump of the signed transaction
+    send <from> <to> <amount> [--nonce <n>]     sign the transaction and send it to the node
+    submit <dump>                               send hex dump of the signed transaction
+
+Password is read from BCT_WALLET_PASSWORD or asked interactively";
+
+static DEFAULT_KEYSTORE: &str = "./wallet.json";
+static DEFAULT_RPC: &str = "127.0.0.1:8332";
+
+#[derive(Debug, Error)]
+#[error("{0}")]
+struct CliError(String);
+
+type CliResult<T> = Result<T, Report<CliError>>;
+
+fn fail<T>(message: impl Into<String>) -> CliResult<T> {
+    Err(Report::new(CliError(message.into())))
+}
+
+struct Options {
+    keystore: String,
+    rpc: SocketAddr,
+    nonce: Option<u64>,
+    arguments: Vec<String>,
+}
+
+fn parse_options(args: impl Iterator<Item = String>) -> CliResult<Options> {
+    let mut keystore = DEFAULT_KEYSTORE.to_string();
+    let mut rpc = DEFAULT_RPC.to_string();
+    let mut nonce = None;
+    let mut arguments = Vec::new();
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut value = || match args.next() {
+            Some(value) => Ok(value),
+            None => fail(format!("{} requires a value", arg)),
+        };
+        match arg.as_str() {
+            "--keystore" => keystore = value()?,
+            "--rpc" => rpc = value()?,
+            "--nonce" => {
+                nonce = Some(
+                    value()?
+                        .parse::<u64>()
+                        .change_context(CliError("nonce should be a number".into()))?,
+                )
+            }
+            _ => arguments.push(arg),
+        }
+    }
+
+    Ok(Options {
+        keystore,
+        rpc: rpc
+            .parse::<SocketAddr>()
+            .change_context(CliError(format!("wrong rpc address {}", rpc)))?,
+        nonce,
+        arguments,
+    })
+}
+
+fn password(confirm: bool) -> CliResult<String> {
+    if let Ok(password) = std::env::var("BCT_WALLET_PASSWORD") {
+        return Ok(password);
+    }
+    let password = rpassword::prompt_password("Password: ")
+        .change_context(CliError("failed to read the password".into()))?;
+    if confirm
+        && rpassword::prompt_password("Repeat password: ")
+            .change_context(CliError("failed to read the password".into()))?
+            != password
+    {
+        return fail("passwords don't match");
+    }
+    Ok(password)
+}
+
+fn parse_hex<const N: usize>(data: &str) -> CliResult<[u8; N]> {
+    hex::decode(data.strip_prefix("0x").unwrap_or(data))
+        .ok()
+        .and_then(|data| data.try_into().ok())
+        .ok_or(Report::new(CliError(format!(
+            "{} is not {} hex encoded bytes",
+            data, N
+        ))))
+}
+
+/// Address of the stored key or the hex encoded one
+fn resolve_address(keystore: &Keystore, name: &str) -> CliResult<Address> {
+    match keystore.get_address(name) {
+        Ok(address) => Ok(address),
+        Err(_) => parse_hex(name),
+    }
+}
+
+async fn call(rpc: &RpcClient, method: &str, params: Value) -> CliResult<Value> {
+    match rpc
+        .call(method, params)
+        .await
+        .change_context(CliError("failed to reach the node".into()))?
+    {
+        Ok(result) => Ok(result),
+        Err(error) => fail(format!("node returned an error: {}", error.message)),
+    }
+}
+
+async fn run(options: Options) -> CliResult<()> {
+    let mut keystore = Keystore::open(&options.keystore)
+        .change_context(CliError("failed to open the keystore".into()))?;
+    let rpc = RpcClient::new(options.rpc);
+    let arguments: Vec<&str> = options.arguments.iter().map(String::as_str).collect();
+
+    match arguments.as_slice() {
+        ["new", name] => {
+            let address = keystore
+                .generate(name, &password(true)?)
+                .change_context(CliError("failed to generate the key".into()))?;
+            keystore
+                .save()
+                .change_context(CliError("failed to save the keystore".into()))?;
+            println!("{}", hex::encode(address));
+        }
+        ["import", name, private_key] => {
+            let private_key: [u8; 32] = parse_hex(private_key)?;
+            let address = keystore
+                .import(name, &private_key, &password(true)?)
+                .change_context(CliError("failed to import the key".into()))?;
+            keystore
+                .save()
+                .change_context(CliError("failed to save the keystore".into()))?;
+            println!("{}", hex::encode(address));
+        }
+        ["list"] => {
+            for (name, address) in keystore
+                .addresses()
+                .change_context(CliError("failed to read the keystore".into()))?
+            {
+                println!("{}\t{}", name, hex::encode(address));
+            }
+        }
+        ["balance", address] => {
+            let address = hex::encode(resolve_address(&keystore, address)?);
+            let amount = call(&rpc, "get_amount", json!([address])).await?;
+            let gas = call(&rpc, "get_gas", json!([address])).await?;
+            println!(
+                "amount: {}\ngas: {}",
+                amount.as_str().unwrap_or_default(),
+                gas.as_str().unwrap_or_default()
+            );
+        }
+        [command @ ("sign" | "send"), from, to, amount] => {
+            let sender = keystore
+                .get_address(from)
+                .change_context(CliError(format!("no key named {}", from)))?;
+            let receiver = resolve_address(&keystore, to)?;
+            let amount = U256::from_dec_str(amount)
+                .change_context(CliError(format!("wrong amount {}", amount)))?;
+            let nonce = match options.nonce {
+                Some(nonce) => nonce,
+                None => call(&rpc, "get_nonce", json!([hex::encode(sender)]))
+                    .await?
+                    .as_u64()
+                    .ok_or(Report::new(CliError("wrong nonce".into())))?,
+            };
+            let private_key = keystore
+                .get_private_key(from, &password(false)?)
+                .change_context(CliError("failed to unlock the key".into()))?;
+
+            let transaction = Transaction::new(
+                sender,
+                receiver,
+                tools::current_timestamp(),
+                nonce,
+                amount,
+                private_key,
+                None,
+            )
+            .change_context(CliError("failed to sign the transaction".into()))?;
+            let dump = hex::encode(
+                transaction
+                    .dump()
+                    .change_context(CliError("failed to dump the transaction".into()))?,
+            );
+
+            if *command == "sign" {
+                println!("{}", dump);
+            } else {
+                let hash = call(&rpc, "send_transaction", json!([dump])).await?;
+                println!("{}", hash.as_str().unwrap_or_default());
+            }
+        }
+        ["submit", dump] => {
+            let hash = call(&rpc, "send_transaction", json!([dump])).await?;
+            println!("{}", hash.as_str().unwrap_or_default());
+        }
+        _ => return fail(USAGE),
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let result = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => run(options).await,
+        Err(report) => Err(report),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(report) => {
+            eprintln!("{:?}", report);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,9 +1,10 @@
 // use crate::blockchaintree::{
 //     BEGINNING_DIFFICULTY, GENESIS_BLOCK, INCEPTION_TIMESTAMP, ROOT_PUBLIC_ADDRESS,
 // };
-use crate::dump_headers::Headers;
+use crate::dump_headers::{Headers, SUMMARIZE_BLOCK_V2, TRANSACTION_BLOCK_V2};
 use crate::errors::*;
-use crate::merkletree;
+use crate::merkletree::{self, MerkleVersion};
+use crate::static_values::BLOCKS_PER_EPOCH;
 use crate::tools;
 use crate::tools::check_pow;
 use crate::tools::recalculate_difficulty;
@@ -24,7 +25,7 @@ macro_rules! bytes_to_u64 {
     };
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BasicInfo {
     pub timestamp: u64,
     pub pow: [u8; 32],
@@ -128,12 +129,80 @@ impl BasicInfo {
     }
 }
 
+/// Layout of the main chain block dump
+///
+/// `V1` blocks are dumped without the state root and the derivative chains anchors,
+/// they stay readable so the chains stored before `V2` can still be loaded and served
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockVersion {
+    V1,
+    V2,
+}
+
+impl BlockVersion {
+    /// Version of the main chain block dump starting with the `header` byte
+    pub fn from_header(header: u8) -> Self {
+        match header {
+            TRANSACTION_BLOCK_V2 | SUMMARIZE_BLOCK_V2 => BlockVersion::V2,
+            _ => BlockVersion::V1,
+        }
+    }
+}
+
+/// Commitment of the main chain block to the latest block of the derivative chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivativeAnchor {
+    pub owner: Address,
+    pub hash: Hash,
+    pub height: U256,
+}
+
+impl DerivativeAnchor {
+    pub fn get_dump_size(&self) -> usize {
+        33 + 32 + tools::u256_size(&self.height)
+    }
+
+    pub fn dump(&self, buffer: &mut Vec<u8>) -> Result<(), BlockError> {
+        buffer.extend(self.owner);
+        buffer.extend(self.hash);
+        tools::dump_u256(&self.height, buffer)
+            .change_context(BlockError::TransactionBlock(TxBlockErrorKind::Dump))
+            .attach_printable("Error dumping anchor height")
+    }
+
+    /// Parses anchor, returns it with the amount of parsed bytes
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), BlockError> {
+        if data.len() <= 33 + 32 {
+            return Err(
+                Report::new(BlockError::TransactionBlock(TxBlockErrorKind::Parse))
+                    .attach_printable("anchor data <= 65"),
+            );
+        }
+        let owner: Address = unsafe { data[0..33].try_into().unwrap_unchecked() };
+        let hash: Hash = unsafe { data[33..65].try_into().unwrap_unchecked() };
+        let (height, height_size) = tools::load_u256(&data[65..])
+            .change_context(BlockError::TransactionBlock(TxBlockErrorKind::Parse))
+            .attach_printable("Error parsing anchor height")?;
+        Ok((
+            DerivativeAnchor {
+                owner,
+                hash,
+                height,
+            },
+            65 + height_size + 1,
+        ))
+    }
+}
+
 #[derive(Debug)]
 pub struct TransactionBlock {
     pub fee: U256,
     pub merkle_tree_root: Hash,
+    pub state_root: Hash,
     pub default_info: BasicInfo,
+    pub anchors: Vec<DerivativeAnchor>,
     pub transactions: Vec<Hash>,
+    pub version: BlockVersion,
 }
 
 impl TransactionBlock {
@@ -147,28 +216,73 @@ impl TransactionBlock {
             fee,
             default_info,
             merkle_tree_root,
+            state_root: [0; 32],
+            anchors: Vec::new(),
             transactions,
+            version: BlockVersion::V2,
         }
     }
 
+    /// Sets root of the state after the block is applied
+    pub fn with_state_root(mut self, state_root: Hash) -> TransactionBlock {
+        self.state_root = state_root;
+        self
+    }
+
+    /// Sets derivative chains anchors of the block
+    pub fn with_anchors(mut self, anchors: Vec<DerivativeAnchor>) -> TransactionBlock {
+        self.anchors = anchors;
+        self
+    }
+
     pub fn get_dump_size(&self) -> usize {
-        1 + tools::u256_size(&self.fee)
+        let size = 1
+            + tools::u256_size(&self.fee)
             + 32
             + self.default_info.get_dump_size()
-            + self.transactions.len() * 32
+            + self.transactions.len() * 32;
+        match self.version {
+            BlockVersion::V1 => size,
+            BlockVersion::V2 => {
+                size + 32
+                    + 2
+                    + self
+                        .anchors
+                        .iter()
+                        .map(|anchor| anchor.get_dump_size())
+                        .sum::<usize>()
+            }
+        }
     }
 
     pub fn dump(&self) -> Result<Vec<u8>, BlockError> {
+        if self.version == BlockVersion::V1
+            && (self.state_root != [0; 32] || !self.anchors.is_empty())
+        {
+            return Err(
+                Report::new(BlockError::TransactionBlock(TxBlockErrorKind::Dump))
+                    .attach_printable("V1 block can't carry state root and anchors"),
+            );
+        }
+
         let size = self.get_dump_size();
 
         let mut to_return = Vec::<u8>::with_capacity(size);
 
         // header
-        to_return.push(Headers::TransactionBlock as u8);
+        to_return.push(match self.version {
+            BlockVersion::V1 => Headers::TransactionBlock as u8,
+            BlockVersion::V2 => TRANSACTION_BLOCK_V2,
+        });
 
         // merkle root
         to_return.extend(self.merkle_tree_root.iter());
 
+        // state root
+        if self.version == BlockVersion::V2 {
+            to_return.extend(self.state_root.iter());
+        }
+
         // default info
         self.default_info
             .dump(&mut to_return)
@@ -180,6 +294,17 @@ impl TransactionBlock {
             .change_context(BlockError::TransactionBlock(TxBlockErrorKind::Dump))
             .attach_printable("Error dumping fee")?;
 
+        // derivative chains anchors
+        if self.version == BlockVersion::V2 {
+            let anchors_amount = u16::try_from(self.anchors.len())
+                .change_context(BlockError::TransactionBlock(TxBlockErrorKind::Dump))
+                .attach_printable("Too many anchors")?;
+            to_return.extend(anchors_amount.to_be_bytes());
+            for anchor in self.anchors.iter() {
+                anchor.dump(&mut to_return)?;
+            }
+        }
+
         // transactions
         for transaction in self.transactions.iter() {
             to_return.extend(transaction.iter());
@@ -188,12 +313,30 @@ impl TransactionBlock {
         Ok(to_return)
     }
 
-    pub fn parse(data: &[u8]) -> Result<Self, BlockError> {
+    /// Parses the block dumped in the `version` layout, data goes after the header byte
+    pub fn parse(data: &[u8], version: BlockVersion) -> Result<Self, BlockError> {
         let mut index: usize = 0;
 
+        let roots_size = match version {
+            BlockVersion::V1 => 32,
+            BlockVersion::V2 => 64,
+        };
+        if data.len() <= roots_size {
+            return Err(
+                Report::new(BlockError::TransactionBlock(TxBlockErrorKind::Parse))
+                    .attach_printable(format!("data length <= {}", roots_size)),
+            );
+        }
+
         let merkle_tree_root: Hash = unsafe { data[0..32].try_into().unwrap_unchecked() };
         index += 32;
 
+        let mut state_root: Hash = [0; 32];
+        if version == BlockVersion::V2 {
+            state_root = unsafe { data[32..64].try_into().unwrap_unchecked() };
+            index += 32;
+        }
+
         let default_info = BasicInfo::parse(&data[index..])
             .change_context(BlockError::TransactionBlock(TxBlockErrorKind::Parse))
             .attach_printable("Error parsing default data")?;
@@ -205,7 +348,25 @@ impl TransactionBlock {
 
         index += fee_size + 1;
 
-        if (data.len() - index) % 32 != 0 {
+        let mut anchors = Vec::new();
+        if version == BlockVersion::V2 {
+            if data.len() < index + 2 {
+                return Err(
+                    Report::new(BlockError::TransactionBlock(TxBlockErrorKind::Parse))
+                        .attach_printable("no anchors amount"),
+                );
+            }
+            let anchors_amount = (&data[index..index + 2]).read_u16::<BigEndian>().unwrap();
+            index += 2;
+            anchors.reserve(anchors_amount as usize);
+            for _ in 0..anchors_amount {
+                let (anchor, anchor_size) = DerivativeAnchor::parse(&data[index..])?;
+                anchors.push(anchor);
+                index += anchor_size;
+            }
+        }
+
+        if !(data.len() - index).is_multiple_of(32) {
             return Err(
                 Report::new(BlockError::TransactionBlock(TxBlockErrorKind::Parse))
                     .attach_printable("transactions % 32 != 0"),
@@ -222,8 +383,11 @@ impl TransactionBlock {
         Ok(Self {
             fee,
             merkle_tree_root,
+            state_root,
             default_info,
+            anchors,
             transactions,
+            version,
         })
     }
 
@@ -246,12 +410,22 @@ pub trait Block {
     fn dump(&self) -> Result<Vec<u8>, BlockError>;
     fn get_info(&self) -> &BasicInfo;
     fn get_merkle_root(&self) -> Hash;
+    /// Root of the accounts and tokens state after the block is applied
+    fn get_state_root(&self) -> Hash;
     fn verify_block(&self, prev_hash: &Hash) -> bool;
     fn get_founder(&self) -> &Address;
     fn get_fee(&self) -> U256;
     fn get_type(&self) -> Headers;
     fn validate(&self, prev_block: Option<BlockArc>) -> Result<bool, BlockError>;
     fn transactions(&self) -> Option<&[Hash]>;
+    /// Derivative chains anchored by the block
+    fn anchors(&self) -> &[DerivativeAnchor] {
+        &[]
+    }
+    /// Layout the block is dumped in
+    fn get_version(&self) -> BlockVersion {
+        BlockVersion::V2
+    }
 }
 
 impl Block for DerivativeBlock {
@@ -276,9 +450,13 @@ impl Block for DerivativeBlock {
     fn hash(&self) -> Result<Hash, BlockError> {
         Ok(tools::hash(&self.dump()?))
     }
+    /// Derivative blocks carry no transactions, their pow is found for the previous hash only
     fn get_merkle_root(&self) -> Hash {
-        unimplemented!()
-        //self.payment_transaction
+        [0; 32]
+    }
+    /// Derivative blocks do not change the accounts state
+    fn get_state_root(&self) -> Hash {
+        [0; 32]
     }
     fn verify_block(&self, prev_hash: &Hash) -> bool {
         self.default_info.previous_hash.eq(prev_hash)
@@ -305,6 +483,10 @@ impl Block for DerivativeBlock {
             return Ok(false);
         }
 
+        if self.default_info.timestamp < prev_block.get_info().timestamp {
+            return Ok(false);
+        }
+
         let mut prev_difficulty = prev_block.get_info().difficulty;
         recalculate_difficulty(
             prev_block.get_info().timestamp,
@@ -317,10 +499,11 @@ impl Block for DerivativeBlock {
         }
 
         if !check_pow(
-            &self.get_merkle_root(),
+            &self.default_info.previous_hash,
             &prev_block.get_info().difficulty,
-            self.transactions().unwrap_or(&[]),
+            &[],
             &self.default_info.pow,
+            MerkleVersion::for_height(&self.default_info.height),
         ) {
             return Ok(false);
         }
@@ -328,7 +511,7 @@ impl Block for DerivativeBlock {
         Ok(true)
     }
 
-    fn transactions<'a>(&'a self) -> Option<&'a [Hash]> {
+    fn transactions(&self) -> Option<&[Hash]> {
         None
     }
 }
@@ -365,6 +548,9 @@ impl Block for TransactionBlock {
     fn get_merkle_root(&self) -> Hash {
         self.merkle_tree_root
     }
+    fn get_state_root(&self) -> Hash {
+        self.state_root
+    }
     fn verify_block(&self, prev_hash: &Hash) -> bool {
         self.default_info.previous_hash.eq(prev_hash)
     }
@@ -395,7 +581,10 @@ impl Block for TransactionBlock {
             return Ok(false);
         }
 
-        let merkle_tree = merkletree::MerkleTree::build_tree(&self.transactions);
+        let merkle_tree = merkletree::MerkleTree::build_tree(
+            &self.transactions,
+            MerkleVersion::for_height(&self.default_info.height),
+        );
         if !self.merkle_tree_root.eq(merkle_tree.get_root()) {
             return Ok(false);
         }
@@ -412,10 +601,11 @@ impl Block for TransactionBlock {
         }
 
         if !check_pow(
-            &self.merkle_tree_root,
+            &self.default_info.previous_hash,
             &prev_block.get_info().difficulty,
             self.transactions().unwrap_or(&[]),
             &self.default_info.pow,
+            MerkleVersion::for_height(&self.default_info.height),
         ) {
             return Ok(false);
         }
@@ -423,23 +613,38 @@ impl Block for TransactionBlock {
         Ok(true)
     }
 
-    fn transactions<'a>(&'a self) -> Option<&'a [Hash]> {
+    fn transactions(&self) -> Option<&[Hash]> {
         Some(&self.transactions)
     }
+
+    fn anchors(&self) -> &[DerivativeAnchor] {
+        &self.anchors
+    }
+
+    fn get_version(&self) -> BlockVersion {
+        self.version
+    }
 }
 
 #[derive(Debug)]
 pub struct SummarizeBlock {
     pub default_info: BasicInfo,
     pub merkle_tree_root: Hash,
+    pub state_root: Hash,
+    pub version: BlockVersion,
 }
 
 impl SummarizeBlock {
-    pub fn parse(data: &[u8]) -> Result<Self, BlockError> {
-        if data.len() <= 32 {
+    /// Parses the block dumped in the `version` layout, data goes after the header byte
+    pub fn parse(data: &[u8], version: BlockVersion) -> Result<Self, BlockError> {
+        let roots_size = match version {
+            BlockVersion::V1 => 32,
+            BlockVersion::V2 => 64,
+        };
+        if data.len() <= roots_size {
             return Err(
                 Report::new(BlockError::SummarizeBlock(SummarizeBlockErrorKind::Parse))
-                    .attach_printable("data length <= 32"),
+                    .attach_printable(format!("data length <= {}", roots_size)),
             );
         }
 
@@ -448,6 +653,12 @@ impl SummarizeBlock {
         let merkle_tree_root: Hash = unsafe { data[0..32].try_into().unwrap_unchecked() };
         index += 32;
 
+        let mut state_root: Hash = [0; 32];
+        if version == BlockVersion::V2 {
+            state_root = unsafe { data[32..64].try_into().unwrap_unchecked() };
+            index += 32;
+        }
+
         let default_info = BasicInfo::parse(&data[index..])
             .change_context(BlockError::SummarizeBlock(SummarizeBlockErrorKind::Parse))
             .attach_printable("Error parsing default data")?;
@@ -455,6 +666,8 @@ impl SummarizeBlock {
         Ok(Self {
             default_info,
             merkle_tree_root,
+            state_root,
+            version,
         })
     }
 }
@@ -474,18 +687,36 @@ impl Block for SummarizeBlock {
     }
 
     fn get_dump_size(&self) -> usize {
-        1 + 32 + self.default_info.get_dump_size()
+        match self.version {
+            BlockVersion::V1 => 1 + 32 + self.default_info.get_dump_size(),
+            BlockVersion::V2 => 1 + 32 + 32 + self.default_info.get_dump_size(),
+        }
     }
 
     fn dump(&self) -> Result<Vec<u8>, BlockError> {
+        if self.version == BlockVersion::V1 && self.state_root != [0; 32] {
+            return Err(
+                Report::new(BlockError::SummarizeBlock(SummarizeBlockErrorKind::Dump))
+                    .attach_printable("V1 block can't carry state root"),
+            );
+        }
+
         let mut to_return: Vec<u8> = Vec::with_capacity(self.get_dump_size());
 
         // header
-        to_return.push(Headers::SummarizeBlock as u8);
+        to_return.push(match self.version {
+            BlockVersion::V1 => Headers::SummarizeBlock as u8,
+            BlockVersion::V2 => SUMMARIZE_BLOCK_V2,
+        });
 
         // merkle tree
         to_return.extend(self.merkle_tree_root.iter());
 
+        // state root
+        if self.version == BlockVersion::V2 {
+            to_return.extend(self.state_root.iter());
+        }
+
         // default info
         self.default_info
             .dump(&mut to_return)
@@ -503,6 +734,10 @@ impl Block for SummarizeBlock {
         self.merkle_tree_root
     }
 
+    fn get_state_root(&self) -> Hash {
+        self.state_root
+    }
+
     fn verify_block(&self, prev_hash: &Hash) -> bool {
         self.default_info.previous_hash.eq(prev_hash)
     }
@@ -548,10 +783,11 @@ impl Block for SummarizeBlock {
         }
 
         if !check_pow(
-            &self.merkle_tree_root,
+            &self.default_info.previous_hash,
             &prev_block.get_info().difficulty,
             self.transactions().unwrap_or(&[]),
             &self.default_info.pow,
+            MerkleVersion::for_height(&self.default_info.height),
         ) {
             return Ok(false);
         }
@@ -562,6 +798,149 @@ impl Block for SummarizeBlock {
     fn transactions(&self) -> Option<&[Hash]> {
         None
     }
+
+    fn get_version(&self) -> BlockVersion {
+        self.version
+    }
+}
+
+/// Header of the main chain block used by the header-first sync
+///
+/// `merkle_root` is the root the pow of the block is computed with,
+/// so the header chain can be validated before the transactions are downloaded.
+/// `state_root` lets the accounts state be proven against the header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub hash: Hash,
+    pub kind: Headers,
+    pub merkle_root: Hash,
+    pub state_root: Hash,
+    pub info: BasicInfo,
+}
+
+impl BlockHeader {
+    pub fn from_block(block: &BlockArc) -> Result<Self, BlockError> {
+        let merkle_root = match block.get_type() {
+            Headers::TransactionBlock => block.get_merkle_root(),
+            _ => *merkletree::MerkleTree::build_tree(
+                &[],
+                MerkleVersion::for_height(&block.get_info().height),
+            )
+            .get_root(),
+        };
+        Ok(Self {
+            hash: block.hash()?,
+            kind: block.get_type(),
+            merkle_root,
+            state_root: block.get_state_root(),
+            info: block.get_info().clone(),
+        })
+    }
+
+    pub fn dump(&self, buffer: &mut Vec<u8>) -> Result<(), BlockError> {
+        buffer.extend(self.hash);
+        buffer.push(self.kind as u8);
+        buffer.extend(self.merkle_root);
+        buffer.extend(self.state_root);
+        self.info.dump(buffer)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, BlockError> {
+        if data.len() <= 97 {
+            return Err(
+                Report::new(BlockError::BasicInfo(BasicInfoErrorKind::Parse))
+                    .attach_printable("header data <= 97"),
+            );
+        }
+
+        let hash: Hash = data[0..32].try_into().unwrap();
+        let kind = Headers::from_u8(data[32])
+            .change_context(BlockError::HeaderError(DumpHeadersErrorKind::UknownHeader))?;
+        let merkle_root: Hash = data[33..65].try_into().unwrap();
+        let state_root: Hash = data[65..97].try_into().unwrap();
+        let info = BasicInfo::parse(&data[97..])?;
+
+        Ok(Self {
+            hash,
+            kind,
+            merkle_root,
+            state_root,
+            info,
+        })
+    }
+
+    /// Checks that the header follows the parent one
+    ///
+    /// Same rules as `MainChain::validate_block_with_parent`, except the ones
+    /// that require transactions of the block
+    pub fn validate(&self, parent: &BlockHeader) -> Result<(), BlockChainTreeError> {
+        let info = &self.info;
+        let prev_info = &parent.info;
+
+        if info.height != prev_info.height + 1 {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongHeight,
+            ))
+            .attach_printable(format!(
+                "header height: {}, previous header height: {}",
+                info.height, prev_info.height
+            ));
+        }
+
+        if info.previous_hash != parent.hash {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongPreviousHash,
+            )
+            .into());
+        }
+
+        if info.timestamp < prev_info.timestamp {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongTimestamp,
+            ))
+            .attach_printable(format!(
+                "header timestamp: {}, previous header timestamp: {}",
+                info.timestamp, prev_info.timestamp
+            ));
+        }
+
+        let mut difficulty = prev_info.difficulty;
+        recalculate_difficulty(prev_info.timestamp, info.timestamp, &mut difficulty);
+        if info.difficulty != difficulty {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongDifficulty,
+            )
+            .into());
+        }
+
+        let summarize_expected = (info.height % BLOCKS_PER_EPOCH).is_zero();
+        let empty_root =
+            *merkletree::MerkleTree::build_tree(&[], MerkleVersion::for_height(&info.height))
+                .get_root();
+        match (self.kind, summarize_expected) {
+            (Headers::SummarizeBlock, true) if self.merkle_root == empty_root => {}
+            (Headers::TransactionBlock, false) => {}
+            _ => {
+                return Err(BlockChainTreeError::BlockValidation(
+                    BlockValidationErrorKind::WrongBlockType,
+                ))
+                .attach_printable(format!("summarize block expected: {}", summarize_expected));
+            }
+        }
+
+        if !tools::check_pow_with_root(
+            &info.previous_hash,
+            &prev_info.difficulty,
+            &self.merkle_root,
+            &info.pow,
+        ) {
+            return Err(
+                BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongPow).into(),
+            );
+        }
+
+        Ok(())
+    }
 }
 
 /// Deserializes block's dump into BlockArc
@@ -575,10 +954,11 @@ pub fn deserialize_main_chain_block(dump: &[u8]) -> Result<BlockArc, BlockError>
 
     let header = Headers::from_u8(*unsafe { dump.get_unchecked(0) })
         .change_context(BlockError::HeaderError(DumpHeadersErrorKind::UknownHeader))?;
+    let version = BlockVersion::from_header(dump[0]);
 
     let block: BlockArc = match header {
-        Headers::TransactionBlock => Arc::new(TransactionBlock::parse(&dump[1..])?),
-        Headers::SummarizeBlock => Arc::new(SummarizeBlock::parse(&dump[1..])?),
+        Headers::TransactionBlock => Arc::new(TransactionBlock::parse(&dump[1..], version)?),
+        Headers::SummarizeBlock => Arc::new(SummarizeBlock::parse(&dump[1..], version)?),
         _ => {
             return Err(
                 Report::new(BlockError::HeaderError(DumpHeadersErrorKind::WrongHeader))
@@ -602,7 +982,7 @@ impl PartialEq for dyn Block + Send + Sync {
 
 impl PartialOrd for dyn Block + Send + Sync {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.get_info().timestamp.cmp(&other.get_info().timestamp))
+        Some(self.cmp(other))
     }
 }
 
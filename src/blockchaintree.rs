@@ -1,31 +1,75 @@
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
 
 use crate::{
-    block::{self, Block as _, BlockArc},
+    block::{self, Block as _, BlockArc, DerivativeAnchor},
     chain,
-    errors::{BCTreeErrorKind, BlockChainTreeError, ChainErrorKind},
-    merkletree,
+    dump_headers::Headers,
+    errors::{
+        BCTreeErrorKind, BlockChainTreeError, BlockValidationErrorKind, ChainErrorKind,
+        TokenErrorKind, TxPoolErrorKind,
+    },
+    journal::{Journal, JournalDb, JournalEntry},
+    merkletree::{self, MerkleVersion},
+    receipt::{self, Receipt, TransactionStatus},
+    state::{self, StateTransition},
+    state_tree::{AccountProof, StateTree},
     static_values::{
-        self, AMOUNT_SUMMARY, BLOCKS_PER_EPOCH, BYTE_GAS_PRICE, COINS_PER_CYCLE, GAS_SUMMARY,
-        OLD_AMOUNT_SUMMARY, OLD_GAS_SUMMARY, ROOT_PUBLIC_ADDRESS,
+        self, AMOUNT_SUMMARY, BLOCKS_PER_EPOCH, BYTE_GAS_PRICE, COINS_PER_CYCLE,
+        DERIVATIVE_BLOCK_GAS, GAS_SUMMARY, MAX_FEE, MAX_FUTURE_BLOCK_TIME, MAX_REORG_DEPTH,
+        NONCES_TREE, OLD_AMOUNT_SUMMARY, OLD_GAS_SUMMARY, ROOT_PUBLIC_ADDRESS,
     },
+    token::{self, TokenInfo},
     tools,
-    transaction::Transaction,
-    transaction::Transactionable,
+    transaction::{self, Transaction, Transactionable, TransactionableItem},
+    txpool::{SharedTxPool, TxPool},
     types::Hash,
 };
 use error_stack::{Report, ResultExt};
+use parking_lot::Mutex;
 use primitive_types::U256;
-use sled::Db;
+use sled::{Db, Tree};
 use std::fs;
+use tokio::sync::RwLock;
+
+/// Data required to mine the next main chain block
+///
+/// `transactions` are taken from the pool in the order of their priority
+/// and are known to be payable by their senders when applied one after another
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    pub previous_hash: Hash,
+    pub height: U256,
+    pub difficulty: Hash,
+    pub transactions: Vec<Hash>,
+}
+
+pub type SharedBlockChainTree = Arc<RwLock<BlockChainTree>>;
+
+/// Owner, height and genesis hash of the derivative chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivativeChainInfo {
+    pub owner: [u8; 33],
+    pub height: U256,
+    pub genesis_hash: Hash,
+}
 
 pub struct BlockChainTree {
     main_chain: chain::MainChain,
+    header_chain: chain::HeaderChain,
     derivative_chains: HashMap<[u8; 33], chain::DerivativeChain>,
     summary_db: Db,
+    nonces: Tree,
+    state_tree: StateTree,
     old_summary_db: Db,
     gas_db: Db,
     old_gas_db: Db,
+    journal: Journal,
+    state_lock: Mutex<()>,
+    tx_pool: SharedTxPool,
     root_folder: String,
 }
 
@@ -43,6 +87,13 @@ impl BlockChainTree {
             .change_context(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::Init))
             .attach_printable("failed to open summary db")?;
 
+        let nonces = summary_db
+            .open_tree(NONCES_TREE)
+            .change_context(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::Init))
+            .attach_printable("failed to open nonces tree")?;
+
+        let state_tree = StateTree::open(&summary_db)?;
+
         // open old summary DB
         let old_summary_db = sled::open(path_summary_old)
             .change_context(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::Init))
@@ -56,30 +107,38 @@ impl BlockChainTree {
         let old_gas_db = sled::open(path_gas_old)
             .change_context(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::Init))
             .attach_printable("failed to open old gas db")?;
+        let journal = Journal::new(root_folder)?;
         let main_chain = chain::MainChain::new(root_folder)?;
+        let header_chain = chain::HeaderChain::new(root_folder)?;
 
-        if main_chain.get_height() == U256::one() {
-            summary_db
-                .transaction(
-                    |db| -> Result<(), sled::transaction::ConflictableTransactionError<()>> {
-                        let mut buf: Vec<u8> =
-                            Vec::with_capacity(tools::u256_size(&COINS_PER_CYCLE));
-                        tools::dump_u256(&COINS_PER_CYCLE, &mut buf).unwrap();
-                        db.insert(&(ROOT_PUBLIC_ADDRESS) as &[u8], buf)?;
-                        Ok(())
-                    },
-                )
-                .unwrap();
-        }
-        Ok(Self {
+        let derivative_chains = chain::DerivativeChain::open_all(root_folder)
+            .change_context(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::Init))
+            .attach_printable("failed to open derivative chains")?
+            .into_iter()
+            .collect();
+
+        let tree = Self {
             main_chain,
-            derivative_chains: HashMap::new(),
+            header_chain,
+            derivative_chains,
             summary_db,
+            nonces,
+            state_tree,
             old_summary_db,
             gas_db,
             old_gas_db,
+            journal,
+            state_lock: Mutex::new(()),
+            tx_pool: Arc::new(RwLock::new(TxPool::new())),
             root_folder: root_folder.into(),
-        })
+        };
+        tree.recover_pending()?;
+
+        if tree.main_chain.get_height() == U256::one() {
+            tree.set_amount(&ROOT_PUBLIC_ADDRESS as &[u8], *COINS_PER_CYCLE)?;
+        }
+
+        Ok(tree)
     }
 
     pub fn get_derivative_chain(
@@ -89,7 +148,7 @@ impl BlockChainTree {
         if let Some(chain) = self.derivative_chains.get(owner) {
             return Ok(chain.clone());
         }
-        let last_block = self.main_chain.get_last_block()?.unwrap(); // practically cannot fail
+        let last_block = self.get_last_main_block()?;
         let derivative_chain = chain::DerivativeChain::new(
             &self.root_folder,
             &hex::encode(owner),
@@ -100,32 +159,291 @@ impl BlockChainTree {
         Ok(derivative_chain)
     }
 
+    /// Returns derivative chain of the owner if it exists, unlike `get_derivative_chain` never creates it
+    pub fn find_derivative_chain(&self, owner: &[u8; 33]) -> Option<chain::DerivativeChain> {
+        self.derivative_chains.get(owner).cloned()
+    }
+
+    /// Lists all derivative chains ordered by their owners
+    pub fn list_derivative_chains(&self) -> Vec<DerivativeChainInfo> {
+        let mut chains: Vec<DerivativeChainInfo> = self
+            .derivative_chains
+            .iter()
+            .map(|(owner, chain)| DerivativeChainInfo {
+                owner: *owner,
+                height: chain.get_height(),
+                genesis_hash: chain.get_genesis_hash(),
+            })
+            .collect();
+        chains.sort_by_key(|chain| chain.owner);
+        chains
+    }
+
+    /// Latest block of the owner's derivative chain anchored in the main chain
+    pub fn get_derivative_anchor(
+        &self,
+        owner: &[u8; 33],
+    ) -> Result<Option<DerivativeAnchor>, Report<BlockChainTreeError>> {
+        match chain::open_anchors_tree(&self.summary_db)?
+            .get(owner)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::GetDerivChain,
+            ))? {
+            Some(dump) => Ok(Some(
+                DerivativeAnchor::parse(&dump)
+                    .change_context(BlockChainTreeError::BlockChainTree(
+                        BCTreeErrorKind::GetDerivChain,
+                    ))?
+                    .0,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Gas minted by the owner's derivative chain blocks that are anchored in the main chain
+    pub fn get_anchored_gas(&self, owner: &[u8; 33]) -> Result<U256, Report<BlockChainTreeError>> {
+        Ok(match self.get_derivative_anchor(owner)? {
+            Some(anchor) => (anchor.height + 1) * *DERIVATIVE_BLOCK_GAS,
+            None => U256::zero(),
+        })
+    }
+
     pub fn get_main_chain(&self) -> chain::MainChain {
         self.main_chain.clone()
     }
 
+    /// Last block of the main chain, the chain always has at least the genesis block
+    fn get_last_main_block(&self) -> Result<BlockArc, Report<BlockChainTreeError>> {
+        self.main_chain
+            .get_last_block()?
+            .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))
+            .attach_printable("main chain has no blocks")
+    }
+
+    pub fn get_header_chain(&self) -> chain::HeaderChain {
+        self.header_chain.clone()
+    }
+
+    pub fn get_tx_pool(&self) -> SharedTxPool {
+        self.tx_pool.clone()
+    }
+
+    /// Applies changes built by `build` to the summary, gas and transactions databases as a whole
+    ///
+    /// If `build` fails nothing is written. Changes are recorded in the journal before
+    /// they are applied, so an interrupted transition is finished on the next startup.
+    ///
+    /// The transition is not a part of any block, so reverting blocks keeps it:
+    /// amounts and gas recorded for undo of the applied blocks are shifted by the change
+    pub fn apply_transition<F>(&self, build: F) -> Result<(), Report<BlockChainTreeError>>
+    where
+        F: FnOnce(&mut StateTransition) -> Result<(), Report<BlockChainTreeError>>,
+    {
+        self.transition(None, build)
+    }
+
+    /// Applies changes of the block with the specified height,
+    /// they are reverted together with the block
+    fn apply_block_transition<F>(
+        &self,
+        height: &U256,
+        build: F,
+    ) -> Result<(), Report<BlockChainTreeError>>
+    where
+        F: FnOnce(&mut StateTransition) -> Result<(), Report<BlockChainTreeError>>,
+    {
+        self.transition(Some(height), build)
+    }
+
+    fn transition<F>(
+        &self,
+        height: Option<&U256>,
+        build: F,
+    ) -> Result<(), Report<BlockChainTreeError>>
+    where
+        F: FnOnce(&mut StateTransition) -> Result<(), Report<BlockChainTreeError>>,
+    {
+        let _guard = self.state_lock.lock();
+        let mut transition = StateTransition::new(
+            &self.summary_db,
+            &self.nonces,
+            &self.gas_db,
+            &self.main_chain,
+        );
+        build(&mut transition)?;
+        let redo = transition.into_changes();
+        if redo.is_empty() {
+            return Ok(());
+        }
+
+        let Some(height) = height else {
+            let rebased = self.rebase_undo(&redo)?;
+            self.journal.begin_change(&redo, &rebased)?;
+            self.apply_entries(&redo)?;
+            return self.journal.finish();
+        };
+
+        let mut undo = Vec::with_capacity(redo.len());
+        for entry in redo.iter() {
+            // transactions are kept while other branches may still include them,
+            // reorganisation removes orphaned ones explicitly
+            if let JournalDb::Transactions = entry.db {
+                continue;
+            }
+            undo.push(JournalEntry {
+                db: entry.db,
+                key: entry.key.clone(),
+                value: self.get_raw(entry.db, &entry.key)?,
+            });
+        }
+
+        self.journal.begin_transition(height, &undo, &redo)?;
+        self.apply_entries(&redo)?;
+        self.journal.finish()
+    }
+
+    /// Shifts journaled amounts and gas of the keys changed outside of blocks by the change,
+    /// returns the updated undo entries with their journal keys
+    fn rebase_undo(
+        &self,
+        redo: &[JournalEntry],
+    ) -> Result<Vec<(Vec<u8>, JournalEntry)>, Report<BlockChainTreeError>> {
+        let load = |value: Option<&Vec<u8>>| -> Result<U256, Report<BlockChainTreeError>> {
+            match value {
+                Some(value) => Ok(tools::load_u256(value)
+                    .change_context(BlockChainTreeError::BlockChainTree(
+                        BCTreeErrorKind::Journal,
+                    ))?
+                    .0),
+                None => Ok(U256::zero()),
+            }
+        };
+
+        let mut rebased = Vec::new();
+        for entry in redo {
+            if !matches!(entry.db, JournalDb::Summary | JournalDb::Gas) {
+                continue;
+            }
+            let previous = load(self.get_raw(entry.db, &entry.key)?.as_ref())?;
+            let current = load(entry.value.as_ref())?;
+            for (journal_key, mut undo) in self.journal.find_undo(entry.db, &entry.key)? {
+                let value = load(undo.value.as_ref())?;
+                let value = if current >= previous {
+                    value.saturating_add(current - previous)
+                } else {
+                    value.saturating_sub(previous - current)
+                };
+                // keys created by the block are removed by its revert unless something is left
+                if value.is_zero() && undo.value.is_none() {
+                    continue;
+                }
+                let mut dump = Vec::with_capacity(tools::u256_size(&value));
+                tools::dump_u256(&value, &mut dump).change_context(
+                    BlockChainTreeError::BlockChainTree(BCTreeErrorKind::Journal),
+                )?;
+                undo.value = Some(dump);
+                rebased.push((journal_key, undo));
+            }
+        }
+        Ok(rebased)
+    }
+
+    fn get_raw(
+        &self,
+        db: JournalDb,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, Report<BlockChainTreeError>> {
+        if db == JournalDb::Transactions {
+            return self.main_chain.get_transaction_raw(
+                <&[u8; 32]>::try_from(key)
+                    .change_context(BlockChainTreeError::BlockChainTree(
+                        BCTreeErrorKind::Journal,
+                    ))
+                    .attach_printable("wrong transaction hash length")?,
+            );
+        }
+        let (source, key) = self.journal_target(db, key)?;
+        Ok(source
+            .get(key)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))?
+            .map(|value| value.to_vec()))
+    }
+
+    /// Tree and key inside of it the journaled key refers to
+    ///
+    /// Transactions are stored by the main chain and have no tree here
+    fn journal_target<'a>(
+        &self,
+        db: JournalDb,
+        key: &'a [u8],
+    ) -> Result<(Tree, &'a [u8]), Report<BlockChainTreeError>> {
+        match db {
+            JournalDb::Summary => Ok((Tree::clone(&self.summary_db), key)),
+            JournalDb::Nonces => Ok((self.nonces.clone(), key)),
+            JournalDb::Gas => Ok((Tree::clone(&self.gas_db), key)),
+            JournalDb::Tokens => Ok((token::open_tokens_tree(&self.summary_db)?, key)),
+            JournalDb::Anchors => Ok((chain::open_anchors_tree(&self.summary_db)?, key)),
+            JournalDb::Receipts => Ok((receipt::open_receipts_tree(&self.summary_db)?, key)),
+            JournalDb::TokenBalances => {
+                let token = <&[u8; 32]>::try_from(key.get(..32).unwrap_or_default())
+                    .change_context(BlockChainTreeError::BlockChainTree(
+                        BCTreeErrorKind::Journal,
+                    ))
+                    .attach_printable("wrong token balance key length")?;
+                Ok((
+                    token::open_balances_tree(&self.summary_db, token)?,
+                    &key[32..],
+                ))
+            }
+            JournalDb::Transactions => Err(Report::new(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))
+            .attach_printable("transactions are stored in the main chain")),
+        }
+    }
+
+    /// Writes journaled values to their databases and updates the changed leaves of the state tree
+    fn apply_entries(&self, entries: &[JournalEntry]) -> Result<(), Report<BlockChainTreeError>> {
+        for entry in entries {
+            if entry.db == JournalDb::Transactions {
+                self.main_chain
+                    .set_transaction_raw(&entry.key, entry.value.clone())?;
+                continue;
+            }
+            let (target, key) = self.journal_target(entry.db, &entry.key)?;
+            match entry.value.as_ref() {
+                Some(value) => target.insert(key, value.as_slice()),
+                None => target.remove(key),
+            }
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))
+            .attach_printable("failed to apply journaled value")?;
+        }
+
+        self.state_tree
+            .update(&state::changed_leaves(entries, |db, key| {
+                self.get_raw(db, key)
+            })?)
+    }
+
+    /// Finishes transition that was interrupted before all of its changes were applied
+    fn recover_pending(&self) -> Result<(), Report<BlockChainTreeError>> {
+        if let Some(entries) = self.journal.get_pending()? {
+            self.apply_entries(&entries)?;
+            self.journal.finish()?;
+        }
+        Ok(())
+    }
+
     pub fn add_amount(
         &self,
         owner: &[u8],
         amount: U256,
     ) -> Result<(), Report<BlockChainTreeError>> {
-        self.summary_db
-            .transaction(
-                |db| -> Result<(), sled::transaction::ConflictableTransactionError<()>> {
-                    let prev_amount = match db.get(owner)? {
-                        Some(v) => tools::load_u256(&v).unwrap().0,
-                        None => U256::zero(),
-                    };
-                    let new_amount = prev_amount + amount;
-                    let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(&new_amount));
-                    tools::dump_u256(&new_amount, &mut buf).unwrap();
-                    db.insert(owner, buf)?;
-                    Ok(())
-                },
-            )
-            .unwrap();
-
-        Ok(())
+        self.apply_transition(|state| state.add_amount(owner, amount))
     }
 
     pub fn set_amount(
@@ -133,18 +451,7 @@ impl BlockChainTree {
         owner: &[u8],
         amount: U256,
     ) -> Result<(), Report<BlockChainTreeError>> {
-        self.summary_db
-            .transaction(
-                |db| -> Result<(), sled::transaction::ConflictableTransactionError<()>> {
-                    let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(&amount));
-                    tools::dump_u256(&amount, &mut buf).unwrap();
-                    db.insert(owner, buf)?;
-                    Ok(())
-                },
-            )
-            .unwrap();
-
-        Ok(())
+        self.apply_transition(|state| state.set_amount(owner, amount))
     }
 
     pub fn sub_amount(
@@ -152,188 +459,799 @@ impl BlockChainTree {
         owner: &[u8],
         amount: U256,
     ) -> Result<(), Report<BlockChainTreeError>> {
-        self.summary_db
-            .transaction(
-                |db| -> Result<(), sled::transaction::ConflictableTransactionError<()>> {
-                    let prev_amount = match db.get(owner)? {
-                        Some(v) => tools::load_u256(&v).unwrap().0,
-                        None => U256::zero(),
-                    };
-                    if prev_amount < amount {
-                        return Err(sled::transaction::ConflictableTransactionError::Abort(()));
-                    }
-                    let new_amount = prev_amount - amount;
-                    let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(&new_amount));
-                    tools::dump_u256(&new_amount, &mut buf).unwrap();
-                    db.insert(owner, buf)?;
-                    Ok(())
-                },
-            )
-            .unwrap();
-
-        Ok(())
+        self.apply_transition(|state| state.sub_amount(owner, amount))
     }
-    pub fn get_amount(&self, owner: &[u8; 33]) -> Result<U256, Report<BlockChainTreeError>> {
-        match self
-            .summary_db
+
+    /// Amount of coins or gas of the owner in the summary or the gas database
+    fn get_balance(&self, db: &Db, owner: &[u8]) -> Result<U256, Report<BlockChainTreeError>> {
+        match db
             .get(owner)
             .change_context(BlockChainTreeError::BlockChainTree(
                 BCTreeErrorKind::GetFunds,
             ))
             .attach_printable("failed to get funds")?
         {
-            Some(v) => Ok(tools::load_u256(&v).unwrap().0),
+            Some(v) => Ok(tools::load_u256(&v)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetFunds,
+                ))?
+                .0),
             None => Ok(U256::zero()),
         }
     }
 
+    pub fn get_amount(&self, owner: &[u8; 33]) -> Result<U256, Report<BlockChainTreeError>> {
+        self.get_balance(&self.summary_db, owner)
+    }
+
     pub fn send_amount(
         &self,
         from: &[u8],
         to: &[u8],
         amount: U256,
     ) -> Result<(), Report<BlockChainTreeError>> {
-        if from == to {
-            return Ok(());
+        self.apply_transition(|state| state.send_amount(from, to, amount))
+    }
+
+    pub fn add_gas(&self, owner: &[u8], amount: U256) -> Result<(), Report<BlockChainTreeError>> {
+        self.apply_transition(|state| state.add_gas(owner, amount))
+    }
+
+    pub fn sub_gas(&self, owner: &[u8], amount: U256) -> Result<(), Report<BlockChainTreeError>> {
+        self.apply_transition(|state| state.sub_gas(owner, amount))
+    }
+
+    /// Returns the nonce the next transaction of the owner must have
+    pub fn get_nonce(&self, owner: &[u8; 33]) -> Result<u64, Report<BlockChainTreeError>> {
+        match self
+            .nonces
+            .get(owner)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::GetNonce,
+            ))
+            .attach_printable("failed to get nonce")?
+        {
+            Some(v) => Ok(<[u8; 8]>::try_from(v.as_ref())
+                .map(u64::from_be_bytes)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetNonce,
+                ))
+                .attach_printable("wrong nonce length")?),
+            None => Ok(0),
         }
-        self.summary_db
-            .transaction(
-                |db| -> Result<(), sled::transaction::ConflictableTransactionError<()>> {
-                    let mut from_amount = match db.get(from)? {
-                        Some(v) => tools::load_u256(&v).unwrap().0,
-                        None => U256::zero(),
-                    };
-                    let mut to_amount = match db.get(to)? {
-                        Some(v) => tools::load_u256(&v).unwrap().0,
-                        None => U256::zero(),
-                    };
-                    if from_amount < amount {
-                        return Err(sled::transaction::ConflictableTransactionError::Abort(()));
-                    }
+    }
 
-                    from_amount -= amount;
-                    to_amount += amount;
+    pub fn get_gas(&self, owner: &[u8; 33]) -> Result<U256, Report<BlockChainTreeError>> {
+        self.get_balance(&self.gas_db, owner)
+    }
 
-                    let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(&from_amount));
-                    tools::dump_u256(&from_amount, &mut buf).unwrap();
-                    db.insert(from, buf)?;
+    /// Root of the state tree committing to the amounts and gas of all accounts and to the tokens
+    pub fn get_state_root(&self) -> Result<Hash, Report<BlockChainTreeError>> {
+        self.state_tree.root()
+    }
 
-                    let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(&to_amount));
-                    tools::dump_u256(&to_amount, &mut buf).unwrap();
-                    db.insert(to, buf)?;
-                    Ok(())
-                },
-            )
-            .unwrap();
+    /// Amount and gas of the owner with the proof against the current state root
+    pub fn get_account_proof(
+        &self,
+        owner: &[u8; 33],
+    ) -> Result<AccountProof, Report<BlockChainTreeError>> {
+        // the state is not changed in the middle of the proof
+        let _guard = self.state_lock.lock();
+        self.state_tree
+            .get_proof(owner, self.get_amount(owner)?, self.get_gas(owner)?)
+    }
 
-        Ok(())
+    pub fn get_amount_with_proof(
+        &self,
+        owner: &[u8; 33],
+    ) -> Result<(U256, AccountProof), Report<BlockChainTreeError>> {
+        let proof = self.get_account_proof(owner)?;
+        Ok((proof.amount, proof))
     }
 
-    pub fn add_gas(&self, owner: &[u8], amount: U256) -> Result<(), Report<BlockChainTreeError>> {
-        self.gas_db
-            .transaction(
-                |db| -> Result<(), sled::transaction::ConflictableTransactionError<()>> {
-                    let prev_amount = match db.get(owner)? {
-                        Some(v) => tools::load_u256(&v).unwrap().0,
-                        None => U256::zero(),
-                    };
-                    let new_amount = prev_amount + amount;
-                    let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(&new_amount));
-                    tools::dump_u256(&new_amount, &mut buf).unwrap();
-                    db.insert(owner, buf)?;
-                    Ok(())
-                },
-            )
-            .unwrap();
+    pub fn get_gas_with_proof(
+        &self,
+        owner: &[u8; 33],
+    ) -> Result<(U256, AccountProof), Report<BlockChainTreeError>> {
+        let proof = self.get_account_proof(owner)?;
+        Ok((proof.gas, proof))
+    }
 
-        Ok(())
+    pub fn send_gas(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.apply_transition(|state| state.send_gas(from, to, amount))
     }
-    pub fn sub_gas(&self, owner: &[u8], amount: U256) -> Result<(), Report<BlockChainTreeError>> {
-        self.gas_db
-            .transaction(
-                |db| -> Result<(), sled::transaction::ConflictableTransactionError<()>> {
-                    let prev_amount = match db.get(owner)? {
-                        Some(v) => tools::load_u256(&v).unwrap().0,
-                        None => U256::zero(),
-                    };
-                    if prev_amount < amount {
-                        return Err(sled::transaction::ConflictableTransactionError::Abort(()));
-                    }
-                    let new_amount = prev_amount - amount;
-                    let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(&new_amount));
-                    tools::dump_u256(&new_amount, &mut buf).unwrap();
-                    db.insert(owner, buf)?;
-                    Ok(())
-                },
-            )
-            .unwrap();
 
-        Ok(())
+    /// Returns issuer and current supply of the token, `None` if it wasn't issued
+    pub fn get_token_info(
+        &self,
+        token: &Hash,
+    ) -> Result<Option<TokenInfo>, Report<BlockChainTreeError>> {
+        match token::open_tokens_tree(&self.summary_db)?
+            .get(token)
+            .change_context(BlockChainTreeError::Token(TokenErrorKind::Parse))
+            .attach_printable("failed to get token")?
+        {
+            Some(dump) => Ok(Some(TokenInfo::parse(&dump)?)),
+            None => Ok(None),
+        }
     }
-    pub fn get_gas(&self, owner: &[u8; 33]) -> Result<U256, Report<BlockChainTreeError>> {
-        match self
-            .gas_db
+
+    pub fn get_token_supply(&self, token: &Hash) -> Result<U256, Report<BlockChainTreeError>> {
+        Ok(self
+            .get_token_info(token)?
+            .ok_or(BlockChainTreeError::Token(TokenErrorKind::UnknownToken))
+            .attach_printable(format!("token: {}", hex::encode(token)))?
+            .supply)
+    }
+
+    pub fn get_token_balance(
+        &self,
+        token: &Hash,
+        owner: &[u8; 33],
+    ) -> Result<U256, Report<BlockChainTreeError>> {
+        match token::open_balances_tree(&self.summary_db, token)?
             .get(owner)
             .change_context(BlockChainTreeError::BlockChainTree(
                 BCTreeErrorKind::GetFunds,
             ))
-            .attach_printable("failed to get gas amount")?
+            .attach_printable("failed to get token balance")?
         {
-            Some(v) => Ok(tools::load_u256(&v).unwrap().0),
+            Some(v) => Ok(tools::load_u256(&v)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetFunds,
+                ))?
+                .0),
             None => Ok(U256::zero()),
         }
     }
 
-    pub fn send_gas(
+    /// Returns all holders of the token with non zero balances
+    pub fn get_token_holders(
         &self,
-        from: &[u8],
-        to: &[u8],
-        amount: U256,
+        token: &Hash,
+    ) -> Result<Vec<([u8; 33], U256)>, Report<BlockChainTreeError>> {
+        let mut holders = Vec::new();
+        for item in token::open_balances_tree(&self.summary_db, token)?.iter() {
+            let (owner, balance) = item
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetFunds,
+                ))
+                .attach_printable("failed to iterate token balances")?;
+            let owner = <[u8; 33]>::try_from(owner.as_ref())
+                .change_context(BlockChainTreeError::Token(TokenErrorKind::Parse))
+                .attach_printable("wrong holder address length")?;
+            let (balance, _) = tools::load_u256(&balance).change_context(
+                BlockChainTreeError::BlockChainTree(BCTreeErrorKind::GetFunds),
+            )?;
+            if !balance.is_zero() {
+                holders.push((owner, balance));
+            }
+        }
+        Ok(holders)
+    }
+
+    pub fn add_new_block(
+        &self,
+        block: BlockArc,
+        transactions: &[Transaction],
     ) -> Result<(), Report<BlockChainTreeError>> {
-        self.gas_db
-            .transaction(
-                |db| -> Result<(), sled::transaction::ConflictableTransactionError<()>> {
-                    let mut from_amount = match db.get(from)? {
-                        Some(v) => tools::load_u256(&v).unwrap().0,
-                        None => U256::zero(),
-                    };
-                    let mut to_amount = match db.get(to)? {
-                        Some(v) => tools::load_u256(&v).unwrap().0,
-                        None => U256::zero(),
-                    };
-                    if from_amount < amount {
-                        return Err(sled::transaction::ConflictableTransactionError::Abort(()));
-                    }
+        // transactions are stored first, so the block is indexed with their addresses
+        self.main_chain.add_transactions(transactions)?;
+
+        self.main_chain.add_block(block)
+    }
 
-                    from_amount -= amount;
-                    to_amount += amount;
+    /// Restores the state to the one before the block with the specified height was applied
+    fn revert_height(&self, height: &U256) -> Result<(), Report<BlockChainTreeError>> {
+        let _guard = self.state_lock.lock();
+        let redo = self.journal.get_undo(height)?;
+        self.journal.begin_revert(height, &redo)?;
+        self.apply_entries(&redo)?;
+        self.journal.finish()
+    }
+    fn prune_journal(&self) -> Result<(), Report<BlockChainTreeError>> {
+        let height = self.main_chain.get_height();
+        if height > U256::from(MAX_REORG_DEPTH) {
+            self.journal.prune(&(height - MAX_REORG_DEPTH))?;
+        }
+        Ok(())
+    }
+
+    /// Applies the transactions and the anchors of the block to the state,
+    /// stores the transactions in the main chain if `store` is set
+    ///
+    /// The block reward and the fees collected from the senders go to the founder
+    /// in the same transition. All senders are checked to be able to pay before any changes are made
+    fn apply_transactions(
+        &self,
+        block: &dyn block::Block,
+        transactions: &[&dyn Transactionable],
+        store: bool,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let fee = block.get_fee();
+        let mut required: HashMap<[u8; 33], (U256, U256)> = HashMap::new();
+        for transaction in transactions {
+            let entry = required
+                .entry(*transaction.get_sender())
+                .or_insert((U256::zero(), U256::zero()));
+            // amounts come from the block, so the spends of a sender must not overflow
+            let amount = fee
+                .checked_add(transaction.get_amount().unwrap_or(U256::zero()))
+                .and_then(|amount| entry.0.checked_add(amount));
+            let gas = BYTE_GAS_PRICE
+                .checked_mul(transaction.get_dump_size().into())
+                .and_then(|gas| entry.1.checked_add(gas));
+            *entry = amount
+                .zip(gas)
+                .ok_or(BlockChainTreeError::BlockValidation(
+                    BlockValidationErrorKind::InsufficientFunds,
+                ))
+                .attach_printable_lazy(|| {
+                    format!(
+                        "spends of the sender overflow: {}",
+                        hex::encode(transaction.get_sender())
+                    )
+                })?;
+        }
 
-                    let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(&from_amount));
-                    tools::dump_u256(&from_amount, &mut buf).unwrap();
-                    db.insert(from, buf)?;
+        for (sender, (amount, gas)) in required.iter() {
+            if self.get_amount(sender)? < *amount || self.get_gas(sender)? < *gas {
+                return Err(BlockChainTreeError::BlockValidation(
+                    BlockValidationErrorKind::InsufficientFunds,
+                ))
+                .attach_printable(format!("sender: {}", hex::encode(sender)));
+            }
+        }
+
+        self.apply_block_transition(&block.get_info().height, |state| {
+            self.block_transition(state, block, transactions, store)
+        })
+    }
 
-                    let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(&to_amount));
-                    tools::dump_u256(&to_amount, &mut buf).unwrap();
-                    db.insert(to, buf)?;
-                    Ok(())
+    /// Applies the transactions with their receipts, the anchors and the reward of the block
+    /// to the transition
+    fn block_transition(
+        &self,
+        state: &mut StateTransition,
+        block: &dyn block::Block,
+        transactions: &[&dyn Transactionable],
+        store: bool,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let fee = block.get_fee();
+        let height = block.get_info().height;
+        for transaction in transactions {
+            if store {
+                state.add_transaction(*transaction)?;
+            }
+            state.apply_transaction(*transaction, fee)?;
+            state.set_receipt(
+                &transaction.hash(),
+                &Receipt {
+                    height,
+                    fee,
+                    gas_used: *BYTE_GAS_PRICE * transaction.get_dump_size(),
                 },
-            )
-            .unwrap();
+            );
+        }
+        self.apply_anchors(state, block.anchors())?;
+        state.apply_block_reward(block.get_founder(), fee * transactions.len())
+    }
 
+    /// Checks that every anchor commits to a known derivative block above the previous anchor
+    /// of the chain, records it and mints the gas of the newly anchored blocks to the chain owner
+    fn apply_anchors(
+        &self,
+        state: &mut StateTransition,
+        anchors: &[DerivativeAnchor],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let mut owners = HashSet::with_capacity(anchors.len());
+        for anchor in anchors {
+            let wrong_anchor = |reason: &'static str| {
+                Report::new(BlockChainTreeError::BlockValidation(
+                    BlockValidationErrorKind::WrongAnchor,
+                ))
+                .attach_printable(reason)
+                .attach_printable(format!("owner: {}", hex::encode(anchor.owner)))
+            };
+            if !owners.insert(anchor.owner) {
+                return Err(wrong_anchor("chain is anchored more than once"));
+            }
+            let anchored_blocks = match state.get_anchor(&anchor.owner)? {
+                Some(previous) if previous.height >= anchor.height => {
+                    return Err(wrong_anchor("anchor does not advance the chain"));
+                }
+                Some(previous) => anchor.height - previous.height,
+                None => anchor.height + 1,
+            };
+            let anchored = match self.derivative_chains.get(&anchor.owner) {
+                Some(derivative_chain) => derivative_chain.find_by_hash(&anchor.hash)?,
+                None => None,
+            }
+            .ok_or_else(|| wrong_anchor("anchored block is not known"))?;
+            if anchored.get_info().height != anchor.height {
+                return Err(wrong_anchor("anchored block has another height"));
+            }
+            state.set_anchor(anchor)?;
+            state.add_gas(&anchor.owner, anchored_blocks * *DERIVATIVE_BLOCK_GAS)?;
+        }
         Ok(())
     }
 
-    pub fn add_new_block(
+    /// Anchors for the tips of the derivative chains that advanced since their last anchor
+    fn collect_anchors(&self) -> Result<Vec<DerivativeAnchor>, Report<BlockChainTreeError>> {
+        let mut anchors = Vec::new();
+        for (owner, derivative_chain) in self.derivative_chains.iter() {
+            let Some(last_block) = derivative_chain.get_last_block()? else {
+                continue;
+            };
+            let height = last_block.get_info().height;
+            if let Some(previous) = self.get_derivative_anchor(owner)? {
+                if previous.height >= height {
+                    continue;
+                }
+            }
+            anchors.push(DerivativeAnchor {
+                owner: *owner,
+                hash: last_block
+                    .hash()
+                    .change_context(BlockChainTreeError::Chain(
+                        ChainErrorKind::FailedToHashBlock,
+                    ))?,
+                height,
+            });
+        }
+        anchors.sort_by_key(|anchor| anchor.owner);
+        Ok(anchors)
+    }
+
+    /// Refills the root address for the new cycle and pays the summarize block reward
+    fn start_cycle(
         &self,
+        height: &U256,
+        founder: &[u8; 33],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.apply_block_transition(height, |state| Self::cycle_transition(state, founder))
+    }
+
+    fn cycle_transition(
+        state: &mut StateTransition,
+        founder: &[u8; 33],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        state.set_amount(&ROOT_PUBLIC_ADDRESS, *COINS_PER_CYCLE)?;
+        state.apply_block_reward(founder, U256::zero())
+    }
+
+    /// Root of the state the block would result in if it was connected to the main chain now,
+    /// the state itself is not changed
+    ///
+    /// Transactions of the block have to be stored in the main chain
+    pub fn preview_state_root(
+        &self,
+        block: &dyn block::Block,
+    ) -> Result<Hash, Report<BlockChainTreeError>> {
+        let transactions = self.get_block_transactions(block, Vec::new())?;
+        self.block_state_root(block, &transactions)
+    }
+
+    fn block_state_root(
+        &self,
+        block: &dyn block::Block,
+        transactions: &[TransactionableItem],
+    ) -> Result<Hash, Report<BlockChainTreeError>> {
+        let _guard = self.state_lock.lock();
+        let mut transition = StateTransition::new(
+            &self.summary_db,
+            &self.nonces,
+            &self.gas_db,
+            &self.main_chain,
+        );
+        if let Headers::SummarizeBlock = block.get_type() {
+            Self::cycle_transition(&mut transition, &block.get_info().founder)?;
+        } else {
+            let transactions: Vec<&dyn Transactionable> = transactions
+                .iter()
+                .map(|transaction| transaction.as_ref() as &dyn Transactionable)
+                .collect();
+            self.block_transition(&mut transition, block, &transactions, false)?;
+        }
+        transition.state_root(&self.state_tree)
+    }
+
+    /// Collects the block's transactions in the block order,
+    /// the ones in `pending` are taken from it and the rest are loaded from the main chain
+    fn get_block_transactions(
+        &self,
+        block: &dyn block::Block,
+        pending: Vec<TransactionableItem>,
+    ) -> Result<Vec<TransactionableItem>, Report<BlockChainTreeError>> {
+        let mut pending: HashMap<Hash, TransactionableItem> = pending
+            .into_iter()
+            .map(|transaction| (transaction.hash(), transaction))
+            .collect();
+        let mut transactions = Vec::new();
+        for hash in block.transactions().unwrap_or(&[]) {
+            if let Some(transaction) = pending.remove(hash) {
+                transactions.push(transaction);
+                continue;
+            }
+            let transaction = self
+                .main_chain
+                .get_transactionable(hash)?
+                .ok_or(BlockChainTreeError::BlockValidation(
+                    BlockValidationErrorKind::MissingTransaction,
+                ))
+                .attach_printable(format!("transaction: {}", hex::encode(hash)))?;
+            transactions.push(transaction);
+        }
+        Ok(transactions)
+    }
+
+    /// Block's transactions that are not stored in the main chain,
+    /// taken from the `received` ones or copied from the pool
+    async fn get_pending_transactions(
+        &self,
+        block: &BlockArc,
+        received: Vec<TransactionableItem>,
+    ) -> Result<Vec<TransactionableItem>, Report<BlockChainTreeError>> {
+        let mut received: HashMap<Hash, TransactionableItem> = received
+            .into_iter()
+            .map(|transaction| (transaction.hash(), transaction))
+            .collect();
+        let tx_pool = self.tx_pool.read().await;
+        let mut transactions = Vec::new();
+        for hash in block.transactions().unwrap_or(&[]) {
+            if self.main_chain.transaction_exists(hash)? {
+                continue;
+            }
+            if let Some(transaction) = received.remove(hash) {
+                transactions.push(transaction);
+                continue;
+            }
+            let Some(transaction) = tx_pool.get(hash) else {
+                continue;
+            };
+            let dump = transaction
+                .dump()
+                .change_context(BlockChainTreeError::Chain(
+                    ChainErrorKind::AddingTransaction,
+                ))?;
+            transactions.push(transaction::parse_transactionable(&dump).change_context(
+                BlockChainTreeError::Chain(ChainErrorKind::AddingTransaction),
+            )?);
+        }
+        Ok(transactions)
+    }
+
+    /// Validates, applies and appends the block to the end of the main chain
+    ///
+    /// The block is checked completely before the state is changed,
+    /// transactions received with the block or taken from the pool are stored
+    /// only with the connected block
+    async fn connect_main_block(
+        &mut self,
         block: BlockArc,
-        transactions: &[Transaction],
+        received: Vec<TransactionableItem>,
     ) -> Result<(), Report<BlockChainTreeError>> {
-        self.main_chain.add_block(block)?;
+        let pending = self.get_pending_transactions(&block, received).await?;
+        let pending_hashes: Vec<Hash> = pending
+            .iter()
+            .map(|transaction| transaction.hash())
+            .collect();
+        {
+            let pending: Vec<&dyn Transactionable> = pending
+                .iter()
+                .map(|transaction| transaction.as_ref() as &dyn Transactionable)
+                .collect();
+            self.main_chain.validate_block(&block, &pending)?;
+        }
+
+        let height = block.get_info().height;
+        if let Headers::SummarizeBlock = block.get_type() {
+            if block.get_merkle_root() != self.summarize(&height)? {
+                return Err(BlockChainTreeError::BlockValidation(
+                    BlockValidationErrorKind::WrongSummary,
+                )
+                .into());
+            }
+        }
+        let transactions = self.get_block_transactions(block.as_ref(), pending)?;
+        Self::check_state_root(
+            block.as_ref(),
+            &self.block_state_root(block.as_ref(), &transactions)?,
+        )?;
+
+        if let Headers::SummarizeBlock = block.get_type() {
+            // rotation of the databases cannot be reverted, so it is done after all checks
+            self.rotate_dbs().await?;
+            if let Err(report) = self.start_cycle(&height, &block.get_info().founder) {
+                self.revert_height(&height)?;
+                return Err(report);
+            }
+        } else {
+            let transactions: Vec<&dyn Transactionable> = transactions
+                .iter()
+                .map(|transaction| transaction.as_ref() as &dyn Transactionable)
+                .collect();
+            if let Err(report) = self.apply_transactions(block.as_ref(), &transactions, true) {
+                self.revert_height(&height)?;
+                return Err(report);
+            }
+        }
+
+        let included = block.transactions().unwrap_or(&[]).to_vec();
+        if let Err(report) = self.main_chain.add_block(block) {
+            self.revert_height(&height)?;
+            self.main_chain.remove_transactions(&pending_hashes)?;
+            return Err(report);
+        }
 
-        self.main_chain.add_transactions(transactions)
+        self.tx_pool.write().await.remove_transactions(&included);
+        self.prune_journal()
     }
 
-    fn summarize(&self) -> Result<[u8; 32], Report<BlockChainTreeError>> {
+    fn check_state_root(
+        block: &dyn block::Block,
+        state_root: &Hash,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        if block.get_state_root() != *state_root {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongStateRoot,
+            ))
+            .attach_printable(format!(
+                "block state root: {}, state root: {}",
+                hex::encode(block.get_state_root()),
+                hex::encode(state_root)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Imports main chain block received from an untrusted source
+    ///
+    /// Blocks extending the chain are checked with every consensus rule of
+    /// `MainChain::validate_block`, summarize block root is checked against the summary database
+    ///
+    /// Blocks forking from the chain are stored as side blocks, if the branch they end
+    /// has more cumulative work than the main chain, the chain is reorganised
+    pub async fn import_main_block(
+        &mut self,
+        block: BlockArc,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.import_main_block_with_transactions(block, Vec::new())
+            .await
+    }
+
+    /// Imports main chain block together with its transactions received from the same source
+    ///
+    /// Received transactions are not stored until the block is accepted,
+    /// the rest of the block's transactions are taken from the pool and the main chain
+    pub async fn import_main_block_with_transactions(
+        &mut self,
+        block: BlockArc,
+        transactions: Vec<TransactionableItem>,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let last_block = self.get_last_main_block()?;
+        let last_hash = last_block
+            .hash()
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToHashBlock,
+            ))?;
+
+        if block.get_info().previous_hash == last_hash {
+            return self.connect_main_block(block, transactions).await;
+        }
+
+        self.import_side_block(block, transactions).await
+    }
+
+    async fn import_side_block(
+        &mut self,
+        block: BlockArc,
+        received: Vec<TransactionableItem>,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let previous_hash = block.get_info().previous_hash;
+        let parent = match self.main_chain.find_by_hash(&previous_hash)? {
+            Some(parent) => parent,
+            None => self.main_chain.find_side_by_hash(&previous_hash)?.ok_or(
+                BlockChainTreeError::BlockValidation(BlockValidationErrorKind::UnknownParent),
+            )?,
+        };
+        let pending = self.get_pending_transactions(&block, received).await?;
+        {
+            let pending: Vec<&dyn Transactionable> = pending
+                .iter()
+                .map(|transaction| transaction.as_ref() as &dyn Transactionable)
+                .collect();
+            self.main_chain
+                .validate_block_with_parent(&block, &parent, &pending)?;
+        }
+
+        // received transactions are kept in the main chain until the branch is connected,
+        // the pooled ones stay in the pool
+        let tx_pool = self.tx_pool.read().await;
+        for transaction in pending.iter() {
+            if tx_pool.get(&transaction.hash()).is_none() {
+                self.main_chain.add_transaction(transaction.as_ref())?;
+            }
+        }
+        drop(tx_pool);
+
+        let hash = self.main_chain.add_side_block(&block)?;
+
+        let (ancestor_height, branch) = match self.main_chain.get_side_branch(&hash)? {
+            Some(branch) => branch,
+            None => return Ok(()),
+        };
+
+        let ancestor = self
+            .main_chain
+            .find_by_height(&ancestor_height)?
+            .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?;
+
+        let mut branch_work = U256::zero();
+        let mut parent_difficulty = ancestor.get_info().difficulty;
+        for block in branch.iter() {
+            branch_work = branch_work.saturating_add(tools::calculate_work(&parent_difficulty));
+            parent_difficulty = block.get_info().difficulty;
+        }
+
+        let mut main_work = U256::zero();
+        let mut parent_difficulty = ancestor.get_info().difficulty;
+        let mut height = ancestor_height + 1;
+        while height < self.main_chain.get_height() {
+            let block = self
+                .main_chain
+                .find_by_height(&height)?
+                .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?;
+            main_work = main_work.saturating_add(tools::calculate_work(&parent_difficulty));
+            parent_difficulty = block.get_info().difficulty;
+            height += U256::one();
+        }
+
+        if branch_work > main_work {
+            self.reorganize(ancestor_height, branch).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Switches the main chain to the side branch
+    ///
+    /// Rolls back blocks above the common ancestor together with their state changes
+    /// and connects the branch, if any block of the branch fails, the previous chain is restored
+    async fn reorganize(
+        &mut self,
+        ancestor_height: U256,
+        branch: Vec<BlockArc>,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let depth = self.main_chain.get_height() - ancestor_height - 1;
+        if depth > U256::from(MAX_REORG_DEPTH) {
+            return Err(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Reorganize,
+            ))
+            .attach_printable(format!("reorganisation is too deep: {}", depth));
+        }
+
+        let mut height = ancestor_height + 1;
+        while height < self.main_chain.get_height() {
+            let block = self
+                .main_chain
+                .find_by_height(&height)?
+                .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?;
+            if let Headers::SummarizeBlock = block.get_type() {
+                return Err(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::Reorganize,
+                ))
+                .attach_printable("reorganisation across epochs is not supported");
+            }
+            height += U256::one();
+        }
+        if branch
+            .iter()
+            .any(|block| matches!(block.get_type(), Headers::SummarizeBlock))
+        {
+            return Err(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Reorganize,
+            ))
+            .attach_printable("reorganisation across epochs is not supported");
+        }
+
+        let disconnected = self.disconnect_main_blocks(&ancestor_height)?;
+
+        for (index, block) in branch.iter().enumerate() {
+            let hash = block.hash().change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToHashBlock,
+            ))?;
+            if let Err(report) = self.connect_main_block(block.clone(), Vec::new()).await {
+                // the error of the branch is returned, the chain stays at the last restored block
+                if let Err(restore) = self
+                    .restore_main_blocks(&ancestor_height, disconnected)
+                    .await
+                {
+                    log::error!("Failed to restore the main chain: {:?}", restore);
+                }
+                return Err(report
+                    .change_context(BlockChainTreeError::BlockChainTree(
+                        BCTreeErrorKind::Reorganize,
+                    ))
+                    .attach_printable(format!(
+                        "failed to connect block #{} of the branch",
+                        index
+                    )));
+            }
+            self.main_chain.remove_side_block(&hash)?;
+        }
+
+        let branch_transactions: HashSet<&Hash> = branch
+            .iter()
+            .flat_map(|block| block.transactions().unwrap_or(&[]).iter())
+            .collect();
+        for block in disconnected.iter() {
+            let orphaned: Vec<Hash> = block
+                .transactions()
+                .unwrap_or(&[])
+                .iter()
+                .filter(|hash| !branch_transactions.contains(hash))
+                .copied()
+                .collect();
+            // orphaned transactions can be included into the following blocks again
+            let mut tx_pool = self.tx_pool.write().await;
+            for hash in orphaned.iter() {
+                if let Some(transaction) = self.main_chain.get_transactionable(hash)? {
+                    let fee = self.transaction_fee(transaction.as_ref())?;
+                    // transactions not fitting into the pool anymore are dropped
+                    if let Err(report) = tx_pool.push(transaction, fee) {
+                        tx_pool.reject(*hash, tools::report_message(&report));
+                    }
+                }
+            }
+            drop(tx_pool);
+            self.main_chain.remove_transactions(&orphaned)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes blocks above the specified height and reverts their state changes
+    ///
+    /// Returns removed blocks, the highest block first
+    fn disconnect_main_blocks(
+        &self,
+        height: &U256,
+    ) -> Result<Vec<BlockArc>, Report<BlockChainTreeError>> {
+        let mut disconnected = Vec::new();
+        while self.main_chain.get_height() - 1 > *height {
+            let block = self.main_chain.remove_last_block()?;
+            self.revert_height(&block.get_info().height)?;
+            disconnected.push(block);
+        }
+        Ok(disconnected)
+    }
+
+    /// Replaces blocks above the specified height with the ones removed by `disconnect_main_blocks`
+    async fn restore_main_blocks(
+        &mut self,
+        height: &U256,
+        blocks: Vec<BlockArc>,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.disconnect_main_blocks(height)?;
+        for block in blocks.into_iter().rev() {
+            let hash = block.hash().change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToHashBlock,
+            ))?;
+            self.connect_main_block(block, Vec::new()).await?;
+            self.main_chain.remove_side_block(&hash)?;
+        }
+        Ok(())
+    }
+
+    /// Root of the summary database for the summarize block with the `height`
+    fn summarize(&self, height: &U256) -> Result<[u8; 32], Report<BlockChainTreeError>> {
         let mut hashes: Vec<[u8; 32]> = Vec::with_capacity(self.summary_db.len());
         for res in self.summary_db.iter() {
             let (address, amount) = res
@@ -361,42 +1279,187 @@ impl BlockChainTree {
             hashes.push(tools::hash(&data_to_hash));
         }
 
-        let merkle_tree = merkletree::MerkleTree::build_tree(&hashes);
+        let merkle_tree =
+            merkletree::MerkleTree::build_tree(&hashes, MerkleVersion::for_height(height));
 
         Ok(*merkle_tree.get_root())
     }
 
+    /// Hash, timestamp and difficulty of the block the next block of the owner's derivative chain
+    /// follows, with the height of the next block
+    ///
+    /// First block of the chain follows the main chain block the chain starts from, `genesis_hash`
+    /// is used for the chains that are not created yet
+    fn derivative_parent(
+        &self,
+        owner: &[u8; 33],
+        genesis_hash: &Hash,
+    ) -> Result<(Hash, u64, Hash, U256), Report<BlockChainTreeError>> {
+        let derivative_chain = self.derivative_chains.get(owner);
+        if let Some(last_block) = derivative_chain
+            .map(|derivative_chain| derivative_chain.get_last_block())
+            .transpose()?
+            .flatten()
+        {
+            let info = last_block.get_info();
+            return Ok((
+                last_block
+                    .hash()
+                    .change_context(BlockChainTreeError::Chain(
+                        ChainErrorKind::FailedToHashBlock,
+                    ))?,
+                info.timestamp,
+                info.difficulty,
+                info.height + 1,
+            ));
+        }
+
+        let genesis_hash = derivative_chain
+            .map(|derivative_chain| derivative_chain.get_genesis_hash())
+            .unwrap_or(*genesis_hash);
+        let genesis = self
+            .main_chain
+            .find_by_hash(&genesis_hash)?
+            .ok_or(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::UnknownParent,
+            ))
+            .attach_printable("derivative chain starts from unknown main chain block")?;
+        Ok((
+            genesis_hash,
+            genesis.get_info().timestamp,
+            static_values::BEGINNING_DIFFICULTY,
+            U256::zero(),
+        ))
+    }
+
+    /// Checks derivative block against the consensus rules of its founder's chain
+    ///
+    /// The block has to follow the chain tip, its timestamp can not be earlier than the parent's one
+    /// or too far in the future, difficulty is retargeted from the parent's one
+    /// and pow is found for the previous hash with the parent's difficulty
+    pub fn validate_derivative_block(
+        &self,
+        block: &block::DerivativeBlock,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let info = block.get_info();
+        let (prev_hash, prev_timestamp, prev_difficulty, height) =
+            self.derivative_parent(&info.founder, &info.previous_hash)?;
+
+        if info.height != height {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongHeight,
+            ))
+            .attach_printable(format!(
+                "block height: {}, expected height: {}",
+                info.height, height
+            ));
+        }
+
+        if info.previous_hash != prev_hash {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongPreviousHash,
+            )
+            .into());
+        }
+
+        if info.timestamp < prev_timestamp {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongTimestamp,
+            ))
+            .attach_printable(format!(
+                "block timestamp: {}, previous block timestamp: {}",
+                info.timestamp, prev_timestamp
+            ));
+        }
+        if info.timestamp > tools::current_timestamp() + MAX_FUTURE_BLOCK_TIME {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::FutureTimestamp,
+            ))
+            .attach_printable(format!("block timestamp: {}", info.timestamp));
+        }
+
+        let mut difficulty = prev_difficulty;
+        tools::recalculate_difficulty(prev_timestamp, info.timestamp, &mut difficulty);
+        if info.difficulty != difficulty {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongDifficulty,
+            )
+            .into());
+        }
+
+        if !tools::check_pow(
+            &prev_hash,
+            &prev_difficulty,
+            &[],
+            &info.pow,
+            MerkleVersion::for_height(&info.height),
+        ) {
+            return Err(
+                BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongPow).into(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Validates derivative block and appends it to its founder's chain
+    ///
+    /// Gas reward of the block is minted by the main chain block anchoring it
+    ///
+    /// Chain is created with the first block, the main chain block it starts from is its genesis
+    pub fn import_derivative_block(
+        &mut self,
+        block: &block::DerivativeBlock,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.validate_derivative_block(block)?;
+
+        let founder = block.get_info().founder;
+        let derivative_chain = match self.derivative_chains.get(&founder) {
+            Some(derivative_chain) => derivative_chain.clone(),
+            None => {
+                let derivative_chain = chain::DerivativeChain::new(
+                    &self.root_folder,
+                    &hex::encode(founder),
+                    &block.get_info().previous_hash,
+                )?;
+                self.derivative_chains
+                    .insert(founder, derivative_chain.clone());
+                derivative_chain
+            }
+        };
+        derivative_chain.add_block(block)
+    }
+
     pub async fn emmit_new_derivative_block(
         &mut self,
         pow: &[u8; 32],
         founder: &[u8; 33],
         timestamp: u64,
     ) -> Result<block::BlockArc, Report<BlockChainTreeError>> {
-        let derivative_chain = self.get_derivative_chain(founder)?;
-        let (prev_hash, mut difficulty, prev_timestamp, height) =
-            if let Some(block) = derivative_chain.get_last_block()? {
-                (
-                    block.hash().unwrap(),
-                    block.get_info().difficulty,
-                    block.get_info().timestamp,
-                    block.get_info().height + 1,
-                )
-            } else {
-                let block = self
-                    .main_chain
-                    .find_by_hash(&derivative_chain.genesis_hash)?
-                    .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindByHashE))?;
-                (
-                    block.hash().unwrap(),
-                    static_values::BEGINNING_DIFFICULTY,
-                    block.get_info().timestamp,
-                    U256::zero(),
-                )
-            };
-
-        if !tools::check_pow(&prev_hash, &difficulty, &[], pow) {
+        let last_block = self.get_last_main_block()?;
+        let main_hash = last_block
+            .hash()
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToHashBlock,
+            ))?;
+        let (prev_hash, prev_timestamp, mut difficulty, height) =
+            self.derivative_parent(founder, &main_hash)?;
+
+        if !tools::check_pow(
+            &prev_hash,
+            &difficulty,
+            &[],
+            pow,
+            MerkleVersion::for_height(&height),
+        ) {
             return Err(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::WrongPow).into());
         };
+        if timestamp < prev_timestamp {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongTimestamp,
+            )
+            .into());
+        }
         tools::recalculate_difficulty(prev_timestamp, timestamp, &mut difficulty);
         let default_info = block::BasicInfo {
             timestamp,
@@ -408,7 +1471,7 @@ impl BlockChainTree {
         };
 
         let block = block::DerivativeBlock { default_info };
-        derivative_chain.add_block(&block)?;
+        self.import_derivative_block(&block)?;
         Ok(Arc::new(block))
     }
 
@@ -419,102 +1482,398 @@ impl BlockChainTree {
         transactions: &[Hash],
         timestamp: u64,
     ) -> Result<block::BlockArc, Report<BlockChainTreeError>> {
-        let last_block = self.main_chain.get_last_block()?.unwrap(); // practically cannot fail
+        let last_block = self.get_last_main_block()?;
         let prev_hash = last_block
             .hash()
             .change_context(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::DumpDb))
             .attach_printable("failed to hash block")?;
 
+        let height = last_block.get_info().height + 1;
+        let version = MerkleVersion::for_height(&height);
         let mut difficulty = last_block.get_info().difficulty;
-        if !tools::check_pow(&prev_hash, &difficulty, transactions, pow) {
+        if !tools::check_pow(&prev_hash, &difficulty, transactions, pow, version) {
             return Err(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::WrongPow).into());
         };
-        tools::recalculate_difficulty(last_block.get_info().timestamp, timestamp, &mut difficulty);
+        // the timestamp comes from the miner, the block has to pass the validation of the peers
+        let prev_timestamp = last_block.get_info().timestamp;
+        if timestamp < prev_timestamp {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongTimestamp,
+            ))
+            .attach_printable(format!(
+                "block timestamp: {}, previous block timestamp: {}",
+                timestamp, prev_timestamp
+            ));
+        }
+        if timestamp > tools::current_timestamp() + MAX_FUTURE_BLOCK_TIME {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::FutureTimestamp,
+            ))
+            .attach_printable(format!("block timestamp: {}", timestamp));
+        }
+        tools::recalculate_difficulty(prev_timestamp, timestamp, &mut difficulty);
         let fee = tools::recalculate_fee(&difficulty);
         let default_info = block::BasicInfo {
             timestamp,
             pow: *pow,
             previous_hash: prev_hash,
-            height: last_block.get_info().height + 1,
+            height,
             difficulty,
             founder: *founder,
         };
-        let new_block: block::BlockArc =
-            if ((last_block.get_info().height + 1) % BLOCKS_PER_EPOCH).is_zero() {
-                if !transactions.is_empty() {
-                    return Err(BlockChainTreeError::BlockChainTree(
-                        BCTreeErrorKind::SummarizeBlockWrongTransactionsAmount,
-                    )
-                    .into());
-                }
+        let new_block: block::BlockArc = if (height % BLOCKS_PER_EPOCH).is_zero() {
+            if !transactions.is_empty() {
+                return Err(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::SummarizeBlockWrongTransactionsAmount,
+                )
+                .into());
+            }
 
-                let merkle_tree_root = self.summarize()?;
+            let merkle_tree_root = self.summarize(&height)?;
 
-                let summarize_block = Arc::new(block::SummarizeBlock {
-                    default_info,
-                    merkle_tree_root,
-                });
-                self.rotate_dbs().await?;
+            self.rotate_dbs().await?;
 
-                self.set_amount(&ROOT_PUBLIC_ADDRESS as &[u8], *COINS_PER_CYCLE)?;
+            self.start_cycle(&height, founder)?;
 
-                summarize_block
-            } else {
-                if transactions.is_empty() {
+            Arc::new(block::SummarizeBlock {
+                default_info,
+                merkle_tree_root,
+                state_root: self.get_state_root()?,
+                version: block::BlockVersion::V2,
+            })
+        } else {
+            if transactions.is_empty() {
+                return Err(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::CreateMainChainBlock,
+                )
+                .into());
+            }
+
+            let merkle_tree = merkletree::MerkleTree::build_tree(transactions, version);
+            let transaction_block = block::TransactionBlock::new(
+                fee,
+                default_info,
+                *merkle_tree.get_root(),
+                Vec::from_iter(transactions.iter().cloned()),
+            )
+            .with_anchors(self.collect_anchors()?);
+
+            let tx_pool = self.tx_pool.read().await;
+            let mut pool_transactions: Vec<&dyn Transactionable> =
+                Vec::with_capacity(transactions.len());
+            let mut seen: HashSet<&Hash> = HashSet::with_capacity(transactions.len());
+            for hash in transactions {
+                if !seen.insert(hash) {
                     return Err(BlockChainTreeError::BlockChainTree(
                         BCTreeErrorKind::CreateMainChainBlock,
-                    )
-                    .into());
+                    ))
+                    .attach_printable(format!("duplicate transaction: {}", hex::encode(hash)));
                 }
+                let transaction = tx_pool
+                    .get(hash)
+                    .ok_or(BlockChainTreeError::BlockChainTree(
+                        BCTreeErrorKind::CreateMainChainBlock,
+                    ))
+                    .attach_printable(format!(
+                        "transaction is not in the pool: {}",
+                        hex::encode(hash)
+                    ))?;
+                pool_transactions.push(transaction.as_ref());
+            }
+            self.apply_transactions(&transaction_block, &pool_transactions, true)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::CreateMainChainBlock,
+                ))?;
 
-                let merkle_tree = merkletree::MerkleTree::build_tree(transactions);
-                let transaction_block = Arc::new(block::TransactionBlock::new(
-                    fee,
-                    default_info,
-                    *merkle_tree.get_root(),
-                    Vec::from_iter(transactions.iter().cloned()),
-                ));
-                transaction_block
-            };
+            Arc::new(transaction_block.with_state_root(self.get_state_root()?))
+        };
 
-        self.main_chain.add_block(new_block.clone())?;
+        if let Err(report) = self.main_chain.add_block(new_block.clone()) {
+            self.revert_height(&new_block.get_info().height)?;
+            return Err(report);
+        }
+        self.tx_pool.write().await.remove_transactions(transactions);
+        self.prune_journal()?;
         Ok(new_block)
     }
 
-    pub fn send_transaction(
+    /// Builds template of the next main chain block from the pooled transactions
+    ///
+    /// Transactions are simulated one after another against the current state,
+    /// the ones that cannot be applied are left in the pool. Fee of the block depends on
+    /// its timestamp, so the highest possible fee is reserved for every transaction
+    pub async fn get_block_template(&self) -> Result<BlockTemplate, Report<BlockChainTreeError>> {
+        let last_block = self.get_last_main_block()?;
+        let previous_hash = last_block
+            .hash()
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToHashBlock,
+            ))?;
+        let height = last_block.get_info().height + 1;
+
+        let mut transactions = Vec::new();
+        if !(height % BLOCKS_PER_EPOCH).is_zero() {
+            let mut tx_pool = self.tx_pool.write().await;
+            tx_pool.expire(tools::current_timestamp());
+            let mut state = StateTransition::new(
+                &self.summary_db,
+                &self.nonces,
+                &self.gas_db,
+                &self.main_chain,
+            );
+            let mut remaining = tx_pool.transactions_by_priority();
+            let mut stale = Vec::new();
+            // transactions of a sender have to go in the order of their nonces,
+            // so the ones skipped for a gap are retried after each pass
+            loop {
+                let mut postponed = Vec::new();
+                let mut progressed = false;
+                for transaction in remaining {
+                    let sender = transaction.get_sender();
+                    let nonce = state.get_nonce(sender)?;
+                    if transaction.get_nonce() < nonce {
+                        stale.push(transaction.hash());
+                        continue;
+                    }
+                    if transaction.get_nonce() > nonce {
+                        postponed.push(transaction);
+                        continue;
+                    }
+                    if state
+                        .try_apply(|state| state.apply_transaction(transaction.as_ref(), *MAX_FEE))
+                        .is_err()
+                    {
+                        continue;
+                    }
+                    transactions.push(transaction.hash());
+                    progressed = true;
+                }
+                if !progressed || postponed.is_empty() {
+                    break;
+                }
+                remaining = postponed;
+            }
+            tx_pool.remove_transactions(&stale);
+            for hash in stale {
+                tx_pool.reject(
+                    hash,
+                    BlockChainTreeError::BlockChainTree(BCTreeErrorKind::WrongNonce).to_string(),
+                );
+            }
+        }
+
+        Ok(BlockTemplate {
+            previous_hash,
+            height,
+            difficulty: last_block.get_info().difficulty,
+            transactions,
+        })
+    }
+
+    /// Receipt of the transaction included into the main chain
+    pub fn get_receipt(
+        &self,
+        transaction_hash: &Hash,
+    ) -> Result<Option<Receipt>, Report<BlockChainTreeError>> {
+        match receipt::open_receipts_tree(&self.summary_db)?
+            .get(transaction_hash)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Receipt,
+            ))? {
+            Some(dump) => Ok(Some(Receipt::parse(&dump)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the transaction is included into the main chain, waits in the pool or was rejected
+    pub async fn get_transaction_status(
+        &self,
+        transaction_hash: &Hash,
+    ) -> Result<TransactionStatus, Report<BlockChainTreeError>> {
+        if let Some(receipt) = self.get_receipt(transaction_hash)? {
+            let block_hash = self
+                .main_chain
+                .find_by_height(&receipt.height)?
+                .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))
+                .attach_printable(format!("receipt height: {}", receipt.height))?
+                .hash()
+                .change_context(BlockChainTreeError::Chain(
+                    ChainErrorKind::FailedToHashBlock,
+                ))?;
+            return Ok(TransactionStatus::Included {
+                block_hash,
+                receipt,
+            });
+        }
+        let tx_pool = self.tx_pool.read().await;
+        if tx_pool.transaction_exists(transaction_hash) {
+            return Ok(TransactionStatus::Pending);
+        }
+        Ok(match tx_pool.get_rejection(transaction_hash) {
+            Some(reason) => TransactionStatus::Rejected {
+                reason: reason.to_string(),
+            },
+            None => TransactionStatus::Unknown,
+        })
+    }
+
+    /// Whole amount paid for the transaction: gas for its bytes and the main chain fee
+    fn transaction_fee(
         &self,
         transaction: &dyn Transactionable,
+    ) -> Result<U256, Report<BlockChainTreeError>> {
+        let last_block = self.get_last_main_block()?;
+        Ok(*BYTE_GAS_PRICE * transaction.get_dump_size()
+            + tools::recalculate_fee(&last_block.get_info().difficulty))
+    }
+
+    /// Validates transaction and puts it into the pool
+    ///
+    /// Signature is verified first. Sender has to be able to pay for the transaction together with
+    /// all of its transactions that are already waiting in the pool and the nonce has to follow them,
+    /// pooled transaction with the same sender and nonce is replaced if the new one pays more.
+    /// The reason of the rejection is remembered by the pool for `get_transaction_status`
+    pub async fn send_transaction(
+        &self,
+        transaction: TransactionableItem,
     ) -> Result<(), Report<BlockChainTreeError>> {
-        let sender_gas_amount = self.get_gas(transaction.get_sender())?;
-        let sender_amount = self.get_amount(transaction.get_sender())?;
-        let amount_of_bytes = transaction.get_dump_size();
-        let gas_required = *BYTE_GAS_PRICE * amount_of_bytes;
-        if sender_gas_amount < gas_required {
+        let hash = transaction.hash();
+        let result = self.pool_transaction(transaction).await;
+        if let Err(report) = result.as_ref() {
+            self.tx_pool
+                .write()
+                .await
+                .reject(hash, tools::report_message(report));
+        }
+        result
+    }
+
+    async fn pool_transaction(
+        &self,
+        transaction: TransactionableItem,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        transaction::verify_transaction(transaction.as_ref()).change_context(
+            BlockChainTreeError::BlockChainTree(BCTreeErrorKind::NewTransaction),
+        )?;
+
+        let last_block = self.get_last_main_block()?;
+        let fee = tools::recalculate_fee(&last_block.get_info().difficulty);
+        let sender = transaction.get_sender();
+        let hash = transaction.hash();
+
+        let mut tx_pool = self.tx_pool.write().await;
+        let now = tools::current_timestamp();
+        tx_pool.expire(now);
+        if tx_pool.is_expired(transaction.get_timestamp(), now) {
+            return Err(BlockChainTreeError::TxPool(TxPoolErrorKind::Expired).into());
+        }
+        if tx_pool.transaction_exists(&hash) || self.main_chain.transaction_exists(&hash)? {
+            return Err(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::NewTransaction,
+            ))
+            .attach_printable("transaction already exists");
+        }
+
+        let replaced = tx_pool
+            .get_by_sender(sender, transaction.get_nonce())
+            .copied();
+        // every pending transaction pays for its bytes and the fee on top of its amount,
+        // the amounts come from the network, so they are summed without overflowing
+        let required = |transaction: &dyn Transactionable| {
+            Some((
+                BYTE_GAS_PRICE.checked_mul(transaction.get_dump_size().into())?,
+                fee.checked_add(transaction.get_amount().unwrap_or_default())?,
+            ))
+        };
+        let (transaction_gas, transaction_amount) = required(transaction.as_ref())
+            .ok_or(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::NewTransaction,
+            ))
+            .attach_printable("transaction amount overflows")?;
+        let mut gas_required = transaction_gas;
+        let mut amount_required = transaction_amount;
+        let mut pending = Vec::new();
+        for pooled in tx_pool.transactions_iter() {
+            if pooled.get_sender() == sender && Some(pooled.hash()) != replaced {
+                (gas_required, amount_required) = required(pooled.as_ref())
+                    .and_then(|(gas, amount)| {
+                        Some((
+                            gas_required.checked_add(gas)?,
+                            amount_required.checked_add(amount)?,
+                        ))
+                    })
+                    .ok_or(BlockChainTreeError::BlockChainTree(
+                        BCTreeErrorKind::NewTransaction,
+                    ))
+                    .attach_printable("pending transactions amount overflows")?;
+                pending.push(pooled.as_ref());
+            }
+        }
+        let pending_count = pending.len() as u64;
+
+        // pooled transactions of the sender take the nonces right after the account one
+        let expected_nonce = self.get_nonce(sender)? + pending_count;
+        if replaced.is_none() && transaction.get_nonce() != expected_nonce {
+            return Err(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::NewTransaction,
+            ))
+            .attach_printable(format!(
+                "wrong nonce: expected {}, got {}",
+                expected_nonce,
+                transaction.get_nonce()
+            ));
+        }
+
+        if self.get_gas(sender)? < gas_required {
             return Err(BlockChainTreeError::BlockChainTree(
                 BCTreeErrorKind::NewTransaction,
             ))
             .attach_printable("not enough gas for the transaction");
         }
-        let last_block = self.main_chain.get_last_block()?.unwrap(); // practically cannot fail
-        let fee = tools::recalculate_fee(&last_block.get_info().difficulty);
-        if sender_amount < fee + transaction.get_amount().unwrap_or(U256::zero()) {
+        if self.get_amount(sender)? < amount_required {
             return Err(BlockChainTreeError::BlockChainTree(
                 BCTreeErrorKind::NewTransaction,
             ))
             .attach_printable("not enough coins to pay the fee");
         }
-        self.main_chain.add_transaction(transaction)?;
-        if let Some(amount) = transaction.get_amount() {
-            // TODO: make into sled transaction
-            self.send_amount(transaction.get_sender(), transaction.get_receiver(), amount)?;
-            //self.sub_amount(transaction.get_sender(), fee)?;
-            self.sub_gas(transaction.get_sender(), gas_required)?;
+
+        // token operations depend on the pending ones, so the whole queue of the sender is simulated
+        pending.push(transaction.as_ref());
+        pending.sort_by_key(|pooled| pooled.get_nonce());
+        let mut state = StateTransition::new(
+            &self.summary_db,
+            &self.nonces,
+            &self.gas_db,
+            &self.main_chain,
+        );
+        for pooled in pending {
+            state
+                .apply_transaction(pooled, *MAX_FEE)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::NewTransaction,
+                ))
+                .attach_printable(format!(
+                    "transaction with nonce {} cannot be applied",
+                    pooled.get_nonce()
+                ))?;
         }
-        Ok(())
+
+        let transaction_fee = fee
+            .checked_add(transaction_gas)
+            .ok_or(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::NewTransaction,
+            ))
+            .attach_printable("transaction fee overflows")?;
+        tx_pool.push(transaction, transaction_fee)
     }
 
     pub async fn flush(&self) -> Result<(), Report<BlockChainTreeError>> {
         self.main_chain.flush().await?;
+        self.header_chain.flush().await?;
+        for derivative_chain in self.derivative_chains.values() {
+            derivative_chain.flush().await?;
+        }
+        self.journal.flush().await?;
         self.summary_db
             .flush_async()
             .await
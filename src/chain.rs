@@ -1,4 +1,10 @@
-use std::{fs::File, io::Read, path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Read,
+    path::Path,
+    sync::Arc,
+};
 
 use async_trait::async_trait;
 use error_stack::{Report, ResultExt};
@@ -10,10 +16,10 @@ use tokio::{fs::OpenOptions, io::AsyncWriteExt};
 use crate::block::{BlockArc, DerivativeBlock};
 use crate::dump_headers::Headers;
 use crate::{
-    block::{self, BasicInfo, Block, SummarizeBlock},
-    errors::{BlockChainTreeError, ChainErrorKind},
-    merkletree::MerkleTree,
-    tools,
+    block::{self, BasicInfo, Block, BlockVersion, SummarizeBlock},
+    errors::{BCTreeErrorKind, BlockChainTreeError, BlockValidationErrorKind, ChainErrorKind},
+    merkletree::{MerkleTree, MerkleVersion},
+    state_tree, tools,
     transaction::Transactionable,
 };
 use crate::{static_values::*, transaction};
@@ -48,11 +54,80 @@ pub trait Chain {
     ) -> Result<Option<Arc<dyn Block + Send + Sync>>, Report<BlockChainTreeError>>;
 }
 
+/// Inception block of the main chain, the same for every node
+pub fn genesis_block() -> BlockArc {
+    let info = BasicInfo::new(
+        INCEPTION_TIMESTAMP,
+        [0; 32],
+        [0u8; 32],
+        U256::zero(),
+        BEGINNING_DIFFICULTY,
+        ROOT_PUBLIC_ADDRESS,
+    );
+    let mut initial_amount = Vec::<u8>::new();
+    initial_amount.extend(ROOT_PUBLIC_ADDRESS.iter());
+    initial_amount.push(b'|');
+    initial_amount.extend(COINS_PER_CYCLE.to_string().as_bytes().iter());
+    initial_amount.push(b'|');
+    initial_amount.push(b'0');
+
+    let merkle_tree = MerkleTree::build_tree(
+        &[tools::hash(&initial_amount)],
+        MerkleVersion::for_height(&U256::zero()),
+    );
+    Arc::new(SummarizeBlock {
+        default_info: info,
+        merkle_tree_root: *merkle_tree.get_root(),
+        // the root funds are set once the chain is created
+        state_root: state_tree::EMPTY_ROOT,
+        // inception block keeps the layout it was created with, so its hash does not change
+        version: BlockVersion::V1,
+    })
+}
+
+/// Proof of inclusion of the transaction into the main chain block
+///
+/// Can be checked against the merkle root of a trusted header without the block body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionProof {
+    pub transaction: [u8; 32],
+    pub block_hash: [u8; 32],
+    pub height: U256,
+    /// Position of the transaction in the block
+    pub index: usize,
+    /// Siblings on the path from the transaction to the merkle root
+    pub path: Vec<[u8; 32]>,
+}
+
+impl TransactionProof {
+    /// Checks that the transaction is included into the block with the `merkle_root`
+    pub fn verify(&self, merkle_root: &[u8; 32]) -> bool {
+        let path: Vec<&[u8; 32]> = self.path.iter().collect();
+        MerkleTree::verify_proof(
+            MerkleVersion::for_height(&self.height),
+            &self.transaction,
+            self.index,
+            merkle_root,
+            &path,
+        )
+    }
+}
+
+/// Transaction of the address history with the height of the block including it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressTransaction {
+    pub transaction: [u8; 32],
+    pub height: U256,
+}
+
 #[derive(Clone)]
 pub struct MainChain {
     blocks: Db,
     height_reference: Db,
+    side_blocks: Db,
     transactions: Db,
+    address_history: sled::Tree,
+    transaction_blocks: sled::Tree,
     height: Arc<RwLock<U256>>,
     difficulty: Arc<RwLock<[u8; 32]>>,
     root: String,
@@ -64,11 +139,13 @@ impl MainChain {
 
         let path_blocks_st = root.join(BLOCKS_FOLDER);
         let path_references_st = root.join(REFERENCES_FOLDER);
+        let path_side_blocks_st = root.join(SIDE_BLOCKS_FOLDER);
         let path_transactions_st = root.join(TRANSACTIONS_FOLDER);
         let path_height_st = root.join(CONFIG_FILE);
 
         let path_blocks = Path::new(&path_blocks_st);
         let path_reference = Path::new(&path_references_st);
+        let path_side_blocks = Path::new(&path_side_blocks_st);
         let path_transactions = Path::new(&path_transactions_st);
         let path_height = Path::new(&path_height_st);
 
@@ -82,11 +159,26 @@ impl MainChain {
             .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
             .attach_printable("failed to open references db")?;
 
+        // open side blocks DB
+        let side_blocks = sled::open(path_side_blocks)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
+            .attach_printable("failed to open side blocks db")?;
+
         // open transactions DB
         let transactions = sled::open(path_transactions)
             .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
             .attach_printable("failed to open transactions db")?;
 
+        let address_history = transactions
+            .open_tree(ADDRESS_HISTORY_TREE)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
+            .attach_printable("failed to open address history tree")?;
+
+        let transaction_blocks = transactions
+            .open_tree(TRANSACTION_BLOCKS_TREE)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
+            .attach_printable("failed to open transaction blocks tree")?;
+
         let file = File::open(path_height);
 
         let (height, difficulty) = if let Ok(mut file) = file {
@@ -109,33 +201,17 @@ impl MainChain {
         let chain = Self {
             blocks,
             height_reference,
+            side_blocks,
             transactions,
+            address_history,
+            transaction_blocks,
             height: Arc::new(RwLock::new(height)),
             difficulty: Arc::new(RwLock::new(difficulty)),
             root: root.to_str().unwrap().to_string(),
         };
         if height.is_zero() {
-            let info = BasicInfo::new(
-                INCEPTION_TIMESTAMP,
-                [0; 32],
-                [0u8; 32],
-                U256::zero(),
-                BEGINNING_DIFFICULTY,
-                ROOT_PUBLIC_ADDRESS,
-            );
-            let mut initial_amount = Vec::<u8>::new();
-            initial_amount.extend(ROOT_PUBLIC_ADDRESS.iter());
-            initial_amount.push(b'|');
-            initial_amount.extend(COINS_PER_CYCLE.to_string().as_bytes().iter());
-            initial_amount.push(b'|');
-            initial_amount.push(b'0');
-
-            let merkle_tree = MerkleTree::build_tree(&[tools::hash(&initial_amount)]);
             chain
-                .add_block(Arc::new(SummarizeBlock {
-                    default_info: info,
-                    merkle_tree_root: *merkle_tree.get_root(),
-                }))
+                .add_block(genesis_block())
                 .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
                 .attach_printable("Failed to insert inception block")?;
         }
@@ -191,6 +267,12 @@ impl MainChain {
             .change_context(BlockChainTreeError::Chain(ChainErrorKind::DumpConfig))
             .attach_printable("failed to flush height references")?;
 
+        self.side_blocks
+            .flush_async()
+            .await
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::DumpConfig))
+            .attach_printable("failed to flush side blocks")?;
+
         self.transactions
             .flush_async()
             .await
@@ -200,10 +282,19 @@ impl MainChain {
         Ok(())
     }
 
+    /// Verifies signatures of the transactions and stores them
     pub fn add_transactions(
         &self,
         transactions: &[impl transaction::Transactionable],
     ) -> Result<(), Report<BlockChainTreeError>> {
+        let to_verify: Vec<&dyn transaction::Transactionable> = transactions
+            .iter()
+            .map(|transaction| transaction as &dyn transaction::Transactionable)
+            .collect();
+        transaction::verify_transactions(&to_verify).change_context(BlockChainTreeError::Chain(
+            ChainErrorKind::AddingTransaction,
+        ))?;
+
         for transaction in transactions {
             let dump = transaction
                 .dump()
@@ -220,10 +311,14 @@ impl MainChain {
         Ok(())
     }
 
+    /// Verifies signature of the transaction and stores it
     pub fn add_transaction(
         &self,
         transaction: &dyn transaction::Transactionable,
     ) -> Result<(), Report<BlockChainTreeError>> {
+        transaction::verify_transaction(transaction).change_context(BlockChainTreeError::Chain(
+            ChainErrorKind::AddingTransaction,
+        ))?;
         let dump = transaction
             .dump()
             .change_context(BlockChainTreeError::Chain(
@@ -258,6 +353,31 @@ impl MainChain {
         Ok(transaction.map(|v| v.to_vec()))
     }
 
+    /// Inserts dumped transaction by its hash or removes it if `dump` is `None`
+    pub fn set_transaction_raw(
+        &self,
+        transaction_hash: &[u8],
+        dump: Option<Vec<u8>>,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        match dump {
+            Some(dump) => self
+                .transactions
+                .insert(transaction_hash, dump)
+                .change_context(BlockChainTreeError::Chain(
+                    ChainErrorKind::AddingTransaction,
+                ))
+                .attach_printable("Failed to insert transaction"),
+            None => self
+                .transactions
+                .remove(transaction_hash)
+                .change_context(BlockChainTreeError::Chain(
+                    ChainErrorKind::FailedToRemoveTransaction,
+                ))
+                .attach_printable("Failed to remove transaction"),
+        }?;
+        Ok(())
+    }
+
     pub fn get_transaction(
         &self,
         transaction_hash: &[u8; 32],
@@ -277,6 +397,20 @@ impl MainChain {
         Ok(None)
     }
 
+    /// Returns stored transaction of any type
+    pub fn get_transactionable(
+        &self,
+        transaction_hash: &[u8; 32],
+    ) -> Result<Option<transaction::TransactionableItem>, Report<BlockChainTreeError>> {
+        match self.get_transaction_raw(transaction_hash)? {
+            Some(tr) => Ok(Some(
+                transaction::parse_transactionable(&tr)
+                    .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHashE))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
     /// Adds new block to the chain db
     ///
     /// Adds block and sets height reference for it
@@ -300,6 +434,8 @@ impl MainChain {
         let mut height_bytes = [0u8; 32];
         height.to_big_endian(&mut height_bytes);
 
+        self.index_transactions(block.as_ref(), &hash, &height_bytes, true)?;
+
         self.blocks
             .insert(height_bytes, dump)
             .change_context(BlockChainTreeError::Chain(ChainErrorKind::AddingBlock))
@@ -315,6 +451,463 @@ impl MainChain {
         Ok(())
     }
 
+    /// Removes the last block of the chain
+    ///
+    /// The removed block is moved to the side blocks, so it can be connected back later
+    pub fn remove_last_block(&self) -> Result<BlockArc, Report<BlockChainTreeError>> {
+        let mut height = self.height.write();
+        if *height <= U256::one() {
+            return Err(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))
+                .attach_printable("the inception block can not be removed");
+        }
+
+        let mut height_bytes = [0u8; 32];
+        (*height - 1).to_big_endian(&mut height_bytes);
+
+        let dump = self
+            .blocks
+            .remove(height_bytes)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?
+            .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))
+            .attach_printable("the last block is missing from blocks db")?;
+
+        let block = block::deserialize_main_chain_block(&dump)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?;
+        let hash = tools::hash(&dump);
+
+        self.index_transactions(block.as_ref(), &hash, &height_bytes, false)?;
+
+        self.height_reference
+            .remove(hash)
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToRemoveHeighReference,
+            ))?;
+
+        self.side_blocks
+            .insert(hash, dump)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::AddingBlock))
+            .attach_printable("Failed to insert block to side blocks db")?;
+
+        *height -= U256::one();
+
+        Ok(block)
+    }
+
+    /// Adds the transactions of the block to the address history and the transaction
+    /// references, or removes them if `insert` is false
+    ///
+    /// Addresses are known only for the stored transactions, so the transactions
+    /// have to be stored before the block is added and removed after it is removed
+    fn index_transactions(
+        &self,
+        block: &dyn Block,
+        block_hash: &[u8; 32],
+        height_bytes: &[u8; 32],
+        insert: bool,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let mut history = sled::Batch::default();
+        let mut references = sled::Batch::default();
+        for transaction_hash in block.transactions().unwrap_or_default() {
+            if let Some(transaction) = self.get_transactionable(transaction_hash)? {
+                for address in [transaction.get_sender(), transaction.get_receiver()] {
+                    let mut key = Vec::with_capacity(97);
+                    key.extend(address);
+                    key.extend(height_bytes);
+                    key.extend(transaction_hash);
+                    if insert {
+                        history.insert(key, &[]);
+                    } else {
+                        history.remove(key);
+                    }
+                }
+            }
+            if insert {
+                references.insert(
+                    &transaction_hash[..],
+                    [&height_bytes[..], block_hash].concat(),
+                );
+            } else {
+                references.remove(&transaction_hash[..]);
+            }
+        }
+        self.address_history
+            .apply_batch(history)
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::TransactionsIndex,
+            ))
+            .attach_printable("failed to update address history")?;
+        self.transaction_blocks
+            .apply_batch(references)
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::TransactionsIndex,
+            ))
+            .attach_printable("failed to update transaction references")
+    }
+
+    /// Transactions of the main chain blocks sent or received by the address, the latest first
+    ///
+    /// Skips `offset` transactions and returns at most `limit` of them
+    pub fn get_address_transactions(
+        &self,
+        address: &[u8; 33],
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<AddressTransaction>, Report<BlockChainTreeError>> {
+        let mut transactions = Vec::new();
+        for item in self
+            .address_history
+            .scan_prefix(address)
+            .rev()
+            .skip(offset)
+            .take(limit)
+        {
+            let (key, _) = item
+                .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindTransaction))
+                .attach_printable("failed to iterate address history")?;
+            if key.len() != 97 {
+                return Err(BlockChainTreeError::Chain(ChainErrorKind::FindTransaction))
+                    .attach_printable("wrong address history key length");
+            }
+            let mut transaction = [0u8; 32];
+            transaction.copy_from_slice(&key[65..]);
+            transactions.push(AddressTransaction {
+                transaction,
+                height: U256::from_big_endian(&key[33..65]),
+            });
+        }
+        Ok(transactions)
+    }
+
+    /// Height and hash of the main chain block containing the transaction
+    pub fn find_transaction_reference(
+        &self,
+        transaction_hash: &[u8; 32],
+    ) -> Result<Option<(U256, [u8; 32])>, Report<BlockChainTreeError>> {
+        let Some(reference) = self
+            .transaction_blocks
+            .get(transaction_hash)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindTransaction))?
+        else {
+            return Ok(None);
+        };
+        if reference.len() != 64 {
+            return Err(BlockChainTreeError::Chain(ChainErrorKind::FindTransaction))
+                .attach_printable("wrong transaction reference length");
+        }
+        let mut block_hash = [0u8; 32];
+        block_hash.copy_from_slice(&reference[32..]);
+        Ok(Some((U256::from_big_endian(&reference[..32]), block_hash)))
+    }
+
+    /// Removes transactions from the transactions tree
+    pub fn remove_transactions(
+        &self,
+        transactions: &[[u8; 32]],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        for transaction in transactions {
+            self.transactions
+                .remove(transaction)
+                .change_context(BlockChainTreeError::Chain(
+                    ChainErrorKind::FailedToRemoveTransaction,
+                ))
+                .attach_printable(format!("transaction: {}", hex::encode(transaction)))?;
+        }
+        Ok(())
+    }
+
+    /// Stores block that does not extend the chain
+    ///
+    /// Side blocks are kept by their hash until they become a part of the heavier branch
+    pub fn add_side_block(
+        &self,
+        block: &BlockArc,
+    ) -> Result<[u8; 32], Report<BlockChainTreeError>> {
+        let dump = block
+            .dump()
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::AddingBlock))?;
+        let hash = tools::hash(&dump);
+
+        self.side_blocks
+            .insert(hash, dump)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::AddingBlock))
+            .attach_printable("Failed to insert block to side blocks db")?;
+
+        Ok(hash)
+    }
+
+    pub fn remove_side_block(&self, hash: &[u8; 32]) -> Result<(), Report<BlockChainTreeError>> {
+        self.side_blocks
+            .remove(hash)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::AddingBlock))
+            .attach_printable("Failed to remove block from side blocks db")?;
+        Ok(())
+    }
+
+    /// Get deserialized side block by it's hash
+    pub fn find_side_by_hash(
+        &self,
+        hash: &[u8; 32],
+    ) -> Result<Option<BlockArc>, Report<BlockChainTreeError>> {
+        let dump = self
+            .side_blocks
+            .get(hash)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHashE))?;
+
+        let deserialized = if let Some(data) = dump {
+            Some(
+                block::deserialize_main_chain_block(&data)
+                    .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHashE))
+                    .attach_printable(format!(
+                        "Failed to deserialize side block with hash {:?}",
+                        hash
+                    ))?,
+            )
+        } else {
+            None
+        };
+
+        Ok(deserialized)
+    }
+
+    /// Get height of the main chain block by it's hash
+    pub fn find_height_by_hash(
+        &self,
+        hash: &[u8; 32],
+    ) -> Result<Option<U256>, Report<BlockChainTreeError>> {
+        Ok(self
+            .height_reference
+            .get(hash)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHashE))?
+            .map(|h| U256::from_big_endian(&h)))
+    }
+
+    /// Collects the side branch ending with the specified block
+    ///
+    /// Returns the height of the main chain block the branch is forked from
+    /// and the blocks of the branch starting from the lowest one
+    pub fn get_side_branch(
+        &self,
+        tip: &[u8; 32],
+    ) -> Result<Option<(U256, Vec<BlockArc>)>, Report<BlockChainTreeError>> {
+        let mut branch: Vec<BlockArc> = Vec::new();
+        let mut hash = *tip;
+        loop {
+            if let Some(height) = self.find_height_by_hash(&hash)? {
+                branch.reverse();
+                return Ok(Some((height, branch)));
+            }
+            if branch.len() > MAX_REORG_DEPTH {
+                return Ok(None);
+            }
+            match self.find_side_by_hash(&hash)? {
+                Some(block) => {
+                    hash = block.get_info().previous_hash;
+                    branch.push(block);
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Validates block received from an untrusted source
+    ///
+    /// Checks header linkage, timestamp, difficulty retarget, block type for the epoch position,
+    /// transactions (presence, duplicates, merkle root), fee and pow against the current last block
+    ///
+    /// Transactions of the block are looked up in `pending` first, then in the chain
+    ///
+    /// Returns an error with `BlockValidationErrorKind` describing the first violated rule
+    pub fn validate_block(
+        &self,
+        block: &BlockArc,
+        pending: &[&dyn Transactionable],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        if block.get_info().height != self.get_height() {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongHeight,
+            ))
+            .attach_printable(format!(
+                "block height: {}, chain height: {}",
+                block.get_info().height,
+                self.get_height()
+            ));
+        }
+
+        let last_block = self
+            .get_last_block()?
+            .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))
+            .attach_printable("failed to get the last block of the chain")?;
+
+        self.validate_block_with_parent(block, &last_block, pending)
+    }
+
+    /// Validates block against the block it references as previous
+    ///
+    /// Same rules as `validate_block`, except the height is checked against the parent
+    pub fn validate_block_with_parent(
+        &self,
+        block: &BlockArc,
+        last_block: &BlockArc,
+        pending: &[&dyn Transactionable],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let info = block.get_info();
+
+        // state root of the new blocks is checked by consensus, so they have to carry it
+        if block.get_version() != BlockVersion::V2 {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongVersion,
+            )
+            .into());
+        }
+
+        if info.height != last_block.get_info().height + 1 {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongHeight,
+            ))
+            .attach_printable(format!(
+                "block height: {}, previous block height: {}",
+                info.height,
+                last_block.get_info().height
+            ));
+        }
+
+        let prev_hash = last_block
+            .hash()
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToHashBlock,
+            ))?;
+
+        if info.previous_hash != prev_hash {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongPreviousHash,
+            )
+            .into());
+        }
+
+        let prev_info = last_block.get_info();
+        if info.timestamp < prev_info.timestamp {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongTimestamp,
+            ))
+            .attach_printable(format!(
+                "block timestamp: {}, previous block timestamp: {}",
+                info.timestamp, prev_info.timestamp
+            ));
+        }
+        if info.timestamp > tools::current_timestamp() + MAX_FUTURE_BLOCK_TIME {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::FutureTimestamp,
+            ))
+            .attach_printable(format!("block timestamp: {}", info.timestamp));
+        }
+
+        let mut difficulty = prev_info.difficulty;
+        tools::recalculate_difficulty(prev_info.timestamp, info.timestamp, &mut difficulty);
+        if info.difficulty != difficulty {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongDifficulty,
+            )
+            .into());
+        }
+
+        let summarize_expected = (info.height % BLOCKS_PER_EPOCH).is_zero();
+        let transactions = match (block.get_type(), summarize_expected) {
+            (Headers::SummarizeBlock, true) => &[][..],
+            (Headers::TransactionBlock, false) => {
+                let transactions = block.transactions().unwrap_or(&[]);
+                self.validate_block_transactions(block, transactions, pending)?;
+                transactions
+            }
+            _ => {
+                return Err(BlockChainTreeError::BlockValidation(
+                    BlockValidationErrorKind::WrongBlockType,
+                ))
+                .attach_printable(format!("summarize block expected: {}", summarize_expected));
+            }
+        };
+
+        if !tools::check_pow(
+            &prev_hash,
+            &prev_info.difficulty,
+            transactions,
+            &info.pow,
+            MerkleVersion::for_height(&info.height),
+        ) {
+            return Err(
+                BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongPow).into(),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn validate_block_transactions(
+        &self,
+        block: &BlockArc,
+        transactions: &[[u8; 32]],
+        pending: &[&dyn Transactionable],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        if transactions.is_empty() {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::EmptyTransactions,
+            )
+            .into());
+        }
+
+        let pending: HashMap<[u8; 32], &dyn Transactionable> = pending
+            .iter()
+            .map(|transaction| (transaction.hash(), *transaction))
+            .collect();
+        let mut unique = HashSet::with_capacity(transactions.len());
+        let mut loaded = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            if !unique.insert(transaction) {
+                return Err(BlockChainTreeError::BlockValidation(
+                    BlockValidationErrorKind::DuplicateTransaction,
+                ))
+                .attach_printable(format!("transaction: {}", hex::encode(transaction)));
+            }
+            if pending.contains_key(transaction) {
+                continue;
+            }
+            loaded.push(
+                self.get_transactionable(transaction)?
+                    .ok_or(BlockChainTreeError::BlockValidation(
+                        BlockValidationErrorKind::MissingTransaction,
+                    ))
+                    .attach_printable(format!("transaction: {}", hex::encode(transaction)))?,
+            );
+        }
+
+        let to_verify: Vec<&dyn Transactionable> = loaded
+            .iter()
+            .map(|transaction| transaction.as_ref() as &dyn Transactionable)
+            .chain(pending.values().copied())
+            .collect();
+        transaction::verify_transactions(&to_verify).change_context(
+            BlockChainTreeError::BlockValidation(BlockValidationErrorKind::InvalidSignature),
+        )?;
+
+        let merkle_tree = MerkleTree::build_tree(
+            transactions,
+            MerkleVersion::for_height(&block.get_info().height),
+        );
+        if block.get_merkle_root() != *merkle_tree.get_root() {
+            return Err(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongMerkleRoot,
+            )
+            .into());
+        }
+
+        if block.get_fee() != tools::recalculate_fee(&block.get_info().difficulty) {
+            return Err(
+                BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongFee).into(),
+            );
+        }
+
+        Ok(())
+    }
+
     /// Get serialized block by it's height
     pub fn find_raw_by_height(
         &self,
@@ -434,6 +1027,244 @@ impl MainChain {
 
         Ok(deserialized)
     }
+
+    /// Finds the main chain block containing the transaction
+    pub fn find_transaction_block(
+        &self,
+        transaction_hash: &[u8; 32],
+    ) -> Result<Option<BlockArc>, Report<BlockChainTreeError>> {
+        match self.find_transaction_reference(transaction_hash)? {
+            Some((height, _)) => self.find_by_height(&height),
+            None => Ok(None),
+        }
+    }
+
+    /// Get proof of inclusion of the transaction into its main chain block
+    ///
+    /// Returns an error if the transaction is not in the chain
+    pub fn get_transaction_proof(
+        &self,
+        transaction_hash: &[u8; 32],
+    ) -> Result<TransactionProof, Report<BlockChainTreeError>> {
+        let block = self
+            .find_transaction_block(transaction_hash)?
+            .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindTransaction))
+            .attach_printable_lazy(|| {
+                format!(
+                    "transaction {} is not in the chain",
+                    hex::encode(transaction_hash)
+                )
+            })?;
+        let block_hash = block.hash().change_context(BlockChainTreeError::Chain(
+            ChainErrorKind::FailedToHashBlock,
+        ))?;
+
+        let height = block.get_info().height;
+        let merkle_tree = MerkleTree::build_tree(
+            block.transactions().unwrap_or_default(),
+            MerkleVersion::for_height(&height),
+        );
+        let index = merkle_tree
+            .get_index(transaction_hash)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindTransaction))?;
+        let mut path: Vec<[u8; 32]> = merkle_tree
+            .get_proof(transaction_hash)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindTransaction))?
+            .into_iter()
+            .copied()
+            .collect();
+        // the last item is the root itself
+        path.pop();
+
+        Ok(TransactionProof {
+            transaction: *transaction_hash,
+            block_hash,
+            height,
+            index,
+            path,
+        })
+    }
+}
+
+/// Tree with the latest anchored block of every derivative chain
+pub fn open_anchors_tree(summary_db: &Db) -> Result<sled::Tree, Report<BlockChainTreeError>> {
+    summary_db
+        .open_tree(ANCHORS_TREE)
+        .change_context(BlockChainTreeError::BlockChainTree(
+            BCTreeErrorKind::GetDerivChain,
+        ))
+        .attach_printable("failed to open anchors tree")
+}
+
+/// Validated headers of the main chain blocks which bodies are not imported yet
+///
+/// Headers are kept by height and continue the last block of the main chain,
+/// so the download of the bodies can be resumed after the restart
+#[derive(Clone)]
+pub struct HeaderChain {
+    headers: Db,
+    hash_reference: sled::Tree,
+}
+
+impl HeaderChain {
+    pub fn new(root: &str) -> Result<Self, Report<BlockChainTreeError>> {
+        let path_headers = Path::new(root).join(HEADERS_DIRECTORY);
+
+        let headers = sled::open(path_headers)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
+            .attach_printable("failed to open headers db")?;
+
+        let hash_reference = headers
+            .open_tree(REFERENCES_FOLDER)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
+            .attach_printable("failed to open headers references tree")?;
+
+        Ok(Self {
+            headers,
+            hash_reference,
+        })
+    }
+
+    pub async fn flush(&self) -> Result<(), Report<BlockChainTreeError>> {
+        self.headers
+            .flush_async()
+            .await
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::DumpConfig))
+            .attach_printable("failed to flush headers db")?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    pub fn insert(&self, header: &block::BlockHeader) -> Result<(), Report<BlockChainTreeError>> {
+        let mut dump = Vec::new();
+        header
+            .dump(&mut dump)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::AddingBlock))?;
+        let mut height = [0u8; 32];
+        header.info.height.to_big_endian(&mut height);
+
+        self.headers
+            .insert(height, dump)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::AddingBlock))
+            .attach_printable("failed to insert header")?;
+        self.hash_reference
+            .insert(header.hash, &height)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::AddingBlock))
+            .attach_printable("failed to insert header reference")?;
+        Ok(())
+    }
+
+    pub fn find_by_height(
+        &self,
+        height: &U256,
+    ) -> Result<Option<block::BlockHeader>, Report<BlockChainTreeError>> {
+        let mut height_serialized = [0u8; 32];
+        height.to_big_endian(&mut height_serialized);
+        self.headers
+            .get(height_serialized)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?
+            .map(|dump| Self::parse(&dump))
+            .transpose()
+    }
+
+    pub fn find_height_by_hash(
+        &self,
+        hash: &[u8; 32],
+    ) -> Result<Option<U256>, Report<BlockChainTreeError>> {
+        Ok(self
+            .hash_reference
+            .get(hash)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHashE))?
+            .map(|height| U256::from_big_endian(&height)))
+    }
+
+    /// Header with the biggest height
+    pub fn get_tip(&self) -> Result<Option<block::BlockHeader>, Report<BlockChainTreeError>> {
+        self.headers
+            .last()
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?
+            .map(|(_, dump)| Self::parse(&dump))
+            .transpose()
+    }
+
+    /// Removes headers starting from the height
+    pub fn remove_from(&self, height: &U256) -> Result<(), Report<BlockChainTreeError>> {
+        let mut height_serialized = [0u8; 32];
+        height.to_big_endian(&mut height_serialized);
+        let removed: Vec<block::BlockHeader> = self
+            .headers
+            .range(height_serialized..)
+            .values()
+            .map(|dump| {
+                Self::parse(
+                    &dump
+                        .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        for header in removed {
+            self.remove(&header)?;
+        }
+        Ok(())
+    }
+
+    /// Removes headers of the imported blocks
+    ///
+    /// All headers are removed if the remaining ones don't continue the main chain
+    pub fn prune(&self, main_chain: &MainChain) -> Result<(), Report<BlockChainTreeError>> {
+        let height = main_chain.get_height();
+        while let Some((_, dump)) = self
+            .headers
+            .first()
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?
+        {
+            let header = Self::parse(&dump)?;
+            if header.info.height >= height {
+                let last_block = main_chain
+                    .get_last_raw_block()?
+                    .ok_or(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))?;
+                if header.info.height != height
+                    || header.info.previous_hash != tools::hash(&last_block)
+                {
+                    self.remove_from(&U256::zero())?;
+                }
+                break;
+            }
+            self.remove(&header)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, header: &block::BlockHeader) -> Result<(), Report<BlockChainTreeError>> {
+        let mut height = [0u8; 32];
+        header.info.height.to_big_endian(&mut height);
+        self.headers
+            .remove(height)
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToRemoveHeighReference,
+            ))
+            .attach_printable("failed to remove header")?;
+        self.hash_reference
+            .remove(header.hash)
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToRemoveHeighReference,
+            ))
+            .attach_printable("failed to remove header reference")?;
+        Ok(())
+    }
+
+    fn parse(dump: &[u8]) -> Result<block::BlockHeader, Report<BlockChainTreeError>> {
+        block::BlockHeader::parse(dump)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHeight))
+            .attach_printable("failed to parse stored header")
+    }
 }
 
 #[derive(Clone)]
@@ -476,6 +1307,7 @@ impl DerivativeChain {
             .attach_printable("failed to open references db")?;
 
         let file = File::open(path_height);
+        let has_config = file.is_ok();
 
         let (height, difficulty, genesis_hash) = if let Ok(mut file) = file {
             let mut height_bytes: [u8; 32] = [0; 32];
@@ -495,11 +1327,10 @@ impl DerivativeChain {
                 .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
                 .attach_printable("failed to read genesis hash")?;
 
-            (
-                U256::from_big_endian(&height_bytes),
-                difficulty,
-                genesis_hash,
-            )
+            // blocks added after the last flush are not reflected in the config
+            let height = U256::from_big_endian(&height_bytes).max(U256::from(blocks.len()));
+
+            (height, difficulty, genesis_hash)
         } else {
             (U256::zero(), BEGINNING_DIFFICULTY, *provided_genesis_hash)
         };
@@ -514,9 +1345,65 @@ impl DerivativeChain {
             root: root.to_str().unwrap().to_string(),
         };
 
+        // genesis hash has to be known when the chain is discovered after restart
+        if !has_config {
+            std::fs::write(path_height, chain.config_bytes())
+                .change_context(BlockChainTreeError::Chain(ChainErrorKind::DumpConfig))
+                .attach_printable("failed to write config")?;
+        }
+
         Ok(chain)
     }
 
+    /// Opens every derivative chain stored in the `DERIVATIVES/` folder of the root
+    ///
+    /// Owner of the chain is the name of its folder in hex, folders with other names
+    /// and without config are skipped
+    pub fn open_all(root: &str) -> Result<Vec<([u8; 33], Self)>, Report<BlockChainTreeError>> {
+        let path = Path::new(root).join(DERIVATIVE_CHAINS_DIRECTORY);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut chains = Vec::new();
+        for entry in std::fs::read_dir(&path)
+            .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
+            .attach_printable("failed to read derivative chains folder")?
+        {
+            let entry = entry
+                .change_context(BlockChainTreeError::Chain(ChainErrorKind::Init))
+                .attach_printable("failed to read derivative chains folder")?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let owner = match hex::decode(name).map(<[u8; 33]>::try_from) {
+                Ok(Ok(owner)) => owner,
+                _ => continue,
+            };
+            if !entry.path().join(CONFIG_FILE).exists() {
+                continue;
+            }
+            chains.push((owner, Self::new(root, name, &[0; 32])?));
+        }
+
+        Ok(chains)
+    }
+
+    pub fn get_genesis_hash(&self) -> [u8; 32] {
+        *self.genesis_hash
+    }
+
+    fn config_bytes(&self) -> Vec<u8> {
+        let mut config = Vec::with_capacity(96);
+        let mut buffer_32_bytes: [u8; 32] = [0; 32];
+        self.height.read().to_big_endian(&mut buffer_32_bytes);
+        config.extend(buffer_32_bytes);
+        config.extend(*self.difficulty.read());
+        config.extend(self.genesis_hash.iter());
+        config
+    }
+
     pub fn get_height(&self) -> U256 {
         *self.height.read()
     }
@@ -534,23 +1421,10 @@ impl DerivativeChain {
             .open(path_config)
             .await
             .change_context(BlockChainTreeError::Chain(ChainErrorKind::DumpConfig))?;
-        let mut buffer_32_bytes: [u8; 32] = [0; 32];
-        self.height.read().to_big_endian(&mut buffer_32_bytes);
-        file.write_all(&buffer_32_bytes)
-            .await
-            .change_context(BlockChainTreeError::Chain(ChainErrorKind::DumpConfig))
-            .attach_printable("failed to write height")?;
-
-        let difficulty = *self.difficulty.read();
-        file.write_all(&difficulty)
-            .await
-            .change_context(BlockChainTreeError::Chain(ChainErrorKind::DumpConfig))
-            .attach_printable("failed to write difficulty")?;
-
-        file.write_all(self.genesis_hash.as_ref())
+        file.write_all(&self.config_bytes())
             .await
             .change_context(BlockChainTreeError::Chain(ChainErrorKind::DumpConfig))
-            .attach_printable("failed to write genesis hash")?;
+            .attach_printable("failed to write config")?;
 
         Ok(())
     }
@@ -0,0 +1,135 @@
+use std::{net::SocketAddr, path::Path};
+
+use error_stack::{Report, ResultExt};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    errors::{ConfigError, ConfigErrorKind},
+    types::Address,
+};
+
+/// Configuration of the node
+///
+/// ```toml
+/// data_dir = "./BlockChainTree"
+/// log_level = "info"
+///
+/// [network]
+/// listen = "0.0.0.0:8333"
+/// peers = ["10.0.0.2:8333"]
+///
+/// [rpc]
+/// listen = "127.0.0.1:8332"
+///
+/// [mining]
+/// founder = "02b28c511fced0ab8ff080867352bc3f92bd0e3b55080b1c89a191d8fb5f5d899f"
+/// threads = 4
+///
+/// [stratum]
+/// listen = "0.0.0.0:3333"
+/// founder = "02b28c511fced0ab8ff080867352bc3f92bd0e3b55080b1c89a191d8fb5f5d899f"
+/// share_difficulty = 16
+/// ```
+///
+/// Rpc server, miner and stratum server are started only if their sections are present
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+    /// Default filter of the logger, `RUST_LOG` takes precedence
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    pub rpc: Option<RpcConfig>,
+    pub mining: Option<MiningConfig>,
+    pub stratum: Option<StratumConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+    #[serde(default = "default_network_listen")]
+    pub listen: SocketAddr,
+    /// Peers to connect to on startup
+    #[serde(default)]
+    pub peers: Vec<SocketAddr>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            listen: default_network_listen(),
+            peers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpcConfig {
+    pub listen: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MiningConfig {
+    /// Address receiving the rewards of the mined blocks, hex encoded
+    #[serde(deserialize_with = "deserialize_address")]
+    pub founder: Address,
+    /// Mining threads, every available core is used if it's not set
+    pub threads: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StratumConfig {
+    pub listen: SocketAddr,
+    /// Address receiving the rewards of the blocks found by the miners, hex encoded
+    #[serde(deserialize_with = "deserialize_address")]
+    pub founder: Address,
+    /// Leading zero bits of the accepted shares
+    #[serde(default = "default_share_difficulty")]
+    pub share_difficulty: u32,
+}
+
+fn default_data_dir() -> String {
+    "./BlockChainTree".into()
+}
+
+fn default_log_level() -> String {
+    "info".into()
+}
+
+fn default_share_difficulty() -> u32 {
+    16
+}
+
+fn default_network_listen() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 8333))
+}
+
+fn deserialize_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+    let string = String::deserialize(deserializer)?;
+    hex::decode(string.strip_prefix("0x").unwrap_or(&string))
+        .ok()
+        .and_then(|address| address.try_into().ok())
+        .ok_or_else(|| serde::de::Error::custom("address should be 33 hex encoded bytes"))
+}
+
+impl NodeConfig {
+    pub fn parse(config: &str) -> Result<Self, Report<ConfigError>> {
+        toml::from_str(config)
+            .change_context(ConfigError::Config(ConfigErrorKind::Parse))
+            .attach_printable("failed to parse the node config")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Report<ConfigError>> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)
+            .change_context(ConfigError::Config(ConfigErrorKind::Read))
+            .attach_printable(format!("failed to read {}", path.display()))?;
+        Self::parse(&config)
+    }
+}
@@ -1,7 +1,15 @@
 use crate::errors::{DumpHeadersError, DumpHeadersErrorKind};
 use error_stack::{Report, Result};
 
+/// Header bytes of the main chain blocks dumped with the state root and the derivative chains anchors
+///
+/// Blocks dumped before them keep `Headers::TransactionBlock` and `Headers::SummarizeBlock` bytes,
+/// both are parsed as the same kinds of blocks, see `block::BlockVersion`
+pub const TRANSACTION_BLOCK_V2: u8 = 6;
+pub const SUMMARIZE_BLOCK_V2: u8 = 7;
+
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Headers {
     Transaction = 0,
     Token = 1,
@@ -19,6 +27,8 @@ impl Headers {
             2 => Ok(Headers::TransactionBlock),
             3 => Ok(Headers::DerivativeBlock),
             4 => Ok(Headers::SummarizeBlock),
+            TRANSACTION_BLOCK_V2 => Ok(Headers::TransactionBlock),
+            SUMMARIZE_BLOCK_V2 => Ok(Headers::SummarizeBlock),
             _ => Err(Report::new(DumpHeadersError::DumpHeadersError(
                 DumpHeadersErrorKind::UknownHeader,
             ))),
@@ -82,11 +82,38 @@ root_errors![
     BlockChainTreeError : "Error ocurred while operating on the blockchain tree" {
         Chain(ChainErrorKind),
         DerivativeChain(DerivChainErrorKind),
-        BlockChainTree(BCTreeErrorKind)
+        BlockChainTree(BCTreeErrorKind),
+        BlockValidation(BlockValidationErrorKind),
+        TxPool(TxPoolErrorKind),
+        Token(TokenErrorKind)
     },
 
     DumpHeadersError : "Error with dump header"{
         DumpHeadersError(DumpHeadersErrorKind)
+    },
+
+    NetworkError : "Error ocurred while operating on the network" {
+        Network(NetworkErrorKind)
+    },
+
+    RpcError : "Error ocurred while operating on the rpc server" {
+        Rpc(RpcErrorKind)
+    },
+
+    ConfigError : "Error ocurred while loading the config" {
+        Config(ConfigErrorKind)
+    },
+
+    WalletError : "Error ocurred while operating on the wallet" {
+        Wallet(WalletErrorKind)
+    },
+
+    StratumError : "Error ocurred while operating on the stratum server" {
+        Stratum(StratumErrorKind)
+    },
+
+    LightClientError : "Error ocurred while operating on the light client" {
+        LightClient(LightClientErrorKind)
     }
 ];
 
@@ -112,7 +139,8 @@ sub_errors![
         Parse: "failed to parse transaction"
     },
     MerkleTreeErrorKind {
-        GettingProof: "failed to get proof"
+        GettingProof: "failed to get proof",
+        ItemNotFound: "item is not in the tree"
     },
     BasicInfoErrorKind {
         Dump: "failed to dump basic info",
@@ -149,7 +177,97 @@ sub_errors![
         FailedToVerify: "failed to verify block",
         FailedToHashBlock: "failed to hash block",
         FailedToRemoveHeighReference: "failed to remove height reference",
-        FailedToRemoveTransaction: "failed to remove transaction"
+        FailedToRemoveTransaction: "failed to remove transaction",
+        TransactionsIndex: "failed to update transactions index"
+    },
+    BlockValidationErrorKind {
+        WrongHeight: "block height does not follow the chain height",
+        WrongPreviousHash: "previous hash does not match the last block of the chain",
+        WrongTimestamp: "block timestamp is earlier than the previous block timestamp",
+        WrongDifficulty: "block difficulty does not follow the retarget rule",
+        WrongPow: "pow does not satisfy the previous block difficulty",
+        WrongBlockType: "block type does not match its position in the epoch",
+        EmptyTransactions: "transaction block has no transactions",
+        DuplicateTransaction: "transaction is referenced more than once",
+        MissingTransaction: "referenced transaction is not present in the transactions tree",
+        WrongMerkleRoot: "merkle root does not match the transactions",
+        WrongFee: "block fee does not match the difficulty",
+        WrongSummary: "summarize block root does not match the summary database",
+        WrongStateRoot: "state root does not match the state after the block",
+        UnknownParent: "previous block is not known",
+        InsufficientFunds: "sender can not pay for the transaction",
+        WrongNonce: "transaction nonce is not the next one of the sender",
+        InvalidSignature: "transaction signature is not valid",
+        WrongTokenOperation: "token operation of the transaction can not be applied",
+        WrongAnchor: "anchor does not follow a known block of the derivative chain",
+        FutureTimestamp: "block timestamp is too far in the future",
+        WrongVersion: "block is dumped in the layout without the state root"
+    },
+    TxPoolErrorKind {
+        AlreadyExists: "transaction is already in the pool",
+        Underpriced: "replacement does not pay more per byte than the pooled transaction",
+        PoolFull: "pool is full of transactions paying more per byte",
+        TooLarge: "transaction is larger than the pool",
+        Expired: "transaction is too old"
+    },
+    TokenErrorKind {
+        OpenTree: "failed to open token tree",
+        Dump: "failed to dump token info",
+        Parse: "failed to parse token info",
+        UnknownToken: "token is not issued",
+        AlreadyIssued: "token is already issued",
+        WrongTokenId: "token id does not match the issuer and nonce",
+        InsufficientBalance: "token balance is not enough",
+        Overflow: "token amount overflow"
+    },
+    NetworkErrorKind {
+        Bind: "failed to bind the listener",
+        Connect: "failed to connect to the peer",
+        Read: "failed to read message",
+        Write: "failed to write message",
+        TooLarge: "message is larger than allowed",
+        Dump: "failed to dump message",
+        Parse: "failed to parse message",
+        Handshake: "handshake failed",
+        WrongGenesis: "peer has another genesis block",
+        Tree: "failed to operate on the blockchain tree"
+    },
+    RpcErrorKind {
+        Bind: "failed to bind the listener",
+        Read: "failed to read request",
+        Write: "failed to write response",
+        TooLarge: "request is larger than allowed",
+        BadRequest: "malformed http request"
+    },
+    ConfigErrorKind {
+        Read: "failed to read the config file",
+        Parse: "failed to parse the config"
+    },
+    StratumErrorKind {
+        Bind: "failed to bind the listener",
+        Read: "failed to read the message",
+        Write: "failed to write the message",
+        TooLarge: "message is larger than allowed",
+        Tree: "failed to operate on the blockchain tree"
+    },
+    LightClientErrorKind {
+        Storage: "failed to operate on the headers storage",
+        InvalidHeader: "header does not follow the previous one",
+        UnknownParent: "headers do not continue any stored header",
+        Reorganize: "reorganisation is too deep",
+        Rpc: "failed to request the full node",
+        WrongResponse: "full node sent a malformed response",
+        WrongProof: "proof does not match the stored headers"
+    },
+    WalletErrorKind {
+        Read: "failed to read the keystore",
+        Write: "failed to write the keystore",
+        Parse: "failed to parse the keystore",
+        KeyNotFound: "key is not found",
+        KeyExists: "key with the same name already exists",
+        WrongKey: "wrong private key",
+        WrongPassword: "wrong password",
+        Encryption: "failed to encrypt the key"
     },
     DerivChainErrorKind {
         Init: "failed to create a new derivative chain",
@@ -175,6 +293,12 @@ sub_errors![
         NewTransaction: "failed to create new transaction",
         CreateMainChainBlock: "failed to create new block for the main chain",
         WrongPow: "supplied pow does not satisfy requirements",
-        SummarizeBlockWrongTransactionsAmount: "summarization block should not have transactions"
+        SummarizeBlockWrongTransactionsAmount: "summarization block should not have transactions",
+        GetNonce: "failed to get nonce",
+        WrongNonce: "nonce is not the next one of the account",
+        Journal: "failed to operate on the undo journal",
+        Reorganize: "failed to reorganize the main chain",
+        StateTree: "failed to operate on the state tree",
+        Receipt: "failed to operate on transaction receipts"
     }
 ];
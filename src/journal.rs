@@ -0,0 +1,367 @@
+use std::path::Path;
+
+use error_stack::{Report, ResultExt};
+use primitive_types::U256;
+use sled::Db;
+
+use crate::{
+    errors::{BCTreeErrorKind, BlockChainTreeError},
+    static_values::UNDO_FOLDER,
+};
+
+// undo entries are keyed by 32 bytes height prefix starting with zero byte,
+// so the pending record and the index never fall into their ranges
+static PENDING_KEY: &[u8] = b"PENDING";
+// index of the undo entries by the changed key, followed by the db, the key and the journal key
+static INDEX_PREFIX: u8 = b'I';
+
+/// Database the journaled value belongs to
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JournalDb {
+    Summary = 0,
+    Gas = 1,
+    Transactions = 2,
+    Nonces = 3,
+    /// Token infos, keyed by token id
+    Tokens = 4,
+    /// Token balances, keyed by token id followed by the owner
+    TokenBalances = 5,
+    /// Latest anchored derivative blocks, keyed by the chain owner
+    Anchors = 6,
+    /// Receipts of the included transactions, keyed by the transaction hash
+    Receipts = 7,
+}
+
+impl JournalDb {
+    pub fn from_u8(tag: u8) -> Result<JournalDb, Report<BlockChainTreeError>> {
+        match tag {
+            0 => Ok(JournalDb::Summary),
+            1 => Ok(JournalDb::Gas),
+            2 => Ok(JournalDb::Transactions),
+            3 => Ok(JournalDb::Nonces),
+            4 => Ok(JournalDb::Tokens),
+            5 => Ok(JournalDb::TokenBalances),
+            6 => Ok(JournalDb::Anchors),
+            7 => Ok(JournalDb::Receipts),
+            _ => Err(Report::new(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))
+            .attach_printable(format!("unknown journal db tag: {}", tag))),
+        }
+    }
+}
+
+/// Value of the key in one of the state databases
+///
+/// For undo entries the value is the one before the change, for redo entries the one after
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub db: JournalDb,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+impl JournalEntry {
+    pub fn get_dump_size(&self) -> usize {
+        2 + self.key.len() + 1 + 4 + self.value.as_ref().map_or(0, |v| v.len())
+    }
+
+    pub fn dump(&self, buffer: &mut Vec<u8>) {
+        buffer.push(self.db as u8);
+        buffer.push(self.key.len() as u8);
+        buffer.extend(self.key.iter());
+        if let Some(value) = self.value.as_ref() {
+            buffer.push(1);
+            buffer.extend((value.len() as u32).to_be_bytes());
+            buffer.extend(value.iter());
+        } else {
+            buffer.push(0);
+        }
+    }
+
+    /// Parses entry, returns it with the amount of bytes read
+    pub fn parse(data: &[u8]) -> Result<(JournalEntry, usize), Report<BlockChainTreeError>> {
+        if data.len() < 3 || data.len() < 3 + data[1] as usize {
+            return Err(Report::new(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))
+            .attach_printable("journal entry is too short"));
+        }
+        let db = JournalDb::from_u8(data[0])?;
+        let mut index = 2 + data[1] as usize;
+        let key = data[2..index].to_vec();
+        let has_value = data[index] == 1;
+        index += 1;
+
+        let value = if has_value {
+            if data.len() < index + 4 {
+                return Err(Report::new(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::Journal,
+                ))
+                .attach_printable("journal entry value length is missing"));
+            }
+            let length =
+                u32::from_be_bytes(unsafe { data[index..index + 4].try_into().unwrap_unchecked() })
+                    as usize;
+            index += 4;
+            if data.len() < index + length {
+                return Err(Report::new(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::Journal,
+                ))
+                .attach_printable("journal entry value is too short"));
+            }
+            let value = data[index..index + length].to_vec();
+            index += length;
+            Some(value)
+        } else {
+            None
+        };
+
+        Ok((JournalEntry { db, key, value }, index))
+    }
+}
+
+/// Prefix of the index keys of the undo entries changing the `key` of the `db`
+fn index_prefix(db: JournalDb, key: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(3 + key.len());
+    prefix.push(INDEX_PREFIX);
+    prefix.push(db as u8);
+    prefix.push(key.len() as u8);
+    prefix.extend(key.iter());
+    prefix
+}
+
+fn index_key(entry: &JournalEntry, journal_key: &[u8]) -> Vec<u8> {
+    let mut key = index_prefix(entry.db, &entry.key);
+    key.extend(journal_key.iter());
+    key
+}
+
+fn dump_entries(entries: &[JournalEntry]) -> Vec<u8> {
+    let mut dump = Vec::with_capacity(entries.iter().map(|e| e.get_dump_size()).sum());
+    for entry in entries {
+        entry.dump(&mut dump);
+    }
+    dump
+}
+
+fn parse_entries(mut data: &[u8]) -> Result<Vec<JournalEntry>, Report<BlockChainTreeError>> {
+    let mut entries = Vec::new();
+    while !data.is_empty() {
+        let (entry, size) = JournalEntry::parse(data)?;
+        entries.push(entry);
+        data = &data[size..];
+    }
+    Ok(entries)
+}
+
+/// Write-ahead journal of the state databases
+///
+/// Every change of the summary, nonces, tokens and gas databases made by a block is recorded
+/// with the height of the block, so the state can be rolled back block by block during reorganisation
+///
+/// Changes spanning several databases are first stored as a pending record,
+/// which is replayed on startup if the node stopped before all of them were applied
+///
+/// Undo entries are also indexed by the key they change, so the entries of a key
+/// are found with a prefix scan
+pub struct Journal {
+    db: Db,
+}
+
+impl Journal {
+    pub fn new(root_folder: &str) -> Result<Self, Report<BlockChainTreeError>> {
+        let db = sled::open(Path::new(root_folder).join(UNDO_FOLDER))
+            .change_context(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::Init))
+            .attach_printable("failed to open undo journal db")?;
+
+        Ok(Self { db })
+    }
+
+    fn height_prefix(height: &U256) -> [u8; 32] {
+        let mut prefix = [0u8; 32];
+        height.to_big_endian(&mut prefix);
+        prefix
+    }
+
+    /// Records undo entries for the specified height and redo entries as pending
+    pub fn begin_transition(
+        &self,
+        height: &U256,
+        undo: &[JournalEntry],
+        redo: &[JournalEntry],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let prefix = Self::height_prefix(height);
+        let mut batch = sled::Batch::default();
+        for entry in undo {
+            let id = self
+                .db
+                .generate_id()
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::Journal,
+                ))?;
+            let mut key = Vec::with_capacity(40);
+            key.extend(prefix);
+            key.extend(id.to_be_bytes());
+            let mut dump = Vec::with_capacity(entry.get_dump_size());
+            entry.dump(&mut dump);
+            batch.insert(index_key(entry, &key), &[]);
+            batch.insert(key, dump);
+        }
+        batch.insert(PENDING_KEY, dump_entries(redo));
+
+        self.db
+            .apply_batch(batch)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))
+            .attach_printable("failed to record journal entries")
+    }
+
+    /// Records redo entries of a change made outside of blocks as pending,
+    /// together with the undo entries it rebased
+    ///
+    /// `rebased` holds the journal keys of the replaced undo entries, as returned by `find_undo`
+    pub fn begin_change(
+        &self,
+        redo: &[JournalEntry],
+        rebased: &[(Vec<u8>, JournalEntry)],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let mut batch = sled::Batch::default();
+        for (key, entry) in rebased {
+            let mut dump = Vec::with_capacity(entry.get_dump_size());
+            entry.dump(&mut dump);
+            batch.insert(key.as_slice(), dump);
+        }
+        batch.insert(PENDING_KEY, dump_entries(redo));
+
+        self.db
+            .apply_batch(batch)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))
+            .attach_printable("failed to record journal entries")
+    }
+
+    /// Returns undo entries of the key of all heights with their journal keys
+    pub fn find_undo(
+        &self,
+        db: JournalDb,
+        key: &[u8],
+    ) -> Result<Vec<(Vec<u8>, JournalEntry)>, Report<BlockChainTreeError>> {
+        let prefix = index_prefix(db, key);
+        let mut entries = Vec::new();
+        for res in self.db.scan_prefix(&prefix) {
+            let (index_key, _) = res.change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))?;
+            let journal_key = &index_key[prefix.len()..];
+            let value = self
+                .db
+                .get(journal_key)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::Journal,
+                ))?
+                .ok_or(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::Journal,
+                ))
+                .attach_printable("indexed undo entry is missing")?;
+            entries.push((journal_key.to_vec(), JournalEntry::parse(&value)?.0));
+        }
+        Ok(entries)
+    }
+
+    /// Returns undo entries of the specified height, latest entry first
+    pub fn get_undo(
+        &self,
+        height: &U256,
+    ) -> Result<Vec<JournalEntry>, Report<BlockChainTreeError>> {
+        let mut entries = Vec::new();
+        for res in self.db.scan_prefix(Self::height_prefix(height)).rev() {
+            let (_, value) = res.change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))?;
+            entries.push(JournalEntry::parse(&value)?.0);
+        }
+        Ok(entries)
+    }
+
+    /// Removes undo entries of the specified height and records redo entries as pending
+    pub fn begin_revert(
+        &self,
+        height: &U256,
+        redo: &[JournalEntry],
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let mut batch = sled::Batch::default();
+        for res in self.db.scan_prefix(Self::height_prefix(height)) {
+            let (key, value) = res.change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))?;
+            batch.remove(index_key(&JournalEntry::parse(&value)?.0, &key));
+            batch.remove(key);
+        }
+        batch.insert(PENDING_KEY, dump_entries(redo));
+
+        self.db
+            .apply_batch(batch)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))
+            .attach_printable("failed to remove journal entries")
+    }
+
+    /// Marks pending entries as applied
+    pub fn finish(&self) -> Result<(), Report<BlockChainTreeError>> {
+        self.db
+            .remove(PENDING_KEY)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))
+            .attach_printable("failed to remove pending entries")?;
+        Ok(())
+    }
+
+    /// Returns entries that were not applied completely
+    pub fn get_pending(&self) -> Result<Option<Vec<JournalEntry>>, Report<BlockChainTreeError>> {
+        match self
+            .db
+            .get(PENDING_KEY)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))? {
+            Some(dump) => Ok(Some(parse_entries(&dump)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes undo entries of all heights lower than the specified one
+    pub fn prune(&self, height: &U256) -> Result<(), Report<BlockChainTreeError>> {
+        let bound = Self::height_prefix(height);
+        let mut batch = sled::Batch::default();
+        for res in self.db.range(..bound) {
+            let (key, value) = res.change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))?;
+            batch.remove(index_key(&JournalEntry::parse(&value)?.0, &key));
+            batch.remove(key);
+        }
+
+        self.db
+            .apply_batch(batch)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Journal,
+            ))
+            .attach_printable("failed to prune journal")
+    }
+
+    pub async fn flush(&self) -> Result<(), Report<BlockChainTreeError>> {
+        self.db
+            .flush_async()
+            .await
+            .change_context(BlockChainTreeError::BlockChainTree(BCTreeErrorKind::DumpDb))
+            .attach_printable("failed to flush undo journal")?;
+
+        Ok(())
+    }
+}
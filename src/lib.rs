@@ -2,14 +2,27 @@
 pub mod block;
 pub mod blockchaintree;
 pub mod chain;
+pub mod config;
 pub mod dump_headers;
 pub mod errors;
+pub mod journal;
+pub mod light;
 pub mod merkletree;
+pub mod miner;
+pub mod network;
+pub mod receipt;
+pub mod rpc;
+pub mod state;
+pub mod state_tree;
 pub mod static_values;
+pub mod stratum;
 pub mod summary_db;
+pub mod sync;
+pub mod token;
 pub mod tools;
 pub mod transaction;
 pub mod txpool;
 pub mod types;
+pub mod wallet;
 
 pub use primitive_types;
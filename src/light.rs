@@ -0,0 +1,308 @@
+use error_stack::{Report, ResultExt};
+use primitive_types::U256;
+use serde_json::{json, Value};
+
+use crate::{
+    block::BlockHeader,
+    chain::{self, HeaderChain, TransactionProof},
+    errors::{LightClientError, LightClientErrorKind},
+    rpc::RpcClient,
+    state_tree::AccountProof,
+    static_values::{MAX_HEADERS_PER_MESSAGE, MAX_REORG_DEPTH},
+    tools,
+    types::Hash,
+};
+
+/// Client following the main chain by the headers of its blocks
+///
+/// Headers are checked with the same rules as during the header-first sync and the chain
+/// with the most work is followed, block bodies are never downloaded. Transactions and
+/// accounts are checked with the proofs served by a full node against the stored headers
+pub struct LightClient {
+    headers: HeaderChain,
+}
+
+impl LightClient {
+    pub fn new(root_folder: &str) -> Result<Self, Report<LightClientError>> {
+        let headers = HeaderChain::new(root_folder)
+            .change_context(LightClientError::LightClient(LightClientErrorKind::Storage))?;
+        if headers.is_empty() {
+            let genesis = BlockHeader::from_block(&chain::genesis_block())
+                .change_context(LightClientError::LightClient(LightClientErrorKind::Storage))?;
+            headers
+                .insert(&genesis)
+                .change_context(LightClientError::LightClient(LightClientErrorKind::Storage))
+                .attach_printable("failed to insert genesis header")?;
+        }
+        Ok(Self { headers })
+    }
+
+    /// Header of the best known block
+    pub fn get_tip(&self) -> Result<BlockHeader, Report<LightClientError>> {
+        self.headers
+            .get_tip()
+            .change_context(LightClientError::LightClient(LightClientErrorKind::Storage))?
+            .ok_or(LightClientError::LightClient(LightClientErrorKind::Storage))
+            .attach_printable("genesis header is missing")
+    }
+
+    /// Amount of the stored headers, same as the height of the main chain
+    pub fn get_height(&self) -> Result<U256, Report<LightClientError>> {
+        Ok(self.get_tip()?.info.height + 1)
+    }
+
+    pub fn find_by_height(
+        &self,
+        height: &U256,
+    ) -> Result<Option<BlockHeader>, Report<LightClientError>> {
+        self.headers
+            .find_by_height(height)
+            .change_context(LightClientError::LightClient(LightClientErrorKind::Storage))
+    }
+
+    fn find_by_hash(&self, hash: &Hash) -> Result<Option<BlockHeader>, Report<LightClientError>> {
+        match self
+            .headers
+            .find_height_by_hash(hash)
+            .change_context(LightClientError::LightClient(LightClientErrorKind::Storage))?
+        {
+            Some(height) => self.find_by_height(&height),
+            None => Ok(None),
+        }
+    }
+
+    /// Adds consecutive headers following one of the stored headers
+    ///
+    /// Headers forking from the stored chain replace it only if they have more work.
+    /// Returns whether the headers became the best chain
+    pub fn add_headers(&self, headers: &[BlockHeader]) -> Result<bool, Report<LightClientError>> {
+        let Some(first) = headers.first() else {
+            return Ok(false);
+        };
+        let parent = self
+            .find_by_hash(&first.info.previous_hash)?
+            .ok_or(LightClientError::LightClient(
+                LightClientErrorKind::UnknownParent,
+            ))
+            .attach_printable(format!(
+                "previous hash: {}",
+                hex::encode(first.info.previous_hash)
+            ))?;
+
+        let mut previous = &parent;
+        let mut branch_work = U256::zero();
+        for header in headers {
+            header
+                .validate(previous)
+                .change_context(LightClientError::LightClient(
+                    LightClientErrorKind::InvalidHeader,
+                ))
+                .attach_printable(format!("height: {}", header.info.height))?;
+            branch_work =
+                branch_work.saturating_add(tools::calculate_work(&previous.info.difficulty));
+            previous = header;
+        }
+
+        let tip = self.get_tip()?;
+        let depth = tip.info.height - parent.info.height;
+        if depth > U256::from(MAX_REORG_DEPTH) {
+            return Err(Report::new(LightClientError::LightClient(
+                LightClientErrorKind::Reorganize,
+            ))
+            .attach_printable(format!("reorganisation depth: {}", depth)));
+        }
+
+        let mut stored_work = U256::zero();
+        let mut parent_difficulty = parent.info.difficulty;
+        let mut height = parent.info.height + 1;
+        while height <= tip.info.height {
+            let header = self
+                .find_by_height(&height)?
+                .ok_or(LightClientError::LightClient(LightClientErrorKind::Storage))?;
+            stored_work = stored_work.saturating_add(tools::calculate_work(&parent_difficulty));
+            parent_difficulty = header.info.difficulty;
+            height += U256::one();
+        }
+        if branch_work <= stored_work {
+            return Ok(false);
+        }
+
+        self.headers
+            .remove_from(&(parent.info.height + 1))
+            .change_context(LightClientError::LightClient(LightClientErrorKind::Storage))?;
+        for header in headers {
+            self.headers
+                .insert(header)
+                .change_context(LightClientError::LightClient(LightClientErrorKind::Storage))?;
+        }
+        Ok(true)
+    }
+
+    /// Checks that the transaction is included into the stored block
+    pub fn verify_transaction(
+        &self,
+        proof: &TransactionProof,
+    ) -> Result<bool, Report<LightClientError>> {
+        Ok(match self.find_by_height(&proof.height)? {
+            Some(header) => header.hash == proof.block_hash && proof.verify(&header.merkle_root),
+            None => false,
+        })
+    }
+
+    /// Checks the account state after the stored block with the `height`
+    pub fn verify_account(
+        &self,
+        proof: &AccountProof,
+        height: &U256,
+    ) -> Result<bool, Report<LightClientError>> {
+        Ok(match self.find_by_height(height)? {
+            Some(header) => proof.verify(&header.state_root),
+            None => false,
+        })
+    }
+
+    /// Downloads headers of the node's main chain
+    ///
+    /// If the node follows another branch, headers are requested again
+    /// from the deepest height a reorganisation can start at
+    pub async fn sync(&self, client: &RpcClient) -> Result<(), Report<LightClientError>> {
+        let mut from = self.get_height()?;
+        let mut stepped_back = false;
+        loop {
+            let headers = request_headers(client, &from).await?;
+            let Some(first) = headers.first() else {
+                return Ok(());
+            };
+            if self.find_by_hash(&first.info.previous_hash)?.is_none() && !stepped_back {
+                stepped_back = true;
+                from = from
+                    .saturating_sub(U256::from(MAX_REORG_DEPTH))
+                    .max(U256::one());
+                continue;
+            }
+            if !self.add_headers(&headers)? || headers.len() < MAX_HEADERS_PER_MESSAGE {
+                return Ok(());
+            }
+            from = self.get_height()?;
+        }
+    }
+
+    /// Requests the inclusion proof of the transaction from the node,
+    /// returns `None` if the node does not know the transaction
+    ///
+    /// The proof has to match the stored headers, so the headers should be synced first
+    pub async fn get_transaction_proof(
+        &self,
+        client: &RpcClient,
+        hash: &Hash,
+    ) -> Result<Option<TransactionProof>, Report<LightClientError>> {
+        let result = call(client, "get_transaction_proof", json!([hex::encode(hash)])).await?;
+        if result.is_null() {
+            return Ok(None);
+        }
+        let proof = parse_transaction_proof(&result)
+            .attach_printable("failed to parse transaction proof")?;
+        if proof.transaction != *hash || !self.verify_transaction(&proof)? {
+            return Err(Report::new(LightClientError::LightClient(
+                LightClientErrorKind::WrongProof,
+            ))
+            .attach_printable(format!("transaction: {}", hex::encode(hash))));
+        }
+        Ok(Some(proof))
+    }
+
+    pub async fn flush(&self) -> Result<(), Report<LightClientError>> {
+        self.headers
+            .flush()
+            .await
+            .change_context(LightClientError::LightClient(LightClientErrorKind::Storage))
+    }
+}
+
+async fn call(
+    client: &RpcClient,
+    method: &str,
+    params: Value,
+) -> Result<Value, Report<LightClientError>> {
+    client
+        .call(method, params)
+        .await
+        .change_context(LightClientError::LightClient(LightClientErrorKind::Rpc))?
+        .map_err(|error| {
+            Report::new(LightClientError::LightClient(LightClientErrorKind::Rpc))
+                .attach_printable(format!("{}: {}", method, error.message))
+        })
+}
+
+/// Headers of the node's main chain starting from the `height`
+async fn request_headers(
+    client: &RpcClient,
+    height: &U256,
+) -> Result<Vec<BlockHeader>, Report<LightClientError>> {
+    let result = call(client, "get_headers", json!([height.to_string()])).await?;
+    let dumps = result
+        .as_array()
+        .ok_or(LightClientError::LightClient(
+            LightClientErrorKind::WrongResponse,
+        ))
+        .attach_printable("headers should be an array")?;
+    let mut headers = Vec::with_capacity(dumps.len());
+    for dump in dumps {
+        headers.push(
+            BlockHeader::parse(&parse_hex(dump)?)
+                .change_context(LightClientError::LightClient(
+                    LightClientErrorKind::WrongResponse,
+                ))
+                .attach_printable("failed to parse header")?,
+        );
+    }
+    Ok(headers)
+}
+
+fn parse_hex(value: &Value) -> Result<Vec<u8>, Report<LightClientError>> {
+    value
+        .as_str()
+        .and_then(|value| hex::decode(value).ok())
+        .ok_or(LightClientError::LightClient(
+            LightClientErrorKind::WrongResponse,
+        ))
+        .attach_printable(format!("not a hex string: {}", value))
+}
+
+fn parse_hash(value: &Value) -> Result<Hash, Report<LightClientError>> {
+    Hash::try_from(parse_hex(value)?)
+        .map_err(|_| {
+            Report::new(LightClientError::LightClient(
+                LightClientErrorKind::WrongResponse,
+            ))
+        })
+        .attach_printable("wrong hash length")
+}
+
+fn parse_transaction_proof(value: &Value) -> Result<TransactionProof, Report<LightClientError>> {
+    let wrong_response = || {
+        Report::new(LightClientError::LightClient(
+            LightClientErrorKind::WrongResponse,
+        ))
+    };
+    Ok(TransactionProof {
+        transaction: parse_hash(&value["transaction"])?,
+        block_hash: parse_hash(&value["block_hash"])?,
+        height: value["height"]
+            .as_str()
+            .and_then(|height| U256::from_dec_str(height).ok())
+            .ok_or_else(wrong_response)
+            .attach_printable("wrong height")?,
+        index: value["index"]
+            .as_u64()
+            .ok_or_else(wrong_response)
+            .attach_printable("wrong index")? as usize,
+        path: value["path"]
+            .as_array()
+            .ok_or_else(wrong_response)
+            .attach_printable("path should be an array")?
+            .iter()
+            .map(parse_hash)
+            .collect::<Result<_, _>>()?,
+    })
+}
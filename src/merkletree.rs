@@ -1,17 +1,79 @@
+use error_stack::{Report, ResultExt};
+use primitive_types::U256;
 use sha2::Digest;
 use sha2::Sha256;
-use std::{convert::TryInto, sync::Arc};
+use std::sync::Arc;
+
+use crate::errors::{MerkleTreeError, MerkleTreeErrorKind};
+use crate::static_values::MERKLE_V2_HEIGHT;
 
 static PADDING_HASH: [u8; 32] = *b"\xff\xff\xff\xff\xff\xff\xff\xff\
                                 \xff\xff\xff\xff\xff\xff\xff\xff\
                                 \xff\xff\xff\xff\xff\xff\xff\xff\
                                 \xff\xff\xff\xff\xff\xff\xff\xff";
 
+static LEAF_PREFIX: u8 = 0x00;
+static NODE_PREFIX: u8 = 0x01;
+
+/// Construction of the merkle tree
+///
+/// `V1` hashes the bytewise AND of the children, so many pairs of children give the same parent
+/// and the proofs can be forged. `V2` hashes the leaves and the concatenated children with
+/// different prefixes, so a leaf can't be passed for a node and the order of the children matters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MerkleVersion {
+    V1,
+    V2,
+}
+
+impl MerkleVersion {
+    /// Version of the trees of the main chain block with the `height`
+    pub fn for_height(height: &U256) -> Self {
+        if *height >= U256::from(MERKLE_V2_HEIGHT) {
+            MerkleVersion::V2
+        } else {
+            MerkleVersion::V1
+        }
+    }
+
+    fn leaf(&self, item: &[u8; 32]) -> [u8; 32] {
+        match self {
+            MerkleVersion::V1 => *item,
+            MerkleVersion::V2 => {
+                let mut hasher = Sha256::new();
+                hasher.update([LEAF_PREFIX]);
+                hasher.update(item);
+                hasher.finalize().into()
+            }
+        }
+    }
+
+    fn node(&self, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        match self {
+            MerkleVersion::V1 => {
+                let mut to_hash = [0u8; 32];
+                for ((byte, left), right) in to_hash.iter_mut().zip(left).zip(right) {
+                    *byte = left & right;
+                }
+                Sha256::digest(to_hash).into()
+            }
+            MerkleVersion::V2 => {
+                let mut hasher = Sha256::new();
+                hasher.update([NODE_PREFIX]);
+                hasher.update(left);
+                hasher.update(right);
+                hasher.finalize().into()
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MerkleTree {
     array_representation: Arc<[[u8; 32]]>,
     depth: usize,
     initial_amount_of_inputs: usize,
+    version: MerkleVersion,
 }
 
 pub fn find_closest_power_of_2(number: usize) -> usize {
@@ -25,7 +87,7 @@ pub fn find_closest_power_of_2(number: usize) -> usize {
 }
 
 impl MerkleTree {
-    pub fn build_tree(items: &[[u8; 32]]) -> MerkleTree {
+    pub fn build_tree(items: &[[u8; 32]], version: MerkleVersion) -> MerkleTree {
         let closest_power_2 = find_closest_power_of_2(items.len());
         let depth = closest_power_2 + 1;
         let leaves_amount = 2usize.pow(closest_power_2 as u32);
@@ -34,61 +96,56 @@ impl MerkleTree {
         let mut array_representation = vec![PADDING_HASH; nodes_total];
 
         for (item, leaf_index) in items.iter().zip(nodes_total - leaves_amount..nodes_total) {
-            unsafe {
-                *(array_representation.get_unchecked_mut(leaf_index)) = *item;
-            }
-        }
-
-        for index in nodes_total - (leaves_amount - items.len())..nodes_total {
-            unsafe {
-                *(array_representation.get_unchecked_mut(index)) = PADDING_HASH;
-            }
+            array_representation[leaf_index] = version.leaf(item);
         }
 
         for left_index in (1..nodes_total - 1).step_by(2).rev() {
-            let mut to_hash = [0u8; 32];
-            unsafe {
-                for (index, (left, right)) in array_representation
-                    .get_unchecked(left_index)
-                    .iter()
-                    .zip(array_representation.get_unchecked(left_index + 1).iter())
-                    .enumerate()
-                {
-                    *to_hash.get_unchecked_mut(index) = *left & *right;
-                }
-
-                let hash = Sha256::digest(to_hash);
-
-                *(array_representation.get_unchecked_mut((left_index - 1) / 2)) =
-                    hash.as_slice().try_into().unwrap_unchecked();
-            }
+            array_representation[(left_index - 1) / 2] = version.node(
+                &array_representation[left_index],
+                &array_representation[left_index + 1],
+            );
         }
 
         MerkleTree {
             array_representation: Arc::<[[u8; 32]]>::from(array_representation),
             depth,
             initial_amount_of_inputs: items.len(),
+            version,
         }
     }
 
-    pub fn get_proof<'a>(&'a self, hash: &[u8; 32]) -> Vec<&'a [u8; 32]> {
+    /// Get position of the `hash` among the items of the tree
+    ///
+    /// Returns an error if the `hash` is not an item of the tree
+    pub fn get_index(&self, hash: &[u8; 32]) -> Result<usize, Report<MerkleTreeError>> {
+        let leaves_amount = 2usize.pow((self.depth - 1) as u32);
+        let leaf = self.version.leaf(hash);
+        self.array_representation[self.array_representation.len() - leaves_amount..]
+            .iter()
+            .take(self.initial_amount_of_inputs)
+            .position(|item| *item == leaf)
+            .ok_or(MerkleTreeError::TreeError(
+                MerkleTreeErrorKind::ItemNotFound,
+            ))
+            .attach_printable_lazy(|| format!("item: {}", hex::encode(hash)))
+    }
+
+    /// Get proof of inclusion of the `hash` into the tree
+    ///
+    /// The proof consists of the siblings on the path from the leaf to the root followed by the root
+    ///
+    /// Returns an error if the `hash` is not a leaf of the tree
+    pub fn get_proof<'a>(
+        &'a self,
+        hash: &[u8; 32],
+    ) -> Result<Vec<&'a [u8; 32]>, Report<MerkleTreeError>> {
         let mut to_return = vec![&[0u8; 32]; self.depth];
 
         let mut to_return_index: usize = 0;
 
         let leaves_amount = 2usize.pow((self.depth - 1) as u32);
-
-        let mut index: isize = 0;
-        for rel_index in self.array_representation.len() - leaves_amount
-            ..self.array_representation.len() - (leaves_amount - self.initial_amount_of_inputs)
-        {
-            index = rel_index as isize;
-            unsafe {
-                if self.array_representation.get_unchecked(rel_index) == hash {
-                    break;
-                }
-            }
-        }
+        let mut index =
+            (self.array_representation.len() - leaves_amount + self.get_index(hash)?) as isize;
 
         while index > 0 {
             let lsb_set = index & 1;
@@ -111,37 +168,36 @@ impl MerkleTree {
                 self.array_representation.get_unchecked(0);
         }
 
-        to_return
+        Ok(to_return)
     }
 
-    pub fn verify_proof(hash: &[u8; 32], root: &[u8; 32], proof: &[&[u8; 32]]) -> bool {
-        let mut hasher = Sha256::new();
-        let mut calculated_root: [u8; 32] = [0; 32];
-
-        for (n, i) in calculated_root.iter_mut().enumerate() {
-            *i = hash[n] & proof[0][n];
+    /// Verifies proof of inclusion of the `hash` into the tree with the `root`
+    ///
+    /// `proof` is the proof returned by `get_proof` without the root, `index` is the position
+    /// of the `hash` among the items of the tree
+    pub fn verify_proof(
+        version: MerkleVersion,
+        hash: &[u8; 32],
+        index: usize,
+        root: &[u8; 32],
+        proof: &[&[u8; 32]],
+    ) -> bool {
+        if proof.is_empty() || proof.len() >= usize::BITS as usize || index >> proof.len() != 0 {
+            return false;
         }
-        hasher.update(calculated_root);
-        calculated_root = unsafe { hasher.finalize().as_slice().try_into().unwrap_unchecked() };
-
-        for idx in proof.iter().skip(1) {
-            let mut hasher = Sha256::new();
 
-            for (n, item) in calculated_root.iter_mut().enumerate() {
-                *item &= *unsafe { idx.get_unchecked(n) };
-            }
-            hasher.update(calculated_root);
-            calculated_root = unsafe { hasher.finalize().as_slice().try_into().unwrap_unchecked() };
+        let mut calculated_root = version.leaf(hash);
+        let mut index = index;
+        for sibling in proof {
+            calculated_root = if index & 1 == 0 {
+                version.node(&calculated_root, sibling)
+            } else {
+                version.node(sibling, &calculated_root)
+            };
+            index >>= 1;
         }
 
-        for i in 0..32 {
-            unsafe {
-                if root.get_unchecked(i) != calculated_root.get_unchecked(i) {
-                    return false;
-                }
-            }
-        }
-        true
+        calculated_root == *root
     }
 
     pub fn get_root(&self) -> &[u8; 32] {
@@ -151,30 +207,40 @@ impl MerkleTree {
 
 #[cfg(test)]
 mod tests {
-    use super::MerkleTree;
+    use super::{MerkleTree, MerkleVersion};
     use rand::Rng;
     use std::time::Instant;
 
     #[test]
     fn merkle_tree_test() {
         let mut rng = rand::thread_rng();
-        let data: &Vec<[u8; 32]> = &vec![[rng.gen(); 32]; 10000];
+        let mut data: Vec<[u8; 32]> = vec![[rng.gen(); 32]; 10000];
+        data[4321] = [1u8; 32];
         let start = Instant::now();
-        let tree = MerkleTree::build_tree(data);
+        let tree = MerkleTree::build_tree(&data, MerkleVersion::V1);
         let duration = start.elapsed();
 
         println!("Time for building: {:?}", duration);
 
-        let proof = tree.get_proof(&[1u8; 32]);
+        let proof = tree.get_proof(&[1u8; 32]).unwrap();
 
         let root = tree.get_root();
 
-        let valid = MerkleTree::verify_proof(&[1u8; 32], root, &proof[0..proof.len() - 1]);
+        let valid = MerkleTree::verify_proof(
+            MerkleVersion::V1,
+            &[1u8; 32],
+            4321,
+            root,
+            &proof[0..proof.len() - 1],
+        );
 
         println!("Root: {:?}", root);
 
         println!("Proof valid: {:?}", valid);
 
         println!("Proof: {:?}", proof);
+
+        assert!(valid);
+        assert!(tree.get_proof(&[2u8; 32]).is_err());
     }
 }
@@ -0,0 +1,247 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use error_stack::ResultExt;
+use parking_lot::Mutex;
+use primitive_types::U256;
+
+use crate::{
+    blockchaintree::{BlockTemplate, SharedBlockChainTree},
+    errors::{BlockChainTreeError, ChainErrorKind},
+    merkletree::{MerkleTree, MerkleVersion},
+    network::Node,
+    static_values::{BLOCKS_PER_EPOCH, HASHES_PER_CHECK, MINER_TIP_CHECK},
+    tools,
+    types::{Address, Hash},
+};
+
+/// Work for the miner, everything except the pow is fixed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MiningJob {
+    pub previous_hash: Hash,
+    pub height: U256,
+    pub difficulty: Hash,
+    /// Root of the merkle tree of `transactions`, built once for the whole job
+    pub merkle_root: Hash,
+    pub transactions: Vec<Hash>,
+}
+
+impl MiningJob {
+    pub fn new(template: BlockTemplate) -> Self {
+        let merkle_root = *MerkleTree::build_tree(
+            &template.transactions,
+            MerkleVersion::for_height(&template.height),
+        )
+        .get_root();
+        Self {
+            previous_hash: template.previous_hash,
+            height: template.height,
+            difficulty: template.difficulty,
+            merkle_root,
+            transactions: template.transactions,
+        }
+    }
+
+    pub fn check(&self, pow: &[u8]) -> bool {
+        tools::check_pow_with_root(
+            &self.previous_hash,
+            &self.difficulty,
+            &self.merkle_root,
+            pow,
+        )
+    }
+}
+
+impl From<BlockTemplate> for MiningJob {
+    fn from(template: BlockTemplate) -> Self {
+        Self::new(template)
+    }
+}
+
+/// Outcome of the single `Miner::mine` call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MiningResult {
+    /// Found pow, `None` if the mining was cancelled
+    pub pow: Option<Hash>,
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl MiningResult {
+    /// Hashes per second
+    pub fn hashrate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.hashes as f64 / seconds
+    }
+}
+
+/// Multithreaded CPU miner
+///
+/// Every worker thread gets its own part of the nonce space: the index of the worker
+/// is put in the first 8 bytes of the pow and the counter in the last 8 bytes
+#[derive(Debug)]
+pub struct Miner {
+    threads: usize,
+    hashes: AtomicU64,
+}
+
+impl Miner {
+    /// `threads` is at least 1
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            hashes: AtomicU64::new(0),
+        }
+    }
+
+    /// Miner with a thread for every available core
+    pub fn with_available_threads() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, |threads| threads.get()))
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Total amount of the hashes computed by the miner, can be read while it's mining
+    pub fn hashes(&self) -> u64 {
+        self.hashes.load(Ordering::Relaxed)
+    }
+
+    /// Searches for the pow of the job until it's found or `cancel` is set
+    ///
+    /// Blocks the current thread
+    pub fn mine(&self, job: &MiningJob, cancel: &AtomicBool) -> MiningResult {
+        let started = Instant::now();
+        let found: Mutex<Option<Hash>> = Mutex::new(None);
+        let done = AtomicBool::new(false);
+        let hashes = AtomicU64::new(0);
+
+        std::thread::scope(|scope| {
+            for worker in 0..self.threads {
+                let (found, done, hashes) = (&found, &done, &hashes);
+                scope.spawn(move || {
+                    let mut pow: Hash = [0; 32];
+                    pow[..8].copy_from_slice(&(worker as u64).to_be_bytes());
+                    let mut counter = 0u64;
+                    while !done.load(Ordering::Relaxed) && !cancel.load(Ordering::Relaxed) {
+                        for checked in 0..HASHES_PER_CHECK {
+                            pow[24..].copy_from_slice(&counter.to_be_bytes());
+                            counter = counter.wrapping_add(1);
+                            if job.check(&pow) {
+                                hashes.fetch_add(checked + 1, Ordering::Relaxed);
+                                self.hashes.fetch_add(checked + 1, Ordering::Relaxed);
+                                found.lock().get_or_insert(pow);
+                                done.store(true, Ordering::Relaxed);
+                                return;
+                            }
+                        }
+                        hashes.fetch_add(HASHES_PER_CHECK, Ordering::Relaxed);
+                        self.hashes.fetch_add(HASHES_PER_CHECK, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        MiningResult {
+            pow: found.into_inner(),
+            hashes: hashes.into_inner(),
+            elapsed: started.elapsed(),
+        }
+    }
+}
+
+/// Mines blocks on top of the main chain until `stop` is set
+///
+/// The current job is cancelled as soon as another block is added to the chain.
+/// Mined blocks are announced to the peers of the `node` if it's set
+pub async fn mine_blocks(
+    miner: Arc<Miner>,
+    tree: SharedBlockChainTree,
+    node: Option<Arc<Node>>,
+    founder: Address,
+    stop: Arc<AtomicBool>,
+) {
+    while !stop.load(Ordering::Relaxed) {
+        let template = match tree.read().await.get_block_template().await {
+            Ok(template) => template,
+            Err(report) => {
+                log::error!("Failed to get the block template: {:?}", report);
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                continue;
+            }
+        };
+        // only summarize blocks are allowed to be empty
+        if !(template.height % BLOCKS_PER_EPOCH).is_zero() && template.transactions.is_empty() {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        let job = MiningJob::new(template);
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let main_chain = tree.read().await.get_main_chain();
+        let watcher = {
+            let (cancel, stop, height) = (cancel.clone(), stop.clone(), job.height);
+            tokio::spawn(async move {
+                while !stop.load(Ordering::Relaxed) && main_chain.get_height() == height {
+                    tokio::time::sleep(Duration::from_millis(MINER_TIP_CHECK)).await;
+                }
+                cancel.store(true, Ordering::Relaxed);
+            })
+        };
+        let result = {
+            let (miner, job) = (miner.clone(), job.clone());
+            tokio::task::spawn_blocking(move || miner.mine(&job, &cancel)).await
+        };
+        watcher.abort();
+
+        let Ok(result) = result else {
+            continue;
+        };
+        let Some(pow) = result.pow else {
+            continue;
+        };
+
+        let block = tree
+            .write()
+            .await
+            .emmit_new_main_block(
+                &pow,
+                &founder,
+                &job.transactions,
+                tools::current_timestamp(),
+            )
+            .await;
+        let hash = block.and_then(|block| {
+            block.hash().change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToHashBlock,
+            ))
+        });
+        let hash = match hash {
+            Ok(hash) => hash,
+            Err(report) => {
+                log::warn!("Mined block is rejected: {:?}", report);
+                continue;
+            }
+        };
+        log::info!(
+            "Mined block {} at height {}, {:.0} H/s",
+            hex::encode(hash),
+            job.height,
+            result.hashrate()
+        );
+        if let Some(node) = &node {
+            if let Err(report) = node.announce_block(&hash).await {
+                log::warn!("Failed to announce the block: {:?}", report);
+            }
+        }
+    }
+}
@@ -0,0 +1,1181 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    block::{self, BlockArc, BlockHeader},
+    blockchaintree::SharedBlockChainTree,
+    chain::{HeaderChain, MainChain},
+    dump_headers::Headers,
+    errors::{
+        BCTreeErrorKind, BlockChainTreeError, BlockValidationErrorKind, ChainErrorKind,
+        NetworkError, NetworkErrorKind,
+    },
+    static_values::{
+        HANDSHAKE_TIMEOUT, MAX_BLOCKS_INVENTORY, MAX_HEADERS_PER_MESSAGE, MAX_MESSAGE_SIZE,
+        NETWORK_VERSION, SYNC_TICK,
+    },
+    sync::{BlockDownloader, DownloadedBlock},
+    tools,
+    transaction::{self, Transactionable, TransactionableItem},
+    types::Hash,
+};
+use error_stack::{Report, ResultExt};
+use parking_lot::Mutex;
+use primitive_types::U256;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+/// Amount of the latest blocks put into the locator one by one
+static LOCATOR_DENSE_BLOCKS: u64 = 10;
+
+/// Block or transaction announced to the peer
+///
+/// `kind` is the dump header of the announced object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InventoryItem {
+    pub kind: Headers,
+    pub hash: Hash,
+}
+
+impl InventoryItem {
+    pub fn is_block(&self) -> bool {
+        matches!(
+            self.kind,
+            Headers::TransactionBlock | Headers::SummarizeBlock
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// First message sent by both sides of the connection
+    Handshake {
+        version: u32,
+        genesis_hash: Hash,
+        last_hash: Hash,
+        height: U256,
+    },
+    /// Announces blocks and transactions the sender has
+    Inventory(Vec<InventoryItem>),
+    /// Asks for the hashes of the blocks following the first known hash of the locator
+    GetBlocks(Vec<Hash>),
+    /// Asks for the announced blocks and transactions
+    GetData(Vec<InventoryItem>),
+    /// Requested objects the sender doesn't have
+    NotFound(Vec<InventoryItem>),
+    /// Dumped main chain block together with the dumps of its transactions
+    Block {
+        block: Vec<u8>,
+        transactions: Vec<Vec<u8>>,
+    },
+    /// Dumped transaction
+    Transaction(Vec<u8>),
+    /// Asks for the headers of the blocks following the first known hash of the locator
+    GetHeaders(Vec<Hash>),
+    /// Headers of the consecutive main chain blocks
+    Headers(Vec<BlockHeader>),
+}
+
+impl Message {
+    fn message_type(&self) -> u8 {
+        match self {
+            Message::Handshake { .. } => 0,
+            Message::Inventory(_) => 1,
+            Message::GetBlocks(_) => 2,
+            Message::GetData(_) => 3,
+            Message::NotFound(_) => 4,
+            Message::Block { .. } => 5,
+            Message::Transaction(_) => 6,
+            Message::GetHeaders(_) => 7,
+            Message::Headers(_) => 8,
+        }
+    }
+
+    /// Dumps message without the length prefix
+    pub fn dump(&self) -> Result<Vec<u8>, Report<NetworkError>> {
+        let mut buffer = vec![self.message_type()];
+        match self {
+            Message::Handshake {
+                version,
+                genesis_hash,
+                last_hash,
+                height,
+            } => {
+                buffer.extend(version.to_be_bytes());
+                buffer.extend(genesis_hash);
+                buffer.extend(last_hash);
+                tools::dump_u256(height, &mut buffer)
+                    .change_context(NetworkError::Network(NetworkErrorKind::Dump))
+                    .attach_printable("failed to dump height")?;
+            }
+            Message::Inventory(items) | Message::GetData(items) | Message::NotFound(items) => {
+                dump_length(items.len(), &mut buffer)?;
+                for item in items {
+                    buffer.push(item.kind as u8);
+                    buffer.extend(item.hash);
+                }
+            }
+            Message::GetBlocks(locator) | Message::GetHeaders(locator) => {
+                dump_length(locator.len(), &mut buffer)?;
+                for hash in locator {
+                    buffer.extend(hash);
+                }
+            }
+            Message::Block {
+                block,
+                transactions,
+            } => {
+                dump_length(block.len(), &mut buffer)?;
+                buffer.extend(block);
+                dump_length(transactions.len(), &mut buffer)?;
+                for transaction in transactions {
+                    dump_length(transaction.len(), &mut buffer)?;
+                    buffer.extend(transaction);
+                }
+            }
+            Message::Transaction(dump) => buffer.extend(dump),
+            Message::Headers(headers) => {
+                dump_length(headers.len(), &mut buffer)?;
+                for header in headers {
+                    let mut dump = Vec::new();
+                    header
+                        .dump(&mut dump)
+                        .change_context(NetworkError::Network(NetworkErrorKind::Dump))?;
+                    dump_length(dump.len(), &mut buffer)?;
+                    buffer.extend(dump);
+                }
+            }
+        }
+        Ok(buffer)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Report<NetworkError>> {
+        let (message_type, mut data) = data
+            .split_first()
+            .ok_or(NetworkError::Network(NetworkErrorKind::Parse))
+            .attach_printable("empty message")?;
+
+        let message = match message_type {
+            0 => {
+                let version = parse_u32(&mut data)?;
+                let genesis_hash = parse_hash(&mut data)?;
+                let last_hash = parse_hash(&mut data)?;
+                // height is dumped with `tools::dump_u256`
+                let height_size = take(&mut data, 1)?[0] as usize;
+                if height_size > 32 {
+                    return Err(Report::new(NetworkError::Network(NetworkErrorKind::Parse))
+                        .attach_printable("failed to parse height"));
+                }
+                let height = U256::from_big_endian(take(&mut data, height_size)?);
+                Message::Handshake {
+                    version,
+                    genesis_hash,
+                    last_hash,
+                    height,
+                }
+            }
+            1..=4 | 7 => {
+                let amount = parse_u32(&mut data)? as usize;
+                if *message_type == 2 || *message_type == 7 {
+                    let mut locator = Vec::with_capacity(amount.min(data.len() / 32));
+                    for _ in 0..amount {
+                        locator.push(parse_hash(&mut data)?);
+                    }
+                    if *message_type == 2 {
+                        Message::GetBlocks(locator)
+                    } else {
+                        Message::GetHeaders(locator)
+                    }
+                } else {
+                    let mut items = Vec::with_capacity(amount.min(data.len() / 33));
+                    for _ in 0..amount {
+                        let kind = Headers::from_u8(take(&mut data, 1)?[0])
+                            .change_context(NetworkError::Network(NetworkErrorKind::Parse))?;
+                        let hash = parse_hash(&mut data)?;
+                        items.push(InventoryItem { kind, hash });
+                    }
+                    match message_type {
+                        1 => Message::Inventory(items),
+                        3 => Message::GetData(items),
+                        _ => Message::NotFound(items),
+                    }
+                }
+            }
+            5 => {
+                let block_size = parse_u32(&mut data)? as usize;
+                let block = take(&mut data, block_size)?.to_vec();
+                let amount = parse_u32(&mut data)? as usize;
+                let mut transactions = Vec::with_capacity(amount.min(data.len() / 4));
+                for _ in 0..amount {
+                    let size = parse_u32(&mut data)? as usize;
+                    transactions.push(take(&mut data, size)?.to_vec());
+                }
+                Message::Block {
+                    block,
+                    transactions,
+                }
+            }
+            6 => {
+                let dump = data.to_vec();
+                data = &[];
+                Message::Transaction(dump)
+            }
+            8 => {
+                let amount = parse_u32(&mut data)? as usize;
+                let mut headers = Vec::with_capacity(amount.min(data.len() / 4));
+                for _ in 0..amount {
+                    let size = parse_u32(&mut data)? as usize;
+                    headers.push(
+                        BlockHeader::parse(take(&mut data, size)?)
+                            .change_context(NetworkError::Network(NetworkErrorKind::Parse))?,
+                    );
+                }
+                Message::Headers(headers)
+            }
+            _ => {
+                return Err(Report::new(NetworkError::Network(NetworkErrorKind::Parse))
+                    .attach_printable(format!("unknown message type {}", message_type)));
+            }
+        };
+
+        if !data.is_empty() {
+            return Err(Report::new(NetworkError::Network(NetworkErrorKind::Parse))
+                .attach_printable("trailing data after the message"));
+        }
+
+        Ok(message)
+    }
+}
+
+fn dump_length(length: usize, buffer: &mut Vec<u8>) -> Result<(), Report<NetworkError>> {
+    let length =
+        u32::try_from(length).change_context(NetworkError::Network(NetworkErrorKind::TooLarge))?;
+    buffer.extend(length.to_be_bytes());
+    Ok(())
+}
+
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8], Report<NetworkError>> {
+    if data.len() < length {
+        return Err(Report::new(NetworkError::Network(NetworkErrorKind::Parse))
+            .attach_printable("message is too short"));
+    }
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+fn parse_u32(data: &mut &[u8]) -> Result<u32, Report<NetworkError>> {
+    Ok(u32::from_be_bytes(take(data, 4)?.try_into().unwrap()))
+}
+
+fn parse_hash(data: &mut &[u8]) -> Result<Hash, Report<NetworkError>> {
+    Ok(take(data, 32)?.try_into().unwrap())
+}
+
+/// Reads length prefixed message
+pub async fn read_message<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Message, Report<NetworkError>> {
+    let length = reader
+        .read_u32()
+        .await
+        .change_context(NetworkError::Network(NetworkErrorKind::Read))? as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(
+            Report::new(NetworkError::Network(NetworkErrorKind::TooLarge))
+                .attach_printable(format!("message of {} bytes", length)),
+        );
+    }
+
+    let mut data = vec![0u8; length];
+    reader
+        .read_exact(&mut data)
+        .await
+        .change_context(NetworkError::Network(NetworkErrorKind::Read))?;
+
+    Message::parse(&data)
+}
+
+/// Writes message with the length prefix
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+) -> Result<(), Report<NetworkError>> {
+    let dump = message.dump()?;
+    if dump.len() > MAX_MESSAGE_SIZE {
+        return Err(
+            Report::new(NetworkError::Network(NetworkErrorKind::TooLarge))
+                .attach_printable(format!("message of {} bytes", dump.len())),
+        );
+    }
+
+    let mut buffer = Vec::with_capacity(4 + dump.len());
+    buffer.extend((dump.len() as u32).to_be_bytes());
+    buffer.extend(dump);
+    writer
+        .write_all(&buffer)
+        .await
+        .change_context(NetworkError::Network(NetworkErrorKind::Write))?;
+    writer
+        .flush()
+        .await
+        .change_context(NetworkError::Network(NetworkErrorKind::Write))
+}
+
+/// Connected peer as seen by the node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub id: u64,
+    pub address: SocketAddr,
+    pub height: U256,
+}
+
+struct Peer {
+    address: SocketAddr,
+    height: U256,
+    sender: mpsc::UnboundedSender<Message>,
+    /// Last block requested during the sync, next part of the chain is asked after it's received
+    sync_target: Option<Hash>,
+}
+
+/// Peer to peer node
+///
+/// Keeps connections with the peers, answers their requests and imports
+/// blocks and transactions they announce into the shared tree.
+///
+/// Peers with the longer chain are synchronized header-first: headers are validated
+/// and stored in the `HeaderChain`, then the bodies are downloaded from every peer
+/// that has them and imported in order
+pub struct Node {
+    tree: SharedBlockChainTree,
+    header_chain: HeaderChain,
+    downloader: Mutex<BlockDownloader>,
+    importing: tokio::sync::Mutex<()>,
+    genesis_hash: Hash,
+    local_address: SocketAddr,
+    peers: Mutex<HashMap<u64, Peer>>,
+    next_peer_id: AtomicU64,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Node {
+    /// Starts listening for the incoming connections
+    pub async fn bind(
+        tree: SharedBlockChainTree,
+        address: SocketAddr,
+    ) -> Result<Arc<Self>, Report<NetworkError>> {
+        let listener = TcpListener::bind(address)
+            .await
+            .change_context(NetworkError::Network(NetworkErrorKind::Bind))
+            .attach_printable(format!("failed to bind {}", address))?;
+        let local_address = listener
+            .local_addr()
+            .change_context(NetworkError::Network(NetworkErrorKind::Bind))?;
+
+        let (main_chain, header_chain) = {
+            let tree = tree.read().await;
+            (tree.get_main_chain(), tree.get_header_chain())
+        };
+        // headers of the interrupted sync are kept if they still continue the chain
+        header_chain
+            .prune(&main_chain)
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?;
+        let genesis_hash = {
+            let genesis = main_chain
+                .find_raw_by_height(&U256::zero())
+                .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                .ok_or(NetworkError::Network(NetworkErrorKind::Tree))
+                .attach_printable("genesis block is missing")?;
+            tools::hash(&genesis)
+        };
+
+        let node = Arc::new(Self {
+            tree,
+            header_chain,
+            downloader: Mutex::new(BlockDownloader::new()),
+            importing: tokio::sync::Mutex::new(()),
+            genesis_hash,
+            local_address,
+            peers: Mutex::new(HashMap::new()),
+            next_peer_id: AtomicU64::new(0),
+            tasks: Mutex::new(Vec::new()),
+        });
+
+        let accepting = node.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                let (stream, address) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(error) => {
+                        log::warn!("Failed to accept connection: {}", error);
+                        continue;
+                    }
+                };
+                let node = accepting.clone();
+                tokio::spawn(async move {
+                    if let Err(report) = node.start_peer(stream, address).await {
+                        log::debug!("Connection with {} is dropped: {:?}", address, report);
+                    }
+                });
+            }
+        });
+        node.tasks.lock().push(handle);
+
+        // lost body requests are sent again
+        let ticking = node.clone();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(SYNC_TICK));
+            loop {
+                interval.tick().await;
+                if let Err(report) = ticking.schedule_bodies().await {
+                    log::warn!("Failed to schedule block bodies: {:?}", report);
+                }
+            }
+        });
+        node.tasks.lock().push(handle);
+
+        Ok(node)
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    pub fn get_tree(&self) -> SharedBlockChainTree {
+        self.tree.clone()
+    }
+
+    /// Connects to the peer and performs the handshake
+    ///
+    /// Returns id of the connected peer
+    pub async fn connect(
+        self: &Arc<Self>,
+        address: SocketAddr,
+    ) -> Result<u64, Report<NetworkError>> {
+        let stream = TcpStream::connect(address)
+            .await
+            .change_context(NetworkError::Network(NetworkErrorKind::Connect))
+            .attach_printable(format!("failed to connect to {}", address))?;
+        self.clone().start_peer(stream, address).await
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let mut peers: Vec<PeerInfo> = self
+            .peers
+            .lock()
+            .iter()
+            .map(|(id, peer)| PeerInfo {
+                id: *id,
+                address: peer.address,
+                height: peer.height,
+            })
+            .collect();
+        peers.sort_by_key(|peer| peer.id);
+        peers
+    }
+
+    /// Announces block of the main chain to every peer
+    pub async fn announce_block(&self, hash: &Hash) -> Result<(), Report<NetworkError>> {
+        let main_chain = self.tree.read().await.get_main_chain();
+        let dump = main_chain
+            .find_raw_by_hash(hash)
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+            .ok_or(NetworkError::Network(NetworkErrorKind::Tree))
+            .attach_printable("announced block is not in the main chain")?;
+        let kind = Headers::from_u8(dump[0])
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?;
+        self.broadcast(
+            Message::Inventory(vec![InventoryItem { kind, hash: *hash }]),
+            None,
+        );
+        Ok(())
+    }
+
+    /// Puts transaction into the pool and announces it to every peer
+    pub async fn send_transaction(
+        &self,
+        transaction: TransactionableItem,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let item = transaction_item(transaction.as_ref()).change_context(
+            BlockChainTreeError::BlockChainTree(BCTreeErrorKind::NewTransaction),
+        )?;
+        self.tree.read().await.send_transaction(transaction).await?;
+        self.broadcast(Message::Inventory(vec![item]), None);
+        Ok(())
+    }
+
+    /// Disconnects every peer and stops listening
+    pub fn shutdown(&self) {
+        for task in self.tasks.lock().drain(..) {
+            task.abort();
+        }
+        self.peers.lock().clear();
+    }
+
+    fn broadcast(&self, message: Message, except: Option<u64>) {
+        for (id, peer) in self.peers.lock().iter() {
+            if Some(*id) != except {
+                let _ = peer.sender.send(message.clone());
+            }
+        }
+    }
+
+    fn send_to(&self, id: u64, message: Message) {
+        if let Some(peer) = self.peers.lock().get(&id) {
+            let _ = peer.sender.send(message);
+        }
+    }
+
+    async fn handshake(&self) -> Result<Message, Report<NetworkError>> {
+        let main_chain = self.tree.read().await.get_main_chain();
+        let last_block = main_chain
+            .get_last_raw_block()
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+            .ok_or(NetworkError::Network(NetworkErrorKind::Tree))?;
+        Ok(Message::Handshake {
+            version: NETWORK_VERSION,
+            genesis_hash: self.genesis_hash,
+            last_hash: tools::hash(&last_block),
+            height: main_chain.get_height(),
+        })
+    }
+
+    async fn start_peer(
+        self: Arc<Self>,
+        stream: TcpStream,
+        address: SocketAddr,
+    ) -> Result<u64, Report<NetworkError>> {
+        let (mut reader, mut writer) = stream.into_split();
+
+        write_message(&mut writer, &self.handshake().await?).await?;
+        let handshake = tokio::time::timeout(
+            Duration::from_secs(HANDSHAKE_TIMEOUT),
+            read_message(&mut reader),
+        )
+        .await
+        .change_context(NetworkError::Network(NetworkErrorKind::Handshake))
+        .attach_printable("handshake timed out")??;
+
+        let height = match handshake {
+            Message::Handshake {
+                version,
+                genesis_hash,
+                height,
+                ..
+            } => {
+                if version != NETWORK_VERSION {
+                    return Err(
+                        Report::new(NetworkError::Network(NetworkErrorKind::Handshake))
+                            .attach_printable(format!("unsupported version {}", version)),
+                    );
+                }
+                if genesis_hash != self.genesis_hash {
+                    return Err(Report::new(NetworkError::Network(
+                        NetworkErrorKind::WrongGenesis,
+                    )));
+                }
+                height
+            }
+            _ => {
+                return Err(
+                    Report::new(NetworkError::Network(NetworkErrorKind::Handshake))
+                        .attach_printable("first message is not a handshake"),
+                );
+            }
+        };
+
+        let id = self.next_peer_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+        self.peers.lock().insert(
+            id,
+            Peer {
+                address,
+                height,
+                sender,
+                sync_target: None,
+            },
+        );
+
+        let writing = tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                if let Err(report) = write_message(&mut writer, &message).await {
+                    log::debug!("Failed to write to {}: {:?}", address, report);
+                    break;
+                }
+            }
+        });
+
+        let node = self.clone();
+        let reading = tokio::spawn(async move {
+            loop {
+                let message = match read_message(&mut reader).await {
+                    Ok(message) => message,
+                    Err(report) => {
+                        log::debug!("Disconnecting {}: {:?}", address, report);
+                        break;
+                    }
+                };
+                if let Err(report) = node.handle_message(id, message).await {
+                    log::warn!("Failed to handle message from {}: {:?}", address, report);
+                }
+            }
+            node.peers.lock().remove(&id);
+            node.downloader.lock().peer_lost(id);
+        });
+
+        {
+            let mut tasks = self.tasks.lock();
+            tasks.retain(|task| !task.is_finished());
+            tasks.push(writing);
+            tasks.push(reading);
+        }
+
+        let main_chain = self.tree.read().await.get_main_chain();
+        if height > self.best_height(&main_chain)? {
+            self.send_to(
+                id,
+                Message::GetHeaders(locator(&main_chain, Some(&self.header_chain))?),
+            );
+        }
+        self.schedule_bodies().await?;
+
+        Ok(id)
+    }
+
+    async fn handle_message(&self, id: u64, message: Message) -> Result<(), Report<NetworkError>> {
+        let main_chain = self.tree.read().await.get_main_chain();
+        match message {
+            Message::Handshake { .. } => {
+                return Err(
+                    Report::new(NetworkError::Network(NetworkErrorKind::Handshake))
+                        .attach_printable("repeated handshake"),
+                );
+            }
+            Message::Inventory(items) => {
+                let mut wanted = Vec::new();
+                for item in items {
+                    if !self.is_known(&main_chain, &item).await? {
+                        wanted.push(item);
+                    }
+                }
+                if let Some(last_block) = wanted.iter().rev().find(|item| item.is_block()) {
+                    if let Some(peer) = self.peers.lock().get_mut(&id) {
+                        peer.sync_target = Some(last_block.hash);
+                    }
+                }
+                if !wanted.is_empty() {
+                    self.send_to(id, Message::GetData(wanted));
+                }
+            }
+            Message::GetBlocks(locator) => {
+                let mut items = Vec::new();
+                for dump in blocks_after(&main_chain, &locator, MAX_BLOCKS_INVENTORY)? {
+                    items.push(InventoryItem {
+                        kind: Headers::from_u8(dump[0])
+                            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?,
+                        hash: tools::hash(&dump),
+                    });
+                }
+                if !items.is_empty() {
+                    self.send_to(id, Message::Inventory(items));
+                }
+            }
+            Message::GetHeaders(locator) => {
+                let mut headers = Vec::new();
+                for dump in blocks_after(&main_chain, &locator, MAX_HEADERS_PER_MESSAGE)? {
+                    let block = block::deserialize_main_chain_block(&dump)
+                        .change_context(NetworkError::Network(NetworkErrorKind::Tree))?;
+                    headers.push(
+                        BlockHeader::from_block(&block)
+                            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?,
+                    );
+                }
+                self.send_to(id, Message::Headers(headers));
+            }
+            Message::Headers(headers) => {
+                let full = headers.len() >= MAX_HEADERS_PER_MESSAGE;
+                let result = self.accept_headers(&main_chain, id, headers);
+                if full && result.is_ok() {
+                    self.send_to(
+                        id,
+                        Message::GetHeaders(locator(&main_chain, Some(&self.header_chain))?),
+                    );
+                }
+                self.schedule_bodies().await?;
+                result?;
+            }
+            Message::GetData(items) => {
+                let mut not_found = Vec::new();
+                for item in items {
+                    match self.get_data(&main_chain, &item).await? {
+                        Some(message) => self.send_to(id, message),
+                        None => not_found.push(item),
+                    }
+                }
+                if !not_found.is_empty() {
+                    self.send_to(id, Message::NotFound(not_found));
+                }
+            }
+            Message::NotFound(items) => {
+                if let Some(peer) = self.peers.lock().get_mut(&id) {
+                    if items.iter().any(|item| Some(item.hash) == peer.sync_target) {
+                        peer.sync_target = None;
+                    }
+                }
+                for item in items {
+                    if let Some(height) = self
+                        .header_chain
+                        .find_height_by_hash(&item.hash)
+                        .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                    {
+                        self.downloader.lock().not_found(&height);
+                    }
+                }
+            }
+            Message::Block {
+                block,
+                transactions,
+            } => {
+                let hash = tools::hash(&block);
+                if let Some(height) = self
+                    .header_chain
+                    .find_height_by_hash(&hash)
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                {
+                    let body = DownloadedBlock {
+                        block,
+                        transactions,
+                    };
+                    if self.downloader.lock().received(height, body) {
+                        self.import_downloaded().await?;
+                        return self.schedule_bodies().await;
+                    }
+                    return Ok(());
+                }
+
+                let result = match block::deserialize_main_chain_block(&block) {
+                    Ok(parsed) => {
+                        self.import_block(&main_chain, Some(id), parsed, hash, &transactions)
+                            .await
+                    }
+                    Err(report) => Err(report.change_context(BlockChainTreeError::Chain(
+                        ChainErrorKind::FailedToVerify,
+                    ))),
+                };
+
+                let continue_sync = match self.peers.lock().get_mut(&id) {
+                    Some(peer) if peer.sync_target == Some(hash) => {
+                        peer.sync_target = None;
+                        peer.height > main_chain.get_height()
+                    }
+                    _ => false,
+                };
+                let unknown_parent = matches!(
+                    result.as_ref().map_err(|report| report.current_context()),
+                    Err(BlockChainTreeError::BlockValidation(
+                        BlockValidationErrorKind::UnknownParent
+                    ))
+                );
+                if continue_sync || unknown_parent {
+                    self.send_to(id, Message::GetBlocks(locator(&main_chain, None)?));
+                }
+
+                match result {
+                    Ok(true) => self.broadcast(
+                        Message::Inventory(vec![InventoryItem {
+                            kind: Headers::from_u8(block[0])
+                                .change_context(NetworkError::Network(NetworkErrorKind::Parse))?,
+                            hash,
+                        }]),
+                        Some(id),
+                    ),
+                    Ok(false) => {}
+                    Err(report) => {
+                        return Err(report
+                            .change_context(NetworkError::Network(NetworkErrorKind::Tree))
+                            .attach_printable("failed to import block"));
+                    }
+                }
+            }
+            Message::Transaction(dump) => {
+                let transaction = transaction::parse_transactionable(&dump)
+                    .change_context(NetworkError::Network(NetworkErrorKind::Parse))?;
+                let item = InventoryItem {
+                    kind: Headers::from_u8(dump[0])
+                        .change_context(NetworkError::Network(NetworkErrorKind::Parse))?,
+                    hash: transaction.hash(),
+                };
+                if self.is_known(&main_chain, &item).await? {
+                    return Ok(());
+                }
+                self.tree
+                    .read()
+                    .await
+                    .send_transaction(transaction)
+                    .await
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))
+                    .attach_printable("transaction is rejected")?;
+                self.broadcast(Message::Inventory(vec![item]), Some(id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Height of the chain including the validated headers
+    fn best_height(&self, main_chain: &MainChain) -> Result<U256, Report<NetworkError>> {
+        let tip = self
+            .header_chain
+            .get_tip()
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?;
+        Ok(match tip {
+            Some(tip) => tip.info.height + 1,
+            None => main_chain.get_height(),
+        })
+    }
+
+    /// Validates headers received from the peer and stores them
+    ///
+    /// Headers have to continue the last stored header or the main chain,
+    /// headers forking from the main chain are left to the block sync
+    fn accept_headers(
+        &self,
+        main_chain: &MainChain,
+        id: u64,
+        headers: Vec<BlockHeader>,
+    ) -> Result<(), Report<NetworkError>> {
+        let first = match headers.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+
+        let mut parent = match self
+            .header_chain
+            .get_tip()
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+        {
+            Some(tip) => tip,
+            None => {
+                let last_block = main_chain
+                    .get_last_block()
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                    .ok_or(NetworkError::Network(NetworkErrorKind::Tree))?;
+                BlockHeader::from_block(&last_block)
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+            }
+        };
+        if first.info.previous_hash != parent.hash {
+            let known = main_chain
+                .find_height_by_hash(&first.info.previous_hash)
+                .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                .is_some();
+            if known {
+                self.send_to(id, Message::GetBlocks(locator(main_chain, None)?));
+            }
+            return Ok(());
+        }
+
+        let mut height = parent.info.height;
+        for header in headers {
+            header
+                .validate(&parent)
+                .change_context(NetworkError::Network(NetworkErrorKind::Tree))
+                .attach_printable(format!("invalid header at height {}", header.info.height))?;
+            self.header_chain
+                .insert(&header)
+                .change_context(NetworkError::Network(NetworkErrorKind::Tree))?;
+            height = header.info.height;
+            parent = header;
+        }
+
+        if let Some(peer) = self.peers.lock().get_mut(&id) {
+            peer.height = peer.height.max(height + 1);
+        }
+        Ok(())
+    }
+
+    /// Requests missing bodies of the stored headers from the peers
+    async fn schedule_bodies(&self) -> Result<(), Report<NetworkError>> {
+        if self.header_chain.is_empty() {
+            return Ok(());
+        }
+        let main_chain = self.tree.read().await.get_main_chain();
+        let from = main_chain.get_height();
+        let to = self.best_height(&main_chain)?;
+        let peers: Vec<(u64, U256)> = self
+            .peers
+            .lock()
+            .iter()
+            .map(|(id, peer)| (*id, peer.height))
+            .collect();
+
+        let scheduled = self
+            .downloader
+            .lock()
+            .schedule(from, to, &peers, Instant::now());
+        for (id, heights) in scheduled {
+            let mut items = Vec::with_capacity(heights.len());
+            for height in heights {
+                let header = self
+                    .header_chain
+                    .find_by_height(&height)
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                    .ok_or(NetworkError::Network(NetworkErrorKind::Tree))
+                    .attach_printable(format!("header at height {} is missing", height))?;
+                items.push(InventoryItem {
+                    kind: header.kind,
+                    hash: header.hash,
+                });
+            }
+            self.send_to(id, Message::GetData(items));
+        }
+        Ok(())
+    }
+
+    /// Imports downloaded bodies following the last block of the main chain
+    ///
+    /// If the block is rejected, its header and the following ones are dropped
+    async fn import_downloaded(&self) -> Result<(), Report<NetworkError>> {
+        let _importing = self.importing.lock().await;
+        let main_chain = self.tree.read().await.get_main_chain();
+        loop {
+            let height = main_chain.get_height();
+            let body = match self.downloader.lock().take(&height) {
+                Some(body) => body,
+                None => return Ok(()),
+            };
+
+            let hash = tools::hash(&body.block);
+            let result = match block::deserialize_main_chain_block(&body.block) {
+                Ok(block) => {
+                    self.import_block(&main_chain, None, block, hash, &body.transactions)
+                        .await
+                }
+                Err(report) => Err(report
+                    .change_context(BlockChainTreeError::Chain(ChainErrorKind::FailedToVerify))),
+            };
+            if let Err(report) = result {
+                self.header_chain
+                    .remove_from(&height)
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))?;
+                self.downloader.lock().reset();
+                return Err(report
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))
+                    .attach_printable(format!("failed to import block at height {}", height)));
+            }
+        }
+    }
+
+    async fn is_known(
+        &self,
+        main_chain: &MainChain,
+        item: &InventoryItem,
+    ) -> Result<bool, Report<NetworkError>> {
+        if item.is_block() {
+            let in_main = main_chain
+                .find_height_by_hash(&item.hash)
+                .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                .is_some();
+            return Ok(in_main
+                || main_chain
+                    .find_side_by_hash(&item.hash)
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                    .is_some());
+        }
+
+        let tx_pool = self.tree.read().await.get_tx_pool();
+        if tx_pool.read().await.transaction_exists(&item.hash) {
+            return Ok(true);
+        }
+        main_chain
+            .transaction_exists(&item.hash)
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))
+    }
+
+    async fn get_data(
+        &self,
+        main_chain: &MainChain,
+        item: &InventoryItem,
+    ) -> Result<Option<Message>, Report<NetworkError>> {
+        if item.is_block() {
+            let block = match main_chain
+                .find_raw_by_hash(&item.hash)
+                .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+            {
+                Some(block) => block,
+                None => return Ok(None),
+            };
+            let parsed = block::deserialize_main_chain_block(&block)
+                .change_context(NetworkError::Network(NetworkErrorKind::Tree))?;
+            let mut transactions = Vec::new();
+            for hash in parsed.transactions().unwrap_or(&[]) {
+                transactions.push(
+                    main_chain
+                        .get_transaction_raw(hash)
+                        .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                        .ok_or(NetworkError::Network(NetworkErrorKind::Tree))
+                        .attach_printable("transaction of the block is missing")?,
+                );
+            }
+            return Ok(Some(Message::Block {
+                block,
+                transactions,
+            }));
+        }
+
+        let tx_pool = self.tree.read().await.get_tx_pool();
+        if let Some(transaction) = tx_pool.read().await.get(&item.hash) {
+            return Ok(Some(Message::Transaction(
+                transaction
+                    .dump()
+                    .change_context(NetworkError::Network(NetworkErrorKind::Dump))?,
+            )));
+        }
+        Ok(main_chain
+            .get_transaction_raw(&item.hash)
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+            .map(Message::Transaction))
+    }
+
+    /// Imports block received from the peer
+    ///
+    /// Transactions received with the block are stored only if the block is accepted.
+    /// Returns `false` if the block is already known
+    async fn import_block(
+        &self,
+        main_chain: &MainChain,
+        id: Option<u64>,
+        block: BlockArc,
+        hash: Hash,
+        transactions: &[Vec<u8>],
+    ) -> Result<bool, Report<BlockChainTreeError>> {
+        if main_chain.find_height_by_hash(&hash)?.is_some()
+            || main_chain.find_side_by_hash(&hash)?.is_some()
+        {
+            return Ok(false);
+        }
+
+        if let Some(id) = id {
+            if let Some(peer) = self.peers.lock().get_mut(&id) {
+                peer.height = peer.height.max(block.get_info().height + 1);
+            }
+        }
+
+        let transactions = transactions
+            .iter()
+            .map(|dump| transaction::parse_transactionable(dump))
+            .collect::<Result<Vec<_>, _>>()
+            .change_context(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::MissingTransaction,
+            ))?;
+        self.tree
+            .write()
+            .await
+            .import_main_block_with_transactions(block, transactions)
+            .await?;
+        self.header_chain.prune(main_chain)?;
+        Ok(true)
+    }
+}
+
+fn transaction_item(
+    transaction: &dyn Transactionable,
+) -> Result<InventoryItem, Report<NetworkError>> {
+    let dump = transaction
+        .dump()
+        .change_context(NetworkError::Network(NetworkErrorKind::Dump))?;
+    Ok(InventoryItem {
+        kind: Headers::from_u8(dump[0])
+            .change_context(NetworkError::Network(NetworkErrorKind::Dump))?,
+        hash: transaction.hash(),
+    })
+}
+
+/// Main chain blocks following the first block of the locator known to the chain
+fn blocks_after(
+    main_chain: &MainChain,
+    locator: &[Hash],
+    limit: usize,
+) -> Result<Vec<Vec<u8>>, Report<NetworkError>> {
+    let mut start = U256::zero();
+    for hash in locator {
+        if let Some(height) = main_chain
+            .find_height_by_hash(hash)
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+        {
+            start = height;
+            break;
+        }
+    }
+
+    let mut blocks = Vec::new();
+    let mut height = start + 1;
+    while height < main_chain.get_height() && blocks.len() < limit {
+        blocks.push(
+            main_chain
+                .find_raw_by_height(&height)
+                .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                .ok_or(NetworkError::Network(NetworkErrorKind::Tree))?,
+        );
+        height += U256::one();
+    }
+    Ok(blocks)
+}
+
+/// Hashes of the blocks used to find the common block with the peer
+///
+/// The latest blocks go one by one, then the step doubles, genesis block is always the last one.
+/// Stored headers are treated as the continuation of the main chain if `header_chain` is passed
+fn locator(
+    main_chain: &MainChain,
+    header_chain: Option<&HeaderChain>,
+) -> Result<Vec<Hash>, Report<NetworkError>> {
+    let main_height = main_chain.get_height();
+    let mut height = match header_chain {
+        Some(header_chain) => header_chain
+            .get_tip()
+            .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+            .map(|tip| tip.info.height)
+            .unwrap_or(main_height - 1),
+        None => main_height - 1,
+    };
+
+    let mut locator = Vec::new();
+    let mut step = U256::one();
+    loop {
+        let hash = match header_chain {
+            Some(header_chain) if height >= main_height => {
+                header_chain
+                    .find_by_height(&height)
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                    .ok_or(NetworkError::Network(NetworkErrorKind::Tree))?
+                    .hash
+            }
+            _ => tools::hash(
+                &main_chain
+                    .find_raw_by_height(&height)
+                    .change_context(NetworkError::Network(NetworkErrorKind::Tree))?
+                    .ok_or(NetworkError::Network(NetworkErrorKind::Tree))?,
+            ),
+        };
+        locator.push(hash);
+        if height.is_zero() {
+            break;
+        }
+        if locator.len() as u64 >= LOCATOR_DENSE_BLOCKS {
+            step *= 2;
+        }
+        height = height.saturating_sub(step);
+    }
+    Ok(locator)
+}
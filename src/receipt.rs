@@ -0,0 +1,67 @@
+use error_stack::{Report, ResultExt};
+use primitive_types::U256;
+use sled::{Db, Tree};
+
+use crate::{
+    errors::{BCTreeErrorKind, BlockChainTreeError},
+    static_values::RECEIPTS_TREE,
+    types::Hash,
+};
+
+/// Outcome of the transaction included into the main chain block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    /// Height of the block that included the transaction
+    pub height: U256,
+    /// Fee paid to the founder of the block
+    pub fee: U256,
+    /// Gas taken for the bytes of the transaction
+    pub gas_used: U256,
+}
+
+impl Receipt {
+    pub fn dump(&self) -> Vec<u8> {
+        let mut dump = vec![0u8; 96];
+        self.height.to_big_endian(&mut dump[..32]);
+        self.fee.to_big_endian(&mut dump[32..64]);
+        self.gas_used.to_big_endian(&mut dump[64..]);
+        dump
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Receipt, Report<BlockChainTreeError>> {
+        if data.len() != 96 {
+            return Err(Report::new(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::Receipt,
+            ))
+            .attach_printable(format!("wrong receipt length: {}", data.len())));
+        }
+        Ok(Receipt {
+            height: U256::from_big_endian(&data[..32]),
+            fee: U256::from_big_endian(&data[32..64]),
+            gas_used: U256::from_big_endian(&data[64..]),
+        })
+    }
+}
+
+/// Tree with the receipts of the main chain transactions, keyed by the transaction hash
+pub fn open_receipts_tree(summary_db: &Db) -> Result<Tree, Report<BlockChainTreeError>> {
+    summary_db
+        .open_tree(RECEIPTS_TREE)
+        .change_context(BlockChainTreeError::BlockChainTree(
+            BCTreeErrorKind::Receipt,
+        ))
+        .attach_printable("failed to open receipts tree")
+}
+
+/// Stage of the transaction on its way to the main chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionStatus {
+    /// Waits in the pool to be included into a block
+    Pending,
+    /// Included into the main chain block with the hash
+    Included { block_hash: Hash, receipt: Receipt },
+    /// Was not accepted into the pool or was dropped from it
+    Rejected { reason: String },
+    /// Was never seen by the node or its rejection is already forgotten
+    Unknown,
+}
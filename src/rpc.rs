@@ -0,0 +1,729 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::{
+    block,
+    blockchaintree::SharedBlockChainTree,
+    dump_headers::Headers,
+    errors::{RpcError, RpcErrorKind},
+    network::Node,
+    receipt::TransactionStatus,
+    static_values::{
+        MAX_HEADERS_PER_MESSAGE, MAX_HISTORY_PAGE, MAX_RPC_HEADERS_SIZE, MAX_RPC_REQUEST_SIZE,
+    },
+    token::TokenOperation,
+    tools,
+    transaction::{self, Transactionable},
+    types::{Address, Hash},
+};
+use error_stack::{Report, ResultExt};
+use primitive_types::U256;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const SERVER_ERROR: i64 = -32000;
+
+/// Error object of the json-rpc response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl MethodError {
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self {
+            code: INVALID_PARAMS,
+            message: message.into(),
+        }
+    }
+
+    /// Error of the tree, printable attachments of the report explain the reason
+    fn server<C: error_stack::Context>(report: Report<C>) -> Self {
+        Self {
+            code: SERVER_ERROR,
+            message: tools::report_message(&report),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({"code": self.code, "message": self.message})
+    }
+}
+
+/// Json-rpc methods of the node
+///
+/// Addresses, hashes and dumps are hex encoded, amounts and heights are decimal strings
+#[derive(Clone)]
+pub struct Rpc {
+    tree: SharedBlockChainTree,
+    node: Option<Arc<Node>>,
+}
+
+impl Rpc {
+    /// Transactions and mined blocks are announced to the peers of the `node` if it's set
+    pub fn new(tree: SharedBlockChainTree, node: Option<Arc<Node>>) -> Self {
+        Self { tree, node }
+    }
+
+    /// Handles json-rpc request or batch of requests
+    ///
+    /// Returns `None` if there is nothing to respond with
+    pub async fn handle(&self, body: &[u8]) -> Option<Value> {
+        let request: Value = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(error) => {
+                return Some(response(
+                    Value::Null,
+                    Err(MethodError {
+                        code: PARSE_ERROR,
+                        message: error.to_string(),
+                    }),
+                ))
+            }
+        };
+
+        match request {
+            Value::Array(batch) if !batch.is_empty() => {
+                let mut responses = Vec::with_capacity(batch.len());
+                for request in batch {
+                    if let Some(response) = self.handle_single(request).await {
+                        responses.push(response);
+                    }
+                }
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            }
+            request => self.handle_single(request).await,
+        }
+    }
+
+    async fn handle_single(&self, request: Value) -> Option<Value> {
+        let id = request.get("id").cloned();
+        let method = match (
+            request.get("jsonrpc").and_then(Value::as_str),
+            request.get("method").and_then(Value::as_str),
+        ) {
+            (Some("2.0"), Some(method)) => method,
+            _ => {
+                return Some(response(
+                    id.unwrap_or(Value::Null),
+                    Err(MethodError {
+                        code: INVALID_REQUEST,
+                        message: "invalid json-rpc request".into(),
+                    }),
+                ))
+            }
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = self.call(method, &params).await;
+        // notifications are not answered
+        id.map(|id| response(id, result))
+    }
+
+    pub async fn call(&self, method: &str, params: &Value) -> Result<Value, MethodError> {
+        match method {
+            "get_height" => {
+                let main_chain = self.tree.read().await.get_main_chain();
+                Ok(json!(main_chain.get_height().to_string()))
+            }
+            "get_amount" => {
+                let address = parse_address(param(params, 0, "address")?)?;
+                let amount = self
+                    .tree
+                    .read()
+                    .await
+                    .get_amount(&address)
+                    .map_err(MethodError::server)?;
+                Ok(json!(amount.to_string()))
+            }
+            "get_gas" => {
+                let address = parse_address(param(params, 0, "address")?)?;
+                let gas = self
+                    .tree
+                    .read()
+                    .await
+                    .get_gas(&address)
+                    .map_err(MethodError::server)?;
+                Ok(json!(gas.to_string()))
+            }
+            "get_account_proof" => {
+                let address = parse_address(param(params, 0, "address")?)?;
+                let proof = self
+                    .tree
+                    .read()
+                    .await
+                    .get_account_proof(&address)
+                    .map_err(MethodError::server)?;
+                Ok(json!({
+                    "amount": proof.amount.to_string(),
+                    "gas": proof.gas.to_string(),
+                    "bitmap": hex::encode(proof.bitmap),
+                    "siblings": proof.siblings.iter().map(hex::encode).collect::<Vec<_>>(),
+                }))
+            }
+            "get_nonce" => {
+                let address = parse_address(param(params, 0, "address")?)?;
+                let nonce = self
+                    .tree
+                    .read()
+                    .await
+                    .get_nonce(&address)
+                    .map_err(MethodError::server)?;
+                Ok(json!(nonce))
+            }
+            "find_by_height" => {
+                let height = parse_u256(param(params, 0, "height")?)?;
+                let main_chain = self.tree.read().await.get_main_chain();
+                match main_chain
+                    .find_raw_by_height(&height)
+                    .map_err(MethodError::server)?
+                {
+                    Some(dump) => block_json(&dump),
+                    None => Ok(Value::Null),
+                }
+            }
+            "find_by_hash" => {
+                let hash = parse_hash(param(params, 0, "hash")?)?;
+                let main_chain = self.tree.read().await.get_main_chain();
+                match main_chain
+                    .find_raw_by_hash(&hash)
+                    .map_err(MethodError::server)?
+                {
+                    Some(dump) => block_json(&dump),
+                    None => Ok(Value::Null),
+                }
+            }
+            "get_headers" => {
+                let mut height = parse_u256(param(params, 0, "height")?)?;
+                let main_chain = self.tree.read().await.get_main_chain();
+                let mut headers = Vec::new();
+                while headers.len() < MAX_HEADERS_PER_MESSAGE {
+                    let Some(block) = main_chain
+                        .find_by_height(&height)
+                        .map_err(MethodError::server)?
+                    else {
+                        break;
+                    };
+                    let mut dump = Vec::new();
+                    block::BlockHeader::from_block(&block)
+                        .and_then(|header| header.dump(&mut dump))
+                        .map_err(MethodError::server)?;
+                    headers.push(hex::encode(dump));
+                    height += U256::one();
+                }
+                Ok(json!(headers))
+            }
+            "get_transaction_proof" => {
+                let hash = parse_hash(param(params, 0, "hash")?)?;
+                let main_chain = self.tree.read().await.get_main_chain();
+                if !main_chain
+                    .transaction_exists(&hash)
+                    .map_err(MethodError::server)?
+                {
+                    return Ok(Value::Null);
+                }
+                let proof = main_chain
+                    .get_transaction_proof(&hash)
+                    .map_err(MethodError::server)?;
+                Ok(json!({
+                    "transaction": hex::encode(proof.transaction),
+                    "block_hash": hex::encode(proof.block_hash),
+                    "height": proof.height.to_string(),
+                    "index": proof.index,
+                    "path": proof.path.iter().map(hex::encode).collect::<Vec<_>>(),
+                }))
+            }
+            "get_transaction_block" => {
+                let hash = parse_hash(param(params, 0, "hash")?)?;
+                let main_chain = self.tree.read().await.get_main_chain();
+                match main_chain
+                    .find_transaction_reference(&hash)
+                    .map_err(MethodError::server)?
+                {
+                    Some((height, block_hash)) => Ok(json!({
+                        "hash": hex::encode(block_hash),
+                        "height": height.to_string(),
+                    })),
+                    None => Ok(Value::Null),
+                }
+            }
+            "get_address_transactions" => {
+                let address = parse_address(param(params, 0, "address")?)?;
+                let offset = param(params, 1, "offset")?
+                    .as_u64()
+                    .ok_or(MethodError::invalid_params("offset should be a number"))?;
+                let limit = param(params, 2, "limit")?
+                    .as_u64()
+                    .ok_or(MethodError::invalid_params("limit should be a number"))?;
+                if limit as usize > MAX_HISTORY_PAGE {
+                    return Err(MethodError::invalid_params(format!(
+                        "limit should not exceed {}",
+                        MAX_HISTORY_PAGE
+                    )));
+                }
+                let main_chain = self.tree.read().await.get_main_chain();
+                let transactions = main_chain
+                    .get_address_transactions(&address, offset as usize, limit as usize)
+                    .map_err(MethodError::server)?;
+                Ok(json!(transactions
+                    .iter()
+                    .map(|transaction| json!({
+                        "hash": hex::encode(transaction.transaction),
+                        "height": transaction.height.to_string(),
+                    }))
+                    .collect::<Vec<_>>()))
+            }
+            "get_transaction" => {
+                let hash = parse_hash(param(params, 0, "hash")?)?;
+                let tree = self.tree.read().await;
+                let tx_pool = tree.get_tx_pool();
+                if let Some(transaction) = tx_pool.read().await.get(&hash) {
+                    return transaction_json(transaction.as_ref(), true);
+                }
+                match tree
+                    .get_main_chain()
+                    .get_transactionable(&hash)
+                    .map_err(MethodError::server)?
+                {
+                    Some(transaction) => transaction_json(transaction.as_ref(), false),
+                    None => Ok(Value::Null),
+                }
+            }
+            "get_transaction_status" => {
+                let hash = parse_hash(param(params, 0, "hash")?)?;
+                let status = self
+                    .tree
+                    .read()
+                    .await
+                    .get_transaction_status(&hash)
+                    .await
+                    .map_err(MethodError::server)?;
+                Ok(match status {
+                    TransactionStatus::Pending => json!({"status": "pending"}),
+                    TransactionStatus::Included {
+                        block_hash,
+                        receipt,
+                    } => json!({
+                        "status": "included",
+                        "block_hash": hex::encode(block_hash),
+                        "height": receipt.height.to_string(),
+                        "fee": receipt.fee.to_string(),
+                        "gas_used": receipt.gas_used.to_string(),
+                    }),
+                    TransactionStatus::Rejected { reason } => {
+                        json!({"status": "rejected", "reason": reason})
+                    }
+                    TransactionStatus::Unknown => json!({"status": "unknown"}),
+                })
+            }
+            "send_transaction" => {
+                let dump = parse_hex(param(params, 0, "transaction")?)?;
+                let transaction = transaction::parse_transactionable(&dump)
+                    .map_err(|report| MethodError::invalid_params(report.to_string()))?;
+                let hash = transaction.hash();
+                match &self.node {
+                    Some(node) => node.send_transaction(transaction).await,
+                    None => self.tree.read().await.send_transaction(transaction).await,
+                }
+                .map_err(MethodError::server)?;
+                Ok(json!(hex::encode(hash)))
+            }
+            "get_block_template" => {
+                let template = self
+                    .tree
+                    .read()
+                    .await
+                    .get_block_template()
+                    .await
+                    .map_err(MethodError::server)?;
+                Ok(json!({
+                    "previous_hash": hex::encode(template.previous_hash),
+                    "height": template.height.to_string(),
+                    "difficulty": hex::encode(template.difficulty),
+                    "transactions": template.transactions.iter().map(hex::encode).collect::<Vec<_>>(),
+                }))
+            }
+            "submit_block" => {
+                let pow = parse_hash(param(params, 0, "pow")?)?;
+                let founder = parse_address(param(params, 1, "founder")?)?;
+                let transactions = param(params, 2, "transactions")?
+                    .as_array()
+                    .ok_or(MethodError::invalid_params(
+                        "transactions should be an array",
+                    ))?
+                    .iter()
+                    .map(parse_hash)
+                    .collect::<Result<Vec<Hash>, _>>()?;
+                let timestamp = param(params, 3, "timestamp")?
+                    .as_u64()
+                    .ok_or(MethodError::invalid_params("timestamp should be a number"))?;
+
+                let block = self
+                    .tree
+                    .write()
+                    .await
+                    .emmit_new_main_block(&pow, &founder, &transactions, timestamp)
+                    .await
+                    .map_err(MethodError::server)?;
+                let hash = block.hash().map_err(MethodError::server)?;
+                if let Some(node) = &self.node {
+                    node.announce_block(&hash)
+                        .await
+                        .map_err(MethodError::server)?;
+                }
+                Ok(json!(hex::encode(hash)))
+            }
+            _ => Err(MethodError {
+                code: METHOD_NOT_FOUND,
+                message: format!("method {} is not found", method),
+            }),
+        }
+    }
+}
+
+fn response(id: Value, result: Result<Value, MethodError>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "result": result, "id": id}),
+        Err(error) => json!({"jsonrpc": "2.0", "error": error.to_json(), "id": id}),
+    }
+}
+
+/// Positional parameter or the named one if parameters are passed as an object
+fn param<'a>(params: &'a Value, index: usize, name: &str) -> Result<&'a Value, MethodError> {
+    match params {
+        Value::Array(params) => params.get(index),
+        Value::Object(params) => params.get(name),
+        _ => None,
+    }
+    .ok_or(MethodError::invalid_params(format!(
+        "missing parameter {}",
+        name
+    )))
+}
+
+fn parse_hex(value: &Value) -> Result<Vec<u8>, MethodError> {
+    let string = value
+        .as_str()
+        .ok_or(MethodError::invalid_params("hex string expected"))?;
+    hex::decode(string.strip_prefix("0x").unwrap_or(string))
+        .map_err(|error| MethodError::invalid_params(error.to_string()))
+}
+
+fn parse_hash(value: &Value) -> Result<Hash, MethodError> {
+    parse_hex(value)?
+        .try_into()
+        .map_err(|_| MethodError::invalid_params("hash should be 32 bytes long"))
+}
+
+fn parse_address(value: &Value) -> Result<Address, MethodError> {
+    parse_hex(value)?
+        .try_into()
+        .map_err(|_| MethodError::invalid_params("address should be 33 bytes long"))
+}
+
+fn parse_u256(value: &Value) -> Result<U256, MethodError> {
+    match value {
+        Value::Number(number) => number.as_u64().map(U256::from),
+        Value::String(string) => U256::from_dec_str(string).ok(),
+        _ => None,
+    }
+    .ok_or(MethodError::invalid_params("unsigned integer expected"))
+}
+
+fn block_json(dump: &[u8]) -> Result<Value, MethodError> {
+    let block = block::deserialize_main_chain_block(dump).map_err(MethodError::server)?;
+    let info = block.get_info();
+    let kind = match block.get_type() {
+        Headers::SummarizeBlock => "summarize",
+        _ => "transaction",
+    };
+    let anchors: Vec<Value> = block
+        .anchors()
+        .iter()
+        .map(|anchor| {
+            json!({
+                "owner": hex::encode(anchor.owner),
+                "hash": hex::encode(anchor.hash),
+                "height": anchor.height.to_string(),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "hash": hex::encode(tools::hash(dump)),
+        "type": kind,
+        "height": info.height.to_string(),
+        "timestamp": info.timestamp,
+        "previous_hash": hex::encode(info.previous_hash),
+        "pow": hex::encode(info.pow),
+        "difficulty": hex::encode(info.difficulty),
+        "founder": hex::encode(info.founder),
+        "merkle_root": hex::encode(block.get_merkle_root()),
+        "state_root": hex::encode(block.get_state_root()),
+        "fee": block.get_fee().to_string(),
+        "transactions": block.transactions().unwrap_or(&[]).iter().map(hex::encode).collect::<Vec<_>>(),
+        "anchors": anchors,
+        "dump": hex::encode(dump),
+    }))
+}
+
+fn transaction_json(
+    transaction: &dyn Transactionable,
+    pending: bool,
+) -> Result<Value, MethodError> {
+    let dump = transaction.dump().map_err(MethodError::server)?;
+    let token = transaction.get_token_action().map(|action| {
+        let operation = match action.operation {
+            TokenOperation::Issue => "issue",
+            TokenOperation::Transfer => "transfer",
+            TokenOperation::Burn => "burn",
+        };
+        json!({
+            "operation": operation,
+            "token": hex::encode(action.token),
+            "amount": action.amount.to_string(),
+        })
+    });
+    let kind = match dump.first().copied() {
+        Some(header) if header == Headers::Token as u8 => "token",
+        _ => "transaction",
+    };
+
+    Ok(json!({
+        "hash": hex::encode(transaction.hash()),
+        "type": kind,
+        "sender": hex::encode(transaction.get_sender()),
+        "receiver": hex::encode(transaction.get_receiver()),
+        "timestamp": transaction.get_timestamp(),
+        "nonce": transaction.get_nonce(),
+        "amount": transaction.get_amount().map(|amount| amount.to_string()),
+        "token": token,
+        "pending": pending,
+        "dump": hex::encode(dump),
+    }))
+}
+
+/// Json-rpc server over http
+///
+/// Every request is a `POST` with the json-rpc body, the connection is closed after the response
+pub struct RpcServer {
+    local_address: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl RpcServer {
+    pub async fn bind(rpc: Rpc, address: SocketAddr) -> Result<Self, Report<RpcError>> {
+        let listener = TcpListener::bind(address)
+            .await
+            .change_context(RpcError::Rpc(RpcErrorKind::Bind))
+            .attach_printable(format!("failed to bind {}", address))?;
+        let local_address = listener
+            .local_addr()
+            .change_context(RpcError::Rpc(RpcErrorKind::Bind))?;
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let (stream, address) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(error) => {
+                        log::warn!("Failed to accept rpc connection: {}", error);
+                        continue;
+                    }
+                };
+                let rpc = rpc.clone();
+                tokio::spawn(async move {
+                    if let Err(report) = serve_connection(&rpc, stream).await {
+                        log::debug!("Rpc connection with {} failed: {:?}", address, report);
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            local_address,
+            handle,
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Stops accepting new connections
+    pub fn shutdown(&self) {
+        self.handle.abort();
+    }
+}
+
+async fn serve_connection(rpc: &Rpc, mut stream: TcpStream) -> Result<(), Report<RpcError>> {
+    let (status, body) = match read_http(&mut stream).await {
+        Ok((start_line, body)) if start_line.starts_with("POST ") => {
+            match rpc.handle(&body).await {
+                Some(response) => ("200 OK", response.to_string()),
+                None => ("204 No Content", String::new()),
+            }
+        }
+        Ok(_) => ("405 Method Not Allowed", String::new()),
+        Err(report) => {
+            let status = match report.current_context() {
+                RpcError::Rpc(RpcErrorKind::TooLarge) => "413 Payload Too Large",
+                _ => "400 Bad Request",
+            };
+            (status, String::new())
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream
+        .write_all(response.as_bytes())
+        .await
+        .change_context(RpcError::Rpc(RpcErrorKind::Write))?;
+    stream
+        .shutdown()
+        .await
+        .change_context(RpcError::Rpc(RpcErrorKind::Write))
+}
+
+/// Reads http message, returns its start line and body
+///
+/// Body is read according to the `Content-Length` header
+async fn read_http<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<(String, Vec<u8>), Report<RpcError>> {
+    let mut buffer = Vec::new();
+    let headers_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+        if buffer.len() > MAX_RPC_HEADERS_SIZE {
+            return Err(Report::new(RpcError::Rpc(RpcErrorKind::TooLarge))
+                .attach_printable("headers are too large"));
+        }
+        let mut chunk = [0u8; 4096];
+        let read = reader
+            .read(&mut chunk)
+            .await
+            .change_context(RpcError::Rpc(RpcErrorKind::Read))?;
+        if read == 0 {
+            return Err(Report::new(RpcError::Rpc(RpcErrorKind::BadRequest))
+                .attach_printable("connection closed before the end of the headers"));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = std::str::from_utf8(&buffer[..headers_end])
+        .change_context(RpcError::Rpc(RpcErrorKind::BadRequest))?;
+    let mut lines = head.split("\r\n");
+    let start_line = lines.next().unwrap_or_default().to_string();
+    let mut content_length = 0usize;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse::<usize>()
+                    .change_context(RpcError::Rpc(RpcErrorKind::BadRequest))
+                    .attach_printable("wrong content length")?;
+            }
+        }
+    }
+    if content_length > MAX_RPC_REQUEST_SIZE {
+        return Err(Report::new(RpcError::Rpc(RpcErrorKind::TooLarge))
+            .attach_printable(format!("body of {} bytes", content_length)));
+    }
+
+    let mut body = buffer.split_off(headers_end + 4);
+    if body.len() > content_length {
+        body.truncate(content_length);
+    }
+    let read = body.len();
+    body.resize(content_length, 0);
+    reader
+        .read_exact(&mut body[read..])
+        .await
+        .change_context(RpcError::Rpc(RpcErrorKind::Read))?;
+
+    Ok((start_line, body))
+}
+
+/// Json-rpc client of the node
+#[derive(Debug, Clone)]
+pub struct RpcClient {
+    address: SocketAddr,
+}
+
+impl RpcClient {
+    pub fn new(address: SocketAddr) -> Self {
+        Self { address }
+    }
+
+    /// Sends json-rpc request, returns the whole response
+    pub async fn request(&self, request: &Value) -> Result<Value, Report<RpcError>> {
+        let body = request.to_string();
+        let mut stream = TcpStream::connect(self.address)
+            .await
+            .change_context(RpcError::Rpc(RpcErrorKind::Write))
+            .attach_printable(format!("failed to connect to {}", self.address))?;
+        let message = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.address,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(message.as_bytes())
+            .await
+            .change_context(RpcError::Rpc(RpcErrorKind::Write))?;
+
+        let (status_line, body) = read_http(&mut stream).await?;
+        if !status_line.contains(" 200 ") {
+            return Err(
+                Report::new(RpcError::Rpc(RpcErrorKind::BadRequest)).attach_printable(status_line)
+            );
+        }
+        serde_json::from_slice(&body)
+            .change_context(RpcError::Rpc(RpcErrorKind::Read))
+            .attach_printable("response is not a json")
+    }
+
+    /// Calls the method, returns its result or the error sent by the server
+    pub async fn call(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<Result<Value, MethodError>, Report<RpcError>> {
+        let mut response = self
+            .request(&json!({"jsonrpc": "2.0", "method": method, "params": params, "id": 0}))
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Ok(Err(MethodError {
+                code: error
+                    .get("code")
+                    .and_then(Value::as_i64)
+                    .unwrap_or(SERVER_ERROR),
+                message: error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+            }));
+        }
+        Ok(Ok(response
+            .get_mut("result")
+            .map(Value::take)
+            .unwrap_or(Value::Null)))
+    }
+}
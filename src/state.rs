@@ -0,0 +1,658 @@
+use std::collections::{HashMap, HashSet};
+
+use error_stack::{Report, ResultExt};
+use primitive_types::U256;
+use sled::{Db, Tree};
+
+use crate::{
+    block::DerivativeAnchor,
+    chain::{self, MainChain},
+    errors::{
+        BCTreeErrorKind, BlockChainTreeError, BlockValidationErrorKind, ChainErrorKind,
+        TokenErrorKind,
+    },
+    journal::{JournalDb, JournalEntry},
+    receipt::{self, Receipt},
+    state_tree::{StateLeaf, StateTree},
+    static_values::{BYTE_GAS_PRICE, MAIN_CHAIN_PAYMENT, ROOT_PUBLIC_ADDRESS},
+    token::{self, TokenAction, TokenInfo, TokenOperation},
+    tools,
+    transaction::Transactionable,
+    types::Hash,
+};
+
+/// Previous value of the changed entry, `None` if the entry was created
+type SavedValue = (usize, Option<Option<Vec<u8>>>);
+
+/// Key of the token balance in the journal: token id followed by the owner
+pub fn token_balance_key(token: &Hash, owner: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(32 + owner.len());
+    key.extend(token.iter());
+    key.extend(owner.iter());
+    key
+}
+
+fn load_u256(value: Option<Vec<u8>>) -> Result<U256, Report<BlockChainTreeError>> {
+    match value {
+        Some(value) => Ok(tools::load_u256(&value)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::GetFunds,
+            ))?
+            .0),
+        None => Ok(U256::zero()),
+    }
+}
+
+/// Leaves of the state tree changed by the entries, in the order of their first change
+///
+/// `get` returns the current value of the journaled key
+pub fn changed_leaves<'e, F>(
+    entries: &'e [JournalEntry],
+    get: F,
+) -> Result<Vec<StateLeaf<'e>>, Report<BlockChainTreeError>>
+where
+    F: Fn(JournalDb, &[u8]) -> Result<Option<Vec<u8>>, Report<BlockChainTreeError>>,
+{
+    let mut seen = HashSet::new();
+    let mut leaves = Vec::new();
+    for entry in entries {
+        let key = entry.key.as_slice();
+        // amount and gas of the owner share the leaf
+        let db = match entry.db {
+            JournalDb::Gas => JournalDb::Summary,
+            db => db,
+        };
+        if !seen.insert((db, key)) {
+            continue;
+        }
+        leaves.push(match db {
+            JournalDb::Summary => StateLeaf::Account {
+                owner: key,
+                amount: load_u256(get(JournalDb::Summary, key)?)?,
+                gas: load_u256(get(JournalDb::Gas, key)?)?,
+            },
+            JournalDb::Tokens => StateLeaf::Token {
+                token: key,
+                info: match get(JournalDb::Tokens, key)? {
+                    Some(dump) => Some(TokenInfo::parse(&dump)?),
+                    None => None,
+                },
+            },
+            JournalDb::TokenBalances => StateLeaf::TokenBalance {
+                key,
+                balance: load_u256(get(JournalDb::TokenBalances, key)?)?,
+            },
+            _ => continue,
+        });
+    }
+    Ok(leaves)
+}
+
+/// Set of changes to the summary, nonces, tokens, gas, receipts and transactions databases
+/// applied as a whole
+///
+/// Reads go through the changes made so far, nothing is written to the databases
+/// until the transition is committed by `BlockChainTree::apply_transition`
+pub struct StateTransition<'a> {
+    summary_db: &'a Db,
+    nonces: &'a Tree,
+    gas_db: &'a Db,
+    main_chain: &'a MainChain,
+    changes: Vec<JournalEntry>,
+    overlay: HashMap<(JournalDb, Vec<u8>), usize>,
+    savepoint: Option<Vec<SavedValue>>,
+}
+
+impl<'a> StateTransition<'a> {
+    pub fn new(
+        summary_db: &'a Db,
+        nonces: &'a Tree,
+        gas_db: &'a Db,
+        main_chain: &'a MainChain,
+    ) -> Self {
+        Self {
+            summary_db,
+            nonces,
+            gas_db,
+            main_chain,
+            changes: Vec::new(),
+            overlay: HashMap::new(),
+            savepoint: None,
+        }
+    }
+
+    /// Returns changed values in the order they were made, one entry per key
+    pub fn into_changes(self) -> Vec<JournalEntry> {
+        self.changes
+    }
+
+    /// Root of the state tree with the changes made so far
+    pub fn state_root(&self, state_tree: &StateTree) -> Result<Hash, Report<BlockChainTreeError>> {
+        state_tree.root_with(&changed_leaves(&self.changes, |db, key| self.get(db, key))?)
+    }
+
+    fn get(
+        &self,
+        db: JournalDb,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, Report<BlockChainTreeError>> {
+        if let Some(index) = self.overlay.get(&(db, key.to_vec())) {
+            return Ok(self.changes[*index].value.clone());
+        }
+        match db {
+            JournalDb::Summary => self
+                .summary_db
+                .get(key)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetFunds,
+                ))
+                .map(|value| value.map(|v| v.to_vec())),
+            JournalDb::Nonces => self
+                .nonces
+                .get(key)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetNonce,
+                ))
+                .map(|value| value.map(|v| v.to_vec())),
+            JournalDb::Gas => self
+                .gas_db
+                .get(key)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetFunds,
+                ))
+                .map(|value| value.map(|v| v.to_vec())),
+            JournalDb::Tokens => token::open_tokens_tree(self.summary_db)?
+                .get(key)
+                .change_context(BlockChainTreeError::Token(TokenErrorKind::Parse))
+                .map(|value| value.map(|v| v.to_vec())),
+            JournalDb::TokenBalances => {
+                if key.len() < 32 {
+                    return Err(BlockChainTreeError::Token(TokenErrorKind::Parse))
+                        .attach_printable("wrong token balance key length");
+                }
+                let token: &Hash = unsafe { key[..32].try_into().unwrap_unchecked() };
+                token::open_balances_tree(self.summary_db, token)?
+                    .get(&key[32..])
+                    .change_context(BlockChainTreeError::BlockChainTree(
+                        BCTreeErrorKind::GetFunds,
+                    ))
+                    .map(|value| value.map(|v| v.to_vec()))
+            }
+            JournalDb::Anchors => chain::open_anchors_tree(self.summary_db)?
+                .get(key)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetDerivChain,
+                ))
+                .map(|value| value.map(|v| v.to_vec())),
+            JournalDb::Receipts => receipt::open_receipts_tree(self.summary_db)?
+                .get(key)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::Receipt,
+                ))
+                .map(|value| value.map(|v| v.to_vec())),
+            JournalDb::Transactions => self.main_chain.get_transaction_raw(
+                <&[u8; 32]>::try_from(key)
+                    .change_context(BlockChainTreeError::Chain(ChainErrorKind::FindByHashE))
+                    .attach_printable("wrong transaction hash length")?,
+            ),
+        }
+    }
+
+    fn set(&mut self, db: JournalDb, key: &[u8], value: Option<Vec<u8>>) {
+        match self.overlay.get(&(db, key.to_vec())) {
+            Some(index) => {
+                let previous = std::mem::replace(&mut self.changes[*index].value, value);
+                if let Some(savepoint) = self.savepoint.as_mut() {
+                    savepoint.push((*index, Some(previous)));
+                }
+            }
+            None => {
+                if let Some(savepoint) = self.savepoint.as_mut() {
+                    savepoint.push((self.changes.len(), None));
+                }
+                self.overlay.insert((db, key.to_vec()), self.changes.len());
+                self.changes.push(JournalEntry {
+                    db,
+                    key: key.to_vec(),
+                    value,
+                });
+            }
+        }
+    }
+
+    /// Runs `apply`, the changes it made are discarded if it fails
+    pub fn try_apply<F>(&mut self, apply: F) -> Result<(), Report<BlockChainTreeError>>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Report<BlockChainTreeError>>,
+    {
+        let outer = self.savepoint.replace(Vec::new());
+        let result = apply(self);
+        let saved = self.savepoint.take().unwrap_or_default();
+        match result {
+            Ok(()) => {
+                self.savepoint = outer.map(|mut outer| {
+                    outer.extend(saved);
+                    outer
+                });
+                Ok(())
+            }
+            Err(report) => {
+                for (index, previous) in saved.into_iter().rev() {
+                    match previous {
+                        Some(value) => self.changes[index].value = value,
+                        None => {
+                            // created entries are always the last ones
+                            let entry = self.changes.remove(index);
+                            self.overlay.remove(&(entry.db, entry.key));
+                        }
+                    }
+                }
+                self.savepoint = outer;
+                Err(report)
+            }
+        }
+    }
+
+    /// Applies the whole effect of the transaction: nonce, coins, block fee, gas and token operation
+    ///
+    /// The fee is taken from the sender, it goes to the block founder with `apply_block_reward`
+    pub fn apply_transaction(
+        &mut self,
+        transaction: &dyn Transactionable,
+        fee: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let sender = transaction.get_sender();
+        self.use_nonce(sender, transaction.get_nonce())
+            .change_context(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongNonce,
+            ))?;
+        self.send_amount(
+            sender,
+            transaction.get_receiver(),
+            transaction.get_amount().unwrap_or(U256::zero()),
+        )
+        .and_then(|_| self.sub_amount(sender, fee))
+        .change_context(BlockChainTreeError::BlockValidation(
+            BlockValidationErrorKind::InsufficientFunds,
+        ))?;
+        self.sub_gas(sender, *BYTE_GAS_PRICE * transaction.get_dump_size())
+            .change_context(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::InsufficientFunds,
+            ))?;
+        if let Some(action) = transaction.get_token_action() {
+            self.apply_token_action(
+                sender,
+                transaction.get_receiver(),
+                transaction.get_nonce(),
+                &action,
+            )
+            .change_context(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::WrongTokenOperation,
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Pays the main chain block reward from the root address and the collected fees to the founder
+    pub fn apply_block_reward(
+        &mut self,
+        founder: &[u8],
+        fees: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.send_amount(&ROOT_PUBLIC_ADDRESS, founder, *MAIN_CHAIN_PAYMENT)
+            .and_then(|_| self.add_amount(founder, fees))
+            .change_context(BlockChainTreeError::BlockValidation(
+                BlockValidationErrorKind::InsufficientFunds,
+            ))
+            .attach_printable("failed to pay the block reward")
+    }
+
+    fn get_u256(&self, db: JournalDb, key: &[u8]) -> Result<U256, Report<BlockChainTreeError>> {
+        match self.get(db, key)? {
+            Some(value) => Ok(tools::load_u256(&value)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetFunds,
+                ))?
+                .0),
+            None => Ok(U256::zero()),
+        }
+    }
+
+    fn set_u256(
+        &mut self,
+        db: JournalDb,
+        key: &[u8],
+        amount: &U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let mut buf: Vec<u8> = Vec::with_capacity(tools::u256_size(amount));
+        tools::dump_u256(amount, &mut buf).change_context(BlockChainTreeError::BlockChainTree(
+            BCTreeErrorKind::AddFunds,
+        ))?;
+        self.set(db, key, Some(buf));
+        Ok(())
+    }
+
+    fn add(
+        &mut self,
+        db: JournalDb,
+        owner: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let new_amount = self
+            .get_u256(db, owner)?
+            .checked_add(amount)
+            .ok_or(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::AddFunds,
+            ))
+            .attach_printable("amount overflow")?;
+        self.set_u256(db, owner, &new_amount)
+    }
+
+    fn sub(
+        &mut self,
+        db: JournalDb,
+        owner: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let prev_amount = self.get_u256(db, owner)?;
+        if prev_amount < amount {
+            return Err(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::DecreaseFunds,
+            ))
+            .attach_printable(format!(
+                "insufficient balance of {}: {} < {}",
+                hex::encode(owner),
+                prev_amount,
+                amount
+            ));
+        }
+        self.set_u256(db, owner, &(prev_amount - amount))
+    }
+
+    fn send(
+        &mut self,
+        db: JournalDb,
+        from: &[u8],
+        to: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        if from == to {
+            return Ok(());
+        }
+        self.sub(db, from, amount)?;
+        self.add(db, to, amount)
+    }
+
+    pub fn get_amount(&self, owner: &[u8]) -> Result<U256, Report<BlockChainTreeError>> {
+        self.get_u256(JournalDb::Summary, owner)
+    }
+
+    pub fn set_amount(
+        &mut self,
+        owner: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.set_u256(JournalDb::Summary, owner, &amount)
+    }
+
+    pub fn add_amount(
+        &mut self,
+        owner: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.add(JournalDb::Summary, owner, amount)
+    }
+
+    pub fn sub_amount(
+        &mut self,
+        owner: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.sub(JournalDb::Summary, owner, amount)
+    }
+
+    pub fn send_amount(
+        &mut self,
+        from: &[u8],
+        to: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.send(JournalDb::Summary, from, to, amount)
+    }
+
+    pub fn get_gas(&self, owner: &[u8]) -> Result<U256, Report<BlockChainTreeError>> {
+        self.get_u256(JournalDb::Gas, owner)
+    }
+
+    pub fn add_gas(
+        &mut self,
+        owner: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.add(JournalDb::Gas, owner, amount)
+    }
+
+    pub fn sub_gas(
+        &mut self,
+        owner: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.sub(JournalDb::Gas, owner, amount)
+    }
+
+    pub fn send_gas(
+        &mut self,
+        from: &[u8],
+        to: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.send(JournalDb::Gas, from, to, amount)
+    }
+
+    /// Returns the nonce the next transaction of the owner must have
+    pub fn get_nonce(&self, owner: &[u8]) -> Result<u64, Report<BlockChainTreeError>> {
+        match self.get(JournalDb::Nonces, owner)? {
+            Some(value) => Ok(<[u8; 8]>::try_from(value.as_ref())
+                .map(u64::from_be_bytes)
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::GetNonce,
+                ))
+                .attach_printable("wrong nonce length")?),
+            None => Ok(0),
+        }
+    }
+
+    /// Checks that the nonce is the next one for the owner and advances it
+    pub fn use_nonce(
+        &mut self,
+        owner: &[u8],
+        nonce: u64,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let expected = self.get_nonce(owner)?;
+        if nonce != expected {
+            return Err(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::WrongNonce,
+            ))
+            .attach_printable(format!(
+                "nonce of {}: expected {}, got {}",
+                hex::encode(owner),
+                expected,
+                nonce
+            ));
+        }
+        self.set(
+            JournalDb::Nonces,
+            owner,
+            Some((expected + 1).to_be_bytes().to_vec()),
+        );
+        Ok(())
+    }
+
+    pub fn get_token_info(
+        &self,
+        token: &Hash,
+    ) -> Result<Option<TokenInfo>, Report<BlockChainTreeError>> {
+        match self.get(JournalDb::Tokens, token)? {
+            Some(dump) => Ok(Some(TokenInfo::parse(&dump)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_token_info(
+        &mut self,
+        token: &Hash,
+        info: &TokenInfo,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.set(JournalDb::Tokens, token, Some(info.dump()?));
+        Ok(())
+    }
+
+    pub fn get_token_balance(
+        &self,
+        token: &Hash,
+        owner: &[u8],
+    ) -> Result<U256, Report<BlockChainTreeError>> {
+        self.get_u256(JournalDb::TokenBalances, &token_balance_key(token, owner))
+    }
+
+    fn add_token_balance(
+        &mut self,
+        token: &Hash,
+        owner: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        self.add(
+            JournalDb::TokenBalances,
+            &token_balance_key(token, owner),
+            amount,
+        )
+        .change_context(BlockChainTreeError::Token(TokenErrorKind::Overflow))
+    }
+
+    fn sub_token_balance(
+        &mut self,
+        token: &Hash,
+        owner: &[u8],
+        amount: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let balance = self.get_token_balance(token, owner)?;
+        if balance < amount {
+            return Err(BlockChainTreeError::Token(
+                TokenErrorKind::InsufficientBalance,
+            ))
+            .attach_printable(format!(
+                "token balance of {}: {} < {}",
+                hex::encode(owner),
+                balance,
+                amount
+            ));
+        }
+        self.set_u256(
+            JournalDb::TokenBalances,
+            &token_balance_key(token, owner),
+            &(balance - amount),
+        )
+    }
+
+    /// Issues, transfers or burns tokens
+    pub fn apply_token_action(
+        &mut self,
+        sender: &[u8; 33],
+        receiver: &[u8; 33],
+        nonce: u64,
+        action: &TokenAction,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let info = self.get_token_info(&action.token)?;
+        match action.operation {
+            TokenOperation::Issue => {
+                if action.token != token::token_id(sender, nonce) {
+                    return Err(BlockChainTreeError::Token(TokenErrorKind::WrongTokenId).into());
+                }
+                if info.is_some() {
+                    return Err(BlockChainTreeError::Token(TokenErrorKind::AlreadyIssued).into());
+                }
+                self.set_token_info(
+                    &action.token,
+                    &TokenInfo {
+                        issuer: *sender,
+                        supply: action.amount,
+                    },
+                )?;
+                self.add_token_balance(&action.token, receiver, action.amount)
+            }
+            TokenOperation::Transfer => {
+                if info.is_none() {
+                    return Err(BlockChainTreeError::Token(TokenErrorKind::UnknownToken).into());
+                }
+                self.sub_token_balance(&action.token, sender, action.amount)?;
+                self.add_token_balance(&action.token, receiver, action.amount)
+            }
+            TokenOperation::Burn => {
+                let mut info =
+                    info.ok_or(BlockChainTreeError::Token(TokenErrorKind::UnknownToken))?;
+                self.sub_token_balance(&action.token, sender, action.amount)?;
+                info.supply -= action.amount;
+                self.set_token_info(&action.token, &info)
+            }
+        }
+    }
+
+    /// Latest anchored block of the owner's derivative chain
+    pub fn get_anchor(
+        &self,
+        owner: &[u8; 33],
+    ) -> Result<Option<DerivativeAnchor>, Report<BlockChainTreeError>> {
+        match self.get(JournalDb::Anchors, owner)? {
+            Some(dump) => Ok(Some(
+                DerivativeAnchor::parse(&dump)
+                    .change_context(BlockChainTreeError::BlockChainTree(
+                        BCTreeErrorKind::GetDerivChain,
+                    ))?
+                    .0,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_anchor(
+        &mut self,
+        anchor: &DerivativeAnchor,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let mut dump = Vec::with_capacity(anchor.get_dump_size());
+        anchor
+            .dump(&mut dump)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::GetDerivChain,
+            ))?;
+        self.set(JournalDb::Anchors, &anchor.owner, Some(dump));
+        Ok(())
+    }
+
+    /// Stores transaction in the main chain transactions tree
+    pub fn add_transaction(
+        &mut self,
+        transaction: &dyn Transactionable,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let dump = transaction
+            .dump()
+            .change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::AddingTransaction,
+            ))?;
+        self.set(JournalDb::Transactions, &tools::hash(&dump), Some(dump));
+        Ok(())
+    }
+
+    pub fn transaction_exists(
+        &self,
+        transaction_hash: &[u8; 32],
+    ) -> Result<bool, Report<BlockChainTreeError>> {
+        Ok(self
+            .get(JournalDb::Transactions, transaction_hash)?
+            .is_some())
+    }
+
+    /// Records the receipt of the included transaction
+    pub fn set_receipt(&mut self, transaction_hash: &Hash, receipt: &Receipt) {
+        self.set(JournalDb::Receipts, transaction_hash, Some(receipt.dump()));
+    }
+}
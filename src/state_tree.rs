@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use error_stack::{Report, ResultExt};
+use primitive_types::U256;
+use sled::{Db, Tree};
+
+use crate::{
+    errors::{BCTreeErrorKind, BlockChainTreeError},
+    static_values::STATE_TREE,
+    token::TokenInfo,
+    tools,
+    types::{Address, Hash},
+};
+
+/// Depth of the leaves, one level per bit of the hashed owner
+const DEPTH: usize = 256;
+
+/// Root of the tree without accounts, every empty subtree hashes to it as well
+pub static EMPTY_ROOT: Hash = [0; 32];
+
+/// Node storage key: depth followed by the path bits above it
+type NodeKey = [u8; 34];
+
+/// Hash of the account leaf, accounts without coins and gas are empty leaves
+fn leaf_hash(owner: &[u8], amount: &U256, gas: &U256) -> Hash {
+    if amount.is_zero() && gas.is_zero() {
+        return EMPTY_ROOT;
+    }
+    let mut data = Vec::with_capacity(1 + owner.len() + 64);
+    data.push(0);
+    data.extend(owner);
+    let mut value = [0u8; 32];
+    amount.to_big_endian(&mut value);
+    data.extend(value);
+    gas.to_big_endian(&mut value);
+    data.extend(value);
+    tools::hash(&data)
+}
+
+/// Value committed by the state tree, the leaf is placed by the hash of its key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateLeaf<'a> {
+    /// Amount and gas of the account, keyed by the owner
+    Account {
+        owner: &'a [u8],
+        amount: U256,
+        gas: U256,
+    },
+    /// Issuer and supply of the token, keyed by the token id
+    Token {
+        token: &'a [u8],
+        info: Option<TokenInfo>,
+    },
+    /// Token balance, keyed by the token id followed by the owner
+    TokenBalance { key: &'a [u8], balance: U256 },
+}
+
+impl StateLeaf<'_> {
+    fn path(&self) -> Hash {
+        match self {
+            StateLeaf::Account { owner, .. } => tools::hash(owner),
+            StateLeaf::Token { token, .. } => tools::hash(token),
+            StateLeaf::TokenBalance { key, .. } => tools::hash(key),
+        }
+    }
+
+    /// Hash of the leaf, missing tokens and zero balances are empty leaves
+    fn hash(&self) -> Hash {
+        let mut value = [0u8; 32];
+        match self {
+            StateLeaf::Account { owner, amount, gas } => leaf_hash(owner, amount, gas),
+            StateLeaf::Token { info: None, .. } => EMPTY_ROOT,
+            StateLeaf::Token {
+                token,
+                info: Some(info),
+            } => {
+                let mut data = Vec::with_capacity(1 + token.len() + info.issuer.len() + 32);
+                data.push(2);
+                data.extend(token.iter());
+                data.extend(info.issuer.iter());
+                info.supply.to_big_endian(&mut value);
+                data.extend(value);
+                tools::hash(&data)
+            }
+            StateLeaf::TokenBalance { balance, .. } if balance.is_zero() => EMPTY_ROOT,
+            StateLeaf::TokenBalance { key, balance } => {
+                let mut data = Vec::with_capacity(1 + key.len() + 32);
+                data.push(3);
+                data.extend(key.iter());
+                balance.to_big_endian(&mut value);
+                data.extend(value);
+                tools::hash(&data)
+            }
+        }
+    }
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    if *left == EMPTY_ROOT && *right == EMPTY_ROOT {
+        return EMPTY_ROOT;
+    }
+    let mut data = [0u8; 65];
+    data[0] = 1;
+    data[1..33].copy_from_slice(left);
+    data[33..].copy_from_slice(right);
+    tools::hash(&data)
+}
+
+fn bit(bits: &[u8; 32], index: usize) -> bool {
+    bits[index / 8] >> (7 - index % 8) & 1 == 1
+}
+
+fn node_key(path: &Hash, depth: usize) -> NodeKey {
+    let mut key = [0u8; 34];
+    key[..2].copy_from_slice(&(depth as u16).to_be_bytes());
+    let full = depth / 8;
+    key[2..2 + full].copy_from_slice(&path[..full]);
+    if !depth.is_multiple_of(8) {
+        key[2 + full] = path[full] & (0xFF << (8 - depth % 8));
+    }
+    key
+}
+
+/// Key of the node sharing the parent with the node at `depth` on the path
+fn sibling_key(path: &Hash, depth: usize) -> NodeKey {
+    let mut sibling = *path;
+    sibling[(depth - 1) / 8] ^= 1 << (7 - (depth - 1) % 8);
+    node_key(&sibling, depth)
+}
+
+/// Proof of the amount and the gas of the account against the state root
+///
+/// `bitmap` marks the levels with non empty siblings, counting from the top,
+/// `siblings` holds only those, starting from the leaf
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountProof {
+    pub owner: Address,
+    pub amount: U256,
+    pub gas: U256,
+    pub bitmap: [u8; 32],
+    pub siblings: Vec<Hash>,
+}
+
+impl AccountProof {
+    /// Root of the state the proof leads to, `None` if the siblings do not match the bitmap
+    pub fn compute_root(&self) -> Option<Hash> {
+        let path = tools::hash(&self.owner);
+        let mut siblings = self.siblings.iter();
+        let mut current = leaf_hash(&self.owner, &self.amount, &self.gas);
+        for depth in (1..=DEPTH).rev() {
+            let sibling = if bit(&self.bitmap, depth - 1) {
+                *siblings.next()?
+            } else {
+                EMPTY_ROOT
+            };
+            current = if bit(&path, depth - 1) {
+                node_hash(&sibling, &current)
+            } else {
+                node_hash(&current, &sibling)
+            };
+        }
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(current)
+    }
+
+    /// Checks that the account has the amount and the gas of the proof in the state with `state_root`
+    pub fn verify(&self, state_root: &Hash) -> bool {
+        self.compute_root().as_ref() == Some(state_root)
+    }
+}
+
+/// Sparse merkle tree of the accounts and tokens state
+///
+/// Account leaves are placed by the hash of the owner and commit to its amount and gas,
+/// token infos and token balances have leaves of their own, see `StateLeaf`.
+/// Empty subtrees hash to zeroes, so only the nodes above existing accounts are stored
+/// and the root depends on the state only, not on the order of the changes
+#[derive(Clone)]
+pub struct StateTree {
+    tree: Tree,
+}
+
+impl StateTree {
+    pub fn open(summary_db: &Db) -> Result<Self, Report<BlockChainTreeError>> {
+        let tree = summary_db
+            .open_tree(STATE_TREE)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::StateTree,
+            ))
+            .attach_printable("failed to open state tree")?;
+        Ok(Self { tree })
+    }
+
+    fn get_node(&self, key: &NodeKey) -> Result<Hash, Report<BlockChainTreeError>> {
+        match self
+            .tree
+            .get(key)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::StateTree,
+            ))? {
+            Some(hash) => Hash::try_from(hash.as_ref())
+                .change_context(BlockChainTreeError::BlockChainTree(
+                    BCTreeErrorKind::StateTree,
+                ))
+                .attach_printable("wrong node length"),
+            None => Ok(EMPTY_ROOT),
+        }
+    }
+
+    pub fn root(&self) -> Result<Hash, Report<BlockChainTreeError>> {
+        self.get_node(&node_key(&EMPTY_ROOT, 0))
+    }
+
+    /// Nodes changed by setting the leaves to the new values, with the new root
+    fn updated_nodes(
+        &self,
+        leaves: &[StateLeaf],
+    ) -> Result<(Hash, HashMap<NodeKey, Hash>), Report<BlockChainTreeError>> {
+        let mut nodes: HashMap<NodeKey, Hash> = HashMap::new();
+        let mut root = None;
+        for leaf in leaves {
+            let path = leaf.path();
+            let mut current = leaf.hash();
+            for depth in (1..=DEPTH).rev() {
+                nodes.insert(node_key(&path, depth), current);
+                let sibling_key = sibling_key(&path, depth);
+                let sibling = match nodes.get(&sibling_key) {
+                    Some(sibling) => *sibling,
+                    None => self.get_node(&sibling_key)?,
+                };
+                current = if bit(&path, depth - 1) {
+                    node_hash(&sibling, &current)
+                } else {
+                    node_hash(&current, &sibling)
+                };
+            }
+            nodes.insert(node_key(&path, 0), current);
+            root = Some(current);
+        }
+        let root = match root {
+            Some(root) => root,
+            None => self.root()?,
+        };
+        Ok((root, nodes))
+    }
+
+    /// Root the tree would have with the leaves set to the new values, nothing is written
+    pub fn root_with(&self, leaves: &[StateLeaf]) -> Result<Hash, Report<BlockChainTreeError>> {
+        Ok(self.updated_nodes(leaves)?.0)
+    }
+
+    /// Sets the leaves to the new values
+    ///
+    /// Nodes are derived from the values only, so repeating the update is harmless
+    pub fn update(&self, leaves: &[StateLeaf]) -> Result<(), Report<BlockChainTreeError>> {
+        if leaves.is_empty() {
+            return Ok(());
+        }
+        let (_, nodes) = self.updated_nodes(leaves)?;
+        let mut batch = sled::Batch::default();
+        for (key, hash) in nodes {
+            if hash == EMPTY_ROOT {
+                batch.remove(&key[..]);
+            } else {
+                batch.insert(&key[..], &hash[..]);
+            }
+        }
+        self.tree
+            .apply_batch(batch)
+            .change_context(BlockChainTreeError::BlockChainTree(
+                BCTreeErrorKind::StateTree,
+            ))
+            .attach_printable("failed to update state tree")
+    }
+
+    /// Proof of the account having the `amount` and the `gas`
+    pub fn get_proof(
+        &self,
+        owner: &Address,
+        amount: U256,
+        gas: U256,
+    ) -> Result<AccountProof, Report<BlockChainTreeError>> {
+        let path = tools::hash(owner);
+        let mut bitmap = [0u8; 32];
+        let mut siblings = Vec::new();
+        for depth in (1..=DEPTH).rev() {
+            let sibling = self.get_node(&sibling_key(&path, depth))?;
+            if sibling != EMPTY_ROOT {
+                bitmap[(depth - 1) / 8] |= 1 << (7 - (depth - 1) % 8);
+                siblings.push(sibling);
+            }
+        }
+        Ok(AccountProof {
+            owner: *owner,
+            amount,
+            gas,
+            bitmap,
+            siblings,
+        })
+    }
+}
@@ -4,19 +4,31 @@ use primitive_types::U256;
 //pub static BLOCKCHAIN_DIRECTORY: &str = "./BlockChainTree/";
 
 pub static AMOUNT_SUMMARY: &str = "SUMMARY/";
+pub static NONCES_TREE: &str = "NONCES";
+pub static TOKENS_TREE: &str = "TOKENS";
+pub static TOKEN_BALANCES_PREFIX: &str = "TOKEN/";
+pub static ANCHORS_TREE: &str = "ANCHORS";
+pub static STATE_TREE: &str = "STATE";
+pub static RECEIPTS_TREE: &str = "RECEIPTS";
 pub static OLD_AMOUNT_SUMMARY: &str = "SUMMARYOLD/";
 
 pub static GAS_SUMMARY: &str = "GASSUMMARY/";
 pub static OLD_GAS_SUMMARY: &str = "GASSUMMARYOLD/";
 
+pub static UNDO_FOLDER: &str = "UNDO/";
+
 pub static MAIN_CHAIN_DIRECTORY: &str = "MAIN/";
+pub static HEADERS_DIRECTORY: &str = "HEADERS/";
 
 pub static DERIVATIVE_CHAINS_DIRECTORY: &str = "DERIVATIVES/";
 pub static CHAINS_FOLDER: &str = "CHAINS/";
 
 pub static BLOCKS_FOLDER: &str = "BLOCKS/";
+pub static SIDE_BLOCKS_FOLDER: &str = "SIDE/";
 pub static REFERENCES_FOLDER: &str = "REF/";
 pub static TRANSACTIONS_FOLDER: &str = "TRANSACTIONS/";
+pub static ADDRESS_HISTORY_TREE: &str = "ADDRESSES";
+pub static TRANSACTION_BLOCKS_TREE: &str = "BLOCKS";
 
 pub static CONFIG_FILE: &str = "Chain.config";
 pub static LOOKUP_TABLE_FILE: &str = "LookUpTable.dat";
@@ -42,13 +54,52 @@ pub static INCEPTION_TIMESTAMP: u64 = 1597924800;
 
 pub static BLOCKS_PER_EPOCH: usize = 1000000;
 
+/// Height of the first main chain block using `MerkleVersion::V2` trees
+pub static MERKLE_V2_HEIGHT: u64 = 50000;
+
 pub static TIME_PER_BLOCK: u64 = 600;
 
+pub static MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
+pub static NETWORK_VERSION: u32 = 1;
+pub static MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+pub static MAX_BLOCKS_INVENTORY: usize = 500;
+pub static HANDSHAKE_TIMEOUT: u64 = 10;
+pub static MAX_HEADERS_PER_MESSAGE: usize = 2000;
+pub static MAX_BODIES_IN_FLIGHT: usize = 16;
+pub static BODIES_WINDOW: usize = 1024;
+pub static BODY_REQUEST_TIMEOUT: u64 = 30;
+pub static SYNC_TICK: u64 = 1;
+
+pub static MAX_RPC_REQUEST_SIZE: usize = 8 * 1024 * 1024;
+pub static MAX_RPC_HEADERS_SIZE: usize = 16 * 1024;
+/// Most transactions of the address history returned at once
+pub static MAX_HISTORY_PAGE: usize = 1000;
+
+pub static HASHES_PER_CHECK: u64 = 1024;
+/// Milliseconds between the checks of the chain tip while mining
+pub static MINER_TIP_CHECK: u64 = 100;
+
+pub static MAX_STRATUM_LINE: usize = 64 * 1024;
+pub static MAX_STRATUM_JOBS: usize = 16;
+/// Seconds between the checks of the block template by the stratum server
+pub static STRATUM_JOB_REFRESH: u64 = 1;
+
+pub static MAX_REORG_DEPTH: usize = 100;
+
+pub static TX_POOL_MAX_TRANSACTIONS: usize = 50000;
+pub static TX_POOL_MAX_BYTES: usize = 64 * 1024 * 1024;
+pub static TX_POOL_EXPIRY: u64 = 3 * 24 * 60 * 60;
+/// Amount of the latest rejected transactions the pool remembers the reasons for
+pub static TX_POOL_MAX_REJECTED: usize = 10000;
+
 lazy_static! {
     pub static ref COIN_FRACTIONS: U256 = U256::from_dec_str("1000000000000000000").unwrap();
     pub static ref INITIAL_FEE: U256 = U256::from_dec_str("25000000000000000").unwrap(); // 100_000_000//4
     pub static ref FEE_STEP: U256 = U256::from_dec_str("62500").unwrap(); // 100_000_000//255
     pub static ref MAIN_CHAIN_PAYMENT: U256 = *INITIAL_FEE;
+    pub static ref DERIVATIVE_BLOCK_GAS: U256 = *MAIN_CHAIN_PAYMENT; // gas minted by a derivative block
+    pub static ref MAX_FEE: U256 = *FEE_STEP * 256usize; // fee of the hardest difficulty
     pub static ref COINS_PER_CYCLE: U256 = (*MAIN_CHAIN_PAYMENT*2000usize*BLOCKS_PER_EPOCH) + *COIN_FRACTIONS*10000usize;
     pub static ref BYTE_GAS_PRICE: U256 = U256::from_dec_str("625000000000").unwrap();
 }
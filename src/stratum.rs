@@ -0,0 +1,440 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use error_stack::{Report, ResultExt};
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{
+    blockchaintree::SharedBlockChainTree,
+    errors::{BlockChainTreeError, ChainErrorKind, StratumError, StratumErrorKind},
+    miner::MiningJob,
+    network::Node,
+    static_values::{BLOCKS_PER_EPOCH, MAX_STRATUM_JOBS, MAX_STRATUM_LINE, STRATUM_JOB_REFRESH},
+    tools,
+    types::{Address, Hash},
+};
+
+pub const OTHER: i64 = 20;
+pub const JOB_NOT_FOUND: i64 = 21;
+pub const DUPLICATE_SHARE: i64 = 22;
+pub const LOW_DIFFICULTY_SHARE: i64 = 23;
+pub const NOT_SUBSCRIBED: i64 = 25;
+
+/// Reads a single line of the json message
+///
+/// Returns `None` if the connection is closed
+pub async fn read_line<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+) -> Result<Option<Value>, Report<StratumError>> {
+    let mut line = String::new();
+    let read = reader
+        .take(MAX_STRATUM_LINE as u64 + 1)
+        .read_line(&mut line)
+        .await
+        .change_context(StratumError::Stratum(StratumErrorKind::Read))?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read > MAX_STRATUM_LINE {
+        return Err(Report::new(StratumError::Stratum(
+            StratumErrorKind::TooLarge,
+        )));
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .change_context(StratumError::Stratum(StratumErrorKind::Read))
+        .attach_printable("message is not a json")
+}
+
+/// Writes json message followed by the line break
+pub async fn write_line<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Value,
+) -> Result<(), Report<StratumError>> {
+    let mut line = message.to_string();
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .await
+        .change_context(StratumError::Stratum(StratumErrorKind::Write))
+}
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    current: Option<u64>,
+    by_id: BTreeMap<u64, MiningJob>,
+    /// Accepted shares of the kept jobs
+    submitted: HashSet<(u64, Hash)>,
+}
+
+struct Session {
+    worker: String,
+    sender: mpsc::UnboundedSender<Value>,
+}
+
+/// Work server for the outside miners
+///
+/// Speaks line-delimited json over tcp, similar to stratum:
+///
+/// - `mining.subscribe [worker]` returns the session id, its extranonce and the share difficulty
+/// - `mining.notify {job_id, previous_hash, merkle_root, difficulty, height, clean}` is sent
+///   by the server for every new job, `clean` means that the previous jobs are stale
+/// - `mining.submit [job_id, pow]` submits a share
+///
+/// The pow of the share has to start with the 8 bytes of the extranonce, so the miners
+/// never search the same nonces. Shares are accepted at the share difficulty, the ones
+/// satisfying the difficulty of the block are emitted as the new block of the `founder`
+pub struct StratumServer {
+    tree: SharedBlockChainTree,
+    node: Option<Arc<Node>>,
+    founder: Address,
+    share_difficulty: Hash,
+    local_address: SocketAddr,
+    jobs: Mutex<Jobs>,
+    sessions: Mutex<HashMap<u64, Session>>,
+    shares: Mutex<HashMap<String, u64>>,
+    next_session: AtomicU64,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl StratumServer {
+    /// Starts listening for the miners
+    ///
+    /// Mined blocks are announced to the peers of the `node` if it's set
+    pub async fn bind(
+        tree: SharedBlockChainTree,
+        node: Option<Arc<Node>>,
+        founder: Address,
+        share_difficulty: Hash,
+        address: SocketAddr,
+    ) -> Result<Arc<Self>, Report<StratumError>> {
+        let listener = TcpListener::bind(address)
+            .await
+            .change_context(StratumError::Stratum(StratumErrorKind::Bind))
+            .attach_printable(format!("failed to bind {}", address))?;
+        let local_address = listener
+            .local_addr()
+            .change_context(StratumError::Stratum(StratumErrorKind::Bind))?;
+
+        let server = Arc::new(Self {
+            tree,
+            node,
+            founder,
+            share_difficulty,
+            local_address,
+            jobs: Mutex::new(Jobs::default()),
+            sessions: Mutex::new(HashMap::new()),
+            shares: Mutex::new(HashMap::new()),
+            next_session: AtomicU64::new(0),
+            tasks: Mutex::new(Vec::new()),
+        });
+        server.refresh_job().await?;
+
+        let accepting = server.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                let (stream, address) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(error) => {
+                        log::warn!("Failed to accept miner connection: {}", error);
+                        continue;
+                    }
+                };
+                let server = accepting.clone();
+                tokio::spawn(async move {
+                    if let Err(report) = server.serve_miner(stream).await {
+                        log::debug!("Miner {} is disconnected: {:?}", address, report);
+                    }
+                });
+            }
+        });
+        server.tasks.lock().push(handle);
+
+        let refreshing = server.clone();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(STRATUM_JOB_REFRESH));
+            loop {
+                interval.tick().await;
+                if let Err(report) = refreshing.refresh_job().await {
+                    log::warn!("Failed to refresh the mining job: {:?}", report);
+                }
+            }
+        });
+        server.tasks.lock().push(handle);
+
+        Ok(server)
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// Accepted shares of every worker
+    pub fn shares(&self) -> HashMap<String, u64> {
+        self.shares.lock().clone()
+    }
+
+    /// Disconnects every miner and stops listening
+    pub fn shutdown(&self) {
+        for task in self.tasks.lock().drain(..) {
+            task.abort();
+        }
+        self.sessions.lock().clear();
+    }
+
+    /// Creates new job if the block template has changed and sends it to the miners
+    pub async fn refresh_job(&self) -> Result<(), Report<StratumError>> {
+        let template = self
+            .tree
+            .read()
+            .await
+            .get_block_template()
+            .await
+            .change_context(StratumError::Stratum(StratumErrorKind::Tree))?;
+
+        let notification = {
+            let mut jobs = self.jobs.lock();
+            let current = jobs.current.and_then(|id| jobs.by_id.get(&id));
+            if let Some(current) = current {
+                if current.previous_hash == template.previous_hash
+                    && current.transactions == template.transactions
+                {
+                    return Ok(());
+                }
+            }
+            let clean =
+                current.is_none_or(|current| current.previous_hash != template.previous_hash);
+            if clean {
+                jobs.by_id.clear();
+                jobs.submitted.clear();
+            }
+            jobs.current = None;
+
+            // only summarize blocks are allowed to be empty
+            if !(template.height % BLOCKS_PER_EPOCH).is_zero() && template.transactions.is_empty() {
+                return Ok(());
+            }
+
+            let id = jobs.next_id;
+            jobs.next_id += 1;
+            let job = MiningJob::new(template);
+            let notification = notify(id, &job, clean);
+            jobs.by_id.insert(id, job);
+            jobs.current = Some(id);
+            while jobs.by_id.len() > MAX_STRATUM_JOBS {
+                if let Some((old, _)) = jobs.by_id.pop_first() {
+                    jobs.submitted.retain(|(job, _)| *job != old);
+                }
+            }
+            notification
+        };
+
+        for session in self.sessions.lock().values() {
+            let _ = session.sender.send(notification.clone());
+        }
+        Ok(())
+    }
+
+    async fn serve_miner(self: Arc<Self>, stream: TcpStream) -> Result<(), Report<StratumError>> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Value>();
+
+        let writing = tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                if write_line(&mut writer, &message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut session = None;
+        let result = loop {
+            let request = match read_line(&mut reader).await {
+                Ok(Some(request)) => request,
+                Ok(None) => break Ok(()),
+                Err(report) => break Err(report),
+            };
+            let id = request.get("id").cloned().unwrap_or(Value::Null);
+            let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+            let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+            let result = match (method, session) {
+                ("mining.subscribe", _) => {
+                    let worker = params
+                        .get(0)
+                        .and_then(Value::as_str)
+                        .unwrap_or("")
+                        .to_string();
+                    let id = self.next_session.fetch_add(1, Ordering::Relaxed);
+                    self.sessions.lock().insert(
+                        id,
+                        Session {
+                            worker,
+                            sender: sender.clone(),
+                        },
+                    );
+                    session = Some(id);
+                    Ok(json!({
+                        "session": id,
+                        "extranonce": hex::encode(id.to_be_bytes()),
+                        "share_difficulty": hex::encode(self.share_difficulty),
+                    }))
+                }
+                ("mining.submit", Some(session)) => self.submit(session, &params).await,
+                ("mining.submit", None) => Err((NOT_SUBSCRIBED, "not subscribed".to_string())),
+                _ => Err((OTHER, format!("unknown method {}", method))),
+            };
+            let response = match result {
+                Ok(result) => json!({"id": id, "result": result, "error": null}),
+                Err((code, message)) => {
+                    json!({"id": id, "result": null, "error": [code, message, null]})
+                }
+            };
+            let _ = sender.send(response);
+
+            // the current job is sent right after the subscription
+            if method == "mining.subscribe" {
+                let jobs = self.jobs.lock();
+                if let Some((id, job)) = jobs
+                    .current
+                    .and_then(|id| jobs.by_id.get(&id).map(|job| (id, job)))
+                {
+                    let _ = sender.send(notify(id, job, true));
+                }
+            }
+        };
+
+        if let Some(session) = session {
+            self.sessions.lock().remove(&session);
+        }
+        drop(sender);
+        let _ = writing.await;
+        result
+    }
+
+    async fn submit(&self, session: u64, params: &Value) -> Result<Value, (i64, String)> {
+        let job_id = params
+            .get(0)
+            .and_then(Value::as_u64)
+            .ok_or((OTHER, "job id expected".to_string()))?;
+        let pow: Hash = params
+            .get(1)
+            .and_then(Value::as_str)
+            .and_then(|pow| hex::decode(pow).ok())
+            .and_then(|pow| pow.try_into().ok())
+            .ok_or((OTHER, "pow should be 32 hex encoded bytes".to_string()))?;
+        if pow[..8] != session.to_be_bytes() {
+            return Err((OTHER, "pow doesn't start with the extranonce".into()));
+        }
+
+        let job = self
+            .jobs
+            .lock()
+            .by_id
+            .get(&job_id)
+            .cloned()
+            .ok_or((JOB_NOT_FOUND, "job is not found".to_string()))?;
+        // share difficulty can't be harder than the difficulty of the block
+        let share_difficulty = if tools::count_leading_zeros(&job.difficulty)
+            < tools::count_leading_zeros(&self.share_difficulty)
+        {
+            job.difficulty
+        } else {
+            self.share_difficulty
+        };
+        if !tools::check_pow_with_root(
+            &job.previous_hash,
+            &share_difficulty,
+            &job.merkle_root,
+            &pow,
+        ) {
+            return Err((LOW_DIFFICULTY_SHARE, "low difficulty share".into()));
+        }
+        if !self.jobs.lock().submitted.insert((job_id, pow)) {
+            return Err((DUPLICATE_SHARE, "duplicate share".into()));
+        }
+
+        let worker = self
+            .sessions
+            .lock()
+            .get(&session)
+            .map(|session| session.worker.clone())
+            .unwrap_or_default();
+        *self.shares.lock().entry(worker).or_default() += 1;
+
+        if job.check(&pow) {
+            self.emit_block(&job, &pow).await;
+        }
+        Ok(Value::Bool(true))
+    }
+
+    async fn emit_block(&self, job: &MiningJob, pow: &Hash) {
+        let block = self
+            .tree
+            .write()
+            .await
+            .emmit_new_main_block(
+                pow,
+                &self.founder,
+                &job.transactions,
+                tools::current_timestamp(),
+            )
+            .await;
+        let hash = block.and_then(|block| {
+            block.hash().change_context(BlockChainTreeError::Chain(
+                ChainErrorKind::FailedToHashBlock,
+            ))
+        });
+        let hash = match hash {
+            Ok(hash) => hash,
+            Err(report) => {
+                log::warn!("Block submitted by the miner is rejected: {:?}", report);
+                return;
+            }
+        };
+        log::info!(
+            "Miners found block {} at height {}",
+            hex::encode(hash),
+            job.height
+        );
+        if let Some(node) = &self.node {
+            if let Err(report) = node.announce_block(&hash).await {
+                log::warn!("Failed to announce the block: {:?}", report);
+            }
+        }
+        if let Err(report) = self.refresh_job().await {
+            log::warn!("Failed to refresh the mining job: {:?}", report);
+        }
+    }
+}
+
+fn notify(id: u64, job: &MiningJob, clean: bool) -> Value {
+    json!({
+        "id": null,
+        "method": "mining.notify",
+        "params": {
+            "job_id": id,
+            "previous_hash": hex::encode(job.previous_hash),
+            "merkle_root": hex::encode(job.merkle_root),
+            "difficulty": hex::encode(job.difficulty),
+            "height": job.height.to_string(),
+            "clean": clean,
+        },
+    })
+}
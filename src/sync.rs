@@ -0,0 +1,113 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use primitive_types::U256;
+
+use crate::static_values::{BODIES_WINDOW, BODY_REQUEST_TIMEOUT, MAX_BODIES_IN_FLIGHT};
+
+/// Dumped block together with the dumps of its transactions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadedBlock {
+    pub block: Vec<u8>,
+    pub transactions: Vec<Vec<u8>>,
+}
+
+/// Schedules the download of the block bodies for the validated headers
+///
+/// Bodies are requested from every peer that has them, at most `MAX_BODIES_IN_FLIGHT`
+/// per peer, and are kept until every preceding block is imported.
+/// Requests which are not answered in `BODY_REQUEST_TIMEOUT` are sent again
+#[derive(Debug, Default)]
+pub struct BlockDownloader {
+    requested: HashMap<U256, (u64, Instant)>,
+    downloaded: BTreeMap<U256, DownloadedBlock>,
+}
+
+impl BlockDownloader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assigns missing bodies in `from..to` to the peers
+    ///
+    /// `peers` are the ids and the heights of the connected peers, bodies are requested
+    /// only from the peers that have them. Returns heights to request grouped by the peer id
+    pub fn schedule(
+        &mut self,
+        from: U256,
+        to: U256,
+        peers: &[(u64, U256)],
+        now: Instant,
+    ) -> Vec<(u64, Vec<U256>)> {
+        let timeout = Duration::from_secs(BODY_REQUEST_TIMEOUT);
+        self.requested
+            .retain(|height, (_, sent)| *height >= from && now.duration_since(*sent) < timeout);
+        self.downloaded = self.downloaded.split_off(&from);
+
+        let mut in_flight: HashMap<u64, usize> = peers.iter().map(|(id, _)| (*id, 0)).collect();
+        for (peer, _) in self.requested.values() {
+            if let Some(amount) = in_flight.get_mut(peer) {
+                *amount += 1;
+            }
+        }
+
+        let mut scheduled: BTreeMap<u64, Vec<U256>> = BTreeMap::new();
+        let to = to.min(from + BODIES_WINDOW);
+        let mut height = from;
+        while height < to {
+            if !self.requested.contains_key(&height) && !self.downloaded.contains_key(&height) {
+                let peer = peers
+                    .iter()
+                    .filter(|(id, peer_height)| {
+                        *peer_height > height && in_flight[id] < MAX_BODIES_IN_FLIGHT
+                    })
+                    .min_by_key(|(id, _)| in_flight[id]);
+                if let Some((id, _)) = peer {
+                    *in_flight.entry(*id).or_default() += 1;
+                    self.requested.insert(height, (*id, now));
+                    scheduled.entry(*id).or_default().push(height);
+                }
+            }
+            height += U256::one();
+        }
+
+        scheduled.into_iter().collect()
+    }
+
+    /// Keeps the received body until it can be imported
+    ///
+    /// Returns `false` if the body was not requested
+    pub fn received(&mut self, height: U256, block: DownloadedBlock) -> bool {
+        if self.requested.remove(&height).is_none() {
+            return false;
+        }
+        self.downloaded.insert(height, block);
+        true
+    }
+
+    /// Takes the body of the block with the height if it was downloaded
+    pub fn take(&mut self, height: &U256) -> Option<DownloadedBlock> {
+        self.downloaded.remove(height)
+    }
+
+    /// Allows the body to be requested from another peer
+    pub fn not_found(&mut self, height: &U256) {
+        self.requested.remove(height);
+    }
+
+    /// Allows the bodies requested from the disconnected peer to be requested again
+    pub fn peer_lost(&mut self, id: u64) {
+        self.requested.retain(|_, (peer, _)| *peer != id);
+    }
+
+    pub fn reset(&mut self) {
+        self.requested.clear();
+        self.downloaded.clear();
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.requested.len()
+    }
+}
@@ -0,0 +1,407 @@
+use std::convert::TryInto;
+
+use error_stack::{Report, Result, ResultExt};
+use primitive_types::U256;
+use secp256k1::{Message, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+use sled::{Db, Tree};
+
+use crate::dump_headers::Headers;
+use crate::errors::*;
+use crate::static_values::{TOKENS_TREE, TOKEN_BALANCES_PREFIX};
+use crate::tools;
+use crate::transaction::{verify_signature, Transactionable};
+use crate::types::{Address, Hash};
+
+/// Operation performed by the token transaction
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenOperation {
+    /// Creates new token, the whole supply goes to the receiver
+    Issue = 0,
+    /// Moves tokens from the sender to the receiver
+    Transfer = 1,
+    /// Destroys tokens of the sender decreasing the supply
+    Burn = 2,
+}
+
+impl TokenOperation {
+    pub fn from_u8(operation: u8) -> Result<TokenOperation, TransactionError> {
+        match operation {
+            0 => Ok(TokenOperation::Issue),
+            1 => Ok(TokenOperation::Transfer),
+            2 => Ok(TokenOperation::Burn),
+            _ => Err(Report::new(TransactionError::Tx(TxErrorKind::Parse))
+                .attach_printable(format!("unknown token operation: {}", operation))),
+        }
+    }
+}
+
+/// Token part of the transaction, see `Transactionable::get_token_action`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenAction {
+    pub operation: TokenOperation,
+    pub token: Hash,
+    pub amount: U256,
+}
+
+/// Identifier of the token issued by the `issuer` with the transaction of the specified nonce
+pub fn token_id(issuer: &Address, nonce: u64) -> Hash {
+    let mut data = Vec::with_capacity(1 + 33 + 8);
+    data.push(Headers::Token as u8);
+    data.extend(issuer.iter());
+    data.extend(nonce.to_be_bytes().iter());
+    tools::hash(&data)
+}
+
+/// Tree with issuers and supplies of all tokens
+pub fn open_tokens_tree(summary_db: &Db) -> Result<Tree, BlockChainTreeError> {
+    summary_db
+        .open_tree(TOKENS_TREE)
+        .change_context(BlockChainTreeError::Token(TokenErrorKind::OpenTree))
+}
+
+/// Tree with balances of the token holders
+pub fn open_balances_tree(summary_db: &Db, token: &Hash) -> Result<Tree, BlockChainTreeError> {
+    let mut name = Vec::with_capacity(TOKEN_BALANCES_PREFIX.len() + 32);
+    name.extend(TOKEN_BALANCES_PREFIX.as_bytes());
+    name.extend(token.iter());
+    summary_db
+        .open_tree(name)
+        .change_context(BlockChainTreeError::Token(TokenErrorKind::OpenTree))
+}
+
+/// Issuer and current supply of the token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub issuer: Address,
+    pub supply: U256,
+}
+
+impl TokenInfo {
+    pub fn dump(&self) -> Result<Vec<u8>, BlockChainTreeError> {
+        let mut dump = Vec::with_capacity(33 + tools::u256_size(&self.supply));
+        dump.extend(self.issuer.iter());
+        tools::dump_u256(&self.supply, &mut dump)
+            .change_context(BlockChainTreeError::Token(TokenErrorKind::Dump))?;
+        Ok(dump)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<TokenInfo, BlockChainTreeError> {
+        if data.len() < 34 {
+            return Err(
+                Report::new(BlockChainTreeError::Token(TokenErrorKind::Parse))
+                    .attach_printable("Data length < 34"),
+            );
+        }
+        let issuer: Address = unsafe { data[..33].try_into().unwrap_unchecked() };
+        let (supply, _) = tools::load_u256(&data[33..])
+            .change_context(BlockChainTreeError::Token(TokenErrorKind::Parse))?;
+        Ok(TokenInfo { issuer, supply })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenTransaction {
+    operation: TokenOperation,
+    sender: Address,
+    receiver: Address,
+    timestamp: u64,
+    nonce: u64,
+    signature: [u8; 64],
+    token: Hash,
+    amount: U256,
+    hash: Hash,
+}
+
+impl TokenTransaction {
+    fn signed_data(
+        operation: TokenOperation,
+        sender: &Address,
+        receiver: &Address,
+        timestamp: u64,
+        nonce: u64,
+        token: &Hash,
+        amount: &U256,
+    ) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(2 + 33 + 33 + 8 + 8 + 32 + 32);
+        data.push(Headers::Token as u8);
+        data.push(operation as u8);
+        data.extend(sender.iter());
+        data.extend(receiver.iter());
+        data.extend(timestamp.to_be_bytes().iter());
+        data.extend(nonce.to_be_bytes().iter());
+        data.extend(token.iter());
+        // amount is signed in its full width, so the signed data is built without failing
+        let mut amount_bytes = [0u8; 32];
+        amount.to_big_endian(&mut amount_bytes);
+        data.extend(amount_bytes.iter());
+        data
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        operation: TokenOperation,
+        sender: Address,
+        receiver: Address,
+        timestamp: u64,
+        nonce: u64,
+        token: Hash,
+        amount: U256,
+        private_key: &[u8; 32],
+    ) -> Result<TokenTransaction, TransactionError> {
+        let data = Self::signed_data(
+            operation, &sender, &receiver, timestamp, nonce, &token, &amount,
+        );
+        let data_hash: [u8; 32] = Sha256::digest(data).into();
+        let message = Message::from_digest_slice(&data_hash)
+            .change_context(TransactionError::Tx(TxErrorKind::Dump))?;
+        let secret_key = SecretKey::from_slice(private_key)
+            .attach_printable("Error parsing private key")
+            .change_context(TransactionError::Tx(TxErrorKind::Parse))?;
+        let signature = Secp256k1::new()
+            .sign_ecdsa(&message, &secret_key)
+            .serialize_compact();
+
+        Self::new_signed(
+            operation, sender, receiver, timestamp, nonce, token, amount, signature,
+        )
+    }
+
+    /// Issues new token with the whole `supply` given to the `receiver`
+    ///
+    /// Token id is derived from the issuer and the nonce, see `token_id`
+    pub fn issue(
+        issuer: Address,
+        receiver: Address,
+        timestamp: u64,
+        nonce: u64,
+        supply: U256,
+        private_key: &[u8; 32],
+    ) -> Result<TokenTransaction, TransactionError> {
+        Self::new(
+            TokenOperation::Issue,
+            issuer,
+            receiver,
+            timestamp,
+            nonce,
+            token_id(&issuer, nonce),
+            supply,
+            private_key,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn transfer(
+        sender: Address,
+        receiver: Address,
+        timestamp: u64,
+        nonce: u64,
+        token: Hash,
+        amount: U256,
+        private_key: &[u8; 32],
+    ) -> Result<TokenTransaction, TransactionError> {
+        Self::new(
+            TokenOperation::Transfer,
+            sender,
+            receiver,
+            timestamp,
+            nonce,
+            token,
+            amount,
+            private_key,
+        )
+    }
+
+    pub fn burn(
+        sender: Address,
+        timestamp: u64,
+        nonce: u64,
+        token: Hash,
+        amount: U256,
+        private_key: &[u8; 32],
+    ) -> Result<TokenTransaction, TransactionError> {
+        Self::new(
+            TokenOperation::Burn,
+            sender,
+            sender,
+            timestamp,
+            nonce,
+            token,
+            amount,
+            private_key,
+        )
+    }
+
+    /// Creates token transaction with already made signature
+    ///
+    /// The signature is not checked here, every ingestion path verifies it
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_signed(
+        operation: TokenOperation,
+        sender: Address,
+        receiver: Address,
+        timestamp: u64,
+        nonce: u64,
+        token: Hash,
+        amount: U256,
+        signature: [u8; 64],
+    ) -> Result<TokenTransaction, TransactionError> {
+        let mut tr = TokenTransaction {
+            operation,
+            sender,
+            receiver,
+            timestamp,
+            nonce,
+            signature,
+            token,
+            amount,
+            hash: [0; 32],
+        };
+        tr.hash = tools::hash(&tr.dump()?);
+        Ok(tr)
+    }
+
+    pub fn get_operation(&self) -> TokenOperation {
+        self.operation
+    }
+
+    pub fn get_token(&self) -> &Hash {
+        &self.token
+    }
+
+    pub fn get_token_amount(&self) -> &U256 {
+        &self.amount
+    }
+}
+
+impl Transactionable for TokenTransaction {
+    fn hash_without_signature(&self) -> [u8; 32] {
+        let data = Self::signed_data(
+            self.operation,
+            &self.sender,
+            &self.receiver,
+            self.timestamp,
+            self.nonce,
+            &self.token,
+            &self.amount,
+        );
+        Sha256::digest(data).into()
+    }
+
+    fn verify(&self) -> Result<bool, TransactionError> {
+        verify_signature(
+            &self.sender,
+            &self.hash_without_signature(),
+            &self.signature,
+        )
+    }
+
+    fn dump(&self) -> Result<Vec<u8>, TransactionError> {
+        let mut dump: Vec<u8> = Vec::with_capacity(self.get_dump_size());
+
+        // header
+        dump.push(Headers::Token as u8);
+        dump.push(self.operation as u8);
+
+        dump.extend(self.sender.iter());
+        dump.extend(self.receiver.iter());
+        dump.extend(self.timestamp.to_be_bytes().iter());
+        dump.extend(self.nonce.to_be_bytes().iter());
+        dump.extend(self.signature.iter());
+        dump.extend(self.token.iter());
+        tools::dump_u256(&self.amount, &mut dump)
+            .change_context(TransactionError::Tx(TxErrorKind::Dump))?;
+
+        Ok(dump)
+    }
+
+    fn get_dump_size(&self) -> usize {
+        1 + 1 + 33 + 33 + 8 + 8 + 64 + 32 + tools::u256_size(&self.amount)
+    }
+
+    /// Parses token transaction, `data` starts right after the header
+    fn parse(data: &[u8]) -> Result<TokenTransaction, TransactionError> {
+        if data.len() < 180 {
+            return Err(Report::new(TransactionError::Tx(TxErrorKind::Parse))
+                .attach_printable("Data length < 180"));
+        }
+        let operation = TokenOperation::from_u8(data[0])?;
+        let mut index: usize = 1;
+
+        let sender: Address = unsafe { data[index..index + 33].try_into().unwrap_unchecked() };
+        index += 33;
+
+        let receiver: Address = unsafe { data[index..index + 33].try_into().unwrap_unchecked() };
+        index += 33;
+
+        let timestamp =
+            u64::from_be_bytes(unsafe { data[index..index + 8].try_into().unwrap_unchecked() });
+        index += 8;
+
+        let nonce =
+            u64::from_be_bytes(unsafe { data[index..index + 8].try_into().unwrap_unchecked() });
+        index += 8;
+
+        let signature: [u8; 64] = unsafe { data[index..index + 64].try_into().unwrap_unchecked() };
+        index += 64;
+
+        let token: Hash = unsafe { data[index..index + 32].try_into().unwrap_unchecked() };
+        index += 32;
+
+        let (amount, amount_size) = tools::load_u256(&data[index..])
+            .attach_printable("Couldn't parse amount")
+            .change_context(TransactionError::Tx(TxErrorKind::Parse))?;
+        index += amount_size + 1;
+
+        if index != data.len() {
+            return Err(Report::new(TransactionError::Tx(TxErrorKind::Parse))
+                .attach_printable("Index != Tx size"));
+        }
+
+        TokenTransaction::new_signed(
+            operation, sender, receiver, timestamp, nonce, token, amount, signature,
+        )
+    }
+
+    fn get_sender(&self) -> &[u8; 33] {
+        &self.sender
+    }
+
+    fn get_receiver(&self) -> &[u8; 33] {
+        &self.receiver
+    }
+
+    fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    fn get_signature(&self) -> &[u8; 64] {
+        &self.signature
+    }
+
+    /// Token transactions do not move coins
+    fn get_amount(&self) -> Option<U256> {
+        None
+    }
+
+    fn get_data(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn hash(&self) -> [u8; 32] {
+        self.hash
+    }
+
+    fn get_token_action(&self) -> Option<TokenAction> {
+        Some(TokenAction {
+            operation: self.operation,
+            token: self.token,
+            amount: self.amount,
+        })
+    }
+}
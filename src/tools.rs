@@ -1,9 +1,8 @@
 use crate::errors::*;
-use crate::merkletree::MerkleTree;
+use crate::merkletree::{MerkleTree, MerkleVersion};
 use crate::static_values::{FEE_STEP, TIME_PER_BLOCK};
-use crate::transaction::{Transaction, Transactionable};
 use crate::types::Hash;
-use error_stack::{Report, Result, ResultExt};
+use error_stack::{AttachmentKind, FrameKind, Report, Result, ResultExt};
 use num_bigint::BigUint;
 use primitive_types::U256;
 use sha2::{Digest, Sha256};
@@ -13,6 +12,7 @@ use std::fs::File;
 use std::io::Read;
 use std::io::Write;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fs, io};
 
 pub fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
@@ -84,7 +84,7 @@ pub fn u256_size(number: &U256) -> usize {
         return 2;
     }
     let mut amount_byte_size: usize = bits_size / 8;
-    if number.bits() % 8 != 0 {
+    if !number.bits().is_multiple_of(8) {
         amount_byte_size += 1;
     }
 
@@ -132,7 +132,7 @@ pub fn bigint_size(number: &BigUint) -> usize {
         return 2;
     }
     let mut amount_byte_size: usize = bits_size / 8;
-    if number.bits() % 8 != 0 {
+    if !number.bits().is_multiple_of(8) {
         amount_byte_size += 1;
     }
 
@@ -197,16 +197,40 @@ pub fn count_leading_zeros(data: &[u8]) -> u32 {
     to_return
 }
 
+/// Difficulty which requires `zeros` leading zero bits of the pow hash
+pub fn difficulty_from_zeros(zeros: u32) -> Hash {
+    let mut difficulty = [0xFF; 32];
+    let zeros = zeros.min(256) as usize;
+    for byte in difficulty.iter_mut().take(zeros / 8) {
+        *byte = 0;
+    }
+    if zeros < 256 {
+        difficulty[zeros / 8] >>= zeros % 8;
+    }
+    difficulty
+}
+
 pub fn check_pow(
     hash: &[u8; 32],
     difficulty: &[u8; 32],
     transactions: &[Hash],
     pow: &[u8],
+    version: MerkleVersion,
+) -> bool {
+    let merkle_tree = MerkleTree::build_tree(transactions, version);
+    check_pow_with_root(hash, difficulty, merkle_tree.get_root(), pow)
+}
+
+/// Same as `check_pow`, but takes the merkle root of the transactions instead of the transactions
+pub fn check_pow_with_root(
+    hash: &[u8; 32],
+    difficulty: &[u8; 32],
+    merkle_root: &[u8; 32],
+    pow: &[u8],
 ) -> bool {
-    let merkle_tree = MerkleTree::build_tree(transactions);
     let mut hasher = Sha256::new();
     hasher.update(hash);
-    hasher.update(merkle_tree.get_root());
+    hasher.update(merkle_root);
     hasher.update(pow);
     let result: [u8; 32] = hasher.finalize().into();
 
@@ -244,12 +268,41 @@ pub fn recalculate_difficulty(prev_timestamp: u64, timestamp: u64, prev_difficul
     }
 }
 
+/// Expected amount of hashes required to satisfy the difficulty
+pub fn calculate_work(difficulty: &Hash) -> U256 {
+    let leading_zeros = count_leading_zeros(difficulty) as usize;
+    if leading_zeros >= 256 {
+        return U256::MAX;
+    }
+    U256::one() << leading_zeros
+}
+
 pub fn recalculate_fee(current_difficulty: &Hash) -> U256 {
     let leading_zeros = count_leading_zeros(current_difficulty);
 
     *FEE_STEP * leading_zeros
 }
 
+/// Current unix time in seconds
+pub fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Context of the report followed by its printable attachments
+pub fn report_message<C: error_stack::Context>(report: &Report<C>) -> String {
+    let mut message = report.current_context().to_string();
+    for frame in report.frames() {
+        if let FrameKind::Attachment(AttachmentKind::Printable(attachment)) = frame.kind() {
+            message.push_str(": ");
+            message.push_str(&attachment.to_string());
+        }
+    }
+    message
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -7,15 +7,94 @@ use std::convert::TryInto;
 use std::fmt::Debug;
 
 use crate::dump_headers::Headers;
+use crate::token::{TokenAction, TokenTransaction};
 use secp256k1::ecdsa::Signature;
 use secp256k1::PublicKey;
-use secp256k1::{Message, Secp256k1, SecretKey};
+use secp256k1::{Message, Secp256k1, SecretKey, VerifyOnly};
 use std::mem::transmute;
+use std::thread;
 
 use error_stack::{Report, Result, ResultExt};
 
 pub type TransactionableItem = Box<dyn Transactionable + Send + Sync>;
 
+lazy_static::lazy_static! {
+    static ref VERIFIER: Secp256k1<VerifyOnly> = Secp256k1::verification_only();
+}
+
+/// Minimal amount of transactions per thread in batch verification
+static VERIFY_CHUNK_SIZE: usize = 64;
+
+/// Checks the signature of the hashed data against the compressed public key of the sender
+pub(crate) fn verify_signature(
+    sender: &[u8; 33],
+    data_hash: &[u8; 32],
+    signature: &[u8; 64],
+) -> Result<bool, TransactionError> {
+    // load sender
+    let sender =
+        PublicKey::from_slice(sender).change_context(TransactionError::Tx(TxErrorKind::Verify))?;
+
+    // load message
+    let message = Message::from_digest_slice(data_hash)
+        .change_context(TransactionError::Tx(TxErrorKind::Verify))?;
+
+    // load signature
+    let signature = Signature::from_compact(signature)
+        .change_context(TransactionError::Tx(TxErrorKind::Verify))?;
+
+    // verifying hashed data with public key
+    Ok(VERIFIER.verify_ecdsa(&message, &signature, &sender).is_ok())
+}
+
+/// Verifies the transaction signature, fails with `TxErrorKind::Verify` if it is not valid
+pub fn verify_transaction(transaction: &dyn Transactionable) -> Result<(), TransactionError> {
+    if !transaction.verify()? {
+        return Err(
+            Report::new(TransactionError::Tx(TxErrorKind::Verify)).attach_printable(format!(
+                "wrong signature of transaction {}",
+                hex::encode(transaction.hash())
+            )),
+        );
+    }
+    Ok(())
+}
+
+/// Verifies signatures of all transactions
+///
+/// Big batches are split between threads, fails if any of the signatures is not valid
+pub fn verify_transactions(transactions: &[&dyn Transactionable]) -> Result<(), TransactionError> {
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let chunk_size = transactions.len().div_ceil(threads).max(VERIFY_CHUNK_SIZE);
+    if transactions.len() <= chunk_size {
+        for transaction in transactions {
+            verify_transaction(*transaction)?;
+        }
+        return Ok(());
+    }
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = transactions
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || -> Result<(), TransactionError> {
+                    for transaction in chunk {
+                        verify_transaction(*transaction)?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().map_err(|_| {
+                Report::new(TransactionError::Tx(TxErrorKind::Verify))
+                    .attach_printable("verification thread panicked")
+            })??;
+        }
+        Ok(())
+    })
+}
+
 impl Eq for TransactionableItem {}
 
 impl Ord for TransactionableItem {
@@ -68,9 +147,29 @@ pub trait Transactionable: Send + Sync {
     fn get_sender(&self) -> &[u8; 33];
     fn get_receiver(&self) -> &[u8; 33];
     fn get_timestamp(&self) -> u64;
+    fn get_nonce(&self) -> u64;
     fn get_signature(&self) -> &[u8; 64];
     fn get_amount(&self) -> Option<U256>;
     fn get_data(&self) -> Option<&[u8]>;
+
+    /// Token operation performed by the transaction, if any
+    fn get_token_action(&self) -> Option<TokenAction> {
+        None
+    }
+}
+
+/// Parses dump of any transaction type by its header
+pub fn parse_transactionable(data: &[u8]) -> Result<TransactionableItem, TransactionError> {
+    let header = data
+        .first()
+        .ok_or(Report::new(TransactionError::Tx(TxErrorKind::Parse)))
+        .attach_printable("empty transaction dump")?;
+    match Headers::from_u8(*header).change_context(TransactionError::Tx(TxErrorKind::Parse))? {
+        Headers::Transaction => Ok(Box::new(Transaction::parse(&data[1..])?)),
+        Headers::Token => Ok(Box::new(TokenTransaction::parse(&data[1..])?)),
+        _ => Err(Report::new(TransactionError::Tx(TxErrorKind::Parse))
+            .attach_printable("header is not a transaction header")),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -78,6 +177,7 @@ pub struct Transaction {
     sender: [u8; 33],
     receiver: [u8; 33],
     timestamp: u64,
+    nonce: u64,
     signature: [u8; 64],
     amount: U256,
     //gas_amount: U256,
@@ -90,6 +190,7 @@ impl Transaction {
         sender: &[u8; 33],
         receiver: &[u8; 33],
         timestamp: u64,
+        nonce: u64,
         amount: &U256,
         //gas_amount: &U256,
         data: Option<&[u8]>,
@@ -98,7 +199,7 @@ impl Transaction {
         let mut hasher = Sha256::new();
 
         let calculated_size: usize =
-            1 + 33 + 33 + 8 + tools::u256_size(amount) + data.map_or(0, |data| data.len());
+            1 + 33 + 33 + 8 + 8 + tools::u256_size(amount) + data.map_or(0, |data| data.len());
 
         let mut concatenated_input: Vec<u8> = Vec::with_capacity(calculated_size);
         concatenated_input.push(Headers::Transaction as u8);
@@ -111,6 +212,7 @@ impl Transaction {
         for byte in timestamp.to_be_bytes().iter() {
             concatenated_input.push(*byte);
         }
+        concatenated_input.extend(nonce.to_be_bytes().iter());
         tools::dump_u256(amount, &mut concatenated_input)
             .attach_printable("Error to dump amount")
             .change_context(TransactionError::Tx(TxErrorKind::Dump))?;
@@ -137,6 +239,7 @@ impl Transaction {
         sender: [u8; 33],
         receiver: [u8; 33],
         timestamp: u64,
+        nonce: u64,
         amount: U256,
         private_key: [u8; 32],
         data: Option<Vec<u8>>,
@@ -145,6 +248,7 @@ impl Transaction {
             &sender,
             &receiver,
             timestamp,
+            nonce,
             &amount,
             data.as_deref(),
             &private_key,
@@ -153,6 +257,7 @@ impl Transaction {
             sender,
             receiver,
             timestamp,
+            nonce,
             signature,
             amount,
             data,
@@ -163,11 +268,15 @@ impl Transaction {
         Ok(tr)
     }
 
+    /// Creates transaction with already made signature
+    ///
+    /// The signature is not checked here, every ingestion path verifies it with `verify_transaction`
     pub fn new_signed(
         //hash: [u8; 32],
         sender: [u8; 33],
         receiver: [u8; 33],
         timestamp: u64,
+        nonce: u64,
         amount: U256,
         data: Option<Vec<u8>>,
         signature: [u8; 64],
@@ -176,6 +285,7 @@ impl Transaction {
             sender,
             receiver,
             timestamp,
+            nonce,
             signature,
             amount,
             data,
@@ -199,6 +309,7 @@ impl Transactionable for Transaction {
             + 33
             + 33
             + 8
+            + 8
             + tools::u256_size(&self.amount)
             + self.data.as_ref().map_or(0, |data| data.len());
 
@@ -213,6 +324,7 @@ impl Transactionable for Transaction {
         for byte in self.timestamp.to_be_bytes().iter() {
             concatenated_input.push(*byte);
         }
+        concatenated_input.extend(self.nonce.to_be_bytes().iter());
         tools::dump_u256(&self.amount, &mut concatenated_input)
             .attach_printable("Error to dump amount")
             .change_context(TransactionError::Tx(TxErrorKind::Dump))
@@ -227,30 +339,11 @@ impl Transactionable for Transaction {
     }
 
     fn verify(&self) -> Result<bool, TransactionError> {
-        let signed_data_hash = self.hash_without_signature();
-
-        // load sender
-        let sender = PublicKey::from_slice(&self.sender)
-            .change_context(TransactionError::Tx(TxErrorKind::Verify))?;
-
-        // creating verifier
-        let verifier = Secp256k1::verification_only();
-
-        // load message
-        let message = Message::from_digest_slice(&signed_data_hash)
-            .change_context(TransactionError::Tx(TxErrorKind::Verify))?;
-
-        // load signature
-        let signature = Signature::from_compact(&self.signature)
-            .change_context(TransactionError::Tx(TxErrorKind::Verify))?;
-
-        // verifying hashed data with public key
-        let result = verifier.verify_ecdsa(&message, &signature, &sender);
-
-        match result {
-            Err(_) => Ok(false),
-            Ok(_) => Ok(true),
-        }
+        verify_signature(
+            &self.sender,
+            &self.hash_without_signature(),
+            &self.signature,
+        )
     }
 
     fn dump(&self) -> Result<Vec<u8>, TransactionError> {
@@ -274,6 +367,9 @@ impl Transactionable for Transaction {
         // timestamp
         transaction_dump.extend(self.timestamp.to_be_bytes().iter());
 
+        // nonce
+        transaction_dump.extend(self.nonce.to_be_bytes().iter());
+
         // signature
         for byte in self.signature.iter() {
             transaction_dump.push(*byte);
@@ -295,6 +391,7 @@ impl Transactionable for Transaction {
         1 + 33
             + 33
             + 8
+            + 8
             + 64
             + tools::u256_size(&self.amount)
             + self.data.as_ref().map_or(0, |data| data.len())
@@ -303,9 +400,9 @@ impl Transactionable for Transaction {
     fn parse(data: &[u8]) -> Result<Transaction, TransactionError> {
         let mut index: usize = 0;
 
-        if data.len() < 139 {
+        if data.len() < 147 {
             return Err(Report::new(TransactionError::Tx(TxErrorKind::Parse))
-                .attach_printable("Data length < 139"));
+                .attach_printable("Data length < 147"));
         }
 
         // parsing sender address
@@ -320,6 +417,10 @@ impl Transactionable for Transaction {
         let timestamp: u64 = u64::from_be_bytes(data[index..index + 8].try_into().unwrap());
         index += 8;
 
+        // parsing nonce
+        let nonce: u64 = u64::from_be_bytes(data[index..index + 8].try_into().unwrap());
+        index += 8;
+
         // parsing signature
         let signature: [u8; 64] = unsafe { data[index..index + 64].try_into().unwrap_unchecked() };
         index += 64;
@@ -346,7 +447,7 @@ impl Transactionable for Transaction {
         }
 
         Ok(Transaction::new_signed(
-            sender, receiver, timestamp, amount, tx_data, signature,
+            sender, receiver, timestamp, nonce, amount, tx_data, signature,
         ))
     }
 
@@ -362,6 +463,10 @@ impl Transactionable for Transaction {
         self.timestamp
     }
 
+    fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+
     fn get_signature(&self) -> &[u8; 64] {
         &self.signature
     }
@@ -1,17 +1,60 @@
-use std::collections::binary_heap::Iter;
-use std::collections::{BinaryHeap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Bound;
 use std::sync::Arc;
 
+use error_stack::Report;
+use primitive_types::U256;
 use tokio::sync::RwLock;
 
+use crate::errors::{BlockChainTreeError, TxPoolErrorKind};
+use crate::static_values::{
+    TX_POOL_EXPIRY, TX_POOL_MAX_BYTES, TX_POOL_MAX_REJECTED, TX_POOL_MAX_TRANSACTIONS,
+};
 use crate::transaction::TransactionableItem;
 
 pub type SharedTxPool = Arc<RwLock<TxPool>>;
 
+/// Limits of the transactions pool
+#[derive(Debug, Clone, Copy)]
+pub struct TxPoolConfig {
+    /// Maximum amount of transactions in the pool
+    pub max_transactions: usize,
+    /// Maximum summary size of dumped transactions in the pool
+    pub max_bytes: usize,
+    /// Seconds after the transaction timestamp it is kept in the pool
+    pub expiry: u64,
+}
+
+impl Default for TxPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_transactions: TX_POOL_MAX_TRANSACTIONS,
+            max_bytes: TX_POOL_MAX_BYTES,
+            expiry: TX_POOL_EXPIRY,
+        }
+    }
+}
+
+/// Priority of the pooled transaction
+///
+/// Higher fee per byte goes first, older transaction goes first among the equal ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct PoolKey {
+    fee_per_byte: U256,
+    timestamp: Reverse<u64>,
+    hash: [u8; 32],
+}
+
 #[derive(Default)]
 pub struct TxPool {
-    pool: BinaryHeap<TransactionableItem>,
-    hashes: HashSet<[u8; 32]>,
+    pool: BTreeMap<PoolKey, TransactionableItem>,
+    hashes: HashMap<[u8; 32], PoolKey>,
+    senders: BTreeMap<([u8; 33], u64), [u8; 32]>,
+    bytes: usize,
+    config: TxPoolConfig,
+    rejected: HashMap<[u8; 32], String>,
+    rejected_order: VecDeque<[u8; 32]>,
 }
 
 impl TxPool {
@@ -20,39 +63,263 @@ impl TxPool {
     }
     pub fn with_capacity(capacity: usize) -> TxPool {
         TxPool {
-            pool: BinaryHeap::with_capacity(capacity),
-            hashes: HashSet::with_capacity(capacity),
+            hashes: HashMap::with_capacity(capacity),
+            ..Default::default()
+        }
+    }
+    pub fn with_config(config: TxPoolConfig) -> TxPool {
+        TxPool {
+            config,
+            ..Default::default()
         }
     }
 
-    pub fn push(&mut self, transaction: TransactionableItem) -> bool {
-        if !self.hashes.insert(transaction.hash()) {
-            return false;
+    pub fn get_config(&self) -> &TxPoolConfig {
+        &self.config
+    }
+
+    /// Sets new limits, evicts the lowest priority transactions that do not fit
+    ///
+    /// Higher nonce transactions of the evicted transaction's sender are evicted with it
+    pub fn set_config(&mut self, config: TxPoolConfig) {
+        self.config = config;
+        while self.len() > self.config.max_transactions || self.bytes > self.config.max_bytes {
+            let Some((key, transaction)) = self.pool.iter().next() else {
+                break;
+            };
+            let evicted: Vec<PoolKey> = std::iter::once(*key)
+                .chain(self.dependents(transaction))
+                .collect();
+            for key in evicted {
+                self.remove_key(&key);
+                self.reject(
+                    key.hash,
+                    BlockChainTreeError::TxPool(TxPoolErrorKind::PoolFull).to_string(),
+                );
+            }
         }
-        self.pool.push(transaction);
-        true
+    }
+
+    /// Keys of the pooled transactions of the same sender with higher nonces
+    ///
+    /// They can't be applied without the transaction, so they leave the pool together with it
+    fn dependents(&self, transaction: &TransactionableItem) -> Vec<PoolKey> {
+        let sender = *transaction.get_sender();
+        self.senders
+            .range((
+                Bound::Excluded((sender, transaction.get_nonce())),
+                Bound::Included((sender, u64::MAX)),
+            ))
+            .map(|(_, hash)| self.hashes[hash])
+            .collect()
+    }
+
+    /// Puts transaction into the pool
+    ///
+    /// `fee` is the whole amount paid for the transaction, the pool is ordered by fee per byte.
+    /// Transaction of the same sender with the same nonce is replaced only if the new one
+    /// pays more per byte. When the pool is full, the lowest paying transactions are evicted
+    /// together with the higher nonce transactions of their senders
+    pub fn push(
+        &mut self,
+        transaction: TransactionableItem,
+        fee: U256,
+    ) -> Result<(), Report<BlockChainTreeError>> {
+        let hash = transaction.hash();
+        if self.hashes.contains_key(&hash) {
+            return Err(Report::new(BlockChainTreeError::TxPool(
+                TxPoolErrorKind::AlreadyExists,
+            )));
+        }
+        let size = transaction.get_dump_size();
+        if size > self.config.max_bytes {
+            return Err(Report::new(BlockChainTreeError::TxPool(
+                TxPoolErrorKind::TooLarge,
+            )));
+        }
+        let key = PoolKey {
+            fee_per_byte: fee / size.max(1),
+            timestamp: Reverse(transaction.get_timestamp()),
+            hash,
+        };
+
+        let sender_key = (*transaction.get_sender(), transaction.get_nonce());
+        let replaced = self.senders.get(&sender_key).map(|hash| self.hashes[hash]);
+        let mut count = self.len() + 1;
+        let mut bytes = self.bytes + size;
+        if let Some(replaced) = replaced.as_ref() {
+            if replaced.fee_per_byte >= key.fee_per_byte {
+                return Err(Report::new(BlockChainTreeError::TxPool(
+                    TxPoolErrorKind::Underpriced,
+                )));
+            }
+            count -= 1;
+            bytes -= self.pool[replaced].get_dump_size();
+        }
+
+        let mut evicted: Vec<PoolKey> = Vec::new();
+        for (pooled_key, pooled) in self.pool.iter() {
+            if count <= self.config.max_transactions && bytes <= self.config.max_bytes {
+                break;
+            }
+            if Some(pooled_key) == replaced.as_ref() || evicted.contains(pooled_key) {
+                continue;
+            }
+            if *pooled_key >= key {
+                break;
+            }
+            if pooled.get_sender() == transaction.get_sender()
+                && pooled.get_nonce() < transaction.get_nonce()
+            {
+                // the new transaction depends on the evicted one
+                return Err(Report::new(BlockChainTreeError::TxPool(
+                    TxPoolErrorKind::PoolFull,
+                )));
+            }
+            for dependent in std::iter::once(*pooled_key).chain(self.dependents(pooled)) {
+                if evicted.contains(&dependent) {
+                    continue;
+                }
+                count -= 1;
+                bytes -= self.pool[&dependent].get_dump_size();
+                evicted.push(dependent);
+            }
+        }
+        if count > self.config.max_transactions || bytes > self.config.max_bytes {
+            return Err(Report::new(BlockChainTreeError::TxPool(
+                TxPoolErrorKind::PoolFull,
+            )));
+        }
+
+        if let Some(replaced) = replaced {
+            self.remove_key(&replaced);
+            self.reject(
+                replaced.hash,
+                format!("replaced by transaction {}", hex::encode(hash)),
+            );
+        }
+        for evicted in evicted {
+            self.remove_key(&evicted);
+            self.reject(
+                evicted.hash,
+                BlockChainTreeError::TxPool(TxPoolErrorKind::PoolFull).to_string(),
+            );
+        }
+
+        self.hashes.insert(hash, key);
+        self.senders.insert(sender_key, hash);
+        self.bytes += size;
+        self.pool.insert(key, transaction);
+        Ok(())
+    }
+
+    fn remove_key(&mut self, key: &PoolKey) -> Option<TransactionableItem> {
+        let transaction = self.pool.remove(key)?;
+        self.hashes.remove(&key.hash);
+        self.senders
+            .remove(&(*transaction.get_sender(), transaction.get_nonce()));
+        self.bytes -= transaction.get_dump_size();
+        Some(transaction)
     }
 
     pub fn len(&self) -> usize {
-        self.hashes.len()
+        self.pool.len()
     }
 
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
-    pub fn transactions_iter(&self) -> Iter<'_, TransactionableItem> {
-        self.pool.iter()
+    /// Summary size of the dumped transactions in the pool
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Iterates over transactions from the highest priority to the lowest
+    pub fn transactions_iter(&self) -> impl Iterator<Item = &TransactionableItem> {
+        self.pool.values().rev()
+    }
+
+    /// Returns transactions in the order they would be popped
+    pub fn transactions_by_priority(&self) -> Vec<&TransactionableItem> {
+        self.transactions_iter().collect()
     }
 
     pub fn pop(&mut self) -> Option<([u8; 32], TransactionableItem)> {
-        let tr = self.pool.pop()?;
-        let hash = tr.hash();
-        self.hashes.remove(&hash);
-        Some((hash, tr))
+        let key = *self.pool.keys().next_back()?;
+        let tr = self.remove_key(&key)?;
+        Some((key.hash, tr))
     }
 
     pub fn transaction_exists(&self, hash: &[u8; 32]) -> bool {
-        self.hashes.contains(hash)
+        self.hashes.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&TransactionableItem> {
+        self.pool.get(self.hashes.get(hash)?)
+    }
+
+    /// Returns hash of the pooled transaction of the sender with the specified nonce
+    pub fn get_by_sender(&self, sender: &[u8; 33], nonce: u64) -> Option<&[u8; 32]> {
+        self.senders.get(&(*sender, nonce))
+    }
+
+    /// Removes transactions with the specified hashes, returns amount of removed transactions
+    pub fn remove_transactions(&mut self, hashes: &[[u8; 32]]) -> usize {
+        let mut removed = 0;
+        for hash in hashes {
+            if let Some(key) = self.hashes.get(hash).copied() {
+                self.remove_key(&key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    pub fn is_expired(&self, timestamp: u64, now: u64) -> bool {
+        timestamp.saturating_add(self.config.expiry) < now
+    }
+
+    /// Removes transactions that are older than the pool expiry together with the higher nonce
+    /// transactions of their senders, returns amount of removed transactions
+    pub fn expire(&mut self, now: u64) -> usize {
+        let mut expired: Vec<PoolKey> = Vec::new();
+        for (key, transaction) in self.pool.iter() {
+            if !self.is_expired(key.timestamp.0, now) {
+                continue;
+            }
+            for dependent in std::iter::once(*key).chain(self.dependents(transaction)) {
+                if !expired.contains(&dependent) {
+                    expired.push(dependent);
+                }
+            }
+        }
+        for key in expired.iter() {
+            self.remove_key(key);
+            self.reject(
+                key.hash,
+                BlockChainTreeError::TxPool(TxPoolErrorKind::Expired).to_string(),
+            );
+        }
+        expired.len()
+    }
+
+    /// Remembers why the transaction was not accepted or was dropped
+    ///
+    /// Only the reasons for the latest `TX_POOL_MAX_REJECTED` transactions are kept
+    pub fn reject(&mut self, hash: [u8; 32], reason: String) {
+        if self.rejected.insert(hash, reason).is_none() {
+            self.rejected_order.push_back(hash);
+        }
+        while self.rejected_order.len() > TX_POOL_MAX_REJECTED {
+            if let Some(forgotten) = self.rejected_order.pop_front() {
+                self.rejected.remove(&forgotten);
+            }
+        }
+    }
+
+    /// Reason the transaction was rejected for, if it is still remembered
+    pub fn get_rejection(&self, hash: &[u8; 32]) -> Option<&str> {
+        self.rejected.get(hash).map(String::as_str)
     }
 }
@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305,
+};
+use error_stack::{Report, ResultExt};
+use secp256k1::{rand::RngCore, PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{WalletError, WalletErrorKind},
+    types::Address,
+};
+
+static KEYSTORE_VERSION: u32 = 1;
+static KDF_LOG_N: u8 = 15;
+static KDF_R: u32 = 8;
+static KDF_P: u32 = 1;
+
+/// Parameters of scrypt used to derive the encryption key from the password
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct KdfParams {
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+/// Private key encrypted with ChaCha20-Poly1305, the address is authenticated along with it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct EncryptedKey {
+    name: String,
+    address: String,
+    kdf: KdfParams,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    keys: Vec<EncryptedKey>,
+}
+
+/// Named secp256k1 keys stored in a json file
+///
+/// Addresses are kept in plain text, so they can be listed without the password,
+/// every private key is encrypted with its own password
+#[derive(Debug)]
+pub struct Keystore {
+    path: PathBuf,
+    keys: Vec<EncryptedKey>,
+}
+
+impl Keystore {
+    /// Opens the keystore, it's empty if the file doesn't exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Report<WalletError>> {
+        let path = path.as_ref().to_path_buf();
+        if !path.exists() {
+            return Ok(Self {
+                path,
+                keys: Vec::new(),
+            });
+        }
+
+        let file = std::fs::read(&path)
+            .change_context(WalletError::Wallet(WalletErrorKind::Read))
+            .attach_printable(format!("failed to read {}", path.display()))?;
+        let file: KeystoreFile = serde_json::from_slice(&file)
+            .change_context(WalletError::Wallet(WalletErrorKind::Parse))?;
+        if file.version != KEYSTORE_VERSION {
+            return Err(Report::new(WalletError::Wallet(WalletErrorKind::Parse))
+                .attach_printable(format!("unsupported keystore version {}", file.version)));
+        }
+
+        Ok(Self {
+            path,
+            keys: file.keys,
+        })
+    }
+
+    /// Writes the keystore to its file
+    pub fn save(&self) -> Result<(), Report<WalletError>> {
+        let file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            keys: self.keys.clone(),
+        };
+        let dump = serde_json::to_vec_pretty(&file)
+            .change_context(WalletError::Wallet(WalletErrorKind::Write))?;
+
+        // the old keystore is replaced only when the new one is completely written
+        let temporary = self.path.with_extension("tmp");
+        std::fs::write(&temporary, dump)
+            .change_context(WalletError::Wallet(WalletErrorKind::Write))
+            .attach_printable(format!("failed to write {}", temporary.display()))?;
+        std::fs::rename(&temporary, &self.path)
+            .change_context(WalletError::Wallet(WalletErrorKind::Write))
+            .attach_printable(format!("failed to replace {}", self.path.display()))
+    }
+
+    /// Generates new keypair, returns its address
+    pub fn generate(&mut self, name: &str, password: &str) -> Result<Address, Report<WalletError>> {
+        let (secret_key, _) = Secp256k1::new().generate_keypair(&mut secp256k1::rand::thread_rng());
+        self.import(name, &secret_key.secret_bytes(), password)
+    }
+
+    /// Adds existing private key, returns its address
+    pub fn import(
+        &mut self,
+        name: &str,
+        private_key: &[u8; 32],
+        password: &str,
+    ) -> Result<Address, Report<WalletError>> {
+        if self.keys.iter().any(|key| key.name == name) {
+            return Err(Report::new(WalletError::Wallet(WalletErrorKind::KeyExists))
+                .attach_printable(format!("name: {}", name)));
+        }
+        let address = address_of(private_key)?;
+
+        let mut salt = [0u8; 32];
+        let mut nonce = [0u8; 12];
+        secp256k1::rand::thread_rng().fill_bytes(&mut salt);
+        secp256k1::rand::thread_rng().fill_bytes(&mut nonce);
+        let kdf = KdfParams {
+            log_n: KDF_LOG_N,
+            r: KDF_R,
+            p: KDF_P,
+            salt: hex::encode(salt),
+        };
+        let cipher = cipher(password, &kdf)?;
+        let ciphertext = cipher
+            .encrypt(
+                (&nonce).into(),
+                Payload {
+                    msg: private_key,
+                    aad: &address,
+                },
+            )
+            .map_err(|_| Report::new(WalletError::Wallet(WalletErrorKind::Encryption)))?;
+
+        self.keys.push(EncryptedKey {
+            name: name.into(),
+            address: hex::encode(address),
+            kdf,
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        });
+        Ok(address)
+    }
+
+    /// Names and addresses of the stored keys
+    pub fn addresses(&self) -> Result<Vec<(String, Address)>, Report<WalletError>> {
+        self.keys
+            .iter()
+            .map(|key| Ok((key.name.clone(), decode(&key.address)?)))
+            .collect()
+    }
+
+    pub fn get_address(&self, name: &str) -> Result<Address, Report<WalletError>> {
+        decode(&self.find(name)?.address)
+    }
+
+    /// Decrypts the private key
+    pub fn get_private_key(
+        &self,
+        name: &str,
+        password: &str,
+    ) -> Result<[u8; 32], Report<WalletError>> {
+        let key = self.find(name)?;
+        let address: Address = decode(&key.address)?;
+        let nonce: [u8; 12] = decode(&key.nonce)?;
+        let ciphertext = hex::decode(&key.ciphertext)
+            .change_context(WalletError::Wallet(WalletErrorKind::Parse))?;
+
+        let private_key = cipher(password, &key.kdf)?
+            .decrypt(
+                (&nonce).into(),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &address,
+                },
+            )
+            .map_err(|_| Report::new(WalletError::Wallet(WalletErrorKind::WrongPassword)))?;
+
+        private_key
+            .try_into()
+            .map_err(|_| Report::new(WalletError::Wallet(WalletErrorKind::Parse)))
+    }
+
+    fn find(&self, name: &str) -> Result<&EncryptedKey, Report<WalletError>> {
+        self.keys
+            .iter()
+            .find(|key| key.name == name)
+            .ok_or(Report::new(WalletError::Wallet(
+                WalletErrorKind::KeyNotFound,
+            )))
+            .attach_printable(format!("name: {}", name))
+    }
+}
+
+/// Compressed public key of the private key
+pub fn address_of(private_key: &[u8; 32]) -> Result<Address, Report<WalletError>> {
+    let secret_key = SecretKey::from_slice(private_key)
+        .change_context(WalletError::Wallet(WalletErrorKind::WrongKey))?;
+    Ok(PublicKey::from_secret_key(&Secp256k1::new(), &secret_key).serialize())
+}
+
+fn cipher(password: &str, kdf: &KdfParams) -> Result<ChaCha20Poly1305, Report<WalletError>> {
+    let salt =
+        hex::decode(&kdf.salt).change_context(WalletError::Wallet(WalletErrorKind::Parse))?;
+    let params = scrypt::Params::new(kdf.log_n, kdf.r, kdf.p, 32).map_err(|_| {
+        Report::new(WalletError::Wallet(WalletErrorKind::Parse))
+            .attach_printable("wrong scrypt parameters")
+    })?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password.as_bytes(), &salt, &params, &mut key)
+        .map_err(|_| Report::new(WalletError::Wallet(WalletErrorKind::Encryption)))?;
+    Ok(ChaCha20Poly1305::new(&key.into()))
+}
+
+fn decode<const N: usize>(data: &str) -> Result<[u8; N], Report<WalletError>> {
+    hex::decode(data)
+        .change_context(WalletError::Wallet(WalletErrorKind::Parse))?
+        .try_into()
+        .map_err(|_| Report::new(WalletError::Wallet(WalletErrorKind::Parse)))
+}
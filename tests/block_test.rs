@@ -1,6 +1,10 @@
 use std::sync::Arc;
 
-use blockchaintree::block::{self, Block, DerivativeBlock};
+use blockchaintree::{
+    block::{self, Block, BlockVersion, DerivativeBlock},
+    chain,
+    dump_headers::Headers,
+};
 use primitive_types::U256;
 
 #[test]
@@ -49,9 +53,10 @@ fn dump_parse_block() {
 
     let dump = block.dump().unwrap();
 
-    let block_loaded = block::TransactionBlock::parse(&dump[1..]).unwrap();
+    let block_loaded = block::TransactionBlock::parse(&dump[1..], BlockVersion::V2).unwrap();
 
     assert_eq!(block.merkle_tree_root, block_loaded.merkle_tree_root);
+    assert_eq!(block.state_root, block_loaded.state_root);
     assert_eq!(block.fee, block_loaded.fee);
     assert_eq!(block.transactions, block_loaded.transactions);
 
@@ -77,6 +82,43 @@ fn dump_parse_block() {
     println!("{:?}", block_loaded);
 }
 
+#[test]
+fn dump_parse_anchored_transaction_block() {
+    let basic_data = block::BasicInfo {
+        timestamp: 160000,
+        pow: [0; 32],
+        previous_hash: [5; 32],
+        height: U256::from(17),
+        difficulty: [101; 32],
+        founder: [6; 33],
+    };
+    let anchors = vec![
+        block::DerivativeAnchor {
+            owner: [2; 33],
+            hash: [3; 32],
+            height: U256::zero(),
+        },
+        block::DerivativeAnchor {
+            owner: [4; 33],
+            hash: [5; 32],
+            height: U256::from(100500),
+        },
+    ];
+    let block = block::TransactionBlock::new(U256::from(62500), basic_data, [5; 32], vec![[1; 32]])
+        .with_anchors(anchors.clone())
+        .with_state_root([9; 32]);
+
+    let dump = block.dump().unwrap();
+
+    let block_loaded = block::TransactionBlock::parse(&dump[1..], BlockVersion::V2).unwrap();
+    assert_eq!(block_loaded.anchors, anchors);
+    assert_eq!(block_loaded.transactions, vec![[1; 32]]);
+    assert_eq!(block_loaded.fee, U256::from(62500));
+    assert_eq!(block_loaded.state_root, [9; 32]);
+
+    assert!(block::TransactionBlock::parse(&dump[1..dump.len() - 40], BlockVersion::V2).is_err());
+}
+
 #[test]
 fn dump_parse_summarize_block() {
     let basic_data = block::BasicInfo {
@@ -90,11 +132,13 @@ fn dump_parse_summarize_block() {
     let block = block::SummarizeBlock {
         default_info: basic_data,
         merkle_tree_root: [5; 32],
+        state_root: [7; 32],
+        version: BlockVersion::V2,
     };
 
     let dump = block.dump().unwrap();
 
-    let block_loaded = block::SummarizeBlock::parse(&dump[1..]).unwrap();
+    let block_loaded = block::SummarizeBlock::parse(&dump[1..], BlockVersion::V2).unwrap();
 
     assert_eq!(block.merkle_tree_root, block_loaded.merkle_tree_root);
 
@@ -120,6 +164,54 @@ fn dump_parse_summarize_block() {
     println!("{:?}", block_loaded);
 }
 
+#[test]
+fn parse_v1_blocks() {
+    // dumps made before the state root and the anchors were added to the blocks
+    let genesis = hex::decode(
+        "04e0f84a6b80c11cb23e6edb113d88557696d5143811c7f88d085a185aadf11df2000000005f3e65\
+         c0000000000000000000000000000000000000000000000000000000000000000000000000000000\
+         000000000000000000000000000000000000000000000000007fffffffffffffffffffffffffffff\
+         ffffffffffffffffffffffffffffffffff031b84c5567b126440995d3ed5aaba0565d71e18346048\
+         19ff9c17f5e9d5dd078f00",
+    )
+    .unwrap();
+    let block = block::deserialize_main_chain_block(&genesis).unwrap();
+    assert_eq!(block.get_type(), Headers::SummarizeBlock);
+    assert_eq!(block.get_version(), BlockVersion::V1);
+    assert_eq!(block.dump().unwrap(), genesis);
+    assert_eq!(
+        hex::encode(block.hash().unwrap()),
+        "c06f59eb3e3ee9770dcf346fcf918da19577263d5ae7dc5c79881ca40365031d"
+    );
+    assert_eq!(
+        chain::genesis_block().hash().unwrap(),
+        block.hash().unwrap()
+    );
+
+    let dump = hex::decode(
+        "02050505050505050505050505050505050505050505050505050505050505050500000000000271\
+         00050505050505050505050505050505050505050505050505050505050505050500000000000000\
+         00000000000000000000000000000000000000000000000000656565656565656565656565656565\
+         65656565656565656565656565656565650606060606060606060606060606060606060606060606\
+         06060606060606060606011102f42401010101010101010101010101010101010101010101010101\
+         010101010101010202020202020202020202020202020202020202020202020202020202020202",
+    )
+    .unwrap();
+    let block = block::deserialize_main_chain_block(&dump).unwrap();
+    assert_eq!(block.get_type(), Headers::TransactionBlock);
+    assert_eq!(block.get_version(), BlockVersion::V1);
+    assert_eq!(block.get_fee(), U256::from(62500));
+    assert_eq!(block.get_info().height, U256::from(17));
+    assert_eq!(block.transactions().unwrap(), &[[1; 32], [2; 32]]);
+    assert_eq!(block.get_state_root(), [0; 32]);
+    assert!(block.anchors().is_empty());
+    assert_eq!(block.dump().unwrap(), dump);
+
+    // V1 layout has no place for the new fields
+    let parsed = block::TransactionBlock::parse(&dump[1..], BlockVersion::V1).unwrap();
+    assert!(parsed.with_state_root([1; 32]).dump().is_err());
+}
+
 #[test]
 fn validate_block_test() {
     let basic_data = block::BasicInfo {
@@ -160,7 +252,7 @@ fn dump_parse_derivative_block() {
     let basic_data = block::BasicInfo {
         timestamp: 160000,
         pow: [0; 32],
-        previous_hash: unsafe { [0; 32].try_into().unwrap_unchecked() },
+        previous_hash: [0; 32],
         height: U256::from_dec_str("2").unwrap(),
         difficulty: [101; 32],
         founder: [6; 33],
@@ -203,7 +295,7 @@ fn validate_derivative_block() {
     let basic_data = block::BasicInfo {
         timestamp: 160000,
         pow: [0; 32],
-        previous_hash: unsafe { [0; 32].try_into().unwrap_unchecked() },
+        previous_hash: [0; 32],
         height: U256::from_dec_str("2").unwrap(),
         difficulty: [101; 32],
         founder: [6; 33],
@@ -214,7 +306,7 @@ fn validate_derivative_block() {
     let basic_data = block::BasicInfo {
         timestamp: 160000,
         pow: [0; 32],
-        previous_hash: unsafe { [0; 32].try_into().unwrap_unchecked() },
+        previous_hash: [0; 32],
         height: U256::from_dec_str("2").unwrap(),
         difficulty: [101; 32],
         founder: [6; 33],
@@ -1,9 +1,33 @@
-use blockchaintree::blockchaintree::BlockChainTree;
+mod common;
+
+use std::sync::Arc;
+
+use blockchaintree::{
+    block::{self, BlockArc, BlockVersion},
+    blockchaintree::{BlockChainTree, DerivativeChainInfo},
+    errors::{
+        BCTreeErrorKind, BlockChainTreeError, BlockValidationErrorKind, TransactionError,
+        TxErrorKind, TxPoolErrorKind,
+    },
+    merkletree::{MerkleTree, MerkleVersion},
+    receipt::{Receipt, TransactionStatus},
+    state_tree::EMPTY_ROOT,
+    static_values::{
+        BEGINNING_DIFFICULTY, BYTE_GAS_PRICE, COINS_PER_CYCLE, DERIVATIVE_BLOCK_GAS,
+        MAIN_CHAIN_PAYMENT, ROOT_PUBLIC_ADDRESS,
+    },
+    tools,
+    transaction::{Transaction, Transactionable, TransactionableItem},
+    types::Hash,
+};
+use common::{find_pow, temp_dir};
 use primitive_types::U256;
+use secp256k1::Secp256k1;
 
 #[tokio::test]
 async fn test_amounts() {
-    let tree = BlockChainTree::new("./BlockChainTree").unwrap();
+    let path = temp_dir("amounts");
+    let tree = BlockChainTree::new(&path).unwrap();
 
     let address_a = [0; 33];
     let address_b = [1; 33];
@@ -23,4 +47,1137 @@ async fn test_amounts() {
         U256::from_dec_str("10000000000").unwrap() - U256::from_dec_str("100").unwrap()
     );
     assert_eq!(amount_b, U256::from_dec_str("100").unwrap());
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[tokio::test]
+async fn test_tx_pool() {
+    let path = temp_dir("tx-pool");
+    let mut tree = BlockChainTree::new(&path).unwrap();
+    let main_chain = tree.get_main_chain();
+    let tx_pool = tree.get_tx_pool();
+
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let sender = public_key.serialize();
+    let receiver = [7; 33];
+    let funds = U256::from_dec_str("1000000000000000000000").unwrap();
+    tree.add_amount(&sender, funds).unwrap();
+
+    let now = tools::current_timestamp();
+    let new_transaction = |timestamp: u64, nonce: u64, amount: U256| {
+        Transaction::new(
+            sender,
+            receiver,
+            now + timestamp,
+            nonce,
+            amount,
+            secret_key.secret_bytes(),
+            None,
+        )
+        .unwrap()
+    };
+
+    // no gas to pay for the transaction
+    let transaction = new_transaction(1, 0, U256::from(100));
+    let forged = Transaction::new_signed(
+        sender,
+        receiver,
+        now + 1,
+        0,
+        U256::from(1000),
+        None,
+        *transaction.get_signature(),
+    );
+    let err = tree.send_transaction(Box::new(forged)).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TransactionError>(),
+        Some(TransactionError::Tx(TxErrorKind::Verify))
+    ));
+
+    let err = tree
+        .send_transaction(Box::new(transaction.clone()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockChainTree(BCTreeErrorKind::NewTransaction)
+    ));
+    assert!(tx_pool.read().await.is_empty());
+    // the reason of the rejection is kept for the status lookup
+    let TransactionStatus::Rejected { reason } = tree
+        .get_transaction_status(&transaction.hash())
+        .await
+        .unwrap()
+    else {
+        panic!("transaction should be rejected");
+    };
+    assert!(reason.ends_with("not enough gas for the transaction"));
+
+    let gas = U256::from_dec_str("1000000000000000000").unwrap();
+    tree.add_gas(&sender, gas).unwrap();
+
+    tree.send_transaction(Box::new(transaction.clone()))
+        .await
+        .unwrap();
+    assert!(tx_pool.read().await.transaction_exists(&transaction.hash()));
+    assert!(tree
+        .send_transaction(Box::new(transaction.clone()))
+        .await
+        .is_err());
+    assert_eq!(
+        tree.get_transaction_status(&transaction.hash())
+            .await
+            .unwrap(),
+        TransactionStatus::Pending
+    );
+
+    // pending spends are taken into account
+    let expensive = new_transaction(2, 1, funds - 50);
+    let err = tree
+        .send_transaction(Box::new(expensive.clone()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockChainTree(BCTreeErrorKind::NewTransaction)
+    ));
+    assert_eq!(tx_pool.read().await.len(), 1);
+
+    // pending transactions pay the fee too
+    let genesis = main_chain.get_last_block().unwrap().unwrap();
+    let fee = tools::recalculate_fee(&genesis.get_info().difficulty);
+    let exhausting = new_transaction(2, 1, funds - 100 - fee);
+    let err = tree
+        .send_transaction(Box::new(exhausting.clone()))
+        .await
+        .unwrap_err();
+    assert!(tools::report_message(&err).ends_with("not enough coins to pay the fee"));
+    assert_eq!(tx_pool.read().await.len(), 1);
+
+    // amount overflowing together with the fee is rejected
+    let err = tree
+        .send_transaction(Box::new(new_transaction(2, 1, U256::MAX)))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockChainTree(BCTreeErrorKind::NewTransaction)
+    ));
+    assert!(tools::report_message(&err).ends_with("transaction amount overflows"));
+    assert_eq!(tx_pool.read().await.len(), 1);
+
+    // so is the amount overflowing together with the pending transactions
+    let err = tree
+        .send_transaction(Box::new(new_transaction(2, 1, U256::MAX - fee)))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockChainTree(BCTreeErrorKind::NewTransaction)
+    ));
+    assert!(tools::report_message(&err).ends_with("pending transactions amount overflows"));
+    assert_eq!(tx_pool.read().await.len(), 1);
+
+    // nonce has to follow the pooled transactions of the sender
+    let err = tree
+        .send_transaction(Box::new(new_transaction(3, 2, U256::one())))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockChainTree(BCTreeErrorKind::NewTransaction)
+    ));
+
+    // transaction with the pooled nonce has to pay more to replace it
+    let underpriced = new_transaction(3, 0, U256::from(100));
+    let err = tree
+        .send_transaction(Box::new(underpriced.clone()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::TxPool(TxPoolErrorKind::Underpriced)
+    ));
+    assert_eq!(
+        tree.get_transaction_status(&underpriced.hash())
+            .await
+            .unwrap(),
+        TransactionStatus::Rejected {
+            reason: BlockChainTreeError::TxPool(TxPoolErrorKind::Underpriced).to_string()
+        }
+    );
+
+    // state is changed only by the block including the transaction
+    assert_eq!(tree.get_amount(&sender).unwrap(), funds);
+    assert_eq!(tree.get_gas(&sender).unwrap(), gas);
+    assert!(!main_chain.transaction_exists(&transaction.hash()).unwrap());
+
+    let template = tree.get_block_template().await.unwrap();
+    assert_eq!(template.transactions, vec![transaction.hash()]);
+    assert_eq!(template.height, main_chain.get_height());
+
+    let pow = find_pow(
+        &template.previous_hash,
+        &template.difficulty,
+        &template.transactions,
+        MerkleVersion::for_height(&template.height),
+    );
+    let last_block = main_chain.get_last_block().unwrap().unwrap();
+    let block = tree
+        .emmit_new_main_block(
+            &pow,
+            &[6; 33],
+            &template.transactions,
+            last_block.get_info().timestamp + 600,
+        )
+        .await
+        .unwrap();
+
+    assert!(tx_pool.read().await.is_empty());
+    assert!(main_chain.transaction_exists(&transaction.hash()).unwrap());
+    // block fee is collected from the sender and paid to the founder with the reward
+    assert_eq!(
+        tree.get_amount(&sender).unwrap(),
+        funds - 100 - block.get_fee()
+    );
+    assert_eq!(
+        tree.get_amount(&[6; 33]).unwrap(),
+        *MAIN_CHAIN_PAYMENT + block.get_fee()
+    );
+    assert_eq!(tree.get_amount(&receiver).unwrap(), U256::from(100));
+    assert!(tree.get_gas(&sender).unwrap() < gas);
+    assert_eq!(tree.get_nonce(&sender).unwrap(), 1);
+
+    let receipt = Receipt {
+        height: block.get_info().height,
+        fee: block.get_fee(),
+        gas_used: *BYTE_GAS_PRICE * transaction.get_dump_size(),
+    };
+    assert_eq!(
+        tree.get_receipt(&transaction.hash()).unwrap(),
+        Some(receipt.clone())
+    );
+    assert_eq!(gas - tree.get_gas(&sender).unwrap(), receipt.gas_used);
+
+    // executed transaction can not be submitted again
+    assert!(tree
+        .send_transaction(Box::new(transaction.clone()))
+        .await
+        .is_err());
+    assert_eq!(
+        tree.get_transaction_status(&transaction.hash())
+            .await
+            .unwrap(),
+        TransactionStatus::Included {
+            block_hash: block.hash().unwrap(),
+            receipt,
+        }
+    );
+    assert_eq!(
+        tree.get_transaction_status(&[1; 32]).await.unwrap(),
+        TransactionStatus::Unknown
+    );
+
+    // transactions missing from the pool cannot be included
+    let template = tree.get_block_template().await.unwrap();
+    let pow = find_pow(
+        &template.previous_hash,
+        &template.difficulty,
+        &[[1; 32]],
+        MerkleVersion::for_height(&template.height),
+    );
+    let last_block = main_chain.get_last_block().unwrap().unwrap();
+    assert!(tree
+        .emmit_new_main_block(
+            &pow,
+            &[6; 33],
+            &[[1; 32]],
+            last_block.get_info().timestamp + 600
+        )
+        .await
+        .is_err());
+    assert_eq!(main_chain.get_height(), template.height);
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+fn mine_transaction_block(
+    parent: &BlockArc,
+    transactions: Vec<[u8; 32]>,
+) -> block::TransactionBlock {
+    let prev_hash = parent.hash().unwrap();
+    let parent_difficulty = parent.get_info().difficulty;
+    let pow = find_pow(
+        &prev_hash,
+        &parent_difficulty,
+        &transactions,
+        MerkleVersion::V1,
+    );
+
+    let timestamp = parent.get_info().timestamp + 600;
+    let mut difficulty = parent_difficulty;
+    tools::recalculate_difficulty(parent.get_info().timestamp, timestamp, &mut difficulty);
+
+    block::TransactionBlock::new(
+        tools::recalculate_fee(&difficulty),
+        block::BasicInfo {
+            timestamp,
+            pow,
+            previous_hash: prev_hash,
+            height: parent.get_info().height + 1,
+            difficulty,
+            founder: [6; 33],
+        },
+        *MerkleTree::build_tree(&transactions, MerkleVersion::V1).get_root(),
+        transactions,
+    )
+}
+
+fn mine_block(parent: &BlockArc, transactions: Vec<[u8; 32]>) -> BlockArc {
+    Arc::new(mine_transaction_block(parent, transactions))
+}
+
+/// Mines the block committing to the state it results in, `builder` has to be at `parent`
+fn mine_sealed_block(
+    builder: &BlockChainTree,
+    parent: &BlockArc,
+    transactions: Vec<[u8; 32]>,
+) -> BlockArc {
+    let block = mine_transaction_block(parent, transactions);
+    let state_root = builder.preview_state_root(&block).unwrap();
+    Arc::new(block.with_state_root(state_root))
+}
+
+#[tokio::test]
+async fn test_import_block_validation() {
+    let path = temp_dir("import");
+    let mut tree = BlockChainTree::new(&path).unwrap();
+    let main_chain = tree.get_main_chain();
+
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let sender = public_key.serialize();
+    tree.add_amount(&sender, U256::from_dec_str("1000000000000000000").unwrap())
+        .unwrap();
+    tree.add_gas(&sender, U256::MAX / 2).unwrap();
+    let transaction = Transaction::new(
+        sender,
+        [21; 33],
+        100,
+        0,
+        U256::from_dec_str("1000").unwrap(),
+        secret_key.secret_bytes(),
+        None,
+    )
+    .unwrap();
+
+    let genesis = main_chain.get_last_block().unwrap().unwrap();
+    let mined = mine_block(&genesis, vec![transaction.hash()]);
+    let prev_hash = genesis.hash().unwrap();
+    let make_block = |previous_hash: [u8; 32], state_root: Hash| -> BlockArc {
+        let mut info = mined.get_info().clone();
+        info.previous_hash = previous_hash;
+        Arc::new(
+            block::TransactionBlock::new(
+                mined.get_fee(),
+                info,
+                mined.get_merkle_root(),
+                vec![transaction.hash()],
+            )
+            .with_state_root(state_root),
+        )
+    };
+
+    // transaction is not stored yet
+    let err = tree
+        .import_main_block(make_block(prev_hash, EMPTY_ROOT))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::MissingTransaction)
+    ));
+
+    // transaction stored bypassing verification still has to be signed properly
+    let forged = Transaction::new_signed(
+        sender,
+        [21; 33],
+        100,
+        0,
+        U256::from_dec_str("1000").unwrap(),
+        None,
+        [33; 64],
+    );
+    main_chain
+        .set_transaction_raw(&transaction.hash(), Some(forged.dump().unwrap()))
+        .unwrap();
+    let err = tree
+        .import_main_block(make_block(prev_hash, EMPTY_ROOT))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::InvalidSignature)
+    ));
+
+    main_chain
+        .add_transactions(std::slice::from_ref(&transaction))
+        .unwrap();
+
+    // blocks without the state root are not accepted anymore
+    let mut legacy = block::TransactionBlock::new(
+        mined.get_fee(),
+        mined.get_info().clone(),
+        mined.get_merkle_root(),
+        vec![transaction.hash()],
+    );
+    legacy.version = BlockVersion::V1;
+    let err = tree.import_main_block(Arc::new(legacy)).await.unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongVersion)
+    ));
+
+    let err = tree
+        .import_main_block(make_block([1; 32], EMPTY_ROOT))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::UnknownParent)
+    ));
+
+    let mut future_info = mined.get_info().clone();
+    future_info.timestamp = tools::current_timestamp() + 3 * 60 * 60;
+    let future = block::TransactionBlock::new(
+        mined.get_fee(),
+        future_info,
+        mined.get_merkle_root(),
+        vec![transaction.hash()],
+    );
+    let err = tree.import_main_block(Arc::new(future)).await.unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::FutureTimestamp)
+    ));
+
+    let err = tree
+        .import_main_block(make_block(prev_hash, EMPTY_ROOT))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongStateRoot)
+    ));
+
+    let state_root = tree.preview_state_root(mined.as_ref()).unwrap();
+    tree.import_main_block(make_block(prev_hash, state_root))
+        .await
+        .unwrap();
+    assert_eq!(main_chain.get_height(), U256::from(2));
+    assert_eq!(
+        tree.get_amount(&[21; 33]).unwrap(),
+        U256::from_dec_str("1000").unwrap()
+    );
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[tokio::test]
+async fn test_reorganisation() {
+    let path = temp_dir("reorg");
+    let builder_path = temp_dir("reorg-builder");
+    let mut tree = BlockChainTree::new(&path).unwrap();
+    let main_chain = tree.get_main_chain();
+    // blocks are built on top of the state of the second tree following the branch being mined
+    let mut builder = BlockChainTree::new(&builder_path).unwrap();
+
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let sender = public_key.serialize();
+    let receiver = [7; 33];
+
+    let funds = U256::from_dec_str("1000000000000000000").unwrap();
+    for tree in [&tree, &builder] {
+        tree.add_amount(&sender, funds).unwrap();
+        tree.add_gas(&sender, U256::MAX / 2).unwrap();
+    }
+
+    // only the builder has the transactions, the tree receives them with the blocks
+    let mut signed = Vec::new();
+    let mut transactions = Vec::new();
+    for (timestamp, nonce, amount) in [(1, 0, 1), (2, 1, 10), (3, 1, 100), (4, 2, 200)] {
+        let transaction = Transaction::new(
+            sender,
+            receiver,
+            timestamp,
+            nonce,
+            U256::from(amount),
+            secret_key.secret_bytes(),
+            None,
+        )
+        .unwrap();
+        builder
+            .get_main_chain()
+            .add_transaction(&transaction)
+            .unwrap();
+        transactions.push(transaction.hash());
+        signed.push(transaction);
+    }
+    let received =
+        |index: usize| -> Vec<TransactionableItem> { vec![Box::new(signed[index].clone())] };
+
+    let genesis = main_chain.get_last_block().unwrap().unwrap();
+    let block_1 = mine_sealed_block(&builder, &genesis, vec![transactions[0]]);
+    tree.import_main_block_with_transactions(block_1.clone(), received(0))
+        .await
+        .unwrap();
+    builder.import_main_block(block_1.clone()).await.unwrap();
+
+    let block_2 = mine_sealed_block(&builder, &block_1, vec![transactions[1]]);
+    tree.import_main_block_with_transactions(block_2.clone(), received(1))
+        .await
+        .unwrap();
+    assert_eq!(tree.get_amount(&receiver).unwrap(), U256::from(11));
+    assert_eq!(tree.get_state_root().unwrap(), block_2.get_state_root());
+
+    // side block is validated with the received transactions before they are stored
+    let side_2 = mine_sealed_block(&builder, &block_1, vec![transactions[2]]);
+    let err = tree.import_main_block(side_2.clone()).await.unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::MissingTransaction)
+    ));
+    assert!(main_chain
+        .find_side_by_hash(&side_2.hash().unwrap())
+        .unwrap()
+        .is_none());
+
+    // competing branch of the same work does not replace the chain
+    tree.import_main_block_with_transactions(side_2.clone(), received(2))
+        .await
+        .unwrap();
+    builder.import_main_block(side_2.clone()).await.unwrap();
+    assert_eq!(main_chain.get_height(), U256::from(3));
+    assert_eq!(
+        main_chain
+            .get_last_block()
+            .unwrap()
+            .unwrap()
+            .hash()
+            .unwrap(),
+        block_2.hash().unwrap()
+    );
+
+    // heavier branch triggers reorganisation
+    let side_3 = mine_sealed_block(&builder, &side_2, vec![transactions[3]]);
+    tree.import_main_block_with_transactions(side_3.clone(), received(3))
+        .await
+        .unwrap();
+    assert_eq!(main_chain.get_height(), U256::from(4));
+    assert_eq!(
+        main_chain
+            .get_last_block()
+            .unwrap()
+            .unwrap()
+            .hash()
+            .unwrap(),
+        side_3.hash().unwrap()
+    );
+    assert_eq!(tree.get_amount(&receiver).unwrap(), U256::from(301));
+    // rewards of the disconnected block are reverted
+    let fees = block_1.get_fee() + side_2.get_fee() + side_3.get_fee();
+    assert_eq!(
+        tree.get_amount(&sender).unwrap(),
+        funds - U256::from(301) - fees
+    );
+    assert_eq!(
+        tree.get_amount(&[6; 33]).unwrap(),
+        *MAIN_CHAIN_PAYMENT * 3usize + fees
+    );
+    assert!(!main_chain.transaction_exists(&transactions[1]).unwrap());
+    assert!(main_chain
+        .find_transaction_reference(&transactions[1])
+        .unwrap()
+        .is_none());
+    // receipts of the disconnected block are reverted
+    assert!(tree.get_receipt(&transactions[1]).unwrap().is_none());
+    assert_eq!(
+        tree.get_receipt(&transactions[3]).unwrap().unwrap().height,
+        U256::from(3)
+    );
+    let history: Vec<(Hash, U256)> = main_chain
+        .get_address_transactions(&receiver, 0, 10)
+        .unwrap()
+        .iter()
+        .map(|item| (item.transaction, item.height))
+        .collect();
+    assert_eq!(
+        history,
+        vec![
+            (transactions[3], U256::from(3)),
+            (transactions[2], U256::from(2)),
+            (transactions[0], U256::one()),
+        ]
+    );
+    assert!(main_chain
+        .find_side_by_hash(&block_2.hash().unwrap())
+        .unwrap()
+        .is_some());
+    assert_eq!(tree.get_nonce(&sender).unwrap(), 3);
+    assert_eq!(tree.get_state_root().unwrap(), side_3.get_state_root());
+
+    // transaction with a used nonce is rejected by block validation
+    let replay = Transaction::new(
+        sender,
+        receiver,
+        5,
+        1,
+        U256::from(1),
+        secret_key.secret_bytes(),
+        None,
+    )
+    .unwrap();
+    let block_4 = mine_block(&side_3, vec![replay.hash()]);
+    let err = tree
+        .import_main_block_with_transactions(block_4, vec![Box::new(replay.clone())])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongNonce)
+    ));
+    assert_eq!(main_chain.get_height(), U256::from(4));
+    assert_eq!(tree.get_amount(&receiver).unwrap(), U256::from(301));
+
+    // block committing to another state is rejected with its changes
+    let transaction = Transaction::new(
+        sender,
+        receiver,
+        6,
+        3,
+        U256::from(1),
+        secret_key.secret_bytes(),
+        None,
+    )
+    .unwrap();
+    let forged = mine_transaction_block(&side_3, vec![transaction.hash()]).with_state_root([1; 32]);
+    let err = tree
+        .import_main_block_with_transactions(Arc::new(forged), vec![Box::new(transaction.clone())])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongStateRoot)
+    ));
+    assert_eq!(main_chain.get_height(), U256::from(4));
+    assert_eq!(tree.get_nonce(&sender).unwrap(), 3);
+    assert_eq!(tree.get_state_root().unwrap(), side_3.get_state_root());
+    assert!(!main_chain.transaction_exists(&transaction.hash()).unwrap());
+
+    drop(tree);
+    drop(builder);
+    let _ = std::fs::remove_dir_all(&path);
+    let _ = std::fs::remove_dir_all(&builder_path);
+}
+
+#[tokio::test]
+async fn test_rejected_block_keeps_state() {
+    let path = temp_dir("rejected");
+    let builder_path = temp_dir("rejected-builder");
+    let mut tree = BlockChainTree::new(&path).unwrap();
+    let main_chain = tree.get_main_chain();
+    let mut builder = BlockChainTree::new(&builder_path).unwrap();
+
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let sender = public_key.serialize();
+    let founder = [6; 33];
+
+    let funds = U256::from_dec_str("1000000000000000000").unwrap();
+    for tree in [&tree, &builder] {
+        tree.add_amount(&sender, funds).unwrap();
+        tree.add_gas(&sender, U256::MAX / 2).unwrap();
+    }
+
+    let mut transactions = Vec::new();
+    for (timestamp, nonce) in [(1, 0), (2, 1), (3, 0), (4, 1)] {
+        let transaction = Transaction::new(
+            sender,
+            [7; 33],
+            timestamp,
+            nonce,
+            U256::one(),
+            secret_key.secret_bytes(),
+            None,
+        )
+        .unwrap();
+        main_chain.add_transaction(&transaction).unwrap();
+        builder
+            .get_main_chain()
+            .add_transaction(&transaction)
+            .unwrap();
+        transactions.push(transaction.hash());
+    }
+
+    let genesis = main_chain.get_last_block().unwrap().unwrap();
+    let block_1 = mine_sealed_block(&tree, &genesis, vec![transactions[0]]);
+    tree.import_main_block(block_1.clone()).await.unwrap();
+    let reward = *MAIN_CHAIN_PAYMENT + block_1.get_fee();
+
+    // changes made outside of blocks after the block was connected
+    for tree in [&tree, &builder] {
+        tree.add_gas(&founder, U256::from(5)).unwrap();
+        tree.add_amount(&founder, U256::from(7)).unwrap();
+    }
+
+    let forged = mine_transaction_block(&block_1, vec![transactions[1]]).with_state_root([1; 32]);
+    let err = tree.import_main_block(Arc::new(forged)).await.unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongStateRoot)
+    ));
+    assert_eq!(tree.get_gas(&founder).unwrap(), U256::from(5));
+    assert_eq!(tree.get_amount(&founder).unwrap(), reward + 7);
+
+    // transactions of the rejected block are not stored from the pool
+    let pooled = Transaction::new(
+        sender,
+        [7; 33],
+        tools::current_timestamp(),
+        1,
+        U256::one(),
+        secret_key.secret_bytes(),
+        None,
+    )
+    .unwrap();
+    let pooled_hash = pooled.hash();
+    tree.send_transaction(Box::new(pooled)).await.unwrap();
+    let forged = mine_transaction_block(&block_1, vec![pooled_hash]).with_state_root([1; 32]);
+    let err = tree.import_main_block(Arc::new(forged)).await.unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongStateRoot)
+    ));
+    assert!(!main_chain.transaction_exists(&pooled_hash).unwrap());
+    assert!(tree.get_tx_pool().read().await.get(&pooled_hash).is_some());
+
+    // disconnected block takes only its own changes back
+    let side_1 = mine_sealed_block(&builder, &genesis, vec![transactions[2]]);
+    builder.import_main_block(side_1.clone()).await.unwrap();
+    let side_2 = mine_sealed_block(&builder, &side_1, vec![transactions[3]]);
+    tree.import_main_block(side_1.clone()).await.unwrap();
+    tree.import_main_block(side_2.clone()).await.unwrap();
+    assert_eq!(main_chain.get_height(), U256::from(3));
+    assert_eq!(tree.get_gas(&founder).unwrap(), U256::from(5));
+    assert_eq!(
+        tree.get_amount(&founder).unwrap(),
+        *MAIN_CHAIN_PAYMENT * 2usize + side_1.get_fee() + side_2.get_fee() + 7
+    );
+    assert_eq!(tree.get_state_root().unwrap(), side_2.get_state_root());
+
+    let _ = std::fs::remove_dir_all(&path);
+    let _ = std::fs::remove_dir_all(&builder_path);
+}
+
+#[tokio::test]
+async fn test_failed_reorganisation_restores_chain() {
+    let path = temp_dir("failed-reorg");
+    let builder_path = temp_dir("failed-reorg-builder");
+    let mut tree = BlockChainTree::new(&path).unwrap();
+    let main_chain = tree.get_main_chain();
+    let mut builder = BlockChainTree::new(&builder_path).unwrap();
+
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let sender = public_key.serialize();
+    let receiver = [7; 33];
+
+    let funds = U256::from_dec_str("1000000000000000000").unwrap();
+    for tree in [&tree, &builder] {
+        tree.add_amount(&sender, funds).unwrap();
+        tree.add_gas(&sender, U256::MAX / 2).unwrap();
+    }
+
+    let mut signed = Vec::new();
+    for (timestamp, nonce) in [(1, 0), (2, 1), (3, 0), (4, 1), (5, 2)] {
+        let transaction = Transaction::new(
+            sender,
+            receiver,
+            timestamp,
+            nonce,
+            U256::from(timestamp),
+            secret_key.secret_bytes(),
+            None,
+        )
+        .unwrap();
+        builder
+            .get_main_chain()
+            .add_transaction(&transaction)
+            .unwrap();
+        signed.push(transaction);
+    }
+    let received =
+        |index: usize| -> Vec<TransactionableItem> { vec![Box::new(signed[index].clone())] };
+
+    let genesis = main_chain.get_last_block().unwrap().unwrap();
+    let side_1 = mine_sealed_block(&builder, &genesis, vec![signed[2].hash()]);
+
+    // main chain stores the transactions received with its blocks
+    let block_1 = mine_sealed_block(&builder, &genesis, vec![signed[0].hash()]);
+    builder.import_main_block(block_1.clone()).await.unwrap();
+    let block_2 = mine_sealed_block(&builder, &block_1, vec![signed[1].hash()]);
+    for (index, block) in [&block_1, &block_2].into_iter().enumerate() {
+        tree.import_main_block_with_transactions(block.clone(), received(index))
+            .await
+            .unwrap();
+    }
+    let state_root = tree.get_state_root().unwrap();
+
+    // heavier branch with a block committing to another state in the middle
+    let side_2: BlockArc =
+        Arc::new(mine_transaction_block(&side_1, vec![signed[3].hash()]).with_state_root([1; 32]));
+    let side_3 = mine_block(&side_2, vec![signed[4].hash()]);
+    for (index, block) in [&side_1, &side_2].into_iter().enumerate() {
+        tree.import_main_block_with_transactions(block.clone(), received(index + 2))
+            .await
+            .unwrap();
+    }
+    let err = tree
+        .import_main_block_with_transactions(side_3.clone(), received(4))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockChainTree(BCTreeErrorKind::Reorganize)
+    ));
+
+    // previous chain is connected back with its transactions
+    assert_eq!(main_chain.get_height(), U256::from(3));
+    assert_eq!(
+        main_chain
+            .get_last_block()
+            .unwrap()
+            .unwrap()
+            .hash()
+            .unwrap(),
+        block_2.hash().unwrap()
+    );
+    assert_eq!(tree.get_state_root().unwrap(), state_root);
+    assert_eq!(tree.get_amount(&receiver).unwrap(), U256::from(3));
+    assert_eq!(tree.get_nonce(&sender).unwrap(), 2);
+    for transaction in &signed[..2] {
+        assert!(main_chain.transaction_exists(&transaction.hash()).unwrap());
+    }
+    for block in [&block_1, &block_2] {
+        assert!(main_chain
+            .find_side_by_hash(&block.hash().unwrap())
+            .unwrap()
+            .is_none());
+    }
+    assert!(main_chain
+        .find_side_by_hash(&side_1.hash().unwrap())
+        .unwrap()
+        .is_some());
+
+    drop(tree);
+    drop(builder);
+    let _ = std::fs::remove_dir_all(&path);
+    let _ = std::fs::remove_dir_all(&builder_path);
+}
+
+#[tokio::test]
+async fn test_state_proofs() {
+    let path = temp_dir("state-proofs");
+    let tree = BlockChainTree::new(&path).unwrap();
+    let owner = [3; 33];
+
+    // the root funds are the only account of the new chain
+    let (amount, proof) = tree.get_amount_with_proof(&ROOT_PUBLIC_ADDRESS).unwrap();
+    assert_eq!(amount, *COINS_PER_CYCLE);
+    let root = tree.get_state_root().unwrap();
+    assert_ne!(root, EMPTY_ROOT);
+    assert!(proof.verify(&root));
+    assert!(proof.siblings.is_empty());
+
+    // missing accounts are proven to be empty
+    let (gas, absent) = tree.get_gas_with_proof(&owner).unwrap();
+    assert!(gas.is_zero());
+    assert!(absent.verify(&root));
+
+    tree.add_gas(&owner, U256::from(10)).unwrap();
+    let new_root = tree.get_state_root().unwrap();
+    assert_ne!(new_root, root);
+    assert!(!absent.verify(&new_root));
+    let (gas, proof) = tree.get_gas_with_proof(&owner).unwrap();
+    assert_eq!(gas, U256::from(10));
+    assert!(proof.verify(&new_root));
+    assert_eq!(proof.siblings.len(), 1);
+
+    // proofs of other values or with missing siblings are rejected
+    let mut forged = proof.clone();
+    forged.amount = U256::one();
+    assert!(!forged.verify(&new_root));
+    let mut forged = proof.clone();
+    forged.siblings.clear();
+    assert!(!forged.verify(&new_root));
+
+    // the root depends on the state only
+    tree.sub_gas(&owner, U256::from(10)).unwrap();
+    assert_eq!(tree.get_state_root().unwrap(), root);
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[tokio::test]
+async fn test_derivative_chains_discovery() {
+    let path = temp_dir("derivatives");
+    let mut tree = BlockChainTree::new(&path).unwrap();
+    assert!(tree.list_derivative_chains().is_empty());
+
+    let genesis = tree
+        .get_main_chain()
+        .get_last_block()
+        .unwrap()
+        .unwrap()
+        .hash()
+        .unwrap();
+    let owner_1 = [2; 33];
+    let owner_2 = [3; 33];
+    tree.get_derivative_chain(&owner_2).unwrap();
+    let chain = tree.get_derivative_chain(&owner_1).unwrap();
+    let pow = find_pow(&genesis, &BEGINNING_DIFFICULTY, &[], MerkleVersion::V1);
+    tree.emmit_new_derivative_block(&pow, &owner_1, tools::current_timestamp())
+        .await
+        .unwrap();
+    assert_eq!(chain.get_height(), U256::one());
+    drop(chain);
+    drop(tree);
+
+    // chains are registered on startup even without being flushed
+    let tree = BlockChainTree::new(&path).unwrap();
+    assert!(tree.find_derivative_chain(&[4; 33]).is_none());
+    assert_eq!(
+        tree.list_derivative_chains(),
+        vec![
+            DerivativeChainInfo {
+                owner: owner_1,
+                height: U256::one(),
+                genesis_hash: genesis,
+            },
+            DerivativeChainInfo {
+                owner: owner_2,
+                height: U256::zero(),
+                genesis_hash: genesis,
+            },
+        ]
+    );
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[tokio::test]
+async fn test_derivative_anchors() {
+    let path = temp_dir("anchors");
+    let mut tree = BlockChainTree::new(&path).unwrap();
+    let main_chain = tree.get_main_chain();
+
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let sender = public_key.serialize();
+    tree.add_amount(&sender, U256::from_dec_str("1000000000000000000").unwrap())
+        .unwrap();
+    tree.add_gas(&sender, U256::MAX / 2).unwrap();
+
+    let owner = [2; 33];
+    let genesis = main_chain.get_last_block().unwrap().unwrap();
+    tree.get_derivative_chain(&owner).unwrap();
+    let pow = find_pow(
+        &genesis.hash().unwrap(),
+        &BEGINNING_DIFFICULTY,
+        &[],
+        MerkleVersion::V1,
+    );
+    let derivative_block = tree
+        .emmit_new_derivative_block(&pow, &owner, tools::current_timestamp())
+        .await
+        .unwrap();
+    assert!(tree.get_derivative_anchor(&owner).unwrap().is_none());
+
+    // derivative chain tip is anchored by the next main chain block
+    let now = tools::current_timestamp();
+    let transaction = Transaction::new(
+        sender,
+        [7; 33],
+        now,
+        0,
+        U256::from(1),
+        secret_key.secret_bytes(),
+        None,
+    )
+    .unwrap();
+    tree.send_transaction(Box::new(transaction)).await.unwrap();
+    let template = tree.get_block_template().await.unwrap();
+    let pow = find_pow(
+        &template.previous_hash,
+        &template.difficulty,
+        &template.transactions,
+        MerkleVersion::for_height(&template.height),
+    );
+    let block = tree
+        .emmit_new_main_block(
+            &pow,
+            &[6; 33],
+            &template.transactions,
+            genesis.get_info().timestamp + 600,
+        )
+        .await
+        .unwrap();
+    let anchor = block::DerivativeAnchor {
+        owner,
+        hash: derivative_block.hash().unwrap(),
+        height: U256::zero(),
+    };
+    assert_eq!(block.anchors(), std::slice::from_ref(&anchor));
+    assert_eq!(tree.get_derivative_anchor(&owner).unwrap(), Some(anchor));
+    assert_eq!(tree.get_anchored_gas(&owner).unwrap(), *MAIN_CHAIN_PAYMENT);
+    assert_eq!(tree.get_gas(&owner).unwrap(), *DERIVATIVE_BLOCK_GAS);
+
+    // anchors of unknown blocks are rejected
+    let transaction = Transaction::new(
+        sender,
+        [7; 33],
+        now + 1,
+        1,
+        U256::from(1),
+        secret_key.secret_bytes(),
+        None,
+    )
+    .unwrap();
+    main_chain.add_transaction(&transaction).unwrap();
+    let mined = mine_block(&block, vec![transaction.hash()]);
+    let forged = block::TransactionBlock::new(
+        mined.get_fee(),
+        mined.get_info().clone(),
+        mined.get_merkle_root(),
+        vec![transaction.hash()],
+    )
+    .with_anchors(vec![block::DerivativeAnchor {
+        owner,
+        hash: [1; 32],
+        height: U256::one(),
+    }]);
+    let err = tree.import_main_block(Arc::new(forged)).await.unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockValidation(BlockValidationErrorKind::WrongAnchor)
+    ));
+    assert_eq!(main_chain.get_height(), U256::from(2));
+    assert_eq!(tree.get_nonce(&sender).unwrap(), 1);
+    assert_eq!(tree.get_gas(&owner).unwrap(), *DERIVATIVE_BLOCK_GAS);
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+#[tokio::test]
+async fn test_derivative_blocks() {
+    let path = temp_dir("derivative-blocks");
+    let mut tree = BlockChainTree::new(&path).unwrap();
+    let owner = [2; 33];
+
+    let genesis = tree
+        .get_main_chain()
+        .get_last_block()
+        .unwrap()
+        .unwrap()
+        .hash()
+        .unwrap();
+    let pow = find_pow(&genesis, &BEGINNING_DIFFICULTY, &[], MerkleVersion::V1);
+    let first = tree
+        .emmit_new_derivative_block(&pow, &owner, tools::current_timestamp() - 1200)
+        .await
+        .unwrap();
+    // gas is minted once the block is anchored in the main chain
+    assert!(tree.get_gas(&owner).unwrap().is_zero());
+
+    let parent_hash = first.hash().unwrap();
+    let parent = first.get_info();
+    let next_block = |timestamp: u64, difficulty: [u8; 32], pow: [u8; 32]| block::DerivativeBlock {
+        default_info: block::BasicInfo {
+            timestamp,
+            pow,
+            previous_hash: parent_hash,
+            height: U256::one(),
+            difficulty,
+            founder: owner,
+        },
+    };
+    let pow = find_pow(&parent_hash, &parent.difficulty, &[], MerkleVersion::V1);
+    let timestamp = parent.timestamp + 600;
+
+    let assert_rejected = |err: error_stack::Report<BlockChainTreeError>,
+                           kind: BlockValidationErrorKind| {
+        match err.current_context() {
+            BlockChainTreeError::BlockValidation(err_kind) => {
+                assert_eq!(err_kind.to_string(), kind.to_string())
+            }
+            context => panic!("unexpected error: {}", context),
+        }
+    };
+    assert_rejected(
+        tree.import_derivative_block(&next_block(timestamp, [1; 32], pow))
+            .unwrap_err(),
+        BlockValidationErrorKind::WrongDifficulty,
+    );
+    assert_rejected(
+        tree.import_derivative_block(&next_block(parent.timestamp - 1, parent.difficulty, pow))
+            .unwrap_err(),
+        BlockValidationErrorKind::WrongTimestamp,
+    );
+    assert_rejected(
+        tree.import_derivative_block(&next_block(
+            tools::current_timestamp() + 3 * 60 * 60,
+            parent.difficulty,
+            pow,
+        ))
+        .unwrap_err(),
+        BlockValidationErrorKind::FutureTimestamp,
+    );
+    let mut wrong_pow = [0u8; 32];
+    for nonce in 0u64.. {
+        wrong_pow[24..].copy_from_slice(&nonce.to_be_bytes());
+        if !tools::check_pow(
+            &parent_hash,
+            &parent.difficulty,
+            &[],
+            &wrong_pow,
+            MerkleVersion::V1,
+        ) {
+            break;
+        }
+    }
+    assert_rejected(
+        tree.import_derivative_block(&next_block(timestamp, parent.difficulty, wrong_pow))
+            .unwrap_err(),
+        BlockValidationErrorKind::WrongPow,
+    );
+
+    tree.import_derivative_block(&next_block(timestamp, parent.difficulty, pow))
+        .unwrap();
+    assert_eq!(
+        tree.find_derivative_chain(&owner).unwrap().get_height(),
+        U256::from(2)
+    );
+    assert!(tree.get_gas(&owner).unwrap().is_zero());
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all(&path);
 }
@@ -1,10 +1,14 @@
 use std::sync::Arc;
 
 use blockchaintree::{
-    block, chain, tools,
+    block, chain,
+    errors::{BlockChainTreeError, ChainErrorKind, TransactionError, TxErrorKind},
+    merkletree::{MerkleTree, MerkleVersion},
+    tools,
     transaction::{self, Transactionable},
 };
 use primitive_types::U256;
+use secp256k1::Secp256k1;
 
 #[tokio::test]
 async fn init_flush_get_block_by_height_chain_test() {
@@ -22,7 +26,7 @@ async fn init_flush_get_block_by_height_chain_test() {
     let basic_data = block::BasicInfo {
         timestamp: 160000,
         pow: [0; 32],
-        previous_hash: unsafe { [0; 32].try_into().unwrap_unchecked() },
+        previous_hash: [0; 32],
         height,
         difficulty: [101; 32],
         founder: [6; 33],
@@ -56,16 +60,39 @@ async fn init_flush_get_block_by_height_chain_test() {
 async fn init_get_transaction_chain_test() {
     let main_chain = chain::MainChain::new("./BlockChainTree").unwrap();
 
-    let transaction = transaction::Transaction::new_signed(
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let transaction = transaction::Transaction::new(
+        public_key.serialize(),
         [20; 33],
+        100,
+        0,
+        U256::from_dec_str("3627836287").unwrap(),
+        secret_key.secret_bytes(),
+        Some(vec![228, 123]),
+    )
+    .unwrap();
+
+    main_chain
+        .add_transactions(std::slice::from_ref(&transaction))
+        .unwrap();
+
+    // transactions with wrong signatures are rejected
+    let forged = transaction::Transaction::new_signed(
+        public_key.serialize(),
         [20; 33],
         100,
+        1,
         U256::from_dec_str("3627836287").unwrap(),
         Some(vec![228, 123]),
-        [33; 64],
+        *transaction.get_signature(),
     );
-
-    main_chain.add_transactions(&[transaction.clone()]).unwrap();
+    let err = main_chain.add_transaction(&forged).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TransactionError>(),
+        Some(TransactionError::Tx(TxErrorKind::Verify))
+    ));
+    assert!(!main_chain.transaction_exists(&forged.hash()).unwrap());
 
     let got_transaction = main_chain
         .get_transaction(&tools::hash(&transaction.dump().unwrap()))
@@ -110,7 +137,7 @@ async fn init_flush_get_block_by_height_deriv_chain_test() {
     let basic_data = block::BasicInfo {
         timestamp: 160000,
         pow: [0; 32],
-        previous_hash: unsafe { [0; 32].try_into().unwrap_unchecked() },
+        previous_hash: [0; 32],
         height: U256::from_dec_str("0").unwrap(),
         difficulty: [101; 32],
         founder: [6; 33],
@@ -148,3 +175,168 @@ async fn init_flush_get_block_by_height_deriv_chain_test() {
         block.default_info.founder
     );
 }
+
+#[test]
+fn transaction_proof_chain_test() {
+    let _ = std::fs::remove_dir_all("./BlockChainTreeProofTest");
+    let main_chain = chain::MainChain::new("./BlockChainTreeProofTest").unwrap();
+
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let transactions: Vec<transaction::Transaction> = (0..5)
+        .map(|nonce| {
+            transaction::Transaction::new(
+                public_key.serialize(),
+                [21; 33],
+                100,
+                nonce,
+                U256::from_dec_str("1000").unwrap(),
+                secret_key.secret_bytes(),
+                None,
+            )
+            .unwrap()
+        })
+        .collect();
+    let hashes: Vec<[u8; 32]> = transactions[..4].iter().map(|tx| tx.hash()).collect();
+    main_chain.add_transactions(&transactions).unwrap();
+
+    let merkle_root = *MerkleTree::build_tree(&hashes, MerkleVersion::V1).get_root();
+    let main_block = block::TransactionBlock::new(
+        U256::one(),
+        block::BasicInfo {
+            timestamp: 160000,
+            pow: [0; 32],
+            previous_hash: [0; 32],
+            height: main_chain.get_height(),
+            difficulty: [101; 32],
+            founder: [6; 33],
+        },
+        merkle_root,
+        hashes.clone(),
+    );
+    let block_hash = block::Block::hash(&main_block).unwrap();
+    main_chain.add_block(Arc::new(main_block)).unwrap();
+
+    for hash in hashes.iter() {
+        let proof = main_chain.get_transaction_proof(hash).unwrap();
+        assert_eq!(proof.transaction, *hash);
+        assert_eq!(proof.block_hash, block_hash);
+        assert_eq!(proof.height, U256::one());
+        assert!(proof.verify(&merkle_root));
+        assert!(!proof.verify(&[1; 32]));
+
+        let mut forged = proof.clone();
+        forged.transaction = transactions[4].hash();
+        assert!(!forged.verify(&merkle_root));
+    }
+
+    // stored transaction which is not included into any block
+    for hash in [transactions[4].hash(), [7; 32]] {
+        let err = main_chain.get_transaction_proof(&hash).unwrap_err();
+        assert!(matches!(
+            err.current_context(),
+            BlockChainTreeError::Chain(ChainErrorKind::FindTransaction)
+        ));
+    }
+
+    drop(main_chain);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeProofTest");
+}
+
+#[test]
+fn address_history_chain_test() {
+    let _ = std::fs::remove_dir_all("./BlockChainTreeHistoryTest");
+    let main_chain = chain::MainChain::new("./BlockChainTreeHistoryTest").unwrap();
+
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let sender = public_key.serialize();
+    let transactions: Vec<transaction::Transaction> = (0..3)
+        .map(|nonce| {
+            transaction::Transaction::new(
+                sender,
+                [21 + nonce as u8; 33],
+                100,
+                nonce,
+                U256::from_dec_str("1000").unwrap(),
+                secret_key.secret_bytes(),
+                None,
+            )
+            .unwrap()
+        })
+        .collect();
+    main_chain.add_transactions(&transactions).unwrap();
+
+    let mut block_hashes = Vec::new();
+    for included in [&transactions[..2], &transactions[2..]] {
+        let hashes: Vec<[u8; 32]> = included.iter().map(|tx| tx.hash()).collect();
+        let main_block = block::TransactionBlock::new(
+            U256::one(),
+            block::BasicInfo {
+                timestamp: 160000,
+                pow: [0; 32],
+                previous_hash: [0; 32],
+                height: main_chain.get_height(),
+                difficulty: [101; 32],
+                founder: [6; 33],
+            },
+            *MerkleTree::build_tree(&hashes, MerkleVersion::V1).get_root(),
+            hashes,
+        );
+        block_hashes.push(block::Block::hash(&main_block).unwrap());
+        main_chain.add_block(Arc::new(main_block)).unwrap();
+    }
+
+    // the latest transactions go first
+    let history = main_chain.get_address_transactions(&sender, 0, 10).unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[0].transaction, transactions[2].hash());
+    assert_eq!(history[0].height, U256::from(2));
+    assert!(history[1..].iter().all(|item| item.height == U256::one()));
+    let page = main_chain.get_address_transactions(&sender, 1, 1).unwrap();
+    assert_eq!(page, history[1..2]);
+    assert!(main_chain
+        .get_address_transactions(&sender, 3, 10)
+        .unwrap()
+        .is_empty());
+
+    let receiver = main_chain
+        .get_address_transactions(&[22; 33], 0, 10)
+        .unwrap();
+    assert_eq!(receiver.len(), 1);
+    assert_eq!(receiver[0].transaction, transactions[1].hash());
+
+    assert_eq!(
+        main_chain
+            .find_transaction_reference(&transactions[2].hash())
+            .unwrap(),
+        Some((U256::from(2), block_hashes[1]))
+    );
+    let block = main_chain
+        .find_transaction_block(&transactions[0].hash())
+        .unwrap()
+        .unwrap();
+    assert_eq!(block.hash().unwrap(), block_hashes[0]);
+
+    // removed block is removed from the index as well
+    main_chain.remove_last_block().unwrap();
+    assert_eq!(
+        main_chain.get_address_transactions(&sender, 0, 10).unwrap(),
+        history[1..]
+    );
+    assert!(main_chain
+        .get_address_transactions(&[23; 33], 0, 10)
+        .unwrap()
+        .is_empty());
+    assert!(main_chain
+        .find_transaction_reference(&transactions[2].hash())
+        .unwrap()
+        .is_none());
+    assert!(main_chain
+        .find_transaction_block(&transactions[2].hash())
+        .unwrap()
+        .is_none());
+
+    drop(main_chain);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeHistoryTest");
+}
@@ -0,0 +1,134 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use blockchaintree::{
+    block::{BlockArc, BlockHeader},
+    blockchaintree::{BlockChainTree, SharedBlockChainTree},
+    merkletree::MerkleVersion,
+    static_values::{ROOT_PRIVATE_ADDRESS, ROOT_PUBLIC_ADDRESS},
+    tools,
+    transaction::Transaction,
+    types::Hash,
+};
+use primitive_types::U256;
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+/// Opens an empty tree at `path`, the root has gas for its transactions
+pub fn open_tree(path: &str) -> BlockChainTree {
+    let _ = std::fs::remove_dir_all(path);
+    let tree = BlockChainTree::new(path).unwrap();
+    tree.add_gas(
+        &ROOT_PUBLIC_ADDRESS,
+        U256::from_dec_str("1000000000000000000").unwrap(),
+    )
+    .unwrap();
+    tree
+}
+
+pub fn open_shared_tree(path: &str) -> SharedBlockChainTree {
+    Arc::new(RwLock::new(open_tree(path)))
+}
+
+/// Searches for the pow satisfying `difficulty`
+pub fn find_pow(
+    previous_hash: &Hash,
+    difficulty: &Hash,
+    transactions: &[Hash],
+    version: MerkleVersion,
+) -> Hash {
+    let mut nonce = U256::zero();
+    loop {
+        let mut pow = [0u8; 32];
+        nonce.to_big_endian(&mut pow);
+        if tools::check_pow(previous_hash, difficulty, transactions, &pow, version) {
+            return pow;
+        }
+        nonce += U256::one();
+    }
+}
+
+/// Transaction of the root sending 100 coins, its nonce follows the pooled root transactions
+pub async fn root_transaction(tree: &BlockChainTree, timestamp: u64) -> Transaction {
+    let pending = tree
+        .get_tx_pool()
+        .read()
+        .await
+        .transactions_iter()
+        .filter(|transaction| transaction.get_sender() == &ROOT_PUBLIC_ADDRESS)
+        .count() as u64;
+    Transaction::new(
+        ROOT_PUBLIC_ADDRESS,
+        [7; 33],
+        timestamp,
+        tree.get_nonce(&ROOT_PUBLIC_ADDRESS).unwrap() + pending,
+        U256::from(100),
+        ROOT_PRIVATE_ADDRESS,
+        None,
+    )
+    .unwrap()
+}
+
+/// Mines a block of the founder with a single new transaction of the root
+pub async fn mine(tree: &mut BlockChainTree, founder: [u8; 33]) -> BlockArc {
+    let transaction = root_transaction(tree, tools::current_timestamp()).await;
+    tree.send_transaction(Box::new(transaction)).await.unwrap();
+
+    let template = tree.get_block_template().await.unwrap();
+    let pow = find_pow(
+        &template.previous_hash,
+        &template.difficulty,
+        &template.transactions,
+        MerkleVersion::for_height(&template.height),
+    );
+    let last_block = tree.get_main_chain().get_last_block().unwrap().unwrap();
+    tree.emmit_new_main_block(
+        &pow,
+        &founder,
+        &template.transactions,
+        last_block.get_info().timestamp + 600,
+    )
+    .await
+    .unwrap()
+}
+
+pub async fn mine_shared(tree: &SharedBlockChainTree, founder: [u8; 33]) -> BlockArc {
+    mine(&mut *tree.write().await, founder).await
+}
+
+/// Header of the main chain block at `height`
+pub fn header(tree: &BlockChainTree, height: u64) -> BlockHeader {
+    let block = tree
+        .get_main_chain()
+        .find_by_height(&U256::from(height))
+        .unwrap()
+        .unwrap();
+    BlockHeader::from_block(&block).unwrap()
+}
+
+pub async fn last_hash(tree: &SharedBlockChainTree) -> Hash {
+    let main_chain = tree.read().await.get_main_chain();
+    main_chain
+        .get_last_block()
+        .unwrap()
+        .unwrap()
+        .hash()
+        .unwrap()
+}
+
+/// Decodes the hex encoded hash of the json response
+pub fn decode(value: &Value) -> Hash {
+    hex::decode(value.as_str().unwrap())
+        .unwrap()
+        .try_into()
+        .unwrap()
+}
+
+/// Path of an empty directory for the test database, unique for the test run
+pub fn temp_dir(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("blockchaintree-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    path.to_string_lossy().into_owned()
+}
@@ -0,0 +1,142 @@
+use std::{net::SocketAddr, time::Duration};
+
+use blockchaintree::{
+    blockchaintree::BlockChainTree,
+    config::{MiningConfig, NetworkConfig, NodeConfig, RpcConfig, StratumConfig},
+    errors::{ConfigError, ConfigErrorKind},
+    rpc::RpcClient,
+};
+use serde_json::{json, Value};
+
+#[test]
+fn test_parse_config() {
+    let config = NodeConfig::parse(
+        r#"
+        data_dir = "./Node"
+        log_level = "debug"
+
+        [network]
+        listen = "127.0.0.1:9000"
+        peers = ["10.0.0.2:8333", "10.0.0.3:8333"]
+
+        [rpc]
+        listen = "127.0.0.1:9001"
+
+        [mining]
+        founder = "0x02b28c511fced0ab8ff080867352bc3f92bd0e3b55080b1c89a191d8fb5f5d899f"
+        threads = 2
+
+        [stratum]
+        listen = "127.0.0.1:9002"
+        founder = "02b28c511fced0ab8ff080867352bc3f92bd0e3b55080b1c89a191d8fb5f5d899f"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config,
+        NodeConfig {
+            data_dir: "./Node".into(),
+            log_level: "debug".into(),
+            network: NetworkConfig {
+                listen: "127.0.0.1:9000".parse().unwrap(),
+                peers: vec![
+                    "10.0.0.2:8333".parse().unwrap(),
+                    "10.0.0.3:8333".parse().unwrap()
+                ],
+            },
+            rpc: Some(RpcConfig {
+                listen: "127.0.0.1:9001".parse().unwrap()
+            }),
+            mining: Some(MiningConfig {
+                founder: [
+                    2, 178, 140, 81, 31, 206, 208, 171, 143, 240, 128, 134, 115, 82, 188, 63, 146,
+                    189, 14, 59, 85, 8, 11, 28, 137, 161, 145, 216, 251, 95, 93, 137, 159,
+                ],
+                threads: Some(2),
+            }),
+            stratum: Some(StratumConfig {
+                listen: "127.0.0.1:9002".parse().unwrap(),
+                founder: [
+                    2, 178, 140, 81, 31, 206, 208, 171, 143, 240, 128, 134, 115, 82, 188, 63, 146,
+                    189, 14, 59, 85, 8, 11, 28, 137, 161, 145, 216, 251, 95, 93, 137, 159,
+                ],
+                share_difficulty: 16,
+            }),
+        }
+    );
+
+    // everything has a default
+    let config = NodeConfig::parse("").unwrap();
+    assert_eq!(config.data_dir, "./BlockChainTree");
+    assert_eq!(config.network, NetworkConfig::default());
+    assert!(config.rpc.is_none() && config.mining.is_none() && config.stratum.is_none());
+
+    for wrong in [
+        "[mining]\nfounder = \"0102\"",
+        "[network]\nlisten = \"localhost\"",
+        "unknown = 1",
+    ] {
+        assert!(matches!(
+            NodeConfig::parse(wrong).unwrap_err().current_context(),
+            ConfigError::Config(ConfigErrorKind::Parse)
+        ));
+    }
+    assert!(matches!(
+        NodeConfig::load("./missing.toml")
+            .unwrap_err()
+            .current_context(),
+        ConfigError::Config(ConfigErrorKind::Read)
+    ));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_node_binary() {
+    let _ = std::fs::remove_dir_all("./BlockChainTreeNodeBinary");
+    std::fs::create_dir_all("./BlockChainTreeNodeBinary").unwrap();
+    let rpc_address: SocketAddr = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+    std::fs::write(
+        "./BlockChainTreeNodeBinary/node.toml",
+        format!(
+            "data_dir = \"./BlockChainTreeNodeBinary/data\"\n\
+             [network]\nlisten = \"127.0.0.1:0\"\n\
+             [rpc]\nlisten = \"{}\"\n",
+            rpc_address
+        ),
+    )
+    .unwrap();
+
+    let mut node = std::process::Command::new(env!("CARGO_BIN_EXE_blockchaintree-node"))
+        .arg("./BlockChainTreeNodeBinary/node.toml")
+        .spawn()
+        .unwrap();
+
+    let client = RpcClient::new(rpc_address);
+    let height = tokio::time::timeout(Duration::from_secs(30), async {
+        loop {
+            if let Ok(Ok(height)) = client.call("get_height", Value::Null).await {
+                break height;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(height, json!("1"));
+
+    // the tree is flushed and can be opened after the termination
+    let status = std::process::Command::new("kill")
+        .args(["-TERM", &node.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(node.wait().unwrap().success());
+    let tree = BlockChainTree::new("./BlockChainTreeNodeBinary/data").unwrap();
+    assert_eq!(tree.get_main_chain().get_height(), 1.into());
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeNodeBinary");
+}
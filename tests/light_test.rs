@@ -0,0 +1,101 @@
+mod common;
+
+use blockchaintree::{
+    blockchaintree::SharedBlockChainTree,
+    errors::{LightClientError, LightClientErrorKind},
+    light::LightClient,
+    rpc::{Rpc, RpcClient, RpcServer},
+};
+use common::{last_hash, mine_shared, open_shared_tree};
+use primitive_types::U256;
+
+async fn open_node(path: &str) -> (SharedBlockChainTree, RpcServer) {
+    let tree = open_shared_tree(path);
+    let server = RpcServer::bind(Rpc::new(tree.clone(), None), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    (tree, server)
+}
+
+#[tokio::test]
+async fn test_light_client() {
+    let (tree, server) = open_node("./BlockChainTreeLightNode").await;
+    let client = RpcClient::new(server.local_address());
+    let first = mine_shared(&tree, [6; 33]).await.transactions().unwrap()[0];
+    mine_shared(&tree, [6; 33]).await;
+
+    let _ = std::fs::remove_dir_all("./BlockChainTreeLightClient");
+    let light = LightClient::new("./BlockChainTreeLightClient").unwrap();
+    assert_eq!(light.get_height().unwrap(), U256::one());
+    light.sync(&client).await.unwrap();
+    assert_eq!(light.get_height().unwrap(), U256::from(3));
+    assert_eq!(light.get_tip().unwrap().hash, last_hash(&tree).await);
+
+    // transactions are checked against the stored headers
+    let proof = light
+        .get_transaction_proof(&client, &first)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(proof.height, U256::one());
+    assert!(light.verify_transaction(&proof).unwrap());
+    let mut forged = proof.clone();
+    forged.transaction = [1; 32];
+    assert!(!light.verify_transaction(&forged).unwrap());
+    let mut forged = proof.clone();
+    forged.height = U256::from(2);
+    assert!(!light.verify_transaction(&forged).unwrap());
+    assert!(light
+        .get_transaction_proof(&client, &[1; 32])
+        .await
+        .unwrap()
+        .is_none());
+
+    // accounts are checked against the state roots
+    let account = tree.read().await.get_account_proof(&[7; 33]).unwrap();
+    assert_eq!(account.amount, U256::from(200));
+    assert!(light.verify_account(&account, &U256::from(2)).unwrap());
+    assert!(!light.verify_account(&account, &U256::one()).unwrap());
+
+    // headers breaking the difficulty chain are rejected
+    let mut header = light.find_by_height(&U256::from(2)).unwrap().unwrap();
+    header.info.difficulty = [0; 32];
+    let err = light.add_headers(&[header]).unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        LightClientError::LightClient(LightClientErrorKind::InvalidHeader)
+    ));
+    assert_eq!(light.get_height().unwrap(), U256::from(3));
+
+    // the client switches to the heavier branch of another node
+    let (other_tree, other_server) = open_node("./BlockChainTreeLightOtherNode").await;
+    let other_client = RpcClient::new(other_server.local_address());
+    for _ in 0..3 {
+        mine_shared(&other_tree, [8; 33]).await;
+    }
+    light.sync(&other_client).await.unwrap();
+    assert_eq!(light.get_height().unwrap(), U256::from(4));
+    assert_eq!(light.get_tip().unwrap().hash, last_hash(&other_tree).await);
+    assert!(!light.verify_transaction(&proof).unwrap());
+    let err = light
+        .get_transaction_proof(&client, &first)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        LightClientError::LightClient(LightClientErrorKind::WrongProof)
+    ));
+
+    // the lighter branch is ignored
+    light.sync(&client).await.unwrap();
+    assert_eq!(light.get_tip().unwrap().hash, last_hash(&other_tree).await);
+
+    server.shutdown();
+    other_server.shutdown();
+    drop(light);
+    drop(tree);
+    drop(other_tree);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeLightNode");
+    let _ = std::fs::remove_dir_all("./BlockChainTreeLightOtherNode");
+    let _ = std::fs::remove_dir_all("./BlockChainTreeLightClient");
+}
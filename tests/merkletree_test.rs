@@ -0,0 +1,100 @@
+use blockchaintree::{
+    merkletree::{MerkleTree, MerkleVersion},
+    static_values::MERKLE_V2_HEIGHT,
+};
+use primitive_types::U256;
+
+fn items(amount: u8) -> Vec<[u8; 32]> {
+    (0..amount).map(|item| [item; 32]).collect()
+}
+
+fn verify(tree: &MerkleTree, version: MerkleVersion, item: &[u8; 32], index: usize) -> bool {
+    let proof = tree.get_proof(item).unwrap();
+    MerkleTree::verify_proof(
+        version,
+        item,
+        index,
+        tree.get_root(),
+        &proof[..proof.len() - 1],
+    )
+}
+
+#[test]
+fn merkle_version_test() {
+    assert_eq!(MerkleVersion::for_height(&U256::zero()), MerkleVersion::V1);
+    assert_eq!(
+        MerkleVersion::for_height(&U256::from(MERKLE_V2_HEIGHT - 1)),
+        MerkleVersion::V1
+    );
+    assert_eq!(
+        MerkleVersion::for_height(&U256::from(MERKLE_V2_HEIGHT)),
+        MerkleVersion::V2
+    );
+
+    // blocks without transactions have the same root
+    assert_eq!(
+        MerkleTree::build_tree(&[], MerkleVersion::V1).get_root(),
+        MerkleTree::build_tree(&[], MerkleVersion::V2).get_root()
+    );
+    assert_ne!(
+        MerkleTree::build_tree(&items(3), MerkleVersion::V1).get_root(),
+        MerkleTree::build_tree(&items(3), MerkleVersion::V2).get_root()
+    );
+}
+
+#[test]
+fn merkle_proof_test() {
+    for version in [MerkleVersion::V1, MerkleVersion::V2] {
+        for amount in 1..10 {
+            let items = items(amount);
+            let tree = MerkleTree::build_tree(&items, version);
+            for (index, item) in items.iter().enumerate() {
+                assert_eq!(tree.get_index(item).unwrap(), index);
+                assert!(verify(&tree, version, item, index));
+            }
+            assert!(tree.get_index(&[0xAA; 32]).is_err());
+            assert!(tree.get_proof(&[0xAA; 32]).is_err());
+        }
+    }
+
+    // children are ordered in the second version
+    let items = items(4);
+    let tree = MerkleTree::build_tree(&items, MerkleVersion::V2);
+    assert!(!verify(&tree, MerkleVersion::V2, &items[1], 0));
+    assert!(!verify(&tree, MerkleVersion::V2, &items[1], 5));
+    assert!(!verify(&tree, MerkleVersion::V1, &items[1], 1));
+    assert!(!MerkleTree::verify_proof(
+        MerkleVersion::V2,
+        &items[1],
+        1,
+        tree.get_root(),
+        &[]
+    ));
+}
+
+#[test]
+fn merkle_forgery_test() {
+    let items = vec![[0x0F; 32], [0x3C; 32]];
+
+    // any item sharing the bits of the leaf covered by its sibling passes the first version
+    let forged = [0xCF; 32];
+    let tree = MerkleTree::build_tree(&items, MerkleVersion::V1);
+    let proof = tree.get_proof(&items[0]).unwrap();
+    assert!(MerkleTree::verify_proof(
+        MerkleVersion::V1,
+        &forged,
+        0,
+        tree.get_root(),
+        &proof[..proof.len() - 1]
+    ));
+
+    let tree = MerkleTree::build_tree(&items, MerkleVersion::V2);
+    let proof = tree.get_proof(&items[0]).unwrap();
+    assert!(!MerkleTree::verify_proof(
+        MerkleVersion::V2,
+        &forged,
+        0,
+        tree.get_root(),
+        &proof[..proof.len() - 1]
+    ));
+}
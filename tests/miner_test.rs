@@ -0,0 +1,131 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use blockchaintree::{
+    blockchaintree::{BlockChainTree, BlockTemplate, SharedBlockChainTree},
+    merkletree::MerkleVersion,
+    miner::{self, Miner, MiningJob},
+    static_values::{ROOT_PRIVATE_ADDRESS, ROOT_PUBLIC_ADDRESS},
+    tools,
+    transaction::Transaction,
+};
+use primitive_types::U256;
+use tokio::sync::RwLock;
+
+fn template(difficulty: [u8; 32]) -> BlockTemplate {
+    BlockTemplate {
+        previous_hash: [1; 32],
+        height: U256::from(10),
+        difficulty,
+        transactions: vec![[2; 32], [3; 32], [4; 32]],
+    }
+}
+
+#[test]
+fn test_miner() {
+    let mut difficulty = [0xFF; 32];
+    difficulty[0] = 0;
+    let template = template(difficulty);
+    let job = MiningJob::new(template.clone());
+
+    let miner = Miner::new(4);
+    let result = miner.mine(&job, &AtomicBool::new(false));
+    let pow = result.pow.unwrap();
+    assert!(job.check(&pow));
+    assert!(tools::check_pow(
+        &template.previous_hash,
+        &template.difficulty,
+        &template.transactions,
+        &pow,
+        MerkleVersion::for_height(&template.height)
+    ));
+    assert!(result.hashes > 0);
+    assert_eq!(miner.hashes(), result.hashes);
+}
+
+#[test]
+fn test_miner_cancellation() {
+    // no pow satisfies this difficulty in a reasonable time
+    let job = MiningJob::new(template([0; 32]));
+    let miner = Arc::new(Miner::new(2));
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    let handle = {
+        let (miner, cancel) = (miner.clone(), cancel.clone());
+        std::thread::spawn(move || miner.mine(&job, &cancel))
+    };
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(miner.hashes() > 0);
+    cancel.store(true, Ordering::Relaxed);
+
+    let result = handle.join().unwrap();
+    assert!(result.pow.is_none());
+    assert!(result.hashes > 0);
+    assert!(result.hashrate() > 0.0);
+}
+
+#[tokio::test]
+async fn test_mine_blocks() {
+    let _ = std::fs::remove_dir_all("./BlockChainTreeMinerTest");
+    let tree = BlockChainTree::new("./BlockChainTreeMinerTest").unwrap();
+    tree.add_gas(
+        &ROOT_PUBLIC_ADDRESS,
+        U256::from_dec_str("1000000000000000000").unwrap(),
+    )
+    .unwrap();
+    let tree: SharedBlockChainTree = Arc::new(RwLock::new(tree));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handle = tokio::spawn(miner::mine_blocks(
+        Arc::new(Miner::new(2)),
+        tree.clone(),
+        None,
+        [6; 33],
+        stop.clone(),
+    ));
+
+    // blocks are mined only when there are transactions
+    for nonce in 0..2 {
+        let transaction = Transaction::new(
+            ROOT_PUBLIC_ADDRESS,
+            [7; 33],
+            tools::current_timestamp(),
+            nonce,
+            U256::from(100),
+            ROOT_PRIVATE_ADDRESS,
+            None,
+        )
+        .unwrap();
+        tree.read()
+            .await
+            .send_transaction(Box::new(transaction))
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while tree.read().await.get_main_chain().get_height() < U256::from(nonce + 2) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    tokio::time::timeout(Duration::from_secs(10), handle)
+        .await
+        .unwrap()
+        .unwrap();
+    let tree = tree.read().await;
+    assert_eq!(tree.get_main_chain().get_height(), U256::from(3));
+    assert_eq!(tree.get_amount(&[7; 33]).unwrap(), U256::from(200));
+    assert!(!tree.get_amount(&[6; 33]).unwrap().is_zero());
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeMinerTest");
+}
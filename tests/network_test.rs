@@ -0,0 +1,184 @@
+mod common;
+
+use std::time::Duration;
+
+use blockchaintree::{
+    dump_headers::Headers,
+    network::{self, InventoryItem, Message, Node},
+    tools,
+    transaction::Transactionable,
+};
+use common::{last_hash, mine_shared, open_shared_tree, root_transaction};
+use primitive_types::U256;
+
+async fn wait_for<F, Fut>(mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while !condition().await {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("condition is not met in time");
+}
+
+#[test]
+fn dump_parse_messages() {
+    let messages = vec![
+        Message::Handshake {
+            version: 1,
+            genesis_hash: [1; 32],
+            last_hash: [2; 32],
+            height: U256::from(12345),
+        },
+        Message::Inventory(vec![
+            InventoryItem {
+                kind: Headers::TransactionBlock,
+                hash: [3; 32],
+            },
+            InventoryItem {
+                kind: Headers::Token,
+                hash: [4; 32],
+            },
+        ]),
+        Message::GetBlocks(vec![[5; 32], [6; 32]]),
+        Message::GetData(vec![InventoryItem {
+            kind: Headers::Transaction,
+            hash: [7; 32],
+        }]),
+        Message::NotFound(Vec::new()),
+        Message::Block {
+            block: vec![1, 2, 3],
+            transactions: vec![vec![4, 5], Vec::new()],
+        },
+        Message::Transaction(vec![8; 100]),
+    ];
+
+    for message in messages {
+        let dump = message.dump().unwrap();
+        assert_eq!(Message::parse(&dump).unwrap(), message);
+        if dump.len() > 1 && !matches!(message, Message::Transaction(_)) {
+            assert!(Message::parse(&dump[..dump.len() - 1]).is_err());
+        }
+    }
+    assert!(Message::parse(&[]).is_err());
+    assert!(Message::parse(&[100]).is_err());
+}
+
+#[tokio::test]
+async fn framed_messages() {
+    let (mut client, mut server) = tokio::io::duplex(1024);
+    let message = Message::GetBlocks(vec![[9; 32]; 10]);
+    network::write_message(&mut client, &message).await.unwrap();
+    network::write_message(&mut client, &Message::NotFound(Vec::new()))
+        .await
+        .unwrap();
+
+    assert_eq!(network::read_message(&mut server).await.unwrap(), message);
+    assert_eq!(
+        network::read_message(&mut server).await.unwrap(),
+        Message::NotFound(Vec::new())
+    );
+}
+
+#[tokio::test]
+async fn test_nodes_sync() {
+    let tree_a = open_shared_tree("./BlockChainTreeNetworkSyncA");
+    let tree_b = open_shared_tree("./BlockChainTreeNetworkSyncB");
+    for _ in 0..3 {
+        mine_shared(&tree_a, [6; 33]).await;
+    }
+
+    let node_a = Node::bind(tree_a.clone(), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let node_b = Node::bind(tree_b.clone(), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    node_b.connect(node_a.local_address()).await.unwrap();
+    assert_eq!(node_b.peers()[0].height, U256::from(4));
+
+    // missing blocks are downloaded after the handshake
+    let expected = last_hash(&tree_a).await;
+    wait_for(|| async { last_hash(&tree_b).await == expected }).await;
+    assert_eq!(
+        tree_b.read().await.get_main_chain().get_height(),
+        U256::from(4)
+    );
+    assert!(tree_b.read().await.get_header_chain().is_empty());
+    wait_for(|| async { node_a.peers().len() == 1 }).await;
+
+    // new block is announced and imported with its transactions
+    let hash = mine_shared(&tree_a, [6; 33]).await.hash().unwrap();
+    node_a.announce_block(&hash).await.unwrap();
+    wait_for(|| async { last_hash(&tree_b).await == hash }).await;
+    let amount_a = tree_a.read().await.get_amount(&[7; 33]).unwrap();
+    assert_eq!(tree_b.read().await.get_amount(&[7; 33]).unwrap(), amount_a);
+    assert_eq!(amount_a, U256::from(400));
+
+    node_a.shutdown();
+    node_b.shutdown();
+    drop(tree_a);
+    drop(tree_b);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeNetworkSyncA");
+    let _ = std::fs::remove_dir_all("./BlockChainTreeNetworkSyncB");
+}
+
+#[tokio::test]
+async fn test_transaction_relay() {
+    let tree_a = open_shared_tree("./BlockChainTreeNetworkRelayA");
+    let tree_b = open_shared_tree("./BlockChainTreeNetworkRelayB");
+    let tree_c = open_shared_tree("./BlockChainTreeNetworkRelayC");
+
+    let node_a = Node::bind(tree_a.clone(), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let node_b = Node::bind(tree_b.clone(), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let node_c = Node::bind(tree_c.clone(), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    node_b.connect(node_a.local_address()).await.unwrap();
+    node_c.connect(node_b.local_address()).await.unwrap();
+    wait_for(|| async { node_a.peers().len() == 1 && node_b.peers().len() == 2 }).await;
+
+    // transaction reaches the pool of the node that is not connected to the sender
+    let transaction = root_transaction(&*tree_a.read().await, tools::current_timestamp()).await;
+    let hash = transaction.hash();
+    node_a
+        .send_transaction(Box::new(transaction))
+        .await
+        .unwrap();
+    for tree in [&tree_b, &tree_c] {
+        wait_for(|| async {
+            let tx_pool = tree.read().await.get_tx_pool();
+            let exists = tx_pool.read().await.transaction_exists(&hash);
+            exists
+        })
+        .await;
+    }
+
+    // block mined by the last node travels back through the middle one
+    let block_hash = mine_shared(&tree_c, [6; 33]).await.hash().unwrap();
+    node_c.announce_block(&block_hash).await.unwrap();
+    wait_for(|| async { last_hash(&tree_a).await == block_hash }).await;
+    assert!(tree_a
+        .read()
+        .await
+        .get_main_chain()
+        .transaction_exists(&hash)
+        .unwrap());
+    assert!(tree_a.read().await.get_tx_pool().read().await.is_empty());
+
+    for node in [node_a, node_b, node_c] {
+        node.shutdown();
+    }
+    drop((tree_a, tree_b, tree_c));
+    for path in ["A", "B", "C"] {
+        let _ = std::fs::remove_dir_all(format!("./BlockChainTreeNetworkRelay{}", path));
+    }
+}
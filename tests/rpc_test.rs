@@ -0,0 +1,304 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use blockchaintree::{
+    merkletree::MerkleVersion,
+    rpc::{self, Rpc, RpcClient, RpcServer},
+    state_tree::AccountProof,
+    static_values::{BYTE_GAS_PRICE, ROOT_PRIVATE_ADDRESS, ROOT_PUBLIC_ADDRESS},
+    tools,
+    transaction::{Transaction, Transactionable},
+};
+use common::{decode, find_pow, open_shared_tree};
+use primitive_types::U256;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn raw(address: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+async fn call(client: &RpcClient, method: &str, params: Value) -> Value {
+    client.call(method, params).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn test_rpc_methods() {
+    let tree = open_shared_tree("./BlockChainTreeRpcTest");
+    let server = RpcServer::bind(Rpc::new(tree.clone(), None), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = RpcClient::new(server.local_address());
+    let root = hex::encode(ROOT_PUBLIC_ADDRESS);
+
+    assert_eq!(call(&client, "get_height", Value::Null).await, json!("1"));
+    assert_eq!(
+        call(&client, "get_gas", json!([root])).await,
+        json!("1000000000000000000")
+    );
+    assert_eq!(
+        call(
+            &client,
+            "get_amount",
+            json!({"address": format!("0x{}", root)})
+        )
+        .await,
+        json!(tree
+            .read()
+            .await
+            .get_amount(&ROOT_PUBLIC_ADDRESS)
+            .unwrap()
+            .to_string())
+    );
+
+    // transaction is sent as a hex dump and is found in the pool
+    let transaction = Transaction::new(
+        ROOT_PUBLIC_ADDRESS,
+        [7; 33],
+        tools::current_timestamp(),
+        0,
+        U256::from(100),
+        ROOT_PRIVATE_ADDRESS,
+        None,
+    )
+    .unwrap();
+    let hash = hex::encode(transaction.hash());
+    let dump = hex::encode(transaction.dump().unwrap());
+    assert_eq!(
+        call(&client, "send_transaction", json!([dump])).await,
+        json!(hash)
+    );
+    let pending = call(&client, "get_transaction", json!([hash])).await;
+    assert_eq!(pending["pending"], json!(true));
+    assert_eq!(pending["amount"], json!("100"));
+    assert_eq!(pending["receiver"], json!(hex::encode([7u8; 33])));
+    assert_eq!(
+        call(&client, "get_transaction_status", json!([hash])).await,
+        json!({"status": "pending"})
+    );
+
+    // block is mined from the template
+    let template = call(&client, "get_block_template", Value::Null).await;
+    assert_eq!(template["height"], json!("1"));
+    assert_eq!(template["transactions"], json!([hash]));
+    let version = MerkleVersion::for_height(
+        &U256::from_dec_str(template["height"].as_str().unwrap()).unwrap(),
+    );
+    let pow = find_pow(
+        &decode(&template["previous_hash"]),
+        &decode(&template["difficulty"]),
+        &[transaction.hash()],
+        version,
+    );
+
+    // timestamp has to be between the previous block and the near future
+    for timestamp in [0, tools::current_timestamp() + 3 * 60 * 60] {
+        let error = client
+            .call(
+                "submit_block",
+                json!([hex::encode(pow), hex::encode([6u8; 33]), [hash], timestamp]),
+            )
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.code, rpc::SERVER_ERROR);
+    }
+    assert_eq!(call(&client, "get_height", Value::Null).await, json!("1"));
+
+    let timestamp = tools::current_timestamp() + 600;
+    let block_hash = call(
+        &client,
+        "submit_block",
+        json!({
+            "pow": hex::encode(pow),
+            "founder": hex::encode([6u8; 33]),
+            "transactions": [hash],
+            "timestamp": timestamp,
+        }),
+    )
+    .await;
+
+    assert_eq!(call(&client, "get_height", Value::Null).await, json!("2"));
+    let block = call(&client, "find_by_height", json!(["1"])).await;
+    assert_eq!(block["hash"], block_hash);
+    assert_eq!(block["type"], json!("transaction"));
+    assert_eq!(block["timestamp"], json!(timestamp));
+    assert_eq!(block["transactions"], json!([hash]));
+    assert_eq!(block["founder"], json!(hex::encode([6u8; 33])));
+    assert_eq!(
+        call(&client, "find_by_hash", json!([block_hash])).await,
+        block
+    );
+    assert_eq!(
+        call(&client, "find_by_height", json!([10])).await,
+        Value::Null
+    );
+    assert_eq!(
+        call(&client, "get_amount", json!([hex::encode([7u8; 33])])).await,
+        json!("100")
+    );
+
+    // the balance is proven against the state root of the block
+    let proof = call(
+        &client,
+        "get_account_proof",
+        json!([hex::encode([7u8; 33])]),
+    )
+    .await;
+    assert_eq!(proof["amount"], json!("100"));
+    let proof = AccountProof {
+        owner: [7; 33],
+        amount: U256::from(100),
+        gas: U256::zero(),
+        bitmap: decode(&proof["bitmap"]),
+        siblings: proof["siblings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(decode)
+            .collect(),
+    };
+    assert!(proof.verify(&decode(&block["state_root"])));
+
+    // the transaction is indexed by the block and the addresses
+    assert_eq!(
+        call(&client, "get_transaction_block", json!([hash])).await,
+        json!({"hash": block_hash, "height": "1"})
+    );
+    let history = json!([{"hash": hash, "height": "1"}]);
+    assert_eq!(
+        call(&client, "get_address_transactions", json!([root, 0, 10])).await,
+        history
+    );
+    assert_eq!(
+        call(
+            &client,
+            "get_address_transactions",
+            json!({"address": hex::encode([7u8; 33]), "offset": 0, "limit": 1})
+        )
+        .await,
+        history
+    );
+    assert_eq!(
+        call(&client, "get_address_transactions", json!([root, 1, 10])).await,
+        json!([])
+    );
+
+    let status = call(&client, "get_transaction_status", json!([hash])).await;
+    assert_eq!(status["status"], json!("included"));
+    assert_eq!(status["block_hash"], block_hash);
+    assert_eq!(status["height"], json!("1"));
+    assert_eq!(status["fee"], block["fee"]);
+    assert_eq!(
+        status["gas_used"],
+        json!((*BYTE_GAS_PRICE * transaction.get_dump_size()).to_string())
+    );
+
+    let confirmed = call(&client, "get_transaction", json!([hash])).await;
+    assert_eq!(confirmed["pending"], json!(false));
+    assert_eq!(confirmed["dump"], json!(dump));
+    assert_eq!(
+        call(&client, "get_transaction", json!([hex::encode([0u8; 32])])).await,
+        Value::Null
+    );
+
+    // the same transaction can't be applied twice
+    let error = client
+        .call("send_transaction", json!([dump]))
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(error.code, rpc::SERVER_ERROR);
+    assert_eq!(
+        call(
+            &client,
+            "get_transaction_status",
+            json!([hex::encode([0u8; 32])])
+        )
+        .await,
+        json!({"status": "unknown"})
+    );
+
+    server.shutdown();
+    drop(tree);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeRpcTest");
+}
+
+#[tokio::test]
+async fn test_rpc_errors() {
+    let tree = open_shared_tree("./BlockChainTreeRpcErrors");
+    let server = RpcServer::bind(Rpc::new(tree.clone(), None), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = RpcClient::new(server.local_address());
+
+    let error = |method: &'static str, params: Value| {
+        let client = client.clone();
+        async move { client.call(method, params).await.unwrap().unwrap_err().code }
+    };
+    assert_eq!(error("unknown", Value::Null).await, rpc::METHOD_NOT_FOUND);
+    assert_eq!(error("get_amount", Value::Null).await, rpc::INVALID_PARAMS);
+    assert_eq!(
+        error("get_amount", json!(["0102"])).await,
+        rpc::INVALID_PARAMS
+    );
+    assert_eq!(
+        error("find_by_hash", json!(["not hex"])).await,
+        rpc::INVALID_PARAMS
+    );
+    assert_eq!(
+        error("send_transaction", json!(["0102"])).await,
+        rpc::INVALID_PARAMS
+    );
+    assert_eq!(
+        error(
+            "get_address_transactions",
+            json!([hex::encode([7u8; 33]), 0, 1001])
+        )
+        .await,
+        rpc::INVALID_PARAMS
+    );
+
+    // batch is answered in order, notifications are skipped
+    let response = client
+        .request(&json!([
+            {"jsonrpc": "2.0", "method": "get_height", "id": 1},
+            {"jsonrpc": "2.0", "method": "get_height"},
+            {"method": "get_height", "id": 3},
+        ]))
+        .await
+        .unwrap();
+    assert_eq!(
+        response,
+        json!([
+            {"jsonrpc": "2.0", "result": "1", "id": 1},
+            {"jsonrpc": "2.0", "error": {"code": rpc::INVALID_REQUEST, "message": "invalid json-rpc request"}, "id": 3},
+        ])
+    );
+
+    // malformed json and other http methods
+    let address = server.local_address();
+    let response = raw(address, "POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\n{{{").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains(&rpc::PARSE_ERROR.to_string()));
+    let response = raw(address, "GET / HTTP/1.1\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 405"));
+    let response = raw(
+        address,
+        "POST / HTTP/1.1\r\nContent-Length: 100000000\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 413"));
+
+    server.shutdown();
+    drop(tree);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeRpcErrors");
+}
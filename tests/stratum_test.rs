@@ -0,0 +1,211 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use blockchaintree::{
+    blockchaintree::SharedBlockChainTree,
+    stratum::{
+        self, StratumServer, DUPLICATE_SHARE, JOB_NOT_FOUND, LOW_DIFFICULTY_SHARE, NOT_SUBSCRIBED,
+        OTHER,
+    },
+    tools,
+    types::Hash,
+};
+use common::{decode, open_shared_tree, root_transaction};
+use primitive_types::U256;
+use serde_json::{json, Value};
+use tokio::{
+    io::BufReader,
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+/// Miner speaking to the stratum server
+struct TestMiner {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    extranonce: [u8; 8],
+    next_id: u64,
+}
+
+impl TestMiner {
+    async fn connect(address: SocketAddr, worker: &str) -> (Self, Value) {
+        let (reader, writer) = TcpStream::connect(address).await.unwrap().into_split();
+        let mut miner = Self {
+            reader: BufReader::new(reader),
+            writer,
+            extranonce: [0; 8],
+            next_id: 0,
+        };
+        let result = miner
+            .request("mining.subscribe", json!([worker]))
+            .await
+            .unwrap();
+        miner.extranonce = hex::decode(result["extranonce"].as_str().unwrap())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let job = miner.notification().await;
+        (miner, job)
+    }
+
+    async fn read(&mut self) -> Value {
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            stratum::read_line(&mut self.reader),
+        )
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap()
+    }
+
+    async fn notification(&mut self) -> Value {
+        let message = self.read().await;
+        assert_eq!(message["method"], "mining.notify");
+        message["params"].clone()
+    }
+
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value, i64> {
+        self.next_id += 1;
+        let request = json!({"id": self.next_id, "method": method, "params": params});
+        stratum::write_line(&mut self.writer, &request)
+            .await
+            .unwrap();
+        let response = self.read().await;
+        assert_eq!(response["id"], self.next_id);
+        match response["error"].as_array() {
+            Some(error) => Err(error[0].as_i64().unwrap()),
+            None => Ok(response["result"].clone()),
+        }
+    }
+
+    async fn submit(&mut self, job: &Value, pow: &Hash) -> Result<Value, i64> {
+        self.request("mining.submit", json!([job["job_id"], hex::encode(pow)]))
+            .await
+    }
+
+    /// Searches for the pow of the session, which satisfies `difficulty` if `solves` is set
+    /// or fails it otherwise
+    fn grind(&self, job: &Value, difficulty: &Hash, solves: bool) -> Hash {
+        let previous_hash = decode(&job["previous_hash"]);
+        let merkle_root = decode(&job["merkle_root"]);
+        let mut pow = [0; 32];
+        pow[..8].copy_from_slice(&self.extranonce);
+        for counter in 0u64.. {
+            pow[24..].copy_from_slice(&counter.to_be_bytes());
+            if tools::check_pow_with_root(&previous_hash, difficulty, &merkle_root, &pow) == solves
+            {
+                break;
+            }
+        }
+        pow
+    }
+}
+
+/// Pools a new transaction of the root
+async fn send_transaction(tree: &SharedBlockChainTree) {
+    let tree = tree.read().await;
+    let transaction = root_transaction(&tree, tools::current_timestamp()).await;
+    tree.send_transaction(Box::new(transaction)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_stratum_server() {
+    let tree = open_shared_tree("./BlockChainTreeStratumTest");
+    send_transaction(&tree).await;
+
+    // any pow is a share
+    let server = StratumServer::bind(
+        tree.clone(),
+        None,
+        [6; 33],
+        tools::difficulty_from_zeros(0),
+        "127.0.0.1:0".parse().unwrap(),
+    )
+    .await
+    .unwrap();
+    let address = server.local_address();
+
+    let (mut first, job) = TestMiner::connect(address, "first").await;
+    let (mut second, second_job) = TestMiner::connect(address, "second").await;
+    assert_eq!(job, second_job);
+    assert_ne!(first.extranonce, second.extranonce);
+    assert_eq!(job["height"], "1");
+    assert_eq!(job["clean"], true);
+    let difficulty = decode(&job["difficulty"]);
+
+    // share below the difficulty of the block
+    let share = first.grind(&job, &difficulty, false);
+    assert_eq!(first.submit(&job, &share).await, Ok(json!(true)));
+    assert_eq!(first.submit(&job, &share).await, Err(DUPLICATE_SHARE));
+    // the pow has to start with the extranonce of the session
+    assert_eq!(second.submit(&job, &share).await, Err(OTHER));
+    assert_eq!(
+        first
+            .request("mining.submit", json!([job["job_id"], "00"]))
+            .await,
+        Err(OTHER)
+    );
+    let unknown = json!({"job_id": 1000, "previous_hash": job["previous_hash"], "merkle_root": job["merkle_root"]});
+    assert_eq!(first.submit(&unknown, &share).await, Err(JOB_NOT_FOUND));
+    assert_eq!(
+        tree.read().await.get_main_chain().get_height(),
+        U256::from(1)
+    );
+
+    // the full solution is emitted as the block
+    let solution = second.grind(&job, &difficulty, true);
+    assert_eq!(second.submit(&job, &solution).await, Ok(json!(true)));
+    assert_eq!(
+        tree.read().await.get_main_chain().get_height(),
+        U256::from(2)
+    );
+    assert!(!tree.read().await.get_amount(&[6; 33]).unwrap().is_zero());
+    let shares = server.shares();
+    assert_eq!(shares["first"], 1);
+    assert_eq!(shares["second"], 1);
+
+    // the new transaction makes the job on top of the new block, the old one is stale
+    send_transaction(&tree).await;
+    server.refresh_job().await.unwrap();
+    let new_job = first.notification().await;
+    assert_eq!(new_job["height"], "2");
+    assert_eq!(new_job["clean"], true);
+    assert_eq!(second.notification().await, new_job);
+    let share = first.grind(&job, &difficulty, false);
+    assert_eq!(first.submit(&job, &share).await, Err(JOB_NOT_FOUND));
+
+    // shares can't be easier than required by the server
+    let strict = StratumServer::bind(
+        tree.clone(),
+        None,
+        [6; 33],
+        tools::difficulty_from_zeros(1),
+        "127.0.0.1:0".parse().unwrap(),
+    )
+    .await
+    .unwrap();
+    let (mut miner, job) = TestMiner::connect(strict.local_address(), "strict").await;
+    let share = miner.grind(&job, &tools::difficulty_from_zeros(1), false);
+    assert_eq!(miner.submit(&job, &share).await, Err(LOW_DIFFICULTY_SHARE));
+
+    // shares are accepted only after the subscription
+    let (reader, mut writer) = TcpStream::connect(address).await.unwrap().into_split();
+    let mut reader = BufReader::new(reader);
+    stratum::write_line(
+        &mut writer,
+        &json!({"id": 1, "method": "mining.submit", "params": [new_job["job_id"], hex::encode(share)]}),
+    )
+    .await
+    .unwrap();
+    let response = stratum::read_line(&mut reader).await.unwrap().unwrap();
+    assert_eq!(response["error"][0], NOT_SUBSCRIBED);
+
+    server.shutdown();
+    strict.shutdown();
+    drop(tree);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeStratumTest");
+}
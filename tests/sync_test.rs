@@ -0,0 +1,194 @@
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use blockchaintree::{
+    block::BlockHeader,
+    blockchaintree::{BlockChainTree, SharedBlockChainTree},
+    errors::{BlockChainTreeError, BlockValidationErrorKind},
+    network::Node,
+    static_values::{BODY_REQUEST_TIMEOUT, MAX_BODIES_IN_FLIGHT},
+    sync::{BlockDownloader, DownloadedBlock},
+};
+use common::{header, mine, open_tree};
+use primitive_types::U256;
+use tokio::sync::RwLock;
+
+macro_rules! assert_rejected {
+    ($header:expr, $parent:expr, $kind:pat) => {
+        assert!(matches!(
+            $header.validate($parent).unwrap_err().current_context(),
+            BlockChainTreeError::BlockValidation($kind)
+        ))
+    };
+}
+
+#[tokio::test]
+async fn test_header_validation() {
+    let _ = std::fs::remove_dir_all("./BlockChainTreeHeadersTest");
+    let mut tree = open_tree("./BlockChainTreeHeadersTest");
+    for _ in 0..3 {
+        mine(&mut tree, [6; 33]).await;
+    }
+
+    let headers: Vec<BlockHeader> = (0..4).map(|height| header(&tree, height)).collect();
+    for pair in headers.windows(2) {
+        pair[1].validate(&pair[0]).unwrap();
+    }
+
+    let mut dump = Vec::new();
+    headers[2].dump(&mut dump).unwrap();
+    assert_eq!(BlockHeader::parse(&dump).unwrap(), headers[2]);
+    assert!(BlockHeader::parse(&dump[..60]).is_err());
+
+    let mut forged = headers[2].clone();
+    forged.info.previous_hash = [1; 32];
+    assert_rejected!(
+        forged,
+        &headers[1],
+        BlockValidationErrorKind::WrongPreviousHash
+    );
+    assert_rejected!(
+        headers[3],
+        &headers[1],
+        BlockValidationErrorKind::WrongHeight
+    );
+
+    let mut forged = headers[2].clone();
+    forged.info.difficulty = [0; 32];
+    assert_rejected!(
+        forged,
+        &headers[1],
+        BlockValidationErrorKind::WrongDifficulty
+    );
+
+    let mut forged = headers[2].clone();
+    forged.merkle_root = [2; 32];
+    forged.info.pow = [0; 32];
+    let mut nonce = 0u64;
+    // some pow values satisfy the difficulty by chance, the header has to fail for one of them
+    loop {
+        forged.info.pow[24..].copy_from_slice(&nonce.to_be_bytes());
+        if forged.validate(&headers[1]).is_err() {
+            break;
+        }
+        nonce += 1;
+    }
+    assert_rejected!(forged, &headers[1], BlockValidationErrorKind::WrongPow);
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeHeadersTest");
+}
+
+#[test]
+fn test_block_downloader() {
+    let mut downloader = BlockDownloader::new();
+    let now = Instant::now();
+    let peers = [
+        (1, U256::from(100)),
+        (2, U256::from(100)),
+        (3, U256::from(5)),
+    ];
+
+    let scheduled = downloader.schedule(U256::one(), U256::from(100), &peers, now);
+    assert_eq!(scheduled.len(), 3);
+    for (id, heights) in scheduled.iter() {
+        if *id == 3 {
+            assert!(heights.iter().all(|height| *height < U256::from(5)));
+        } else {
+            assert_eq!(heights.len(), MAX_BODIES_IN_FLIGHT);
+        }
+    }
+    let amount: usize = scheduled.iter().map(|(_, heights)| heights.len()).sum();
+    assert_eq!(downloader.in_flight(), amount);
+
+    // everything is already requested
+    assert!(downloader
+        .schedule(U256::one(), U256::from(100), &peers, now)
+        .is_empty());
+
+    // bodies are returned in order regardless of the order they came in
+    let body = |height: u8| DownloadedBlock {
+        block: vec![height],
+        transactions: Vec::new(),
+    };
+    assert!(downloader.received(U256::from(2), body(2)));
+    assert!(!downloader.received(U256::from(500), body(1)));
+    assert!(downloader.take(&U256::one()).is_none());
+    assert!(downloader.received(U256::from(1), body(1)));
+    assert_eq!(downloader.take(&U256::one()), Some(body(1)));
+    assert_eq!(downloader.take(&U256::from(2)), Some(body(2)));
+
+    // requests of the lost peer go to the other ones
+    downloader.peer_lost(1);
+    let scheduled = downloader.schedule(U256::from(3), U256::from(100), &peers[1..], now);
+    assert!(!scheduled.is_empty());
+    assert!(scheduled.iter().all(|(id, _)| *id != 1));
+
+    // unanswered requests are sent again
+    let later = now + Duration::from_secs(BODY_REQUEST_TIMEOUT + 1);
+    let scheduled = downloader.schedule(U256::from(3), U256::from(100), &peers, later);
+    let amount: usize = scheduled.iter().map(|(_, heights)| heights.len()).sum();
+    assert_eq!(amount, downloader.in_flight());
+    assert_eq!(amount, MAX_BODIES_IN_FLIGHT * 2);
+}
+
+#[tokio::test]
+async fn test_resume_interrupted_sync() {
+    let _ = std::fs::remove_dir_all("./BlockChainTreeResumeA");
+    let _ = std::fs::remove_dir_all("./BlockChainTreeResumeB");
+    let mut tree_a = open_tree("./BlockChainTreeResumeA");
+    for _ in 0..4 {
+        mine(&mut tree_a, [6; 33]).await;
+    }
+
+    // headers are downloaded, but the node stops before the bodies are imported
+    {
+        let tree_b = open_tree("./BlockChainTreeResumeB");
+        let header_chain = tree_b.get_header_chain();
+        for height in 1..5 {
+            header_chain.insert(&header(&tree_a, height)).unwrap();
+        }
+        tree_b.flush().await.unwrap();
+    }
+
+    let tree_b = BlockChainTree::new("./BlockChainTreeResumeB").unwrap();
+    let header_chain = tree_b.get_header_chain();
+    assert_eq!(header_chain.len(), 4);
+    assert_eq!(
+        header_chain.get_tip().unwrap().unwrap().hash,
+        header(&tree_a, 4).hash
+    );
+
+    let tree_a: SharedBlockChainTree = Arc::new(RwLock::new(tree_a));
+    let tree_b: SharedBlockChainTree = Arc::new(RwLock::new(tree_b));
+    let node_a = Node::bind(tree_a.clone(), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let node_b = Node::bind(tree_b.clone(), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    node_b.connect(node_a.local_address()).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while tree_b.read().await.get_main_chain().get_height() < U256::from(5) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(header_chain.is_empty());
+    assert_eq!(
+        tree_b.read().await.get_amount(&[7; 33]).unwrap(),
+        U256::from(400)
+    );
+
+    node_a.shutdown();
+    node_b.shutdown();
+    drop((tree_a, tree_b));
+    let _ = std::fs::remove_dir_all("./BlockChainTreeResumeA");
+    let _ = std::fs::remove_dir_all("./BlockChainTreeResumeB");
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use blockchaintree::{
+    block,
+    blockchaintree::BlockChainTree,
+    errors::{BCTreeErrorKind, BlockChainTreeError},
+    merkletree::MerkleVersion,
+    token::{self, TokenOperation, TokenTransaction},
+    tools,
+    transaction::{self, Transactionable},
+};
+use common::find_pow;
+use primitive_types::U256;
+use secp256k1::Secp256k1;
+
+#[test]
+fn dump_parse_token_transaction() {
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let issuer = public_key.serialize();
+
+    let transaction = TokenTransaction::issue(
+        issuer,
+        [20; 33],
+        100,
+        3,
+        U256::from(1000000),
+        &secret_key.secret_bytes(),
+    )
+    .unwrap();
+    assert_eq!(transaction.get_token(), &token::token_id(&issuer, 3));
+
+    let dump = transaction.dump().unwrap();
+    assert_eq!(dump.len(), transaction.get_dump_size());
+
+    let parsed = transaction::parse_transactionable(&dump).unwrap();
+    assert_eq!(parsed.hash(), transaction.hash());
+    assert_eq!(parsed.get_nonce(), 3);
+    assert_eq!(parsed.get_amount(), None);
+    let action = parsed.get_token_action().unwrap();
+    assert_eq!(action.operation, TokenOperation::Issue);
+    assert_eq!(action.amount, U256::from(1000000));
+    assert!(parsed.verify().unwrap());
+
+    let mut corrupted = dump.clone();
+    corrupted[1] = 10;
+    assert!(transaction::parse_transactionable(&corrupted).is_err());
+
+    let resigned = TokenTransaction::new_signed(
+        TokenOperation::Issue,
+        issuer,
+        [20; 33],
+        100,
+        3,
+        token::token_id(&issuer, 3),
+        U256::from(1000000),
+        *transaction.get_signature(),
+    )
+    .unwrap();
+    assert_eq!(resigned.hash(), transaction.hash());
+
+    // the whole range of amounts is signed
+    let transfer = TokenTransaction::transfer(
+        issuer,
+        [20; 33],
+        101,
+        4,
+        token::token_id(&issuer, 3),
+        U256::MAX,
+        &secret_key.secret_bytes(),
+    )
+    .unwrap();
+    assert!(transfer.verify().unwrap());
+}
+
+#[tokio::test]
+async fn token_lifecycle_test() {
+    let _ = std::fs::remove_dir_all("./BlockChainTreeTokenTest");
+    let mut tree = BlockChainTree::new("./BlockChainTreeTokenTest").unwrap();
+    let main_chain = tree.get_main_chain();
+
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let issuer = public_key.serialize();
+    let private_key = secret_key.secret_bytes();
+    let holder = [7; 33];
+    tree.add_amount(
+        &issuer,
+        U256::from_dec_str("1000000000000000000000").unwrap(),
+    )
+    .unwrap();
+    tree.add_gas(&issuer, U256::from_dec_str("1000000000000000000").unwrap())
+        .unwrap();
+
+    let now = tools::current_timestamp();
+    let issue =
+        TokenTransaction::issue(issuer, issuer, now, 0, U256::from(1000), &private_key).unwrap();
+    let token = *issue.get_token();
+    let transfer = TokenTransaction::transfer(
+        issuer,
+        holder,
+        now + 1,
+        1,
+        token,
+        U256::from(300),
+        &private_key,
+    )
+    .unwrap();
+    let burn =
+        TokenTransaction::burn(issuer, now + 2, 2, token, U256::from(100), &private_key).unwrap();
+
+    // pending operations of the sender are taken into account
+    for transaction in [issue.clone(), transfer.clone(), burn.clone()] {
+        tree.send_transaction(Box::new(transaction)).await.unwrap();
+    }
+    let overspend = TokenTransaction::transfer(
+        issuer,
+        holder,
+        now + 3,
+        3,
+        token,
+        U256::from(601),
+        &private_key,
+    )
+    .unwrap();
+    let err = tree
+        .send_transaction(Box::new(overspend))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::BlockChainTree(BCTreeErrorKind::NewTransaction)
+    ));
+    let unknown = TokenTransaction::transfer(
+        issuer,
+        holder,
+        now + 3,
+        3,
+        [9; 32],
+        U256::from(1),
+        &private_key,
+    )
+    .unwrap();
+    assert!(tree.send_transaction(Box::new(unknown)).await.is_err());
+    assert!(tree.get_token_info(&token).unwrap().is_none());
+
+    let template = tree.get_block_template().await.unwrap();
+    assert_eq!(
+        template.transactions,
+        vec![issue.hash(), transfer.hash(), burn.hash()]
+    );
+    let pow = find_pow(
+        &template.previous_hash,
+        &template.difficulty,
+        &template.transactions,
+        MerkleVersion::for_height(&template.height),
+    );
+    let last_block = main_chain.get_last_block().unwrap().unwrap();
+    tree.emmit_new_main_block(
+        &pow,
+        &[6; 33],
+        &template.transactions,
+        last_block.get_info().timestamp + 600,
+    )
+    .await
+    .unwrap();
+
+    let info = tree.get_token_info(&token).unwrap().unwrap();
+    assert_eq!(info.issuer, issuer);
+    assert_eq!(tree.get_token_supply(&token).unwrap(), U256::from(900));
+    assert_eq!(
+        tree.get_token_balance(&token, &issuer).unwrap(),
+        U256::from(600)
+    );
+    assert_eq!(
+        tree.get_token_balance(&token, &holder).unwrap(),
+        U256::from(300)
+    );
+    let mut holders = tree.get_token_holders(&token).unwrap();
+    holders.sort();
+    let mut expected = vec![(issuer, U256::from(600)), (holder, U256::from(300))];
+    expected.sort();
+    assert_eq!(holders, expected);
+    assert_eq!(tree.get_nonce(&issuer).unwrap(), 3);
+
+    // stored token transactions are loaded with their type
+    let stored = main_chain
+        .get_transactionable(&transfer.hash())
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.get_token_action(), transfer.get_token_action());
+
+    // token balances are committed in the state root,
+    // the blocks differ only in the receiver of the transferred tokens
+    let last_block = main_chain.get_last_block().unwrap().unwrap();
+    let mut roots = Vec::new();
+    for receiver in [holder, [8; 33]] {
+        let transfer = TokenTransaction::transfer(
+            issuer,
+            receiver,
+            now + 4,
+            3,
+            token,
+            U256::from(1),
+            &private_key,
+        )
+        .unwrap();
+        main_chain.add_transaction(&transfer).unwrap();
+        let block = block::TransactionBlock::new(
+            last_block.get_fee(),
+            block::BasicInfo {
+                timestamp: last_block.get_info().timestamp + 600,
+                pow: [0; 32],
+                previous_hash: last_block.hash().unwrap(),
+                height: last_block.get_info().height + 1,
+                difficulty: last_block.get_info().difficulty,
+                founder: [6; 33],
+            },
+            [0; 32],
+            vec![transfer.hash()],
+        );
+        roots.push(tree.preview_state_root(&block).unwrap());
+    }
+    assert_ne!(roots[0], roots[1]);
+    assert_ne!(roots[0], tree.get_state_root().unwrap());
+
+    drop(tree);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeTokenTest");
+}
@@ -1,4 +1,7 @@
-use blockchaintree::tools::{self, check_pow};
+use blockchaintree::{
+    merkletree::MerkleVersion,
+    tools::{check_pow, count_leading_zeros, difficulty_from_zeros},
+};
 
 #[test]
 fn check_pow_test() {
@@ -8,28 +11,39 @@ fn check_pow_test() {
         0xC9, 0xD1,
     ];
 
-    assert_eq!(
-        check_pow(
-            &hash,
-            &[
-                0xF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
-                0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
-                0xFF, 0xFF, 0xFF, 0xFF
-            ],
-            &[0x0, 0x7A, 0x9, 0xDE, 0x81, 0x32, 0x58, 0x4F, 0x6D, 0xE8]
-        ),
-        false
-    );
-    assert_eq!(
-        check_pow(
-            &hash,
-            &[
-                0xF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
-                0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
-                0xFF, 0xFF, 0xFF, 0xFF
-            ],
-            &[0x3A, 0x91, 0x24, 0x45, 0xC9, 0x65, 0x60, 0xD5, 0x1E, 0x69]
-        ),
-        true
-    );
+    assert!(!check_pow(
+        &hash,
+        &[
+            0xF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0xFF, 0xFF, 0xFF, 0xFF
+        ],
+        &[],
+        &[0x0, 0x7A, 0x9, 0xDE, 0x81, 0x32, 0x58, 0x4F, 0x6D, 0xE8],
+        MerkleVersion::V1
+    ));
+    // pow is hashed together with the merkle root of the transactions, so the nonce
+    // differs from the one found for the hash of the previous hash and the nonce alone
+    assert!(check_pow(
+        &hash,
+        &[
+            0xF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0xFF, 0xFF, 0xFF, 0xFF
+        ],
+        &[],
+        &[0x3A, 0x91, 0x24, 0x45, 0xC9, 0x65, 0x60, 0xD5, 0x1E, 0x10],
+        MerkleVersion::V1
+    ));
+}
+
+#[test]
+fn difficulty_from_zeros_test() {
+    assert_eq!(difficulty_from_zeros(0), [0xFF; 32]);
+    assert_eq!(difficulty_from_zeros(256), [0; 32]);
+    for zeros in [1, 7, 8, 9, 20, 255] {
+        assert_eq!(count_leading_zeros(&difficulty_from_zeros(zeros)), zeros);
+    }
+    let difficulty = difficulty_from_zeros(12);
+    assert_eq!(difficulty[..3], [0, 0x0F, 0xFF]);
 }
@@ -1,3 +1,4 @@
+use blockchaintree::errors::{TransactionError, TxErrorKind};
 use blockchaintree::transaction::{self, Transactionable};
 use primitive_types::U256;
 use secp256k1::Secp256k1;
@@ -8,6 +9,7 @@ fn dump_parse_transaction() {
         [0; 33],
         [20; 33],
         100,
+        0,
         U256::from_dec_str("3627836287").unwrap(),
         None,
         [33; 64],
@@ -42,6 +44,7 @@ fn hash_transaction() {
         [10; 33],
         [20; 33],
         100,
+        0,
         U256::from_dec_str("3627836287").unwrap(),
         None,
         [33; 64],
@@ -61,6 +64,7 @@ fn sign_verify_transaction() {
         public_key.serialize(),
         public_key.serialize(),
         100,
+        7,
         U256::from_dec_str("3627836287").unwrap(),
         secret_key.secret_bytes(),
         Some(vec![1, 3, 3, 3, 3, 3, 3]),
@@ -69,3 +73,46 @@ fn sign_verify_transaction() {
 
     assert!(transaction.verify().unwrap());
 }
+
+#[test]
+fn verify_transactions_batch() {
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+
+    let transactions: Vec<transaction::Transaction> = (0..200)
+        .map(|nonce| {
+            transaction::Transaction::new(
+                public_key.serialize(),
+                [20; 33],
+                100,
+                nonce,
+                U256::from(nonce),
+                secret_key.secret_bytes(),
+                None,
+            )
+            .unwrap()
+        })
+        .collect();
+    let mut batch: Vec<&dyn Transactionable> = transactions
+        .iter()
+        .map(|transaction| transaction as &dyn Transactionable)
+        .collect();
+    transaction::verify_transactions(&batch).unwrap();
+
+    let forged = transaction::Transaction::new_signed(
+        public_key.serialize(),
+        [20; 33],
+        100,
+        0,
+        U256::from(1000),
+        None,
+        *transactions[0].get_signature(),
+    );
+    assert!(!forged.verify().unwrap());
+    batch[150] = &forged;
+    let err = transaction::verify_transactions(&batch).unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        TransactionError::Tx(TxErrorKind::Verify)
+    ));
+}
@@ -0,0 +1,229 @@
+use blockchaintree::{
+    errors::{BlockChainTreeError, TxPoolErrorKind},
+    static_values::BYTE_GAS_PRICE,
+    transaction::{Transaction, Transactionable},
+    txpool::{TxPool, TxPoolConfig},
+};
+use primitive_types::U256;
+
+fn transaction(sender: u8, timestamp: u64, amount: u64) -> Box<Transaction> {
+    nonce_transaction(sender, 0, timestamp, amount)
+}
+
+fn nonce_transaction(sender: u8, nonce: u64, timestamp: u64, amount: u64) -> Box<Transaction> {
+    Box::new(Transaction::new_signed(
+        [sender; 33],
+        [100; 33],
+        timestamp,
+        nonce,
+        U256::from(amount),
+        None,
+        [0; 64],
+    ))
+}
+
+fn fee(transaction: &Transaction, per_byte: u64) -> U256 {
+    U256::from(per_byte) * transaction.get_dump_size()
+}
+
+#[test]
+fn fee_per_byte_order_test() {
+    let mut pool = TxPool::new();
+    let cheap = transaction(1, 10, 1);
+    let expensive = transaction(2, 20, 1);
+    let older = transaction(3, 5, 1);
+
+    pool.push(cheap.clone(), fee(&cheap, 1)).unwrap();
+    pool.push(expensive.clone(), fee(&expensive, 3)).unwrap();
+    pool.push(older.clone(), fee(&older, 1)).unwrap();
+
+    let order: Vec<[u8; 32]> = pool.transactions_iter().map(|tr| tr.hash()).collect();
+    assert_eq!(order, vec![expensive.hash(), older.hash(), cheap.hash()]);
+
+    let err = pool.push(cheap.clone(), fee(&cheap, 10)).unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::TxPool(TxPoolErrorKind::AlreadyExists)
+    ));
+
+    assert_eq!(pool.pop().unwrap().0, expensive.hash());
+    assert_eq!(pool.len(), 2);
+}
+
+#[test]
+fn size_limits_test() {
+    let size = transaction(0, 0, 0).get_dump_size();
+    let mut pool = TxPool::with_config(TxPoolConfig {
+        max_transactions: 2,
+        max_bytes: size * 10,
+        expiry: 1000,
+    });
+
+    let low = transaction(1, 1, 1);
+    let middle = transaction(2, 1, 1);
+    let high = transaction(3, 1, 1);
+    pool.push(low.clone(), fee(&low, 1)).unwrap();
+    pool.push(middle.clone(), fee(&middle, 2)).unwrap();
+
+    // the lowest paying transaction is evicted
+    pool.push(high.clone(), fee(&high, 3)).unwrap();
+    assert_eq!(pool.len(), 2);
+    assert!(!pool.transaction_exists(&low.hash()));
+    assert_eq!(
+        pool.get_rejection(&low.hash()),
+        Some(
+            BlockChainTreeError::TxPool(TxPoolErrorKind::PoolFull)
+                .to_string()
+                .as_str()
+        )
+    );
+
+    // new transaction paying less than everything in the full pool is rejected
+    let err = pool.push(low.clone(), fee(&low, 1)).unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::TxPool(TxPoolErrorKind::PoolFull)
+    ));
+
+    // byte limit
+    pool.set_config(TxPoolConfig {
+        max_transactions: 10,
+        max_bytes: size,
+        expiry: 1000,
+    });
+    assert_eq!(pool.len(), 1);
+    assert!(pool.transaction_exists(&high.hash()));
+    assert_eq!(pool.bytes(), size);
+    assert!(pool.get_rejection(&middle.hash()).is_some());
+    assert!(pool.get_rejection(&high.hash()).is_none());
+}
+
+#[test]
+fn expiry_test() {
+    let mut pool = TxPool::with_config(TxPoolConfig {
+        expiry: 100,
+        ..Default::default()
+    });
+    let old = transaction(1, 1000, 1);
+    let new = transaction(2, 1100, 1);
+    pool.push(old.clone(), fee(&old, 1)).unwrap();
+    pool.push(new.clone(), fee(&new, 1)).unwrap();
+
+    assert_eq!(pool.expire(1100), 0);
+    assert_eq!(pool.expire(1101), 1);
+    assert!(!pool.transaction_exists(&old.hash()));
+    assert!(pool.transaction_exists(&new.hash()));
+    assert_eq!(
+        pool.get_rejection(&old.hash()),
+        Some(
+            BlockChainTreeError::TxPool(TxPoolErrorKind::Expired)
+                .to_string()
+                .as_str()
+        )
+    );
+    assert!(pool.is_expired(1000, 1101));
+
+    // higher nonce transactions of the sender expire with the one they depend on
+    let first = nonce_transaction(3, 0, 1200, 1);
+    let second = nonce_transaction(3, 1, 1250, 1);
+    pool.push(first.clone(), fee(&first, 1)).unwrap();
+    pool.push(second.clone(), fee(&second, 1)).unwrap();
+    assert_eq!(pool.expire(1301), 3);
+    assert!(pool.is_empty());
+    assert!(pool.get_by_sender(&[3; 33], 1).is_none());
+    assert!(pool.get_rejection(&second.hash()).is_some());
+}
+
+#[test]
+fn replace_by_fee_test() {
+    let mut pool = TxPool::new();
+    let original = transaction(1, 10, 1);
+    let replacement = transaction(1, 10, 2);
+    pool.push(original.clone(), fee(&original, 2)).unwrap();
+
+    let err = pool
+        .push(replacement.clone(), fee(&replacement, 2))
+        .unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::TxPool(TxPoolErrorKind::Underpriced)
+    ));
+
+    pool.push(replacement.clone(), fee(&replacement, 3))
+        .unwrap();
+    assert_eq!(pool.len(), 1);
+    assert!(!pool.transaction_exists(&original.hash()));
+    assert_eq!(pool.get_by_sender(&[1; 33], 0), Some(&replacement.hash()));
+    assert_eq!(
+        pool.get_rejection(&original.hash()).unwrap(),
+        format!(
+            "replaced by transaction {}",
+            hex::encode(replacement.hash())
+        )
+    );
+
+    // gas price is the minimal fee per byte of a transaction paying only for its bytes
+    let other = transaction(2, 10, 1);
+    pool.push(other.clone(), *BYTE_GAS_PRICE * other.get_dump_size())
+        .unwrap();
+    assert_eq!(
+        pool.transactions_iter().next().unwrap().hash(),
+        other.hash()
+    );
+}
+
+#[test]
+fn eviction_keeps_nonce_order_test() {
+    let size = transaction(0, 0, 0).get_dump_size();
+    let mut pool = TxPool::with_config(TxPoolConfig {
+        max_transactions: 3,
+        max_bytes: size * 10,
+        expiry: 1000,
+    });
+
+    let first = nonce_transaction(1, 0, 1, 1);
+    let second = nonce_transaction(1, 1, 1, 1);
+    let other = transaction(2, 1, 1);
+    pool.push(first.clone(), fee(&first, 1)).unwrap();
+    pool.push(second.clone(), fee(&second, 5)).unwrap();
+    pool.push(other.clone(), fee(&other, 2)).unwrap();
+
+    // evicting the lowest paying transaction evicts the higher nonce of the same sender too
+    let new = transaction(3, 1, 1);
+    pool.push(new.clone(), fee(&new, 3)).unwrap();
+    assert_eq!(pool.len(), 2);
+    assert!(!pool.transaction_exists(&first.hash()));
+    assert!(!pool.transaction_exists(&second.hash()));
+    assert!(pool.get_by_sender(&[1; 33], 1).is_none());
+    assert!(pool.get_rejection(&second.hash()).is_some());
+
+    // transaction can't evict the lower nonce transaction it depends on
+    pool.set_config(TxPoolConfig {
+        max_transactions: 2,
+        max_bytes: size * 10,
+        expiry: 1000,
+    });
+    let next = nonce_transaction(2, 1, 1, 1);
+    let err = pool.push(next.clone(), fee(&next, 4)).unwrap_err();
+    assert!(matches!(
+        err.current_context(),
+        BlockChainTreeError::TxPool(TxPoolErrorKind::PoolFull)
+    ));
+    assert!(pool.transaction_exists(&other.hash()));
+
+    // shrinking the pool evicts the dependent transactions as well
+    pool.set_config(TxPoolConfig {
+        max_transactions: 3,
+        max_bytes: size * 10,
+        expiry: 1000,
+    });
+    pool.push(next.clone(), fee(&next, 4)).unwrap();
+    pool.set_config(TxPoolConfig {
+        max_transactions: 2,
+        max_bytes: size * 10,
+        expiry: 1000,
+    });
+    assert_eq!(pool.len(), 1);
+    assert!(pool.transaction_exists(&new.hash()));
+    assert!(!pool.transaction_exists(&next.hash()));
+}
@@ -0,0 +1,155 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use blockchaintree::{
+    blockchaintree::BlockChainTree,
+    errors::{WalletError, WalletErrorKind},
+    rpc::{Rpc, RpcServer},
+    static_values::{ROOT_PRIVATE_ADDRESS, ROOT_PUBLIC_ADDRESS},
+    transaction,
+    wallet::{self, Keystore},
+};
+use primitive_types::U256;
+use tokio::{process::Command, sync::RwLock};
+
+macro_rules! assert_wallet_error {
+    ($result:expr, $kind:pat) => {
+        assert!(matches!(
+            $result.unwrap_err().current_context(),
+            WalletError::Wallet($kind)
+        ))
+    };
+}
+
+/// Runs the wallet binary, returns its output
+async fn wallet(rpc: SocketAddr, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_bct-wallet"))
+        .args([
+            "--keystore",
+            "./BlockChainTreeWalletBinary/wallet.json",
+            "--rpc",
+            &rpc.to_string(),
+        ])
+        .args(args)
+        .env("BCT_WALLET_PASSWORD", "password")
+        .output()
+        .await
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[test]
+fn test_keystore() {
+    let _ = std::fs::remove_dir_all("./BlockChainTreeKeystore");
+    std::fs::create_dir_all("./BlockChainTreeKeystore").unwrap();
+    let path = "./BlockChainTreeKeystore/wallet.json";
+
+    let mut keystore = Keystore::open(path).unwrap();
+    let address = keystore.generate("main", "password").unwrap();
+    let root = keystore
+        .import("root", &ROOT_PRIVATE_ADDRESS, "other password")
+        .unwrap();
+    assert_eq!(root, ROOT_PUBLIC_ADDRESS);
+    assert_wallet_error!(
+        keystore.generate("main", "password"),
+        WalletErrorKind::KeyExists
+    );
+    assert_wallet_error!(
+        keystore.import("zero", &[0; 32], "password"),
+        WalletErrorKind::WrongKey
+    );
+    keystore.save().unwrap();
+
+    // private keys are not stored in plain text
+    let file = std::fs::read_to_string(path).unwrap();
+    assert!(!file.contains(&hex::encode(ROOT_PRIVATE_ADDRESS)));
+
+    let keystore = Keystore::open(path).unwrap();
+    assert_eq!(
+        keystore.addresses().unwrap(),
+        vec![("main".to_string(), address), ("root".to_string(), root)]
+    );
+    let private_key = keystore.get_private_key("main", "password").unwrap();
+    assert_eq!(wallet::address_of(&private_key).unwrap(), address);
+    assert_eq!(
+        keystore.get_private_key("root", "other password").unwrap(),
+        ROOT_PRIVATE_ADDRESS
+    );
+    assert_wallet_error!(
+        keystore.get_private_key("main", "wrong"),
+        WalletErrorKind::WrongPassword
+    );
+    assert_wallet_error!(
+        keystore.get_address("missing"),
+        WalletErrorKind::KeyNotFound
+    );
+
+    std::fs::write(path, "{}").unwrap();
+    assert_wallet_error!(Keystore::open(path), WalletErrorKind::Parse);
+
+    let _ = std::fs::remove_dir_all("./BlockChainTreeKeystore");
+}
+
+#[tokio::test]
+async fn test_wallet_binary() {
+    let _ = std::fs::remove_dir_all("./BlockChainTreeWalletBinary");
+    let tree = BlockChainTree::new("./BlockChainTreeWalletBinary/data").unwrap();
+    tree.add_gas(
+        &ROOT_PUBLIC_ADDRESS,
+        U256::from_dec_str("1000000000000000000").unwrap(),
+    )
+    .unwrap();
+    let tree = Arc::new(RwLock::new(tree));
+    let server = RpcServer::bind(Rpc::new(tree.clone(), None), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let address = server.local_address();
+
+    let root = hex::encode(ROOT_PUBLIC_ADDRESS);
+    assert_eq!(
+        wallet(
+            address,
+            &["import", "root", &hex::encode(ROOT_PRIVATE_ADDRESS)]
+        )
+        .await,
+        root
+    );
+    let receiver = wallet(address, &["new", "receiver"]).await;
+    assert_eq!(
+        wallet(address, &["list"]).await,
+        format!("root\t{}\nreceiver\t{}", root, receiver)
+    );
+    assert!(wallet(address, &["balance", "root"])
+        .await
+        .ends_with("gas: 1000000000000000000"));
+
+    // transaction is signed offline and submitted later
+    let dump = wallet(
+        address,
+        &["sign", "root", "receiver", "100", "--nonce", "0"],
+    )
+    .await;
+    let transaction = transaction::parse_transactionable(&hex::decode(&dump).unwrap()).unwrap();
+    assert!(transaction.verify().unwrap());
+    assert_eq!(hex::encode(transaction.get_receiver()), receiver);
+    assert_eq!(transaction.get_amount(), Some(U256::from(100)));
+
+    let hash = wallet(address, &["submit", &dump]).await;
+    assert_eq!(hash, hex::encode(transaction.hash()));
+    assert!(tree
+        .read()
+        .await
+        .get_tx_pool()
+        .read()
+        .await
+        .transaction_exists(&transaction.hash()));
+
+    server.shutdown();
+    drop(tree);
+    let _ = std::fs::remove_dir_all("./BlockChainTreeWalletBinary");
+}